@@ -3,3 +3,13 @@ use gc_arena::make_arena;
 use super::vm::VirtualMachine;
 
 make_arena!(pub GcArena, VirtualMachine);
+
+/// Pays off the arena's allocation debt, then lets the VM's hooks know if any collection work
+/// was done
+pub fn collect_debt(arena: &mut GcArena) {
+    if arena.allocation_debt() > 0.0 {
+        arena.collect_debt();
+        let allocated = arena.total_allocated();
+        arena.mutate(|_, vm| vm.notify_gc(allocated));
+    }
+}
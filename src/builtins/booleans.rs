@@ -0,0 +1,21 @@
+use gc_arena::MutationContext;
+
+use crate::value::Value;
+use crate::vm::{Result, Stack, VirtualMachine};
+
+pub fn is_boolean<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::Bool(stack.read()[1].is_bool())))
+}
+
+/// Returns `#t` for `#f` and `#f` for anything else
+pub fn not<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::Bool(stack.read()[1].is_falsey())))
+}
@@ -0,0 +1,55 @@
+//! Boxes from SRFI 111, which are mutable cells holding a single value
+
+use gc_arena::{GcCell, MutationContext};
+
+use crate::object::Object;
+use crate::value::{TypeError, Value};
+use crate::vm::{borrow_mut, Result, Stack, VirtualMachine};
+
+pub fn make_box<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let value = stack.read()[1];
+    Ok(Some(Value::boxed(mc, Object::Cell(value))))
+}
+
+pub fn is_box<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    match stack.read()[1] {
+        Value::Box(object) => Ok(Some(Value::Bool(object.read().is_cell()))),
+        _ => Ok(Some(Value::Bool(false))),
+    }
+}
+
+pub fn unbox<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let cell = as_cell(stack.read()[1])?;
+    let value = *cell.read().as_cell()?;
+    Ok(Some(value))
+}
+
+pub fn set_box<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let cell = as_cell(args[1])?;
+    *borrow_mut(&cell, mc)?.as_cell_mut()? = args[2];
+    Ok(Some(Value::Void))
+}
+
+fn as_cell<'gc>(value: Value<'gc>) -> Result<GcCell<'gc, Object<'gc>>> {
+    match value {
+        Value::Box(object) if object.read().is_cell() => Ok(object),
+        _ => Err(TypeError(format!("'{}' is not a box", value)).into()),
+    }
+}
@@ -0,0 +1,188 @@
+use gc_arena::{GcCell, MutationContext};
+
+use super::{checked_index, checked_range};
+use crate::object::{ObjString, ObjVector, Object};
+use crate::value::{TypeError, Value};
+use crate::vm::{borrow_mut, InterpretError, Result, Stack, VirtualMachine};
+
+/// Longest bytevector `make-bytevector` makes, so that a huge length is an error rather than an
+/// allocation that brings the whole process down
+const MAX_LENGTH: f64 = (1u64 << 28) as f64;
+
+pub fn is_bytevector<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    match args[1] {
+        Value::Box(object) => Ok(Some(Value::Bool(object.read().is_bytevector()))),
+        _ => Ok(Some(Value::Bool(false))),
+    }
+}
+
+/// Makes a bytevector of `k` bytes, all set to `fill` (or 0)
+pub fn make_bytevector<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let k = args[1].as_number()?;
+    if !(0.0..=MAX_LENGTH).contains(&k) || k.fract() != 0.0 {
+        return Err(InterpretError::RuntimeError(format!(
+            "{} is not a valid bytevector length",
+            args[1]
+        )));
+    }
+    let fill = match args.get(2) {
+        Some(fill) => byte(*fill)?,
+        None => 0,
+    };
+
+    Ok(Some(new_bytevector(vec![fill; k as usize], mc)))
+}
+
+/// Makes a bytevector out of its arguments
+pub fn bytevector<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let bytes = stack.read()[1..]
+        .iter()
+        .map(|value| byte(*value))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(new_bytevector(bytes, mc)))
+}
+
+pub fn bytevector_length<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let bytevector = as_bytevector(stack.read()[1])?;
+    let length = bytevector.read().as_bytevector()?.as_slice().len();
+    Ok(Some(Value::Number(length as f64)))
+}
+
+pub fn bytevector_u8_ref<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let bytevector = as_bytevector(args[1])?;
+    let bytevector = bytevector.read();
+    let bytes = bytevector.as_bytevector()?.as_slice();
+    let byte = bytes[checked_index(args[2], bytes.len(), "bytevector")?];
+    Ok(Some(Value::Number(byte as f64)))
+}
+
+pub fn bytevector_u8_set<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let bytevector = as_bytevector(args[1])?;
+    let value = byte(args[3])?;
+    let mut bytevector = borrow_mut(&bytevector, mc)?;
+    let bytes = bytevector.as_bytevector_mut()?.as_slice_mut();
+    bytes[checked_index(args[2], bytes.len(), "bytevector")?] = value;
+    Ok(Some(Value::Void))
+}
+
+/// Makes a new bytevector out of the bytes of a bytevector, optionally only those from `start`
+/// up to (but not including) `end`
+pub fn bytevector_copy<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let bytes = bytevector_bytes(args[1])?;
+    let range = checked_range(&args, 2, "bytevector", args[1], bytes.len())?;
+    Ok(Some(new_bytevector(bytes[range].to_vec(), mc)))
+}
+
+/// Makes a new bytevector out of the bytes of all of its arguments, in order
+pub fn bytevector_append<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut bytes = Vec::new();
+    for &bytevector in &stack.read()[1..] {
+        bytes.extend(bytevector_bytes(bytevector)?);
+    }
+    Ok(Some(new_bytevector(bytes, mc)))
+}
+
+/// Decodes the bytes of a bytevector (optionally only those from `start` up to `end`) as UTF-8
+pub fn utf8_to_string<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let bytes = bytevector_bytes(args[1])?;
+    let range = checked_range(&args, 2, "bytevector", args[1], bytes.len())?;
+    let string = String::from_utf8(bytes[range].to_vec())
+        .map_err(|_| InterpretError::RuntimeError(format!("{} is not valid UTF-8", args[1])))?;
+    Ok(Some(Value::boxed(
+        mc,
+        Object::String(ObjString::from(string)),
+    )))
+}
+
+/// Encodes the characters of a string (optionally only those from `start` up to `end`) as UTF-8
+pub fn string_to_utf8<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let string: String = match args[1] {
+        Value::String(s) => s.as_str().into_owned(),
+        Value::Box(b) => b.read().as_string()?.as_str().into_owned(),
+        string => return Err(TypeError(format!("'{}' is not a string", string)).into()),
+    };
+    let chars: Vec<char> = string.chars().collect();
+    let range = checked_range(&args, 2, "string", args[1], chars.len())?;
+    let bytes = chars[range].iter().collect::<String>().into_bytes();
+    Ok(Some(new_bytevector(bytes, mc)))
+}
+
+pub(crate) fn new_bytevector<'gc>(bytes: Vec<u8>, mc: MutationContext<'gc, '_>) -> Value<'gc> {
+    Value::boxed(
+        mc,
+        Object::Bytevector(ObjVector::new(bytes.into_boxed_slice())),
+    )
+}
+
+/// Gets a value as a byte, which is an exact integer from 0 to 255
+pub(crate) fn byte(value: Value<'_>) -> Result<u8> {
+    let n = value.as_number()?;
+    if !(0.0..=255.0).contains(&n) || n.fract() != 0.0 {
+        return Err(InterpretError::RuntimeError(format!(
+            "{} is not a byte",
+            value
+        )));
+    }
+    Ok(n as u8)
+}
+
+fn as_bytevector<'gc>(value: Value<'gc>) -> Result<GcCell<'gc, Object<'gc>>> {
+    match value {
+        Value::Box(object) if object.read().is_bytevector() => Ok(object),
+        _ => Err(TypeError(format!("'{}' is not a bytevector", value)).into()),
+    }
+}
+
+/// Copies out the bytes of a bytevector
+pub(crate) fn bytevector_bytes(value: Value<'_>) -> Result<Vec<u8>> {
+    let bytevector = as_bytevector(value)?;
+    let bytes = bytevector.read().as_bytevector()?.as_slice().to_vec();
+    Ok(bytes)
+}
@@ -1,7 +1,20 @@
 use gc_arena::MutationContext;
 
 use crate::value::{Char, Value};
-use crate::vm::{Result, Stack, VirtualMachine};
+use crate::vm::{InterpretError, Result, Stack, VirtualMachine};
+
+/// The zero of every run of ten decimal digits (general category Nd) in Unicode, which the
+/// standard library doesn't expose
+const DIGIT_ZEROS: &[u32] = &[
+    0x0030, 0x0660, 0x06F0, 0x07C0, 0x0966, 0x09E6, 0x0A66, 0x0AE6, 0x0B66, 0x0BE6, 0x0C66, 0x0CE6,
+    0x0D66, 0x0DE6, 0x0E50, 0x0ED0, 0x0F20, 0x1040, 0x1090, 0x17E0, 0x1810, 0x1946, 0x19D0, 0x1A80,
+    0x1A90, 0x1B50, 0x1BB0, 0x1C40, 0x1C50, 0xA620, 0xA8D0, 0xA900, 0xA9D0, 0xA9F0, 0xAA50, 0xABF0,
+    0xFF10, 0x104A0, 0x10D30, 0x10D40, 0x11066, 0x110F0, 0x11136, 0x111D0, 0x112F0, 0x11450,
+    0x114D0, 0x11650, 0x116C0, 0x116D0, 0x116DA, 0x11730, 0x118E0, 0x11950, 0x11BF0, 0x11C50,
+    0x11D50, 0x11DA0, 0x11DE0, 0x11F50, 0x16130, 0x16A60, 0x16AC0, 0x16B50, 0x16D70, 0x1CCF0,
+    0x1D7CE, 0x1D7D8, 0x1D7E2, 0x1D7EC, 0x1D7F6, 0x1E140, 0x1E2F0, 0x1E4F0, 0x1E5F1, 0x1E950,
+    0x1FBF0,
+];
 
 pub fn is_char<'gc>(
     _: &VirtualMachine<'gc>,
@@ -117,6 +130,23 @@ pub fn is_char_lower_case<'gc>(
     Ok(Some(Value::Bool(c.is_lowercase())))
 }
 
+/// Gets the one character a case mapping maps to, or `c` itself when it maps to several, like ß
+/// does to SS. A single character can only ever be mapped to a single character.
+fn map_case(c: char, mut mapping: impl Iterator<Item = char>) -> char {
+    match (mapping.next(), mapping.next()) {
+        (Some(mapped), None) => mapped,
+        _ => c,
+    }
+}
+
+fn upcase(c: char) -> char {
+    map_case(c, c.to_uppercase())
+}
+
+fn downcase(c: char) -> char {
+    map_case(c, c.to_lowercase())
+}
+
 pub fn char_upcase<'gc>(
     _: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
@@ -124,7 +154,7 @@ pub fn char_upcase<'gc>(
 ) -> Result<Option<Value<'gc>>> {
     let args = stack.read();
     let c = args[1].as_char()?;
-    Ok(Some(Value::Char(Char(c.to_ascii_uppercase()))))
+    Ok(Some(Value::Char(Char(upcase(c)))))
 }
 
 pub fn char_downcase<'gc>(
@@ -134,5 +164,67 @@ pub fn char_downcase<'gc>(
 ) -> Result<Option<Value<'gc>>> {
     let args = stack.read();
     let c = args[1].as_char()?;
-    Ok(Some(Value::Char(Char(c.to_ascii_lowercase()))))
+    Ok(Some(Value::Char(Char(downcase(c)))))
+}
+
+/// Approximates simple Unicode case folding by upcasing and then downcasing, the same way
+/// `string-foldcase` does, so that final sigma folds to σ like the other sigmas
+pub fn char_foldcase<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let c = args[1].as_char()?;
+    Ok(Some(Value::Char(Char(downcase(upcase(c))))))
+}
+
+pub fn char_to_integer<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let c = args[1].as_char()?;
+    Ok(Some(Value::Number(c as u32 as f64)))
+}
+
+/// Gets the character with the given code point, which has to be a Unicode scalar value (so not
+/// a surrogate)
+pub fn integer_to_char<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let n = args[1].as_number()?;
+    let c = Some(n)
+        .filter(|n| n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64)
+        .and_then(|n| char::from_u32(n as u32));
+    match c {
+        Some(c) => Ok(Some(Value::Char(Char(c)))),
+        None => Err(InterpretError::RuntimeError(format!(
+            "{} is not a Unicode scalar value",
+            args[1]
+        ))),
+    }
+}
+
+/// Gets the value of a decimal digit in any script, or `#f` for any other character
+pub fn digit_value<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let c = args[1].as_char()? as u32;
+    let zero = DIGIT_ZEROS
+        .iter()
+        .take_while(|&&zero| zero <= c)
+        .last()
+        .filter(|&&zero| c - zero < 10);
+    match zero {
+        Some(zero) => Ok(Some(Value::Number((c - zero) as f64))),
+        None => Ok(Some(Value::Bool(false))),
+    }
 }
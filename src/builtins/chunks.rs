@@ -0,0 +1,99 @@
+//! Natives for putting bytecode together by hand, so that a compiler written in Scheme can emit
+//! code for this VM directly. Chunks are checked when they're made into procedures, but the
+//! checks can't catch everything (e.g. code that calls a constant that isn't a procedure is only
+//! an error once it runs), which is why they're only built with the `self-hosting` feature.
+
+use gc_arena::{GcCell, MutationContext};
+
+use crate::chunk::Chunk;
+use crate::compiler::verify::verify;
+use crate::compiler::Upvalues;
+use crate::object::{ObjFunction, Object};
+use crate::value::{TypeError, Value};
+use crate::vm::{borrow_mut, InterpretError, Result, Stack, VirtualMachine};
+
+/// Gets a non-negative integer argument that's no bigger than `max`
+fn integer_argument(value: Value<'_>, max: usize) -> Result<usize> {
+    let number = value.as_number()?;
+    if number.fract() != 0.0 || number < 0.0 || number > max as f64 {
+        return Err(InterpretError::RuntimeError(format!(
+            "{} is not an integer between 0 and {}",
+            value, max
+        )));
+    }
+    Ok(number as usize)
+}
+
+fn as_chunk_cell<'gc>(value: Value<'gc>) -> Result<GcCell<'gc, Object<'gc>>> {
+    match value {
+        Value::Box(object) if object.read().is_chunk() => Ok(object),
+        _ => Err(TypeError(format!("'{}' is not a chunk", value)).into()),
+    }
+}
+
+pub fn make_chunk<'gc>(
+    _: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::boxed(mc, Object::Chunk(Chunk::new()))))
+}
+
+/// `(chunk-emit! chunk byte [line])` appends a byte of code to a chunk, returning the offset it
+/// was written at. Without a line, the byte is put on the same line as the one before it.
+pub fn chunk_emit<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let cell = as_chunk_cell(args[1])?;
+    let byte = integer_argument(args[2], u8::MAX as usize)? as u8;
+    let line = match args.get(3) {
+        Some(line) => Some(integer_argument(*line, usize::MAX)?),
+        None => None,
+    };
+
+    let mut object = borrow_mut(&cell, mc)?;
+    let chunk = object.as_chunk_mut()?;
+    let line = line.unwrap_or_else(|| chunk.lines().last().map_or(0, |(_, line)| *line));
+    let offset = chunk.code().len();
+    chunk.write(byte, line);
+    Ok(Some(Value::Number(offset as f64)))
+}
+
+/// `(chunk-add-constant! chunk value)` adds a value to a chunk's constant pool, returning its
+/// index
+pub fn chunk_add_constant<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let cell = as_chunk_cell(args[1])?;
+    let mut object = borrow_mut(&cell, mc)?;
+    let index = object.as_chunk_mut()?.add_constant(args[2]);
+    Ok(Some(Value::Number(index as f64)))
+}
+
+/// `(make-procedure-from-chunk chunk arity [variadic? [name]])` turns a copy of a chunk into a
+/// procedure without any upvalues. Later changes to the chunk don't affect the procedure.
+pub fn make_procedure_from_chunk<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let cell = as_chunk_cell(args[1])?;
+    let arity = integer_argument(args[2], usize::MAX)?;
+    let variadic = args.get(3).is_some_and(Value::is_truthy);
+    let name = match args.get(4) {
+        Some(name) => Some(name.as_symbol()?),
+        None => None,
+    };
+
+    let chunk = cell.read().as_chunk()?.clone();
+    let function = ObjFunction::new(mc, arity, variadic, chunk, Upvalues::default(), name);
+    verify(&function)?;
+    Ok(Some(Value::boxed(mc, Object::Function(function))))
+}
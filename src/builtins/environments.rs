@@ -0,0 +1,139 @@
+use gc_arena::{GcCell, MutationContext};
+
+use super::list_from;
+use super::macros::{expand_macros, EVAL_EXPANSION_CONTINUATION};
+use crate::chunk::{GlobalTable, Globals};
+use crate::compiler::bootstrap::{self, CompileOptions};
+use crate::compiler::SourceMap;
+use crate::memory::{Symbol, Token};
+use crate::object::{ObjString, Object};
+use crate::value::{TypeError, Value};
+use crate::vm::{InterpretError, Result, Stack, VirtualMachine};
+
+/// Gets the global variables of an environment object
+pub(crate) fn environment_arg<'gc>(environment: Value<'gc>) -> Result<Globals<'gc>> {
+    match environment {
+        Value::Box(object) => match &*object.read() {
+            Object::Namespace(globals) => Ok(*globals),
+            _ => Err(TypeError(format!("'{}' is not an environment", environment)).into()),
+        },
+        _ => Err(TypeError(format!("'{}' is not an environment", environment)).into()),
+    }
+}
+
+/// Makes an environment object out of a new table of global variables
+fn new_environment<'gc>(
+    bindings: impl IntoIterator<Item = (Symbol<'gc>, Value<'gc>)>,
+    mc: MutationContext<'gc, '_>,
+) -> Value<'gc> {
+    let globals = GcCell::allocate(mc, bindings.into_iter().collect::<GlobalTable<'_>>());
+    Value::boxed(mc, Object::Namespace(globals))
+}
+
+/// Checks that the version given to `scheme-report-environment` or `null-environment` is 5, the
+/// only one there is
+fn check_report_version(version: Value<'_>) -> Result<()> {
+    if version.as_number()? != 5.0 {
+        return Err(InterpretError::RuntimeError(format!(
+            "Unsupported report version {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+/// Makes an environment holding just what the given import sets bring in, e.g.
+/// `(environment '(only (scheme base) + car))`
+pub fn environment<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let sets = stack.read()[1..].to_vec();
+    let mut bindings = Vec::new();
+    for set in sets {
+        bindings.extend(vm.resolve_import(set, mc)?);
+    }
+    Ok(Some(new_environment(bindings, mc)))
+}
+
+/// Gets the environment programs run in, where the REPL defines things
+pub fn interaction_environment<'gc>(
+    vm: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(vm.interaction_environment()))
+}
+
+/// Makes an environment with all of the builtins bound, as they were before anything redefined
+/// them
+pub fn scheme_report_environment<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    check_report_version(stack.read()[1])?;
+    let base = vm.intern_symbol(Token::new(mc, ObjString::from("base")), mc);
+    let scheme = vm.intern_symbol(Token::new(mc, ObjString::from("scheme")), mc);
+    let name = list_from(vec![Value::Symbol(scheme), Value::Symbol(base)], mc);
+    Ok(Some(new_environment(vm.resolve_import(name, mc)?, mc)))
+}
+
+/// Makes an environment with nothing bound, where only the special forms work
+pub fn null_environment<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    check_report_version(stack.read()[1])?;
+    Ok(Some(new_environment(Vec::new(), mc)))
+}
+
+pub fn is_environment<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let is_environment = match stack.read()[1] {
+        Value::Box(object) => object.read().is_namespace(),
+        _ => false,
+    };
+    Ok(Some(Value::Bool(is_environment)))
+}
+
+/// Evaluates an expression in an environment, or where programs run if none is given. Definitions
+/// it makes go in that environment, and the globals it refers to are looked up there too.
+pub fn eval<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (expr, environment) = {
+        let args = stack.read();
+        (args[1], args.get(2).copied())
+    };
+    let globals = environment.map(environment_arg).transpose()?;
+    let target = globals.unwrap_or_else(|| vm.globals());
+    if expand_macros(vm, stack, target, EVAL_EXPANSION_CONTINUATION, mc)? {
+        return Ok(None);
+    }
+    let options = CompileOptions {
+        explain: vm.explain().is_some(),
+        warnings: vm.warnings().then(Default::default),
+        ..CompileOptions::default()
+    };
+
+    let source = SourceMap::default();
+    let name = bootstrap::source_name(expr, &source, mc);
+    let function =
+        bootstrap::compile_in(expr, vm.tables(), source, &options, globals, Some(name), mc)?;
+    for warning in options.take_warnings() {
+        vm.warn(&warning, mc);
+    }
+    vm.notify_compile(&function);
+    let thunk = Value::boxed(mc, Object::Function(function));
+    stack.write(mc).push(thunk);
+    vm.tail_call_value(thunk, stack, 0, mc)?;
+    Ok(None)
+}
@@ -1,37 +1,188 @@
+use std::collections::HashMap;
+
 use gc_arena::{Gc, GcCell, MutationContext};
 
+use super::uncons;
+use crate::object::Object;
 use crate::value::Value;
 use crate::vm::{Result, Stack, VirtualMachine};
 
 pub fn is_eqv<'gc>(
-    vm: &VirtualMachine<'gc>,
+    _: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
-    mc: MutationContext<'gc, '_>,
+    _: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
     let args = stack.read();
-    use Value::*;
-    match (args[1], args[2]) {
-        (Bool(b1), Bool(b2)) => Ok(Some(Bool(b1 == b2))),
-        (Char(c1), Char(c2)) => Ok(Some(Bool(c1 == c2))),
-        (Number(_), Number(_)) => super::equal_number(vm, stack, mc),
-        (Null, Null) => Ok(Some(Bool(true))),
-        (Pair(pair1), Pair(pair2)) => Ok(Some(Bool(Gc::ptr_eq(pair1, pair2)))),
-        (String(string1), String(string2)) => Ok(Some(Bool(Gc::ptr_eq(string1, string2)))),
-        (Box(obj1), Box(obj2)) => (Ok(Some(Bool(GcCell::ptr_eq(obj1, obj2))))),
-        (Symbol(s1), Symbol(s2)) => Ok(Some(Bool(s1 == s2))),
-        (Void, Void) => Ok(Some(Bool(true))),
-        (_, _) => Ok(Some(Bool(false))),
-    }
+    Ok(Some(Value::Bool(eqv(args[1], args[2]))))
 }
 
 pub fn is_eq<'gc>(
-    vm: &VirtualMachine<'gc>,
+    _: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
-    mc: MutationContext<'gc, '_>,
+    _: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
     let args = stack.read();
-    match (args[1], args[2]) {
-        (Value::Number(n1), Value::Number(n2)) => Ok(Some(Value::Bool(n1 == n2))),
-        (_, _) => is_eqv(vm, stack, mc),
+    Ok(Some(Value::Bool(eq(args[1], args[2]))))
+}
+
+pub fn is_equal<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    Ok(Some(Value::Bool(equal(args[1], args[2]))))
+}
+
+/// Rust-side `eq?`, for builtins that compare values without calling back into the VM
+pub(crate) fn eq<'gc>(first: Value<'gc>, second: Value<'gc>) -> bool {
+    match (first, second) {
+        (Value::Number(n1), Value::Number(n2)) => n1 == n2,
+        (_, _) => eqv(first, second),
+    }
+}
+
+/// Rust-side `eqv?`, for builtins that compare values without calling back into the VM
+pub(crate) fn eqv<'gc>(first: Value<'gc>, second: Value<'gc>) -> bool {
+    use Value::*;
+    match (first, second) {
+        (Bool(b1), Bool(b2)) => b1 == b2,
+        (Char(c1), Char(c2)) => c1 == c2,
+        (Number(n1), Number(n2)) => (n1 - n2).abs() <= f64::EPSILON,
+        (Null, Null) | (Void, Void) | (Eof, Eof) => true,
+        (Pair(pair1), Pair(pair2)) => Gc::ptr_eq(pair1, pair2),
+        (String(string1), String(string2)) => Gc::ptr_eq(string1, string2),
+        (Vector(vector1), Vector(vector2)) => Gc::ptr_eq(vector1, vector2),
+        (Box(obj1), Box(obj2)) => GcCell::ptr_eq(obj1, obj2),
+        (Symbol(s1), Symbol(s2)) => s1 == s2,
+        (_, _) => false,
+    }
+}
+
+/// Rust-side `equal?`: compares pairs, strings, vectors and bytevectors structurally, whether they're
+/// constants or mutable objects. Pairs and vectors that have already been compared are merged
+/// into one equivalence class and assumed equal if they meet again, so comparing cyclic
+/// structures terminates.
+pub(crate) fn equal<'gc>(first: Value<'gc>, second: Value<'gc>) -> bool {
+    let mut classes = UnionFind::default();
+
+    // Work through an explicit list so deep structures don't overflow the Rust stack
+    let mut pending = vec![(first, second)];
+    while let Some((first, second)) = pending.pop() {
+        if eqv(first, second) {
+            continue;
+        }
+
+        if let (Some(id1), Some(id2)) = (identity(first), identity(second)) {
+            if !classes.union(id1, id2) {
+                continue;
+            }
+        }
+
+        if let (Some((car1, cdr1)), Some((car2, cdr2))) = (uncons(first), uncons(second)) {
+            pending.push((cdr1, cdr2));
+            pending.push((car1, car2));
+            continue;
+        }
+
+        match (bytevector_bytes(first), bytevector_bytes(second)) {
+            (Some(bytes1), Some(bytes2)) if bytes1 == bytes2 => continue,
+            (None, None) => {}
+            _ => return false,
+        }
+
+        match (string_bytes(first), string_bytes(second)) {
+            (Some(bytes1), Some(bytes2)) if bytes1 == bytes2 => {}
+            (None, None) => match (vector_items(first), vector_items(second)) {
+                (Some(items1), Some(items2)) if items1.len() == items2.len() => {
+                    pending.extend(items1.into_iter().zip(items2).rev());
+                }
+                _ => return false,
+            },
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Identifies the pair or vector behind a value, for tracking which have been compared
+fn identity(value: Value<'_>) -> Option<usize> {
+    match value {
+        Value::Pair(pair) => Some(Gc::as_ptr(pair) as usize),
+        Value::Vector(vector) => Some(Gc::as_ptr(vector) as usize),
+        Value::Box(object) => match &*object.read() {
+            Object::Pair(_) | Object::Vector(_) => Some(object.as_ptr() as usize),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Disjoint sets of object identities
+#[derive(Default)]
+struct UnionFind(HashMap<usize, usize>);
+
+impl UnionFind {
+    fn find(&mut self, id: usize) -> usize {
+        let mut root = id;
+        while let Some(&parent) = self.0.get(&root) {
+            if parent == root {
+                break;
+            }
+            root = parent;
+        }
+
+        // Point everything on the way straight at the root
+        let mut current = id;
+        while current != root {
+            let parent = self.0.insert(current, root).unwrap_or(root);
+            current = parent;
+        }
+        root
+    }
+
+    /// Merges the sets containing `first` and `second`, returning `false` if they were already
+    /// the same set
+    fn union(&mut self, first: usize, second: usize) -> bool {
+        let (root1, root2) = (self.find(first), self.find(second));
+        if root1 == root2 {
+            false
+        } else {
+            self.0.insert(root1, root2);
+            true
+        }
+    }
+}
+
+fn bytevector_bytes(value: Value<'_>) -> Option<Vec<u8>> {
+    match value {
+        Value::Box(object) => match &*object.read() {
+            Object::Bytevector(bytevector) => Some(bytevector.as_slice().to_vec()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn string_bytes(value: Value<'_>) -> Option<Vec<u8>> {
+    match value {
+        Value::String(string) => Some(string.as_bytes().to_vec()),
+        Value::Box(object) => match &*object.read() {
+            Object::String(string) => Some(string.as_bytes().to_vec()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn vector_items(value: Value<'_>) -> Option<Vec<Value<'_>>> {
+    match value {
+        Value::Vector(vector) => Some(vector.as_slice().iter().map(|&item| item.into()).collect()),
+        Value::Box(object) => match &*object.read() {
+            Object::Vector(vector) => Some(vector.as_slice().to_vec()),
+            _ => None,
+        },
+        _ => None,
     }
 }
@@ -0,0 +1,266 @@
+use gc_arena::MutationContext;
+
+use super::{list_from, string_arg};
+use crate::object::{ErrorKind, ObjError, ObjNative, ObjPair, ObjString, Object};
+use crate::printer;
+use crate::value::{TypeError, Value};
+use crate::vm::{peek, InterpretError, Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    WITH_EXCEPTION_HANDLER_CONTINUATION => "%with-exception-handler-continuation", with_exception_handler_continuation;
+    RAISE_CONTINUATION => "%raise-continuation", raise_continuation;
+    RAISE_CONTINUABLE_CONTINUATION => "%raise-continuable-continuation", raise_continuable_continuation;
+}
+
+/// Calls `thunk` with `handler` installed as the innermost exception handler
+pub fn with_exception_handler<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (handler, thunk) = {
+        let args = stack.read();
+        (args[1], args[2])
+    };
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(
+        1,
+        false,
+        WITH_EXCEPTION_HANDLER_CONTINUATION,
+        None,
+    ));
+
+    stack.write(mc).push(thunk);
+    vm.call_value(thunk, stack, 0, mc)?;
+
+    // Installed after the call so that returning from the thunk restores the outer handlers
+    let handlers = *vm.handlers().read();
+    *vm.handlers().write(mc) = Value::boxed(mc, Object::Pair(ObjPair::new(handler, handlers)));
+    Ok(None)
+}
+
+fn with_exception_handler_continuation<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(peek(stack, 0)))
+}
+
+/// Raises a non-continuable exception
+pub fn raise<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let obj = stack.read()[1];
+    raise_with(vm, stack, obj, false, mc)
+}
+
+/// Raises an exception whose handler's result is returned to the caller of `raise-continuable`
+pub fn raise_continuable<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let obj = stack.read()[1];
+    raise_with(vm, stack, obj, true, mc)
+}
+
+/// Raises a new error object with a message and any number of irritants
+pub fn error<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (message, irritants) = {
+        let args = stack.read();
+        (args[1], args[2..].to_vec())
+    };
+    // Only checked, since the error object keeps the message itself
+    string_arg(message)?;
+
+    let obj = Value::boxed(
+        mc,
+        Object::Error(ObjError::new(message, irritants.into_boxed_slice())),
+    );
+    raise_with(vm, stack, obj, false, mc)
+}
+
+/// Runs `f` on the error object `value`, or fails if it isn't one
+fn with_error_object<'gc, T>(value: Value<'gc>, f: impl FnOnce(&ObjError<'gc>) -> T) -> Result<T> {
+    match value {
+        Value::Box(object) => Ok(f(object.read().as_error()?)),
+        _ => Err(TypeError(format!("'{}' is not an error object", value)).into()),
+    }
+}
+
+/// Checks whether a value is the kind of error object described by `kind`
+fn is_error_of_kind(value: Value<'_>, kind: Option<ErrorKind>) -> bool {
+    match value {
+        Value::Box(object) => match &*object.read() {
+            Object::Error(error) => kind.is_none_or(|kind| error.kind() == kind),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+pub fn is_error_object<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let is_error = is_error_of_kind(stack.read()[1], None);
+    Ok(Some(Value::Bool(is_error)))
+}
+
+pub fn error_object_message<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let message = with_error_object(stack.read()[1], |error| error.message())?;
+    Ok(Some(message))
+}
+
+/// Gets a new list of an error object's irritants
+pub fn error_object_irritants<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let irritants = with_error_object(stack.read()[1], |error| error.irritants().to_vec())?;
+    Ok(Some(list_from(irritants, mc)))
+}
+
+/// Checks whether a value is an error object raised because a file or other port couldn't be
+/// opened, read from or written to
+pub fn is_file_error<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let is_error = is_error_of_kind(stack.read()[1], Some(ErrorKind::File));
+    Ok(Some(Value::Bool(is_error)))
+}
+
+/// Checks whether a value is an error object raised because input couldn't be parsed
+pub fn is_read_error<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let is_error = is_error_of_kind(stack.read()[1], Some(ErrorKind::Read));
+    Ok(Some(Value::Bool(is_error)))
+}
+
+/// Passes an error the interpreter stopped with on to the installed exception handlers as an
+/// error object. Errors are returned as is when there's no handler installed, as are errors that
+/// already went through the handlers.
+pub(crate) fn raise_interpret_error<'gc>(
+    vm: &VirtualMachine<'gc>,
+    err: InterpretError,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let kind = match err {
+        InterpretError::UncaughtException(_)
+        | InterpretError::LoadError { .. }
+        | InterpretError::FuelExhausted
+        | InterpretError::Interrupted => return Err(err),
+        InterpretError::IoError(_) => ErrorKind::File,
+        InterpretError::CompileError(_) | InterpretError::Utf8Error(_) => ErrorKind::Read,
+        _ => ErrorKind::General,
+    };
+    if let Value::Null = *vm.handlers().read() {
+        return Err(err);
+    }
+
+    let message = match err {
+        InterpretError::RuntimeError(message) => message,
+        err => err.to_string(),
+    };
+    let message = Value::boxed(mc, Object::String(ObjString::from(message)));
+    let obj = Value::boxed(
+        mc,
+        Object::Error(ObjError::new(message, Box::new([])).with_kind(kind)),
+    );
+    let stack = vm.suspend_procedure(mc);
+    raise_with(vm, stack, obj, false, mc)?;
+    Ok(())
+}
+
+/// Describes an exception that nothing handled. Error objects are reported by their message and
+/// irritants rather than printed like other values.
+pub(crate) fn describe_uncaught(obj: Value<'_>) -> String {
+    if let Value::Box(object) = obj {
+        if let Ok(Object::Error(error)) = object.try_read().as_deref() {
+            return printer::ErrorReport(error).to_string();
+        }
+    }
+    obj.to_string()
+}
+
+/// Calls the innermost exception handler with `obj`. The handler runs with the outer handlers
+/// installed so that it can re-raise.
+pub(crate) fn raise_with<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    obj: Value<'gc>,
+    continuable: bool,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let handlers = *vm.handlers().read();
+    let (handler, outer) = match handlers {
+        Value::Box(handlers) => {
+            let handlers = handlers.read();
+            let handlers = handlers.as_pair()?;
+            (handlers.car(), handlers.cdr())
+        }
+        _ => return Err(InterpretError::UncaughtException(describe_uncaught(obj))),
+    };
+
+    let continuation = if continuable {
+        RAISE_CONTINUABLE_CONTINUATION
+    } else {
+        RAISE_CONTINUATION
+    };
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, continuation, None));
+
+    // Kept below the handler's result, for reporting a handler that returns when it shouldn't
+    stack.write(mc).push(obj);
+    stack.write(mc).push(handler);
+    stack.write(mc).push(obj);
+    vm.call_value(handler, stack, 1, mc)?;
+    *vm.handlers().write(mc) = outer;
+    Ok(None)
+}
+
+fn raise_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    // The error is raised where the handler ran, so it goes to the outer handlers rather than
+    // back to the one that returned
+    let outer = match *vm.handlers().read() {
+        Value::Box(handlers) => handlers.read().as_pair()?.cdr(),
+        handlers => handlers,
+    };
+    *vm.handlers().write(mc) = outer;
+    Err(InterpretError::RuntimeError(format!(
+        "Exception handler returned from non-continuable raise of '{}'",
+        peek(stack, 1)
+    )))
+}
+
+fn raise_continuable_continuation<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(peek(stack, 0)))
+}
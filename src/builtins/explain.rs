@@ -0,0 +1,71 @@
+use gc_arena::MutationContext;
+
+use crate::object::ObjNative;
+use crate::value::Value;
+use crate::vm::{borrow_mut, peek, Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    EXPLAIN => "%explain", explain;
+    EXPLAIN_CONTINUATION => "%explain-continuation", explain_continuation;
+}
+
+/// Runs a form compiled for `--explain`, printing it before calling the thunk that evaluates it
+/// and printing its result afterwards. Forms nested too deeply are run without being printed.
+pub fn explain<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (form, thunk) = {
+        let args = stack.read();
+        (args[1], args[2])
+    };
+
+    let nesting = vm.enter_explained();
+    if is_shown(vm, nesting) {
+        print_explained(vm, &format!("{}\n", form), nesting, mc)?;
+    }
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, EXPLAIN_CONTINUATION, None));
+
+    stack.write(mc).push(thunk);
+    vm.call_value(thunk, stack, 0, mc)?;
+    Ok(None)
+}
+
+fn explain_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let result = peek(stack, 0);
+    let form = stack.read()[1];
+
+    let nesting = vm.leave_explained();
+    if is_shown(vm, nesting) {
+        print_explained(vm, &format!("{} => {}\n", form, result), nesting, mc)?;
+    }
+
+    Ok(Some(result))
+}
+
+fn is_shown(vm: &VirtualMachine<'_>, nesting: usize) -> bool {
+    vm.explain().is_some_and(|depth| nesting < depth)
+}
+
+/// Writes a line to the current output port, indented to show how deeply it's nested
+fn print_explained<'gc>(
+    vm: &VirtualMachine<'gc>,
+    line: &str,
+    nesting: usize,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let port = *vm.current_output_port().read();
+    let mut port = borrow_mut(&port, mc)?;
+    let port = port.as_write_port_mut()?;
+    port.write_str(&"  ".repeat(nesting))?;
+    port.write_str(line)?;
+    Ok(())
+}
@@ -0,0 +1,191 @@
+use std::fs;
+
+use gc_arena::{Gc, MutationContext};
+
+use super::{environment_arg, find_on_load_path, list_to_vec, load_once};
+use crate::object::{ObjNative, ObjString, Object, RegisteredNative};
+use crate::value::{TypeError, Value};
+use crate::vm::{
+    imported_library, library_files, library_name, BuiltinGroup, InterpretError, Library,
+    Procedure, Result, Stack, VirtualMachine,
+};
+
+internal_natives! {
+    DEFINE_LIBRARY => "%define-library", define_library;
+    DEFINE_LIBRARY_CONTINUATION => "%define-library-continuation", define_library_continuation;
+    IMPORT => "%import", import;
+    DEFINE_LIBRARY_LOAD_CONTINUATION => "%define-library-load-continuation", define_library_load_continuation;
+    IMPORT_LOAD_CONTINUATION => "%import-load-continuation", import_load_continuation;
+}
+
+/// Defines a library, which `define-library` compiles into a call to. It takes the library's name,
+/// its export specs and import sets (all quoted), and a thunk running its body with the library's
+/// own global variables. The imports are bound in those globals before the body runs.
+pub fn define_library<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (name, exports, imports, body) = {
+        let args = stack.read();
+        (args[1], args[2], args[3], args[4])
+    };
+    let name = library_name(name)?;
+    let globals = body
+        .as_object()?
+        .read()
+        .as_function()?
+        .chunk()
+        .globals()
+        .ok_or_else(|| TypeError(format!("'{}' is not a library body", body)))?;
+
+    let exports = list_to_vec(exports)?
+        .into_iter()
+        .map(|spec| match spec {
+            Value::Symbol(name) => Ok((name, name)),
+            _ => match &list_to_vec(spec)?[..] {
+                [Value::Symbol(keyword), Value::Symbol(from), Value::Symbol(to)]
+                    if keyword.as_str() == "rename" =>
+                {
+                    Ok((*from, *to))
+                }
+                _ => Err(InterpretError::RuntimeError(format!(
+                    "'{}' is not a valid export",
+                    spec
+                ))),
+            },
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let imports = list_to_vec(imports)?;
+    if load_imported_library(vm, stack, &imports, DEFINE_LIBRARY_LOAD_CONTINUATION, mc)? {
+        return Ok(None);
+    }
+    for set in imports {
+        let bindings = vm.resolve_import(set, mc)?;
+        globals.write(mc).extend(bindings);
+    }
+    vm.define_library(name, Library::new(globals, exports), mc);
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(
+        1,
+        false,
+        DEFINE_LIBRARY_CONTINUATION,
+        None,
+    ));
+
+    stack.write(mc).push(body);
+    vm.call_value(body, stack, 0, mc)?;
+    Ok(None)
+}
+
+/// Drops whatever the library body returned, since a definition has no value
+fn define_library_continuation<'gc>(
+    _: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::Void))
+}
+
+/// Binds everything the given import sets bring in as globals, which `import` compiles into a call
+/// to. The first argument is the environment to bind them in, or `#f` for the VM's globals.
+pub fn import<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (environment, sets) = {
+        let args = stack.read();
+        (args[1], args[2..].to_vec())
+    };
+    let globals = match environment {
+        Value::Bool(false) => vm.globals(),
+        _ => environment_arg(environment)?,
+    };
+    if load_imported_library(vm, stack, &sets, IMPORT_LOAD_CONTINUATION, mc)? {
+        return Ok(None);
+    }
+    // Resolved up front so that a bad import set doesn't leave half of the bindings behind
+    let bindings = sets
+        .into_iter()
+        .map(|set| vm.resolve_import(set, mc))
+        .collect::<Result<Vec<_>>>()?;
+    globals.write(mc).extend(bindings.into_iter().flatten());
+    Ok(Some(Value::Void))
+}
+
+/// Loads the file defining the first library `sets` import from that isn't defined yet but can be
+/// found on the load path, then picks up with `continuation`. Returns whether it's loading one.
+/// Files are only loaded once, so a file that doesn't define the library it's named after is left
+/// for the import to report as unknown. Without the file builtins, libraries that aren't defined
+/// yet are errors instead.
+fn load_imported_library<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    sets: &[Value<'gc>],
+    continuation: RegisteredNative,
+    mc: MutationContext<'gc, '_>,
+) -> Result<bool> {
+    for set in sets {
+        let name = imported_library(*set)?;
+        if vm.library_globals(&library_name(name)?).is_some() {
+            continue;
+        }
+        if !vm.has_builtins(BuiltinGroup::Files) {
+            return Err(InterpretError::RuntimeError(format!(
+                "Can't load library {} without the file builtins",
+                name
+            )));
+        }
+
+        let path = library_files(name)?
+            .iter()
+            .find_map(|file| find_on_load_path(vm, file));
+        let path = match path {
+            Some(path) if !vm.is_loaded(&fs::canonicalize(&path)?) => path,
+            _ => continue,
+        };
+
+        // Write the procedure that should pick up execution after this procedure call finishes
+        *vm.procedure().write(mc) =
+            Procedure::Native(ObjNative::registered(1, false, continuation, None));
+
+        let load = Value::boxed(
+            mc,
+            Object::Native(ObjNative::registered(
+                1,
+                false,
+                ("load-once", load_once),
+                None,
+            )),
+        );
+        let path = ObjString::from(path.to_string_lossy().into_owned());
+        stack.write(mc).push(load);
+        stack.write(mc).push(Value::String(Gc::allocate(mc, path)));
+        vm.call_value(load, stack, 1, mc)?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Tries defining the library again once a library it imports has been loaded
+fn define_library_load_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    stack.write(mc).pop();
+    define_library(vm, stack, mc)
+}
+
+/// Tries importing again once a library being imported has been loaded
+fn import_load_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    stack.write(mc).pop();
+    import(vm, stack, mc)
+}
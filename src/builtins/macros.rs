@@ -0,0 +1,252 @@
+use gc_arena::MutationContext;
+
+use super::{compile, eq, eqv, eval, list_to_vec, uncons};
+use crate::chunk::Globals;
+use crate::compiler::bootstrap;
+use crate::compiler::expander::{self, copy_tree};
+use crate::memory::{Symbol, Token};
+use crate::object::{ObjNative, ObjPair, ObjString, Object, RegisteredNative};
+use crate::value::{TypeError, Value};
+use crate::vm::{Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    RENAME => "%rename", rename;
+    COMPARE => "%compare", compare;
+    COMPILE_EXPANSION_CONTINUATION => "%compile-expansion-continuation", compile_expansion_continuation;
+    EVAL_EXPANSION_CONTINUATION => "%eval-expansion-continuation", eval_expansion_continuation;
+    MACROEXPAND_CONTINUATION => "%macroexpand-continuation", macroexpand_continuation;
+    MACROEXPAND_1_CONTINUATION => "%macroexpand-1-continuation", macroexpand_1_continuation;
+}
+
+/// Makes a macro transformer out of a procedure taking the form to expand and `rename` and
+/// `compare` procedures, for `define-syntax`. `rename` gives back an alias for an identifier that
+/// refers to what the identifier means where the macro is defined, which can't capture or be
+/// captured by anything at the use, and `compare` checks whether two identifiers mean the same.
+pub fn er_macro_transformer<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let procedure = stack.read()[1];
+    let is_procedure = match procedure {
+        Value::Box(object) => object.read().is_procedure(),
+        _ => false,
+    };
+    if !is_procedure {
+        return Err(TypeError(format!("'{}' is not a procedure", procedure)).into());
+    }
+    Ok(Some(Value::boxed(mc, Object::Macro(procedure))))
+}
+
+/// The `rename` procedure handed to macro transformers. Renaming the same identifier twice during
+/// one expansion gives the same alias.
+fn rename<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let identifier = stack.read()[1];
+    let name = identifier.as_symbol()?;
+    let renames = *vm.renames().read();
+    for entry in list_to_vec(renames)? {
+        if let Some((from, to)) = uncons(entry) {
+            if eq(from, identifier) {
+                return Ok(Some(to));
+            }
+        }
+    }
+
+    let alias = Value::Symbol(Symbol::uninterned(Token::new(
+        mc,
+        ObjString::from(name.as_str().into_owned()),
+    )));
+    let entry = Value::boxed(mc, Object::Pair(ObjPair::new(identifier, alias)));
+    *vm.renames().write(mc) = Value::boxed(mc, Object::Pair(ObjPair::new(entry, renames)));
+    Ok(Some(alias))
+}
+
+/// The `compare` procedure handed to macro transformers. Aliases are the same identifier as the
+/// one they were renamed from, since macros can only be defined at the top level.
+fn compare<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (a, b) = {
+        let args = stack.read();
+        (args[1], args[2])
+    };
+    let is_same = match (a, b) {
+        (Value::Symbol(a), Value::Symbol(b)) => a.as_str() == b.as_str(),
+        _ => eqv(a, b),
+    };
+    Ok(Some(Value::Bool(is_same)))
+}
+
+/// Calls a macro transformer on `form`, with a fresh set of renames, then picks up with
+/// `continuation`. The renames of whatever was being expanded before are saved on the stack.
+fn call_transformer<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    transformer: Value<'gc>,
+    form: Value<'gc>,
+    continuation: RegisteredNative,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let saved = std::mem::replace(&mut *vm.renames().write(mc), Value::Null);
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, continuation, None));
+
+    let rename = Value::boxed(
+        mc,
+        Object::Native(ObjNative::registered(1, false, RENAME, None)),
+    );
+    let compare = Value::boxed(
+        mc,
+        Object::Native(ObjNative::registered(2, false, COMPARE, None)),
+    );
+    stack
+        .write(mc)
+        .extend([saved, transformer, form, rename, compare]);
+    vm.call_value(transformer, stack, 3, mc)?;
+    Ok(())
+}
+
+/// Takes what a macro transformer returned off of the stack, putting back the renames of whatever
+/// was being expanded before
+fn transformer_result<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Value<'gc> {
+    let mut stack = stack.write(mc);
+    let expansion = stack.pop().unwrap();
+    *vm.renames().write(mc) = stack.pop().unwrap();
+    expansion
+}
+
+/// Expands the first use of a macro in the form passed to the native running on `stack`, which
+/// `compile` and `eval` do before compiling anything, then picks up with `continuation`. Returns
+/// whether there was a use to expand. The form is copied the first time so that the one passed
+/// in is left alone.
+pub(super) fn expand_macros<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    globals: Globals<'gc>,
+    continuation: RegisteredNative,
+    mc: MutationContext<'gc, '_>,
+) -> Result<bool> {
+    let form = stack.read()[1];
+    if expander::find_macro_use(form, globals, vm.tables()).is_none() {
+        return Ok(false);
+    }
+
+    let form = copy_tree(form, mc);
+    stack.write(mc)[1] = form;
+    let found = match expander::find_macro_use(form, globals, vm.tables()) {
+        Some(found) => found,
+        None => return Ok(false),
+    };
+    stack
+        .write(mc)
+        .push(found.location.unwrap_or(Value::Bool(false)));
+    call_transformer(vm, stack, found.transformer, found.form, continuation, mc)?;
+    Ok(true)
+}
+
+/// Puts the expansion of a macro use where the use was
+fn substitute_expansion<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let expansion = transformer_result(vm, stack, mc);
+    let location = stack.write(mc).pop().unwrap();
+    match location {
+        Value::Box(pair) => pair.write(mc).as_pair_mut()?.set_car(expansion),
+        _ => stack.write(mc)[1] = expansion,
+    }
+    Ok(())
+}
+
+/// Expands `compile`'s form further, or compiles it once there are no macro uses left
+fn compile_expansion_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    substitute_expansion(vm, stack, mc)?;
+    compile(vm, stack, mc)
+}
+
+/// Expands `eval`'s form further, or evaluates it once there are no macro uses left
+fn eval_expansion_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    substitute_expansion(vm, stack, mc)?;
+    eval(vm, stack, mc)
+}
+
+/// Expands the outermost form of an expression once, returning it unchanged if it isn't a form
+/// that expands into another one, e.g. `(macroexpand-1 '(swap! x y))`
+pub fn macroexpand_1<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let form = stack.read()[1];
+    if let Some(expansion) = bootstrap::expand_once(form, mc)? {
+        return Ok(Some(expansion));
+    }
+    match expander::macro_transformer(form, vm.globals(), vm.tables()) {
+        Some(transformer) => {
+            call_transformer(vm, stack, transformer, form, MACROEXPAND_1_CONTINUATION, mc)?;
+            Ok(None)
+        }
+        None => Ok(Some(form)),
+    }
+}
+
+fn macroexpand_1_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(transformer_result(vm, stack, mc)))
+}
+
+/// Expands the outermost form of an expression until it's no longer one that expands. Subforms
+/// are left as they are.
+pub fn macroexpand<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut form = stack.read()[1];
+    while let Some(expansion) = bootstrap::expand_once(form, mc)? {
+        form = expansion;
+    }
+    match expander::macro_transformer(form, vm.globals(), vm.tables()) {
+        Some(transformer) => {
+            stack.write(mc)[1] = form;
+            call_transformer(vm, stack, transformer, form, MACROEXPAND_CONTINUATION, mc)?;
+            Ok(None)
+        }
+        None => Ok(Some(form)),
+    }
+}
+
+/// Keeps expanding what a macro transformer returned
+fn macroexpand_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let expansion = transformer_result(vm, stack, mc);
+    stack.write(mc)[1] = expansion;
+    macroexpand(vm, stack, mc)
+}
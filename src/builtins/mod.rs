@@ -1,21 +1,91 @@
+/// Declares the natives a module only creates internally (see `INTERNAL_NATIVES`), each as a
+/// constant pairing the function with the name it's registered under
+macro_rules! internal_natives {
+    ($($constant:ident => $name:literal, $function:ident;)*) => {
+        $(pub(crate) const $constant: crate::object::RegisteredNative = ($name, $function);)*
+
+        pub(super) const INTERNAL_NATIVES: &[crate::object::RegisteredNative] = &[$($constant),*];
+    };
+}
+
+mod booleans;
+mod boxes;
+mod bytevectors;
 mod characters;
+#[cfg(feature = "self-hosting")]
+mod chunks;
+mod environments;
 mod equality;
+mod exceptions;
+mod explain;
+mod libraries;
+mod macros;
 mod numbers;
 mod pairs;
 mod ports;
 mod procedures;
+mod profile;
+mod records;
+#[cfg(feature = "regex")]
+mod regexps;
 mod repl;
+mod sort;
 mod strings;
 mod symbols;
+mod threads;
 mod vectors;
+mod void;
 
+pub use booleans::*;
+pub use boxes::*;
+pub use bytevectors::*;
 pub use characters::*;
+#[cfg(feature = "self-hosting")]
+pub use chunks::*;
+pub use environments::*;
 pub use equality::*;
+pub use exceptions::*;
+pub use explain::*;
+pub use libraries::*;
+pub use macros::*;
 pub use numbers::*;
 pub use pairs::*;
 pub use ports::*;
 pub use procedures::*;
+pub use profile::*;
+pub use records::*;
+#[cfg(feature = "regex")]
+pub use regexps::*;
 pub use repl::*;
+pub use sort::*;
 pub use strings::*;
 pub use symbols::*;
+pub use threads::*;
 pub use vectors::*;
+pub use void::*;
+
+use crate::object::RegisteredNative;
+
+/// Every native that's only created internally (continuations of other natives, the REPL and
+/// loader thunks, etc.), by the names serialized continuations refer to them by. The names can't
+/// clash with anything bound in Scheme.
+const INTERNAL_NATIVES: &[&[RegisteredNative]] = &[
+    exceptions::INTERNAL_NATIVES,
+    explain::INTERNAL_NATIVES,
+    libraries::INTERNAL_NATIVES,
+    macros::INTERNAL_NATIVES,
+    pairs::INTERNAL_NATIVES,
+    ports::INTERNAL_NATIVES,
+    procedures::INTERNAL_NATIVES,
+    records::INTERNAL_NATIVES,
+    repl::INTERNAL_NATIVES,
+    sort::INTERNAL_NATIVES,
+    threads::INTERNAL_NATIVES,
+    vectors::INTERNAL_NATIVES,
+];
+
+pub(crate) fn internal_natives() -> impl Iterator<Item = RegisteredNative> {
+    INTERNAL_NATIVES
+        .iter()
+        .flat_map(|natives| natives.iter().copied())
+}
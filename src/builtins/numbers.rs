@@ -172,3 +172,36 @@ pub fn gte_number<'gc>(
 
     Ok(Some(Value::Bool(true)))
 }
+
+/// Returns a pseudo-random integer in `[0, n)` when `n` is an integer, or a real in `[0, n)`
+/// otherwise
+pub fn random<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let limit = stack.read()[1].as_number()?;
+    if limit <= 0f64 {
+        return Err(InterpretError::RuntimeError(format!(
+            "Expected a positive limit but got {}",
+            limit
+        )));
+    }
+
+    let result = vm.next_random() * limit;
+    if limit.fract() == 0f64 {
+        Ok(Some(Value::Number(result.floor())))
+    } else {
+        Ok(Some(Value::Number(result)))
+    }
+}
+
+pub fn random_seed<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let seed = stack.read()[1].as_number()?;
+    vm.seed_random(seed as u64);
+    Ok(Some(Value::Void))
+}
@@ -1,8 +1,26 @@
-use gc_arena::MutationContext;
+use gc_arena::{GcCell, MutationContext};
 
-use crate::object::{ObjPair, Object};
+use super::{eq, equal, eqv};
+use crate::object::{ObjNative, ObjPair, Object, RegisteredNative};
 use crate::value::Value;
-use crate::vm::{InterpretError, Result, Stack, VirtualMachine};
+use crate::vm::{borrow_mut, peek, InterpretError, Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    ASSOC_STEP => "%assoc-step", assoc_step;
+    ASSOC_CONTINUATION => "%assoc-continuation", assoc_continuation;
+    MEMBER_STEP => "%member-step", member_step;
+    MEMBER_CONTINUATION => "%member-continuation", member_continuation;
+    MAP_STEP => "%map-step", map_step;
+    MAP_CONTINUATION => "%map-continuation", map_continuation;
+    FOR_EACH_STEP => "%for-each-step", for_each_step;
+    FOR_EACH_CONTINUATION => "%for-each-continuation", for_each_continuation;
+    FILTER_STEP => "%filter-step", filter_step;
+    FILTER_CONTINUATION => "%filter-continuation", filter_continuation;
+    FOLD_LEFT_STEP => "%fold-left-step", fold_left_step;
+    FOLD_LEFT_CONTINUATION => "%fold-left-continuation", fold_left_continuation;
+    FOLD_RIGHT_STEP => "%fold-right-step", fold_right_step;
+    FOLD_RIGHT_CONTINUATION => "%fold-right-continuation", fold_right_continuation;
+}
 
 pub fn cons<'gc>(
     _: &VirtualMachine<'gc>,
@@ -37,12 +55,11 @@ pub fn set_car<'gc>(
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let args = stack.read();
-    args[1]
-        .as_object()?
-        .write(mc)
-        .as_pair_mut()?
-        .set_car(args[2]);
+    let (pair, value) = {
+        let args = stack.read();
+        (args[1].as_object()?, args[2])
+    };
+    borrow_mut(&pair, mc)?.as_pair_mut()?.set_car(value);
     Ok(Some(Value::Void))
 }
 
@@ -67,15 +84,47 @@ pub fn set_cdr<'gc>(
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let args = stack.read();
-    args[1]
-        .as_object()?
-        .write(mc)
-        .as_pair_mut()?
-        .set_cdr(args[2]);
+    let (pair, value) = {
+        let args = stack.read();
+        (args[1].as_object()?, args[2])
+    };
+    borrow_mut(&pair, mc)?.as_pair_mut()?.set_cdr(value);
     Ok(Some(Value::Void))
 }
 
+/// Defines the compositions of `car` and `cdr`, e.g. `cadr`, where the letters between the `c`
+/// and the `r` name the accessors to apply from right to left
+macro_rules! cxr {
+    ($($name:ident),* $(,)?) => {
+        $(
+            pub fn $name<'gc>(
+                _: &VirtualMachine<'gc>,
+                stack: Stack<'gc>,
+                _: MutationContext<'gc, '_>,
+            ) -> Result<Option<Value<'gc>>> {
+                let path = stringify!($name);
+                compose_cxr(&path[1..path.len() - 1], stack.read()[1]).map(Some)
+            }
+        )*
+    };
+}
+
+cxr! {
+    caar, cadr, cdar, cddr, caaar, caadr, cadar,
+    caddr, cdaar, cdadr, cddar, cdddr, caaaar, caaadr,
+    caadar, caaddr, cadaar, cadadr, caddar, cadddr, cdaaar,
+    cdaadr, cdadar, cdaddr, cddaar, cddadr, cdddar, cddddr,
+}
+
+/// Applies the accessors named by `path` (`a` for `car`, `d` for `cdr`) from right to left
+fn compose_cxr<'gc>(path: &str, value: Value<'gc>) -> Result<Value<'gc>> {
+    path.chars().rev().try_fold(value, |current, accessor| {
+        let (car, cdr) = uncons(current)
+            .ok_or_else(|| InterpretError::RuntimeError(format!("{} is not a pair", current)))?;
+        Ok(if accessor == 'a' { car } else { cdr })
+    })
+}
+
 pub fn is_pair<'gc>(
     _: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
@@ -88,3 +137,792 @@ pub fn is_pair<'gc>(
         _ => Ok(Some(Value::Bool(false))),
     }
 }
+
+pub fn is_null<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::Bool(stack.read()[1].is_null())))
+}
+
+/// Whether a value is a proper list. Circular lists (made with `set-cdr!`) aren't, and are
+/// caught with Floyd's cycle detection instead of being followed forever.
+pub fn is_list<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let list = stack.read()[1];
+    let mut slow = list;
+    let mut fast = list;
+    loop {
+        for _ in 0..2 {
+            match uncons(fast) {
+                Some((_, rest)) => fast = rest,
+                None => return Ok(Some(Value::Bool(fast.is_null()))),
+            }
+        }
+
+        // The fast pointer has already been over these pairs, so they can't run out
+        slow = uncons(slow).unwrap().1;
+        if eq(slow, fast) {
+            return Ok(Some(Value::Bool(false)));
+        }
+    }
+}
+
+/// Gets the sublist of a list obtained by skipping its first `k` elements
+pub fn list_tail<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    nth_tail(args[1], list_index(args[2])?).map(Some)
+}
+
+pub fn list_ref<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let k = list_index(args[2])?;
+    match uncons(nth_tail(args[1], k)?) {
+        Some((element, _)) => Ok(Some(element)),
+        None => Err(out_of_range(k, args[1])),
+    }
+}
+
+pub fn list_set<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let k = list_index(args[2])?;
+    match nth_tail(args[1], k)? {
+        Value::Box(object) if object.read().is_pair() => {
+            borrow_mut(&object, mc)?.as_pair_mut()?.set_car(args[3]);
+            Ok(Some(Value::Void))
+        }
+        Value::Pair(_) => Err(InterpretError::RuntimeError(format!(
+            "Can't modify the constant list {}",
+            args[1]
+        ))),
+        _ => Err(out_of_range(k, args[1])),
+    }
+}
+
+/// Copies the pairs making up a list, keeping the same elements and the same final cdr if it's
+/// improper. Anything other than a pair is returned as is.
+pub fn list_copy<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let list = stack.read()[1];
+    let mut elements = Vec::new();
+    let mut tail = list;
+    while let Some((element, rest)) = uncons(tail) {
+        elements.push(element);
+        tail = rest;
+    }
+
+    let copy = elements.into_iter().rev().fold(tail, |acc, element| {
+        Value::boxed(mc, Object::Pair(ObjPair::new(element, acc)))
+    });
+    Ok(Some(copy))
+}
+
+/// Appends lists by pointing the last pair of each one at the next list, rather than copying
+/// them. Every argument but the last must be a mutable list. (extension)
+pub fn append_mut<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let lists = stack.read()[1..].to_vec();
+    let mut result = Value::Null;
+    let mut last_pair: Option<GcCell<'gc, Object<'gc>>> = None;
+    for (i, &list) in lists.iter().enumerate() {
+        if list.is_null() {
+            continue;
+        }
+
+        match last_pair {
+            Some(pair) => borrow_mut(&pair, mc)?.as_pair_mut()?.set_cdr(list),
+            None => result = list,
+        }
+        if i + 1 < lists.len() {
+            last_pair = Some(last_mutable_pair(list)?);
+        }
+    }
+
+    Ok(Some(result))
+}
+
+/// Reverses a mutable list in place by turning its cdrs around, returning the new first pair
+/// (extension)
+pub fn reverse_mut<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut reversed = Value::Null;
+    let mut current = stack.read()[1];
+    while !current.is_null() {
+        let pair = mutable_pair(current)?;
+        let mut object = borrow_mut(&pair, mc)?;
+        let pair = object.as_pair_mut()?;
+        let next = pair.cdr();
+        pair.set_cdr(reversed);
+
+        reversed = current;
+        current = next;
+    }
+
+    Ok(Some(reversed))
+}
+
+/// Finds the last pair of a mutable list
+fn last_mutable_pair<'gc>(list: Value<'gc>) -> Result<GcCell<'gc, Object<'gc>>> {
+    let mut pair = mutable_pair(list)?;
+    loop {
+        let next = pair.read().as_pair()?.cdr();
+        if next.is_null() {
+            return Ok(pair);
+        }
+        pair = mutable_pair(next)?;
+    }
+}
+
+fn mutable_pair<'gc>(value: Value<'gc>) -> Result<GcCell<'gc, Object<'gc>>> {
+    match value {
+        Value::Box(object) if object.read().is_pair() => Ok(object),
+        _ => Err(InterpretError::RuntimeError(format!(
+            "{} is not a mutable pair",
+            value
+        ))),
+    }
+}
+
+/// Follows `k` cdrs down a list
+fn nth_tail(list: Value<'_>, k: usize) -> Result<Value<'_>> {
+    let mut tail = list;
+    for _ in 0..k {
+        tail = match uncons(tail) {
+            Some((_, rest)) => rest,
+            None if tail.is_null() => return Err(out_of_range(k, list)),
+            None => return Err(improper_list(list)),
+        };
+    }
+
+    Ok(tail)
+}
+
+fn list_index(index: Value<'_>) -> Result<usize> {
+    let k = index.as_number()?;
+    if k < 0f64 || k.fract() != 0f64 {
+        return Err(InterpretError::RuntimeError(format!(
+            "'{}' is not a valid list index",
+            index
+        )));
+    }
+
+    Ok(k as usize)
+}
+
+fn out_of_range(k: usize, list: Value<'_>) -> InterpretError {
+    InterpretError::RuntimeError(format!("Index {} is out of range for list {}", k, list))
+}
+
+pub fn assq<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    assoc_with(args[1], args[2], eq).map(Some)
+}
+
+pub fn assv<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    assoc_with(args[1], args[2], eqv).map(Some)
+}
+
+/// Finds the first entry of an association list whose key is `equal?` to the given one, or
+/// satisfies the optional comparison procedure
+pub fn assoc<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let alist = {
+        let args = stack.read();
+        if args.len() == 3 {
+            return assoc_with(args[1], args[2], equal).map(Some);
+        }
+        args[2]
+    };
+
+    stack.write(mc).push(alist);
+    assoc_step(vm, stack, mc)
+}
+
+/// Calls the comparison procedure on the next entry of the list on top of the stack. Expects
+/// the stack to look like `[assoc, key, alist, compare, remaining]`.
+fn assoc_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let remaining = peek(stack, 0);
+    if remaining.is_null() {
+        return Ok(Some(Value::Bool(false)));
+    }
+
+    let (key, compare) = {
+        let args = stack.read();
+        (args[1], args[3])
+    };
+    let (entry, _) = uncons(remaining).ok_or_else(|| improper_list(stack.read()[2]))?;
+    let (entry_key, _) = uncons(entry).ok_or_else(|| not_an_entry(entry))?;
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, ASSOC_CONTINUATION, None));
+
+    stack.write(mc).push(compare);
+    stack.write(mc).push(key);
+    stack.write(mc).push(entry_key);
+    vm.call_value(compare, stack, 2, mc)?;
+    Ok(None)
+}
+
+fn assoc_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let found = stack.write(mc).pop().unwrap();
+    let (entry, rest) = uncons(peek(stack, 0)).unwrap();
+    if found.is_truthy() {
+        return Ok(Some(entry));
+    }
+
+    *stack.write(mc).last_mut().unwrap() = rest;
+    assoc_step(vm, stack, mc)
+}
+
+fn assoc_with<'gc>(
+    key: Value<'gc>,
+    alist: Value<'gc>,
+    compare: fn(Value<'gc>, Value<'gc>) -> bool,
+) -> Result<Value<'gc>> {
+    let mut remaining = alist;
+    while let Some((entry, rest)) = uncons(remaining) {
+        let (entry_key, _) = uncons(entry).ok_or_else(|| not_an_entry(entry))?;
+        if compare(key, entry_key) {
+            return Ok(entry);
+        }
+        remaining = rest;
+    }
+
+    if remaining.is_null() {
+        Ok(Value::Bool(false))
+    } else {
+        Err(improper_list(alist))
+    }
+}
+
+pub fn memq<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    member_with(args[1], args[2], eq).map(Some)
+}
+
+pub fn memv<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    member_with(args[1], args[2], eqv).map(Some)
+}
+
+/// Finds the first tail of a list whose car is `equal?` to the given value, or satisfies the
+/// optional comparison procedure
+pub fn member<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let list = {
+        let args = stack.read();
+        if args.len() == 3 {
+            return member_with(args[1], args[2], equal).map(Some);
+        }
+        args[2]
+    };
+
+    stack.write(mc).push(list);
+    member_step(vm, stack, mc)
+}
+
+/// Calls the comparison procedure on the next element of the list on top of the stack.
+/// Expects the stack to look like `[member, value, list, compare, remaining]`.
+fn member_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let remaining = peek(stack, 0);
+    if remaining.is_null() {
+        return Ok(Some(Value::Bool(false)));
+    }
+
+    let (value, compare) = {
+        let args = stack.read();
+        (args[1], args[3])
+    };
+    let (element, _) = uncons(remaining).ok_or_else(|| improper_list(stack.read()[2]))?;
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, MEMBER_CONTINUATION, None));
+
+    stack.write(mc).push(compare);
+    stack.write(mc).push(value);
+    stack.write(mc).push(element);
+    vm.call_value(compare, stack, 2, mc)?;
+    Ok(None)
+}
+
+fn member_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let found = stack.write(mc).pop().unwrap();
+    let remaining = peek(stack, 0);
+    if found.is_truthy() {
+        return Ok(Some(remaining));
+    }
+
+    let (_, rest) = uncons(remaining).unwrap();
+    *stack.write(mc).last_mut().unwrap() = rest;
+    member_step(vm, stack, mc)
+}
+
+fn member_with<'gc>(
+    value: Value<'gc>,
+    list: Value<'gc>,
+    compare: fn(Value<'gc>, Value<'gc>) -> bool,
+) -> Result<Value<'gc>> {
+    let mut remaining = list;
+    while let Some((element, rest)) = uncons(remaining) {
+        if compare(value, element) {
+            return Ok(remaining);
+        }
+        remaining = rest;
+    }
+
+    if remaining.is_null() {
+        Ok(Value::Bool(false))
+    } else {
+        Err(improper_list(list))
+    }
+}
+
+/// Applies a procedure elementwise to one or more lists, stopping at the end of the shortest
+/// one, and returns the list of results
+pub fn map<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    start_mapping(vm, stack, MAP_STEP, mc)
+}
+
+/// Applies a procedure elementwise to one or more lists for its side effects
+pub fn for_each<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    start_mapping(vm, stack, FOR_EACH_STEP, mc)
+}
+
+/// Hands `[procedure, results, lists...]` over to `step`, which calls the procedure once per
+/// set of elements. Each step gets a fresh set of arguments instead of updating its stack in
+/// place, so re-entering a continuation captured by the procedure resumes from the right
+/// elements, and the results list is only ever consed onto so earlier returns aren't mutated.
+fn start_mapping<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    step: RegisteredNative,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut args = stack.read()[1..].to_vec();
+    args.insert(1, Value::Null);
+    call_step(vm, stack, step, args, mc)
+}
+
+pub(crate) fn call_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    step: RegisteredNative,
+    args: Vec<Value<'gc>>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let step = Value::boxed(
+        mc,
+        Object::Native(ObjNative::registered(3, true, step, None)),
+    );
+    let arg_count = args.len();
+    stack.write(mc).push(step);
+    stack.write(mc).extend(args);
+    vm.tail_call_value(step, stack, arg_count, mc)?;
+    Ok(None)
+}
+
+/// Splits each list into its first element and the rest, or returns `None` once any of them
+/// runs out
+fn next_elements<'gc>(lists: &[Value<'gc>]) -> Result<Option<(Vec<Value<'gc>>, Vec<Value<'gc>>)>> {
+    let mut cars = Vec::with_capacity(lists.len());
+    let mut cdrs = Vec::with_capacity(lists.len());
+    for &list in lists {
+        match uncons(list) {
+            Some((car, cdr)) => {
+                cars.push(car);
+                cdrs.push(cdr);
+            }
+            None if list.is_null() => return Ok(None),
+            None => return Err(improper_list(list)),
+        }
+    }
+
+    Ok(Some((cars, cdrs)))
+}
+
+/// Calls the procedure on the next elements, with `continuation` picking up the result
+pub(crate) fn call_on_next<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    cars: Vec<Value<'gc>>,
+    continuation: RegisteredNative,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let procedure = stack.read()[1];
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, continuation, None));
+
+    let arg_count = cars.len();
+    stack.write(mc).push(procedure);
+    stack.write(mc).extend(cars);
+    vm.call_value(procedure, stack, arg_count, mc)?;
+    Ok(None)
+}
+
+fn map_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let next = next_elements(&stack.read()[3..])?;
+    match next {
+        Some((cars, _)) => call_on_next(vm, stack, cars, MAP_CONTINUATION, mc),
+        None => {
+            let results = list_to_vec(stack.read()[2])?;
+            Ok(Some(list_from(results.into_iter().rev(), mc)))
+        }
+    }
+}
+
+fn map_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (procedure, results, result, lists) = {
+        let args = stack.read();
+        let len = args.len();
+        (args[1], args[2], args[len - 1], args[3..len - 1].to_vec())
+    };
+    let (_, cdrs) = next_elements(&lists)?.unwrap();
+
+    let mut args = vec![
+        procedure,
+        Value::boxed(mc, Object::Pair(ObjPair::new(result, results))),
+    ];
+    args.extend(cdrs);
+    call_step(vm, stack, MAP_STEP, args, mc)
+}
+
+fn for_each_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let next = next_elements(&stack.read()[3..])?;
+    match next {
+        Some((cars, _)) => call_on_next(vm, stack, cars, FOR_EACH_CONTINUATION, mc),
+        None => Ok(Some(Value::Void)),
+    }
+}
+
+fn for_each_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (procedure, lists) = {
+        let args = stack.read();
+        (args[1], args[3..args.len() - 1].to_vec())
+    };
+    let (_, cdrs) = next_elements(&lists)?.unwrap();
+
+    let mut args = vec![procedure, Value::Null];
+    args.extend(cdrs);
+    call_step(vm, stack, FOR_EACH_STEP, args, mc)
+}
+
+/// Returns a list of the elements of a list that satisfy a predicate, in their original order
+pub fn filter<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    start_mapping(vm, stack, FILTER_STEP, mc)
+}
+
+fn filter_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let next = next_elements(&stack.read()[3..])?;
+    match next {
+        Some((cars, _)) => call_on_next(vm, stack, cars, FILTER_CONTINUATION, mc),
+        None => {
+            let kept = list_to_vec(stack.read()[2])?;
+            Ok(Some(list_from(kept.into_iter().rev(), mc)))
+        }
+    }
+}
+
+fn filter_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (predicate, mut kept, list, result) = {
+        let args = stack.read();
+        (args[1], args[2], args[3], args[4])
+    };
+    let (element, rest) = uncons(list).unwrap();
+    if result.is_truthy() {
+        kept = Value::boxed(mc, Object::Pair(ObjPair::new(element, kept)));
+    }
+
+    call_step(vm, stack, FILTER_STEP, vec![predicate, kept, rest], mc)
+}
+
+/// Combines the elements of one or more lists from left to right, calling the procedure with
+/// the result so far followed by the next elements, e.g. `(fold-left - 0 '(1 2))` is
+/// `(- (- 0 1) 2)`
+pub fn fold_left<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read()[1..].to_vec();
+    call_step(vm, stack, FOLD_LEFT_STEP, args, mc)
+}
+
+/// Combines the elements of one or more lists from right to left, calling the procedure with
+/// the next elements followed by the result so far, e.g. `(fold-right - 0 '(1 2))` is
+/// `(- 1 (- 2 0))`
+pub fn fold_right<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut args = stack.read()[1..].to_vec();
+
+    // Walk the lists backwards, dropping anything past the end of the shortest one
+    let lists = args[2..]
+        .iter()
+        .map(|&list| list_to_vec(list))
+        .collect::<Result<Vec<_>>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    for (arg, list) in args[2..].iter_mut().zip(lists) {
+        *arg = list_from(list.into_iter().take(len).rev(), mc);
+    }
+
+    call_step(vm, stack, FOLD_RIGHT_STEP, args, mc)
+}
+
+/// Combines the elements of a list with `(f element result)`, starting from its first element,
+/// or returns `ridentity` if it's empty, e.g. `(reduce + 0 '(1 2 3))` is `(+ 3 (+ 2 1))`
+pub fn reduce<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (procedure, ridentity, list) = {
+        let args = stack.read();
+        (args[1], args[2], args[3])
+    };
+    match uncons(list) {
+        Some((first, rest)) => {
+            call_step(vm, stack, FOLD_RIGHT_STEP, vec![procedure, first, rest], mc)
+        }
+        None if list.is_null() => Ok(Some(ridentity)),
+        None => Err(improper_list(list)),
+    }
+}
+
+fn fold_left_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (result, next) = {
+        let args = stack.read();
+        (args[2], next_elements(&args[3..])?)
+    };
+    match next {
+        Some((mut cars, _)) => {
+            cars.insert(0, result);
+            call_on_next(vm, stack, cars, FOLD_LEFT_CONTINUATION, mc)
+        }
+        None => Ok(Some(result)),
+    }
+}
+
+fn fold_left_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = next_fold_args(stack)?;
+    call_step(vm, stack, FOLD_LEFT_STEP, args, mc)
+}
+
+/// Like `fold_left_step`, but passes the result so far after the elements instead of before
+fn fold_right_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (result, next) = {
+        let args = stack.read();
+        (args[2], next_elements(&args[3..])?)
+    };
+    match next {
+        Some((mut cars, _)) => {
+            cars.push(result);
+            call_on_next(vm, stack, cars, FOLD_RIGHT_CONTINUATION, mc)
+        }
+        None => Ok(Some(result)),
+    }
+}
+
+fn fold_right_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = next_fold_args(stack)?;
+    call_step(vm, stack, FOLD_RIGHT_STEP, args, mc)
+}
+
+/// Builds the arguments for the next step of a fold from a stack that looks like
+/// `[step, procedure, result, lists..., new result]`
+fn next_fold_args<'gc>(stack: Stack<'gc>) -> Result<Vec<Value<'gc>>> {
+    let args = stack.read();
+    let len = args.len();
+    let (_, cdrs) = next_elements(&args[3..len - 1])?.unwrap();
+
+    let mut next = vec![args[1], args[len - 1]];
+    next.extend(cdrs);
+    Ok(next)
+}
+
+fn not_an_entry(entry: Value<'_>) -> InterpretError {
+    InterpretError::RuntimeError(format!("'{}' is not an association list entry", entry))
+}
+
+/// Splits a (const or boxed) pair into its car and cdr
+pub(crate) fn uncons(value: Value<'_>) -> Option<(Value<'_>, Value<'_>)> {
+    match value {
+        Value::Pair(pair) => Some((pair.car().into(), pair.cdr().into())),
+        Value::Box(object) => match &*object.read() {
+            Object::Pair(pair) => Some((pair.car(), pair.cdr())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Builds a proper list out of the given values
+pub(crate) fn list_from<'gc, I>(values: I, mc: MutationContext<'gc, '_>) -> Value<'gc>
+where
+    I: IntoIterator<Item = Value<'gc>>,
+    I::IntoIter: DoubleEndedIterator,
+{
+    values.into_iter().rev().fold(Value::Null, |acc, value| {
+        Value::boxed(mc, Object::Pair(ObjPair::new(value, acc)))
+    })
+}
+
+/// Collects the elements of a proper list. A circular list is an error rather than a walk that
+/// never ends, and is found the same way `list?` finds one.
+pub(crate) fn list_to_vec(list: Value<'_>) -> Result<Vec<Value<'_>>> {
+    let mut values = Vec::new();
+    let mut curr = list;
+    let mut slow = list;
+    loop {
+        match curr {
+            Value::Null => return Ok(values),
+            Value::Pair(pair) => {
+                values.push(pair.car().into());
+                curr = pair.cdr().into();
+            }
+            Value::Box(object) => {
+                let object = object.read();
+                let pair = object.as_pair().map_err(|_| improper_list(list))?;
+                values.push(pair.car());
+                curr = pair.cdr();
+            }
+            _ => return Err(improper_list(list)),
+        }
+
+        // `curr` has already been over these pairs, so they can't run out
+        if values.len() % 2 == 0 {
+            slow = uncons(slow).unwrap().1;
+            if eq(slow, curr) {
+                return Err(InterpretError::RuntimeError(format!(
+                    "'{}' is a circular list",
+                    list
+                )));
+            }
+        }
+    }
+}
+
+fn improper_list(list: Value<'_>) -> InterpretError {
+    InterpretError::RuntimeError(format!("'{}' is not a proper list", list))
+}
@@ -1,11 +1,26 @@
-use gc_arena::MutationContext;
+use std::fs::File;
+use std::rc::Rc;
+use std::time::Duration;
+
+use gc_arena::{GcCell, MutationContext};
 use pest::Parser;
 
-use crate::compiler;
-use crate::object::{ObjReadPort, ObjPair, Object};
+use super::{byte, bytevector_bytes, checked_range, new_bytevector, string_arg};
+use crate::compiler::{self, SourceMap};
+use crate::memory::Token;
+use crate::object::{
+    Buffering, DecodeErrorMode, Encoding, ObjNative, ObjPair, ObjReadPort, ObjString, ObjWritePort,
+    Object, PortSource,
+};
 use crate::scanner::{Rule, SchemeParser};
-use crate::value::{Char, Value};
-use crate::vm::{InterpretError, Result, Stack, VirtualMachine};
+use crate::value::{Char, DisplayStyle, Print, TypeError, Value};
+use crate::vm::{borrow_mut, peek, InterpretError, Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    WITH_OUTPUT_TO_STRING_CONTINUATION => "%with-output-to-string-continuation", with_output_to_string_continuation;
+    WITH_INPUT_FROM_STRING_CONTINUATION => "%with-input-from-string-continuation", with_input_from_string_continuation;
+    CALL_WITH_FILE_PORT_CONTINUATION => "%call-with-file-port-continuation", call_with_file_port_continuation;
+}
 
 pub fn is_input_port<'gc>(
     _: &VirtualMachine<'gc>,
@@ -27,6 +42,467 @@ pub fn is_output_port<'gc>(
     )))
 }
 
+pub fn is_port<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let is_port = match stack.read()[1] {
+        Value::Box(object) => matches!(&*object.read(), Object::ReadPort(_) | Object::WritePort(_)),
+        _ => false,
+    };
+    Ok(Some(Value::Bool(is_port)))
+}
+
+/// `(input-port-open? port)` checks whether a port can still be read from
+pub fn is_input_port_open<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = port_object(stack.read()[1])?;
+    let is_open = match &*port.read() {
+        Object::ReadPort(port) => port.is_open(),
+        _ => false,
+    };
+    Ok(Some(Value::Bool(is_open)))
+}
+
+/// `(output-port-open? port)` checks whether a port can still be written to
+pub fn is_output_port_open<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = port_object(stack.read()[1])?;
+    let is_open = match &*port.read() {
+        Object::WritePort(port) => port.is_open(),
+        _ => false,
+    };
+    Ok(Some(Value::Bool(is_open)))
+}
+
+/// Closes a port of either direction. Closing a port that's already closed does nothing.
+pub fn close_port<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    close_port_object(port_object(stack.read()[1])?, mc)?;
+    Ok(Some(Value::Void))
+}
+
+/// Closes the port behind a value that's already known to be a port
+pub(crate) fn close_port_object<'gc>(
+    port: GcCell<'gc, Object<'gc>>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    match &mut *borrow_mut(&port, mc)? {
+        Object::ReadPort(port) => port.close()?,
+        Object::WritePort(port) => port.close()?,
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn close_input_port<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[1].as_object()?;
+    borrow_mut(&port, mc)?.as_read_port_mut()?.close()?;
+    Ok(Some(Value::Void))
+}
+
+pub fn close_output_port<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[1].as_object()?;
+    borrow_mut(&port, mc)?.as_write_port_mut()?.close()?;
+    Ok(Some(Value::Void))
+}
+
+/// Gets the object behind a port of either direction
+fn port_object<'gc>(value: Value<'gc>) -> Result<GcCell<'gc, Object<'gc>>> {
+    if let Value::Box(object) = value {
+        if matches!(&*object.read(), Object::ReadPort(_) | Object::WritePort(_)) {
+            return Ok(object);
+        }
+    }
+    Err(TypeError(format!("'{}' is not a port", value)).into())
+}
+
+/// Opens a file for reading, optionally with an encoding and a decoding error mode (`replace`
+/// to substitute U+FFFD for invalid input, or `raise` to report an error)
+pub fn open_input_file<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let path = path_arg(args[1])?;
+    let encoding = encoding_arg(args.get(2).copied())?;
+    let mode = decode_error_mode_arg(args.get(3).copied())?;
+    let port = ObjReadPort::seekable(File::open(&path)?, encoding, mode)
+        .with_source(PortSource::File(path.into()));
+    Ok(Some(Value::boxed(mc, Object::ReadPort(port))))
+}
+
+/// Opens a file for writing, optionally with an encoding and a buffering mode (`none` to write
+/// everything out straight away, `line` to write out each finished line, or `full`, the default,
+/// to write out whenever the buffer fills up or the port is flushed)
+pub fn open_output_file<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let path = path_arg(args[1])?;
+    let encoding = encoding_arg(args.get(2).copied())?;
+    let buffering = buffering_arg(args.get(3).copied())?;
+    let port = ObjWritePort::seekable(File::create(&path)?, encoding)
+        .with_buffering(buffering)
+        .with_source(PortSource::File(path.into()));
+    Ok(Some(Value::boxed(mc, Object::WritePort(port))))
+}
+
+/// `(flush-output-port [port])` passes on anything an output port is holding back
+pub fn flush_output_port<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = match stack.read().get(1) {
+        Some(port) => port.as_object()?,
+        None => *vm.current_output_port().read(),
+    };
+    borrow_mut(&port, mc)?.as_write_port_mut()?.flush()?;
+    Ok(Some(Value::Void))
+}
+
+/// Gets the name of the encoding used by a port
+pub fn port_encoding<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[1].as_object()?;
+    let encoding = match &*port.read() {
+        Object::ReadPort(port) => port.encoding(),
+        Object::WritePort(port) => port.encoding(),
+        _ => return Err(TypeError(format!("'{}' is not a port", stack.read()[1])).into()),
+    };
+
+    let name = vm.intern_symbol(Token::new(mc, encoding.name().into()), mc);
+    Ok(Some(Value::Symbol(name)))
+}
+
+/// Gets the position of a port in bytes from the start of its input or output, where textual
+/// input ports count the bytes of their decoded (UTF-8) input (extension)
+pub fn port_position<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[1].as_object()?;
+    let position = match &mut *borrow_mut(&port, mc)? {
+        Object::ReadPort(port) => port.position()?,
+        Object::WritePort(port) => port.position()?,
+        _ => return Err(TypeError(format!("'{}' is not a port", stack.read()[1])).into()),
+    };
+    Ok(Some(Value::Number(position as f64)))
+}
+
+/// Moves a file (or in-memory input) port to a position like the ones `port-position` returns
+/// (extension)
+pub fn set_port_position<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (port, position) = {
+        let args = stack.read();
+        let position = args[2].as_number()?;
+        if position < 0.0 || position.fract() != 0.0 {
+            return Err(InterpretError::RuntimeError(format!(
+                "{} is not a valid port position",
+                args[2]
+            )));
+        }
+        (args[1].as_object()?, position as usize)
+    };
+
+    match &mut *borrow_mut(&port, mc)? {
+        Object::ReadPort(port) => port.set_position(position)?,
+        Object::WritePort(port) => port.set_position(position)?,
+        _ => return Err(TypeError(format!("'{}' is not a port", stack.read()[1])).into()),
+    }
+    Ok(Some(Value::Void))
+}
+
+pub(super) fn path_arg(path: Value<'_>) -> Result<String> {
+    match path {
+        Value::String(s) => Ok(s.as_str().into_owned()),
+        Value::Box(b) => Ok(b.read().as_string()?.as_str().into_owned()),
+        _ => Err(TypeError(format!("'{}' is not a string", path)).into()),
+    }
+}
+
+/// Parses an optional encoding name, defaulting to UTF-8
+fn encoding_arg(encoding: Option<Value<'_>>) -> Result<Encoding> {
+    match encoding {
+        None => Ok(Encoding::default()),
+        Some(encoding) => encoding
+            .as_symbol()?
+            .as_str()
+            .parse()
+            .map_err(InterpretError::RuntimeError),
+    }
+}
+
+fn buffering_arg(buffering: Option<Value<'_>>) -> Result<Buffering> {
+    match buffering {
+        None => Ok(Buffering::default()),
+        Some(buffering) => buffering
+            .as_symbol()?
+            .as_str()
+            .parse()
+            .map_err(InterpretError::RuntimeError),
+    }
+}
+
+fn decode_error_mode_arg(mode: Option<Value<'_>>) -> Result<DecodeErrorMode> {
+    match mode {
+        None => Ok(DecodeErrorMode::default()),
+        Some(mode) => mode
+            .as_symbol()?
+            .as_str()
+            .parse()
+            .map_err(InterpretError::RuntimeError),
+    }
+}
+
+pub fn open_input_string<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let string = stack.read()[1];
+    let port = match string {
+        Value::String(s) => ObjReadPort::string(s.as_bytes()),
+        Value::Box(b) => ObjReadPort::string(b.read().as_string()?.as_bytes()),
+        _ => return Err(TypeError(format!("'{}' is not a string", string)).into()),
+    };
+
+    Ok(Some(Value::boxed(mc, Object::ReadPort(port))))
+}
+
+pub fn open_output_string<'gc>(
+    _: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::boxed(
+        mc,
+        Object::WritePort(ObjWritePort::string()),
+    )))
+}
+
+pub fn get_output_string<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[1].as_object()?;
+    let port = port.read();
+    let contents = match port.as_write_port()? {
+        port if !port.is_binary() => port.contents(),
+        _ => None,
+    };
+    let contents = contents.ok_or_else(|| {
+        InterpretError::RuntimeError(format!("'{}' is not a string output port", port))
+    })?;
+
+    Ok(Some(Value::boxed(
+        mc,
+        Object::String(ObjString::new(contents.into())),
+    )))
+}
+
+/// Calls a thunk with the current output port bound to a fresh string port, returning
+/// everything written to it
+///
+/// The caller's continuation is saved before the port is rebound, so the original port is
+/// restored both on normal return and when escaping via a continuation.
+pub fn with_output_to_string<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let thunk = stack.read()[1];
+    let port = Value::boxed(mc, Object::WritePort(ObjWritePort::string()));
+    stack.write(mc).push(port);
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(
+        3,
+        false,
+        WITH_OUTPUT_TO_STRING_CONTINUATION,
+        None,
+    ));
+
+    stack.write(mc).push(thunk);
+    vm.call_value(thunk, stack, 0, mc)?;
+    *vm.current_output_port().write(mc) = port.as_object()?;
+    Ok(None)
+}
+
+fn with_output_to_string_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[2];
+    get_output_string(vm, GcCell::allocate(mc, vec![Value::Void, port]), mc)
+}
+
+/// Calls a thunk with the current input port bound to a string port reading from the given
+/// string, returning the thunk's result
+pub fn with_input_from_string<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = open_input_string(vm, stack, mc)?.unwrap();
+    let thunk = stack.read()[2];
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(
+        3,
+        false,
+        WITH_INPUT_FROM_STRING_CONTINUATION,
+        None,
+    ));
+
+    stack.write(mc).push(thunk);
+    vm.call_value(thunk, stack, 0, mc)?;
+    *vm.current_input_port().write(mc) = port.as_object()?;
+    Ok(None)
+}
+
+fn with_input_from_string_continuation<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(peek(stack, 0)))
+}
+
+/// Calls a procedure with a port reading from the given file, returning the procedure's result
+/// once the port is closed
+pub fn call_with_input_file<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let path = stack.read()[1];
+    let port = open_input_file(vm, GcCell::allocate(mc, vec![Value::Void, path]), mc)?;
+    call_with_file_port(vm, stack, port.unwrap(), false, mc)
+}
+
+/// Calls a procedure with a port writing to the given file, returning the procedure's result
+/// once the port is closed
+pub fn call_with_output_file<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let path = stack.read()[1];
+    let port = open_output_file(vm, GcCell::allocate(mc, vec![Value::Void, path]), mc)?;
+    call_with_file_port(vm, stack, port.unwrap(), false, mc)
+}
+
+/// Calls a thunk with the current input port reading from the given file
+pub fn with_input_from_file<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let path = stack.read()[1];
+    let port = open_input_file(vm, GcCell::allocate(mc, vec![Value::Void, path]), mc)?;
+    call_with_file_port(vm, stack, port.unwrap(), true, mc)
+}
+
+/// Calls a thunk with the current output port writing to the given file
+pub fn with_output_to_file<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let path = stack.read()[1];
+    let port = open_output_file(vm, GcCell::allocate(mc, vec![Value::Void, path]), mc)?;
+    call_with_file_port(vm, stack, port.unwrap(), true, mc)
+}
+
+/// Calls the procedure in `stack[2]`, either with `port` as its argument or, when `current` is
+/// set, as a thunk with `port` bound as the current input or output port
+///
+/// The frame saved for the call owns the port, so the port is closed when the procedure returns
+/// and also when a continuation escapes past it. The current ports come back with that frame.
+fn call_with_file_port<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    port: Value<'gc>,
+    current: bool,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let procedure = stack.read()[2];
+    stack.write(mc).push(port);
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(
+        1,
+        false,
+        CALL_WITH_FILE_PORT_CONTINUATION,
+        None,
+    ));
+
+    stack.write(mc).push(procedure);
+    if current {
+        vm.call_value(procedure, stack, 0, mc)?;
+    } else {
+        stack.write(mc).push(port);
+        vm.call_value(procedure, stack, 1, mc)?;
+    }
+
+    let port = port.as_object()?;
+    if let Some(frame) = *vm.parent_continuation(mc).read() {
+        if GcCell::ptr_eq(frame.read().stack(), stack) {
+            frame.write(mc).set_port(port);
+        }
+    }
+    if current {
+        match &*port.read() {
+            Object::ReadPort(_) => *vm.current_input_port().write(mc) = port,
+            _ => *vm.current_output_port().write(mc) = port,
+        }
+    }
+    Ok(None)
+}
+
+fn call_with_file_port_continuation<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[3];
+    close_port_object(port.as_object()?, mc)?;
+    Ok(Some(peek(stack, 0)))
+}
+
 pub fn current_input_port<'gc>(
     vm: &VirtualMachine<'gc>,
     _: Stack<'gc>,
@@ -43,31 +519,24 @@ pub fn current_output_port<'gc>(
     Ok(Some(Value::Box(*vm.current_output_port().read())))
 }
 
+pub fn current_error_port<'gc>(
+    vm: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::Box(*vm.current_error_port().read())))
+}
+
 pub fn is_char_ready<'gc>(
     vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     _: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let len = stack.read().len() - 1;
-    let result = match len {
-        0 => vm
-            .current_input_port()
-            .read()
-            .read()
-            .as_read_port()?
-            .is_char_ready(),
-        1 => stack.read()[1]
-            .as_object()?
-            .read()
-            .as_read_port()?
-            .is_char_ready(),
-        _ => {
-            return Err(InterpretError::RuntimeError(format!(
-                "Expected 0 or 1 arguments, but received {}",
-                len
-            )))
-        }
-    };
+    let port = port_arg(vm, stack)?;
+    let port = port.read();
+    let port = port.as_read_port()?;
+    port.check_textual()?;
+    let result = port.is_char_ready()?;
 
     Ok(Some(Value::Bool(result)))
 }
@@ -78,18 +547,9 @@ pub fn read_char<'gc>(
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
     let len = stack.read().len() - 1;
-    let result = match len {
-        0 => vm
-            .current_input_port()
-            .read()
-            .write(mc)
-            .as_read_port_mut()?
-            .read_char()?,
-        1 => stack.read()[1]
-            .as_object()?
-            .write(mc)
-            .as_read_port_mut()?
-            .read_char()?,
+    let port = match len {
+        0 => *vm.current_input_port().read(),
+        1 => stack.read()[1].as_object()?,
         _ => {
             return Err(InterpretError::RuntimeError(format!(
                 "Expected 0 or 1 arguments, but received {}",
@@ -98,6 +558,9 @@ pub fn read_char<'gc>(
         }
     };
 
+    flush_before_console_read(vm, port, mc);
+    let result = borrow_mut(&port, mc)?.as_read_port_mut()?.read_char()?;
+
     let result = match result {
         Some(character) => Value::Char(Char(character)),
         None => Value::Eof,
@@ -106,24 +569,92 @@ pub fn read_char<'gc>(
     Ok(Some(result))
 }
 
+/// Reads a character from the port, or returns `#f` if none arrives within the given number
+/// of seconds
+pub fn read_char_timeout<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (port, seconds) = {
+        let args = stack.read();
+        (args[1].as_object()?, args[2])
+    };
+    let timeout = Duration::try_from_secs_f64(seconds.as_number()?).map_err(|_| {
+        InterpretError::RuntimeError(format!("'{}' is not a valid timeout", seconds))
+    })?;
+
+    let mut port = borrow_mut(&port, mc)?;
+    let port = port.as_read_port_mut()?;
+    if !port.wait_for_char(timeout)? {
+        return Ok(Some(Value::Bool(false)));
+    }
+
+    let result = match port.read_char()? {
+        Some(character) => Value::Char(Char(character)),
+        None => Value::Eof,
+    };
+
+    Ok(Some(result))
+}
+
+/// `(read-line [port])` reads the rest of the current line, without its line ending
+pub fn read_line<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = port_arg(vm, stack)?;
+    flush_before_console_read(vm, port, mc);
+    let result = borrow_mut(&port, mc)?.as_read_port_mut()?.read_line()?;
+    Ok(Some(string_or_eof(result, mc)))
+}
+
+/// `(read-string k [port])` reads up to `k` characters, stopping early at the end of the input
+pub fn read_string<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (count, port) = {
+        let args = stack.read();
+        let count = args[1].as_number()?;
+        if count < 0.0 || count.fract() != 0.0 {
+            return Err(InterpretError::RuntimeError(format!(
+                "{} is not a valid number of characters",
+                args[1]
+            )));
+        }
+        let port = match args.get(2) {
+            Some(port) => port.as_object()?,
+            None => *vm.current_input_port().read(),
+        };
+        (count as usize, port)
+    };
+
+    flush_before_console_read(vm, port, mc);
+    let result = borrow_mut(&port, mc)?
+        .as_read_port_mut()?
+        .read_string(count)?;
+    Ok(Some(string_or_eof(result, mc)))
+}
+
+fn string_or_eof<'gc>(string: Option<String>, mc: MutationContext<'gc, '_>) -> Value<'gc> {
+    match string {
+        Some(string) => Value::boxed(mc, Object::String(ObjString::from(string))),
+        None => Value::Eof,
+    }
+}
+
 pub fn peek_char<'gc>(
     vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
     let len = stack.read().len() - 1;
-    let result = match len {
-        0 => vm
-            .current_input_port()
-            .read()
-            .write(mc)
-            .as_read_port_mut()?
-            .peek_char()?,
-        1 => stack.read()[1]
-            .as_object()?
-            .write(mc)
-            .as_read_port_mut()?
-            .peek_char()?,
+    let port = match len {
+        0 => *vm.current_input_port().read(),
+        1 => stack.read()[1].as_object()?,
         _ => {
             return Err(InterpretError::RuntimeError(format!(
                 "Expected 0 or 1 arguments, but received {}",
@@ -132,6 +663,9 @@ pub fn peek_char<'gc>(
         }
     };
 
+    flush_before_console_read(vm, port, mc);
+    let result = borrow_mut(&port, mc)?.as_read_port_mut()?.peek_char()?;
+
     let result = match result {
         Some(character) => Value::Char(Char(character)),
         None => Value::Eof,
@@ -145,23 +679,11 @@ pub fn write_char<'gc>(
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let args = stack.read();
-    let len = args.len() - 1;
-    let character = args[1].as_char()?;
-
-    let _ = match len {
-        1 => vm
-            .current_output_port()
-            .read()
-            .write(mc)
-            .as_write_port_mut()?
-            .write_char(character)?,
-
-        2 => stack.read()[2]
-            .as_object()?
-            .write(mc)
-            .as_write_port_mut()?
-            .write_char(character)?,
+    let len = stack.read().len() - 1;
+    let character = stack.read()[1].as_char()?;
+    let port = match len {
+        1 => *vm.current_output_port().read(),
+        2 => stack.read()[2].as_object()?,
         _ => {
             return Err(InterpretError::RuntimeError(format!(
                 "Expected 1 or 2 arguments, but received {}",
@@ -170,6 +692,10 @@ pub fn write_char<'gc>(
         }
     };
 
+    borrow_mut(&port, mc)?
+        .as_write_port_mut()?
+        .write_char(character)?;
+
     Ok(Some(Value::Void))
 }
 
@@ -178,21 +704,12 @@ pub fn read<'gc>(
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let args = stack.read();
-    let port = if args.len() == 1 {
-        *vm.current_input_port().read()
-    } else if args.len() == 2 {
-        args[1].as_object()?
-    } else {
-        return Err(InterpretError::RuntimeError(format!(
-            "Expected 0 or 1 arguments, but received {}",
-            args.len()
-        )));
-    };
+    let port = port_arg(vm, stack)?;
 
-    let mut port = port.write(mc);
+    let mut port = borrow_mut(&port, mc)?;
     let port = port.as_read_port_mut()?;
-    let (result, consumed) = match read_from_port(vm, port, mc) {
+    port.check_textual()?;
+    let (result, consumed) = match read_from_port(vm, port, None, mc) {
         Ok((None, consumed)) => (Ok(Some(Value::Eof)), consumed),
         Ok((value, consumed)) => (Ok(value), consumed),
         Err((err, consumed)) => (Err(err), consumed),
@@ -206,21 +723,22 @@ pub fn read_input<'gc>(
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let args = stack.read();
-    let port = if args.len() == 1 {
-        *vm.current_input_port().read()
-    } else if args.len() == 2 {
-        args[1].as_object()?
-    } else {
-        return Err(InterpretError::RuntimeError(format!(
-            "Expected 0 or 1 arguments, but received {}",
-            args.len()
-        )));
-    };
+    let port = port_arg(vm, stack)?;
+    flush_before_console_read(vm, port, mc);
 
-    let mut port = port.write(mc);
+    // Keep track of where the datum came from so the code compiled from it can refer back to it
+    let file = vm
+        .load_context()
+        .into_iter()
+        .next()
+        .map(|(file, _)| Rc::from(file));
+    let mut source = SourceMap::new(file, 1);
+
+    let mut port = borrow_mut(&port, mc)?;
     let port = port.as_read_port_mut()?;
-    let (result, consumed) = match read_from_port(vm, port, mc) {
+    let read = read_from_port(vm, port, Some(&mut source), mc);
+    vm.set_source_map(source);
+    let (result, consumed) = match read {
         Ok((None, consumed)) => (Ok(Some(Value::Null)), consumed),
         Ok((Some(value), consumed)) => {
             let result = Value::boxed(
@@ -235,11 +753,342 @@ pub fn read_input<'gc>(
     result
 }
 
+/// `(write obj [port])` writes the external representation of a value, which `read` can parse
+/// back in
+pub fn write<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let output = stack.read()[1].styled(DisplayStyle::Write).to_string();
+    write_output(vm, stack, 1, &output, mc)
+}
+
+/// `(display obj [port])` writes a value for people to read, with strings and characters
+/// written as their raw contents
+pub fn display<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let output = stack.read()[1].styled(DisplayStyle::Display).to_string();
+    write_output(vm, stack, 1, &output, mc)
+}
+
+pub fn newline<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    write_output(vm, stack, 0, "\n", mc)
+}
+
+/// `(write-string string [port [start [end]]])` writes the characters of a string, optionally
+/// only those from `start` up to `end`
+pub fn write_string<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let output = {
+        let args = stack.read();
+        let string = string_arg(args[1])?;
+        let chars: Vec<char> = string.chars().collect();
+        let range = checked_range(&args, 3, "string", args[1], chars.len())?;
+        chars[range].iter().collect::<String>()
+    };
+
+    let port = match stack.read().get(2) {
+        Some(port) => port.as_object()?,
+        None => *vm.current_output_port().read(),
+    };
+    borrow_mut(&port, mc)?
+        .as_write_port_mut()?
+        .write_str(&output)?;
+    Ok(Some(Value::Void))
+}
+
+/// Writes `output` to the port after a writer's `count` required arguments, or to the current
+/// output port if it wasn't given one
+fn write_output<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    count: usize,
+    output: &str,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = {
+        let args = stack.read();
+        match args.len() - 1 - count {
+            0 => *vm.current_output_port().read(),
+            1 => args[count + 1].as_object()?,
+            _ => {
+                return Err(InterpretError::RuntimeError(format!(
+                    "Expected {} or {} arguments, but received {}",
+                    count,
+                    count + 1,
+                    args.len() - 1
+                )))
+            }
+        }
+    };
+
+    borrow_mut(&port, mc)?
+        .as_write_port_mut()?
+        .write_str(output)?;
+    Ok(Some(Value::Void))
+}
+
+pub fn open_binary_input_file<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let path = path_arg(stack.read()[1])?;
+    let port =
+        ObjReadPort::binary_seekable(File::open(&path)?).with_source(PortSource::File(path.into()));
+    Ok(Some(Value::boxed(mc, Object::ReadPort(port))))
+}
+
+pub fn open_binary_output_file<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let path = path_arg(args[1])?;
+    let buffering = buffering_arg(args.get(2).copied())?;
+    let port = ObjWritePort::seekable(File::create(&path)?, Encoding::default())
+        .into_binary()
+        .with_buffering(buffering)
+        .with_source(PortSource::File(path.into()));
+    Ok(Some(Value::boxed(mc, Object::WritePort(port))))
+}
+
+pub fn open_input_bytevector<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let bytes = bytevector_bytes(stack.read()[1])?;
+    Ok(Some(Value::boxed(
+        mc,
+        Object::ReadPort(ObjReadPort::bytevector(&bytes)),
+    )))
+}
+
+pub fn open_output_bytevector<'gc>(
+    _: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::boxed(
+        mc,
+        Object::WritePort(ObjWritePort::bytevector()),
+    )))
+}
+
+pub fn get_output_bytevector<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[1].as_object()?;
+    let port = port.read();
+    let bytes = match port.as_write_port()? {
+        port if port.is_binary() => port.contents(),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        InterpretError::RuntimeError(format!("'{}' is not a bytevector output port", port))
+    })?;
+
+    Ok(Some(new_bytevector(bytes.to_vec(), mc)))
+}
+
+pub fn is_binary_port<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::Bool(
+        port_is_binary(stack.read()[1]) == Some(true),
+    )))
+}
+
+pub fn is_textual_port<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::Bool(
+        port_is_binary(stack.read()[1]) == Some(false),
+    )))
+}
+
+/// Checks whether a value is a binary port, or returns `None` if it isn't a port at all
+fn port_is_binary(value: Value<'_>) -> Option<bool> {
+    match value {
+        Value::Box(object) => match &*object.read() {
+            Object::ReadPort(port) => Some(port.is_binary()),
+            Object::WritePort(port) => Some(port.is_binary()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// `(read-u8 [port])` reads a byte from a binary port
+pub fn read_u8<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = port_arg(vm, stack)?;
+    let result = borrow_mut(&port, mc)?.as_read_port_mut()?.read_u8()?;
+    Ok(Some(
+        result.map_or(Value::Eof, |byte| Value::Number(byte as f64)),
+    ))
+}
+
+/// `(peek-u8 [port])` gets the next byte from a binary port without consuming it
+pub fn peek_u8<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = port_arg(vm, stack)?;
+    let result = borrow_mut(&port, mc)?.as_read_port_mut()?.peek_u8()?;
+    Ok(Some(
+        result.map_or(Value::Eof, |byte| Value::Number(byte as f64)),
+    ))
+}
+
+/// `(u8-ready? [port])` checks whether a byte can be read from a binary port without blocking
+pub fn is_u8_ready<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = port_arg(vm, stack)?;
+    let port = port.read();
+    let port = port.as_read_port()?;
+    port.check_binary()?;
+    Ok(Some(Value::Bool(port.is_char_ready()?)))
+}
+
+/// `(read-bytevector k [port])` reads up to `k` bytes from a binary port
+pub fn read_bytevector<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (count, port) = {
+        let args = stack.read();
+        let count = args[1].as_number()?;
+        if count < 0.0 || count.fract() != 0.0 {
+            return Err(InterpretError::RuntimeError(format!(
+                "{} is not a valid number of bytes",
+                args[1]
+            )));
+        }
+        let port = match args.get(2) {
+            Some(port) => port.as_object()?,
+            None => *vm.current_input_port().read(),
+        };
+        (count as usize, port)
+    };
+
+    let result = borrow_mut(&port, mc)?
+        .as_read_port_mut()?
+        .read_bytes(count)?;
+    Ok(Some(
+        result.map_or(Value::Eof, |bytes| new_bytevector(bytes, mc)),
+    ))
+}
+
+/// `(write-u8 byte [port])` writes a byte to a binary port
+pub fn write_u8<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (value, port) = {
+        let args = stack.read();
+        let port = match args.get(2) {
+            Some(port) => port.as_object()?,
+            None => *vm.current_output_port().read(),
+        };
+        (byte(args[1])?, port)
+    };
+
+    borrow_mut(&port, mc)?
+        .as_write_port_mut()?
+        .write_u8(value)?;
+    Ok(Some(Value::Void))
+}
+
+/// `(write-bytevector bytevector [port [start [end]]])` writes the bytes of a bytevector to a
+/// binary port, optionally only those from `start` up to `end`
+pub fn write_bytevector<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (bytes, port) = {
+        let args = stack.read();
+        let bytes = bytevector_bytes(args[1])?;
+        let range = checked_range(&args, 3, "bytevector", args[1], bytes.len())?;
+        let port = match args.get(2) {
+            Some(port) => port.as_object()?,
+            None => *vm.current_output_port().read(),
+        };
+        (bytes[range].to_vec(), port)
+    };
+
+    borrow_mut(&port, mc)?
+        .as_write_port_mut()?
+        .write_bytes(&bytes)?;
+    Ok(Some(Value::Void))
+}
+
+/// Flushes the current output port before reading from the console, so that a prompt written
+/// without a line ending shows up before waiting for input
+fn flush_before_console_read<'gc>(
+    vm: &VirtualMachine<'gc>,
+    port: GcCell<'gc, Object<'gc>>,
+    mc: MutationContext<'gc, '_>,
+) {
+    let is_console = matches!(
+        port.read().as_read_port().map(ObjReadPort::source),
+        Ok(PortSource::Console)
+    );
+    if is_console {
+        vm.flush_output(mc);
+    }
+}
+
+/// Gets the port a reader was called with, or the current input port if it wasn't given one
+fn port_arg<'gc>(vm: &VirtualMachine<'gc>, stack: Stack<'gc>) -> Result<GcCell<'gc, Object<'gc>>> {
+    let args = stack.read();
+    match args.len() {
+        1 => Ok(*vm.current_input_port().read()),
+        2 => Ok(args[1].as_object()?),
+        len => Err(InterpretError::RuntimeError(format!(
+            "Expected 0 or 1 arguments, but received {}",
+            len
+        ))),
+    }
+}
+
+/// Reads a datum from a port, recording where its lists came from in `source_map` if given
 fn read_from_port<'gc>(
     vm: &VirtualMachine<'gc>,
     input_port: &mut ObjReadPort,
+    source_map: Option<&mut SourceMap>,
     mc: MutationContext<'gc, '_>,
 ) -> std::result::Result<(Option<Value<'gc>>, usize), (InterpretError, usize)> {
+    let (line, column) = (input_port.line(), input_port.column());
     let buf = input_port
         .fill_buf()
         .map_err(|e| (InterpretError::from(e), 0))?;
@@ -261,16 +1110,49 @@ fn read_from_port<'gc>(
 
     let pair = pair.unwrap();
     let len = pair.as_span().end();
-    let expr = compiler::read(pair, vm, mc).map_err(|e| (InterpretError::from(e), orig_len))?;
+    let expr = compiler::read(pair.clone(), vm, mc)
+        .map_err(|e| (InterpretError::from(e), orig_len))?
+        .into_boxed_value(mc);
+    if let Some(source_map) = source_map {
+        let skipped = &orig_source[..white_len];
+        let first_line = line + skipped.matches('\n').count();
+        let first_column = match skipped.rfind('\n') {
+            Some(newline) => skipped[newline + 1..].chars().count(),
+            None => column + skipped.chars().count(),
+        };
+        *source_map = SourceMap::new(source_map.file().cloned(), first_line);
+        source_map.record(pair, expr, first_line, first_column);
+    }
+
     let result = if orig_source[(len + white_len)..].trim_start().is_empty() {
-        (Some(expr.into_boxed_value(mc)), orig_len)
+        (Some(expr), orig_len)
     } else {
-        (Some(expr.into_boxed_value(mc)), len + white_len)
+        (Some(expr), len + white_len)
     };
 
     Ok(result)
 }
 
+pub fn port_line<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[1].as_object()?;
+    let line = port.read().as_read_port()?.line();
+    Ok(Some(Value::Number(line as f64)))
+}
+
+pub fn port_column<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let port = stack.read()[1].as_object()?;
+    let column = port.read().as_read_port()?.column();
+    Ok(Some(Value::Number(column as f64)))
+}
+
 pub fn is_eof_object<'gc>(
     _: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
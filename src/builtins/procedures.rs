@@ -1,8 +1,19 @@
-use gc_arena::MutationContext;
+use std::fs;
 
-use crate::object::{ObjNative, Object, ObjFunction};
+use gc_arena::{GcCell, MutationContext};
+
+use super::{eqv, path_arg};
+use crate::chunk::{Chunk, OpCode};
+use crate::compiler::Upvalues;
+use crate::object::{ObjContinuation, ObjFunction, ObjNative, Object};
+use crate::serialize::{deserialize_continuation, serialize_continuation};
 use crate::value::Value;
-use crate::vm::{Procedure, Result, Stack, VirtualMachine};
+use crate::vm::{InterpretError, Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    CALL_WITH_PROMPT_CONTINUATION => "%call-with-prompt-continuation", call_with_prompt_continuation;
+    CALL_WITH_VALUES_CONTINUATION => "%call-with-values-continuation", call_with_values_continuation;
+}
 
 pub fn is_procedure<'gc>(
     _: &VirtualMachine<'gc>,
@@ -40,7 +51,12 @@ pub fn call_with_current_continuation<'gc>(
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let continuation = vm.parent_continuation().read().unwrap().read().clone();
+    let continuation = vm
+        .parent_continuation(mc)
+        .read()
+        .unwrap()
+        .read()
+        .snapshot(mc);
     stack
         .write(mc)
         .push(Value::boxed(mc, Object::Continuation(continuation)));
@@ -49,12 +65,129 @@ pub fn call_with_current_continuation<'gc>(
     Ok(None)
 }
 
+/// Writes a continuation out to a file, so that it can be resumed later (possibly by another
+/// process) with `load-continuation`. This is experimental - see the `serialize` module for what
+/// does and doesn't survive the trip.
+pub fn save_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (continuation, path) = {
+        let args = stack.read();
+        (args[1], path_arg(args[2])?)
+    };
+    fs::write(path, serialize_continuation(vm, continuation)?)?;
+    Ok(Some(Value::Void))
+}
+
+/// Reads a continuation written by `save-continuation` back in
+pub fn load_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let path = path_arg(stack.read()[1])?;
+    let bytes = fs::read(path)?;
+    Ok(Some(deserialize_continuation(vm, &bytes, mc)?))
+}
+
+/// Calls a thunk with a prompt installed, so that `abort-to-prompt` with a matching (`eqv?`)
+/// tag can jump back out to it. When that happens the handler is called with the delimited
+/// continuation of the abort, from the thunk's body up to the prompt, followed by the values
+/// that were passed to `abort-to-prompt`.
+pub fn call_with_prompt<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (tag, thunk) = {
+        let args = stack.read();
+        (args[1], args[2])
+    };
+
+    // Write the procedure that should pick up execution after this procedure call finishes
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(
+        1,
+        false,
+        CALL_WITH_PROMPT_CONTINUATION,
+        None,
+    ));
+    stack.write(mc).push(thunk);
+    vm.call_value(thunk, stack, 0, mc)?;
+
+    // The frame saved for the call is the one that `abort-to-prompt` unwinds to
+    if let Some(frame) = *vm.parent_continuation(mc).read() {
+        if GcCell::ptr_eq(frame.read().stack(), stack) {
+            frame.write(mc).set_prompt(tag);
+        }
+    }
+    Ok(None)
+}
+
+fn call_with_prompt_continuation<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(stack.read()[4]))
+}
+
+/// Unwinds to the innermost prompt with the given tag and calls its handler
+pub fn abort_to_prompt<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let tag = stack.read()[1];
+    let caller = *vm.parent_continuation(mc).read();
+    let mut frame = caller;
+    let prompt = loop {
+        match frame {
+            Some(current) if current.read().prompt().is_some_and(|t| eqv(t, tag)) => break current,
+            Some(current) => frame = current.read().frames(),
+            None => {
+                return Err(InterpretError::RuntimeError(format!(
+                    "No prompt with tag '{}' is installed",
+                    tag
+                )))
+            }
+        }
+    };
+
+    // A continuation that has nothing left to do before the prompt just returns its argument
+    let continuation = caller
+        .and_then(|caller| ObjContinuation::delimit(caller, prompt, mc))
+        .map(|continuation| Value::boxed(mc, Object::Continuation(continuation)))
+        .unwrap_or_else(|| {
+            Value::boxed(
+                mc,
+                Object::Native(ObjNative::registered(
+                    1,
+                    false,
+                    ("identity", identity),
+                    None,
+                )),
+            )
+        });
+
+    let values = stack.read()[2..].to_vec();
+    vm.apply_continuation(prompt, mc);
+    let stack = prompt.read().stack();
+    let handler = stack.read()[3];
+    stack.write(mc).push(handler);
+    stack.write(mc).push(continuation);
+    stack.write(mc).extend(values.iter().copied());
+    vm.tail_call_value(handler, stack, values.len() + 1, mc)?;
+    Ok(None)
+}
+
 pub fn values<'gc>(
     vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let continuation = vm.parent_continuation().read().unwrap().read().clone();
+    let continuation = vm.parent_continuation(mc).read().unwrap().read().clone();
     let arg_count = stack.read().len() - 1;
     vm.tail_call_value(
         Value::boxed(mc, Object::Continuation(continuation)),
@@ -72,10 +205,10 @@ pub fn call_with_values<'gc>(
 ) -> Result<Option<Value<'gc>>> {
     let producer = stack.read()[1];
     // Write the procedure that should pick up execution after this procedure call finishes
-    *vm.procedure().write(mc) = Procedure::Native(ObjNative::new(
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(
         2,
         false,
-        call_with_values_continuation,
+        CALL_WITH_VALUES_CONTINUATION,
         None,
     ));
     vm.call_value(producer, stack, 0, mc)?;
@@ -93,6 +226,75 @@ fn call_with_values_continuation<'gc>(
     Ok(None)
 }
 
+pub fn identity<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(stack.read()[1]))
+}
+
+/// Creates a procedure that ignores its arguments and always returns the given value
+pub fn constantly<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let value = stack.read()[1];
+
+    let mut chunk = Chunk::new();
+    chunk.write_constant(value, 1);
+    chunk.write(OpCode::Return.into(), 1);
+
+    let function = ObjFunction::new(mc, 1, true, chunk, Upvalues::default(), None);
+    Ok(Some(Value::boxed(mc, Object::Function(function))))
+}
+
+/// Creates a procedure that applies the rightmost procedure to its arguments, and then feeds
+/// the result through each of the remaining procedures from right to left
+pub fn compose<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let procedures = stack.read()[1..].to_vec();
+    let (innermost, rest) = match procedures.split_last() {
+        Some(split) => split,
+        None => {
+            let identity = ObjNative::registered(1, false, ("identity", identity), None);
+            return Ok(Some(Value::boxed(mc, Object::Native(identity))));
+        }
+    };
+
+    let apply = Value::boxed(
+        mc,
+        Object::Native(ObjNative::registered(2, true, ("apply", apply), None)),
+    );
+
+    // Emits `(p0 (p1 ... (apply pn args)))`, where `args` is the rest parameter in slot 1
+    let mut chunk = Chunk::new();
+    for procedure in rest {
+        chunk.write_constant(*procedure, 1);
+    }
+    chunk.write_constant(apply, 1);
+    chunk.write_constant(*innermost, 1);
+    chunk.write(OpCode::GetLocal.into(), 1);
+    chunk.write(1, 1);
+    let mut arg_counts = vec![2];
+    arg_counts.resize(procedures.len(), 1);
+    if let Some((last, inner)) = arg_counts.split_last() {
+        for arg_count in inner {
+            chunk.write(OpCode::Call.into(), 1);
+            chunk.write(*arg_count, 1);
+        }
+        chunk.write(OpCode::TailCall.into(), 1);
+        chunk.write(*last, 1);
+    }
+
+    let function = ObjFunction::new(mc, 1, true, chunk, Upvalues::default(), None);
+    Ok(Some(Value::boxed(mc, Object::Function(function))))
+}
+
 // fn make_procedure<'gc>(
 //     vm: &VirtualMachine<'gc>,
 //     stack: Stack<'gc>,
@@ -0,0 +1,33 @@
+//! Profiling Scheme code. See the `profile` module of the VM for what's recorded.
+
+use gc_arena::MutationContext;
+
+use crate::value::Value;
+use crate::vm::{borrow_mut, InterpretError, Result, Stack, VirtualMachine};
+
+/// `(profile-start!)` starts counting calls to each procedure and timing them, from scratch
+pub fn profile_start<'gc>(
+    vm: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    vm.start_profiling();
+    Ok(Some(Value::Void))
+}
+
+/// `(profile-report)` writes a table of the procedures called since `(profile-start!)` to the
+/// current output port, the ones that took the most time first
+pub fn profile_report<'gc>(
+    vm: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let report = vm
+        .profile_report()
+        .ok_or_else(|| InterpretError::RuntimeError("Profiling hasn't been started".to_string()))?;
+    let port = *vm.current_output_port().read();
+    borrow_mut(&port, mc)?
+        .as_write_port_mut()?
+        .write_str(&report)?;
+    Ok(Some(Value::Void))
+}
@@ -0,0 +1,91 @@
+//! The natives behind the procedures `define-record-type` defines. Each of those procedures
+//! passes its record type (and the index of the field it's for, if any) on to one of these
+//! along with its own arguments.
+
+use gc_arena::{GcCell, MutationContext};
+
+use crate::object::{ObjRecord, Object};
+use crate::value::{TypeError, Value};
+use crate::vm::{borrow_mut, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    MAKE_RECORD => "%make-record", make_record;
+    IS_RECORD => "%record?", is_record;
+    RECORD_REF => "%record-ref", record_ref;
+    RECORD_SET => "%record-set!", record_set;
+}
+
+/// `(%make-record type field...)` makes a record with the given field values
+pub fn make_record<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let record_type = args[1].as_object()?;
+    let fields = args[2..].to_vec().into_boxed_slice();
+    Ok(Some(Value::boxed(
+        mc,
+        Object::Record(ObjRecord::new(record_type, fields)),
+    )))
+}
+
+/// `(%record? type obj)` checks whether `obj` is a record of the given type
+pub fn is_record<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let record_type = args[1].as_object()?;
+    let is_record = match args[2] {
+        Value::Box(object) => match &*object.read() {
+            Object::Record(record) => record.is_a(record_type),
+            _ => false,
+        },
+        _ => false,
+    };
+    Ok(Some(Value::Bool(is_record)))
+}
+
+/// `(%record-ref type index record)` gets a field of a record of the given type
+pub fn record_ref<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let record = record_of_type(args[1], args[3])?;
+    let index = args[2].as_number()? as usize;
+    let value = record.read().as_record()?.fields()[index];
+    Ok(Some(value))
+}
+
+/// `(%record-set! type index record value)` sets a field of a record of the given type
+pub fn record_set<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let record = record_of_type(args[1], args[3])?;
+    let index = args[2].as_number()? as usize;
+    borrow_mut(&record, mc)?.as_record_mut()?.fields_mut()[index] = args[4];
+    Ok(Some(Value::Void))
+}
+
+/// Gets the object behind `value`, as long as it's a record of `record_type`
+fn record_of_type<'gc>(
+    record_type: Value<'gc>,
+    value: Value<'gc>,
+) -> Result<GcCell<'gc, Object<'gc>>> {
+    let record_type = record_type.as_object()?;
+    if let Value::Box(object) = value {
+        if matches!(&*object.read(), Object::Record(record) if record.is_a(record_type)) {
+            return Ok(object);
+        }
+    }
+
+    let name = record_type.read().as_record_type()?.record_name();
+    Err(TypeError(format!("'{}' is not a {} record", value, name)).into())
+}
@@ -0,0 +1,163 @@
+//! Regular expressions, backed by the `regex` crate, which are only built with the `regex`
+//! feature. Wherever a regexp is expected, a string can be given instead, which is compiled on
+//! the spot. Positions in strings are counted in characters, like everywhere else.
+
+use gc_arena::MutationContext;
+use regex::{Captures, Regex};
+
+use super::{list_from, string_arg};
+use crate::object::{ObjPair, ObjRegex, ObjString, Object};
+use crate::value::{TypeError, Value};
+use crate::vm::{InterpretError, Result, Stack, VirtualMachine};
+
+/// Compiles a regular expression
+fn compile(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|error| {
+        InterpretError::RuntimeError(format!("Invalid regexp {:?}: {}", pattern, error))
+    })
+}
+
+/// Gets a regexp argument, compiling it if it's a string
+fn regex_arg(value: Value<'_>) -> Result<Regex> {
+    if let Value::Box(object) = value {
+        if let Ok(regex) = object.read().as_regex() {
+            return Ok(regex.regex().clone());
+        }
+    }
+
+    match string_arg(value) {
+        Ok(pattern) => compile(&pattern),
+        Err(_) => Err(TypeError(format!("'{}' is not a regexp", value)).into()),
+    }
+}
+
+fn new_string<'gc>(string: &str, mc: MutationContext<'gc, '_>) -> Value<'gc> {
+    Value::boxed(mc, Object::String(ObjString::from(string)))
+}
+
+/// Turns the captures of a match into a list with an element for the whole match and one for
+/// each group, made by `group` (or `#f` for groups that didn't take part in the match)
+fn captures_list<'gc>(
+    captures: &Captures<'_>,
+    mut group: impl FnMut(regex::Match<'_>) -> Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Value<'gc> {
+    let groups: Vec<_> = captures
+        .iter()
+        .map(|capture| capture.map_or(Value::Bool(false), &mut group))
+        .collect();
+    list_from(groups, mc)
+}
+
+pub fn regexp<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let regex = compile(&string_arg(stack.read()[1])?)?;
+    Ok(Some(Value::boxed(mc, Object::Regex(ObjRegex::new(regex)))))
+}
+
+pub fn is_regexp<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    match stack.read()[1] {
+        Value::Box(object) => Ok(Some(Value::Bool(object.read().is_regex()))),
+        _ => Ok(Some(Value::Bool(false))),
+    }
+}
+
+/// `(regexp-match regexp string)` finds the first match in a string, returning a list of the
+/// matched text and the text of each group, or `#f` if there's no match
+pub fn regexp_match<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let regex = regex_arg(args[1])?;
+    let string = string_arg(args[2])?;
+    let result = match regex.captures(&string) {
+        Some(captures) => captures_list(&captures, |m| new_string(m.as_str(), mc), mc),
+        None => Value::Bool(false),
+    };
+    Ok(Some(result))
+}
+
+/// `(regexp-match-positions regexp string)` is like `regexp-match`, but gives the `(start . end)`
+/// of the match and of each group instead of their text
+pub fn regexp_match_positions<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let regex = regex_arg(args[1])?;
+    let string = string_arg(args[2])?;
+    let char_index = |byte: usize| Value::Number(string[..byte].chars().count() as f64);
+    let result = match regex.captures(&string) {
+        Some(captures) => captures_list(
+            &captures,
+            |m| {
+                let pair = ObjPair::new(char_index(m.start()), char_index(m.end()));
+                Value::boxed(mc, Object::Pair(pair))
+            },
+            mc,
+        ),
+        None => Value::Bool(false),
+    };
+    Ok(Some(result))
+}
+
+/// `(regexp-replace regexp string replacement)` replaces the first match in a string.
+/// `$1`, `${name}` and so on in the replacement stand for the text of the match's groups.
+pub fn regexp_replace<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let regex = regex_arg(args[1])?;
+    let string = string_arg(args[2])?;
+    let replacement = string_arg(args[3])?;
+    Ok(Some(new_string(
+        &regex.replace(&string, replacement.as_str()),
+        mc,
+    )))
+}
+
+/// `(regexp-replace* regexp string replacement)` is like `regexp-replace`, but replaces every
+/// match
+pub fn regexp_replace_all<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let regex = regex_arg(args[1])?;
+    let string = string_arg(args[2])?;
+    let replacement = string_arg(args[3])?;
+    Ok(Some(new_string(
+        &regex.replace_all(&string, replacement.as_str()),
+        mc,
+    )))
+}
+
+/// `(regexp-split regexp string)` splits a string on every match, returning a list of the
+/// strings in between
+pub fn regexp_split<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let regex = regex_arg(args[1])?;
+    let string = string_arg(args[2])?;
+    let fields: Vec<_> = regex
+        .split(&string)
+        .map(|field| new_string(field, mc))
+        .collect();
+    Ok(Some(list_from(fields, mc)))
+}
@@ -1,16 +1,36 @@
-use std::fs::File;
-use std::io::{self, Write};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 
-use gc_arena::MutationContext;
+use gc_arena::{Gc, MutationContext};
 
-use crate::compiler::bootstrap;
+use super::macros::{expand_macros, COMPILE_EXPANSION_CONTINUATION};
+use super::{describe_uncaught, environment_arg, list_from, list_to_vec, string_arg, uncons};
+use crate::chunk::Globals;
+use crate::compiler::bootstrap::{self, CompileOptions};
 use crate::memory::{Symbol, Token};
-use crate::object::{ObjNative, ObjReadPort, ObjString, Object};
+use crate::object::{self, ObjNative, ObjPair, ObjReadPort, ObjString, Object, PortSource};
+use crate::serialize;
 use crate::value::Value;
-use crate::vm::{peek, InterpretError, Procedure, Result, Stack, VirtualMachine};
+use crate::vm::{borrow_mut, peek, InterpretError, Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    HALT => "%halt", halt;
+    READ_THUNK => "%read-thunk", read_thunk;
+    COMPILE_THUNK => "%compile-thunk", compile_thunk;
+    EVAL_THUNK => "%eval-thunk", eval_thunk;
+    PRINT_THUNK => "%print-thunk", print_thunk;
+    REPL_EXCEPTION_HANDLER => "%repl-exception-handler", repl_exception_handler;
+    LOAD_READ_THUNK => "%load-read-thunk", load_read_thunk;
+    LOAD_COMPILE_THUNK => "%load-compile-thunk", load_compile_thunk;
+    LOAD_EVAL_THUNK => "%load-eval-thunk", load_eval_thunk;
+    LOAD_EVAL_CONTINUATION_THUNK => "%load-eval-continuation-thunk", load_eval_continuation_thunk;
+    COMPILE_FILE_SAVE_THUNK => "%compile-file-save-thunk", compile_file_save_thunk;
+    COMPILE_FILE_EVAL_THUNK => "%compile-file-eval-thunk", compile_file_eval_thunk;
+}
 
-pub fn read_thunk<'gc>(
+fn read_thunk<'gc>(
     vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
@@ -20,14 +40,16 @@ pub fn read_thunk<'gc>(
         .read()
         .read()
         .as_read_port()?
-        .is_char_ready();
+        .is_char_ready()?;
     if !is_char_ready {
+        vm.flush_output(mc);
         print!(">> ");
         let _ = io::stdout().flush();
     }
 
     // Write the procedure that should pick up execution after this procedure call finishes
-    *vm.procedure().write(mc) = Procedure::Native(ObjNative::new(1, false, compile_thunk, None));
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, COMPILE_THUNK, None));
 
     let read = Value::boxed(
         mc,
@@ -64,7 +86,7 @@ fn compile_thunk<'gc>(
     if result.is_null() {
         let repl = Value::boxed(
             mc,
-            Object::Native(ObjNative::new(0, false, read_thunk, None)),
+            Object::Native(ObjNative::registered(0, false, READ_THUNK, None)),
         );
         stack.write(mc).push(repl);
 
@@ -74,9 +96,7 @@ fn compile_thunk<'gc>(
 
     let result = car(result).unwrap();
     if result.is_eof() {
-        let frame = *vm.parent_continuation().read();
-        if let Some(frame) = frame {
-            vm.apply_continuation(frame, mc);
+        if vm.resume_caller(mc) {
             vm.push_stack(result, mc);
             return Ok(None);
         } else {
@@ -84,15 +104,31 @@ fn compile_thunk<'gc>(
         }
     }
 
+    if let Some(command) = repl_command(result) {
+        if run_repl_command(vm, &command, mc)? {
+            return Ok(None);
+        }
+
+        let repl = Value::boxed(
+            mc,
+            Object::Native(ObjNative::registered(0, false, READ_THUNK, None)),
+        );
+        stack.write(mc).push(repl);
+
+        vm.tail_call_value(repl, stack, 0, mc)?;
+        return Ok(None);
+    }
+
     // Write the procedure that should pick up execution after this procedure call finishes
-    *vm.procedure().write(mc) = Procedure::Native(ObjNative::new(1, false, eval_thunk, None));
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, EVAL_THUNK, None));
 
     let compile = Value::boxed(
         mc,
-        Object::Native(ObjNative::new(
+        Object::Native(ObjNative::registered(
             1,
             false,
-            compile,
+            ("compile", compile),
             Some(Symbol::uninterned(Token::new(
                 mc,
                 ObjString::from("compile"),
@@ -106,6 +142,50 @@ fn compile_thunk<'gc>(
     Ok(None)
 }
 
+/// Picks out REPL commands such as `,back`, which the reader gives back as `(unquote back)`
+fn repl_command(datum: Value<'_>) -> Option<String> {
+    let (head, rest) = uncons(datum)?;
+    let (command, rest) = uncons(rest)?;
+    match (head, command) {
+        (Value::Symbol(head), Value::Symbol(command))
+            if &*head.as_str() == "unquote" && rest.is_null() =>
+        {
+            Some(command.as_str().into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Runs a REPL command, returning whether it handed execution over to somewhere else
+fn run_repl_command<'gc>(
+    vm: &VirtualMachine<'gc>,
+    command: &str,
+    mc: MutationContext<'gc, '_>,
+) -> Result<bool> {
+    match command {
+        "back" | "forward" | "resume" if !vm.is_time_travelling() => {
+            println!("Time travel is off, start the REPL with --time-travel to record snapshots")
+        }
+        "back" => match vm.step_back() {
+            Some(snapshot) => println!("{}", snapshot),
+            None => println!("There's no earlier snapshot"),
+        },
+        "forward" => match vm.step_forward() {
+            Some(snapshot) => println!("{}", snapshot),
+            None => println!("There's no later snapshot"),
+        },
+        "resume" => {
+            vm.resume_snapshot(mc)?;
+            return Ok(true);
+        }
+        _ => println!(
+            "Unknown command ,{} (try ,back ,forward or ,resume)",
+            command
+        ),
+    }
+    Ok(false)
+}
+
 fn eval_thunk<'gc>(
     vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
@@ -114,9 +194,48 @@ fn eval_thunk<'gc>(
     let eval = peek(stack, 0);
 
     // Write the procedure that should pick up execution after this procedure call finishes
-    *vm.procedure().write(mc) = Procedure::Native(ObjNative::new(1, false, print_thunk, None));
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(1, false, PRINT_THUNK, None));
 
     vm.call_value(eval, stack, 0, mc)?;
+
+    // Anything raised and not handled while evaluating goes back to the prompt
+    let handler = Value::boxed(
+        mc,
+        Object::Native(ObjNative::registered(
+            1,
+            false,
+            REPL_EXCEPTION_HANDLER,
+            None,
+        )),
+    );
+    let handlers = *vm.handlers().read();
+    *vm.handlers().write(mc) = Value::boxed(mc, Object::Pair(ObjPair::new(handler, handlers)));
+    Ok(None)
+}
+
+/// Top-level exception handler installed by the REPL. Reports the condition along with the
+/// procedures (and loaded files) that were active when it was raised, then abandons the
+/// evaluation.
+fn repl_exception_handler<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let condition = stack.read()[1];
+    vm.write_error_line(
+        &format!("uncaught exception: {}", describe_uncaught(condition)),
+        mc,
+    );
+
+    for frame in vm.backtrace() {
+        vm.write_error_line(&format!("  in {}", frame), mc);
+    }
+    for (file, form) in vm.load_context() {
+        vm.write_error_line(&format!("  in form {} of {}", form, file), mc);
+    }
+
+    vm.reset_repl(mc);
     Ok(None)
 }
 
@@ -127,12 +246,13 @@ fn print_thunk<'gc>(
 ) -> Result<Option<Value<'gc>>> {
     let result = stack.write(mc).pop().unwrap();
     if !result.is_void() && !result.is_eof() {
+        vm.flush_output(mc);
         println!("{}", result);
     }
 
     let repl = Value::boxed(
         mc,
-        Object::Native(ObjNative::new(0, false, read_thunk, None)),
+        Object::Native(ObjNative::registered(0, false, READ_THUNK, None)),
     );
     stack.write(mc).push(repl);
 
@@ -140,23 +260,261 @@ fn print_thunk<'gc>(
     Ok(None)
 }
 
+/// Compiles an expression into a procedure that evaluates it. Given an alist of options as well,
+/// returns the procedure paired with an alist describing the compiled code, e.g.
+/// `(compile '(+ 1 2) '((emit-debug-info . #f)))`. The options are:
+///
+/// - `optimization-level`: how hard to try to make the code faster (1 by default, 0 for
+///   no optimization)
+/// - `emit-debug-info`: whether to keep the source file and lines (`#t` by default)
+/// - `target-environment`: where globals are looked up, either `global` or an environment object
+/// - `warnings`: whether to warn about unused variables and unreachable code (`#t` with
+///   `--warnings`)
 pub fn compile<'gc>(
-    _: &VirtualMachine<'gc>,
+    vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let value = stack.read()[1];
-    let result = bootstrap::compile(value, mc)?;
-    Ok(Some(Value::boxed(mc, Object::Function(result))))
+    let (value, alist) = {
+        let args = stack.read();
+        (args[1], args.get(2).copied())
+    };
+    let mut options = CompileOptions {
+        explain: vm.explain().is_some(),
+        warnings: vm.warnings().then(Default::default),
+        ..CompileOptions::default()
+    };
+    let globals = match alist {
+        Some(alist) => parse_compile_options(alist, &mut options)?,
+        None => None,
+    };
+    let target = globals.unwrap_or_else(|| vm.globals());
+    if expand_macros(vm, stack, target, COMPILE_EXPANSION_CONTINUATION, mc)? {
+        return Ok(None);
+    }
+
+    let source = vm.take_source_map();
+    let name = bootstrap::source_name(value, &source, mc);
+    let result = bootstrap::compile_in(
+        value,
+        vm.tables(),
+        source,
+        &options,
+        globals,
+        Some(name),
+        mc,
+    )?;
+    for warning in options.take_warnings() {
+        vm.warn(&warning, mc);
+    }
+    vm.notify_compile(&result);
+    let metadata = alist.map(|_| compile_metadata(vm, &result, &options, mc));
+    let procedure = Value::boxed(mc, Object::Function(result));
+    match metadata {
+        Some(metadata) => Ok(Some(Value::boxed(
+            mc,
+            Object::Pair(ObjPair::new(procedure, metadata)),
+        ))),
+        None => Ok(Some(procedure)),
+    }
+}
+
+/// Reads the options given to `compile`, returning the globals of the target environment if it's
+/// not the VM's own
+fn parse_compile_options<'gc>(
+    alist: Value<'gc>,
+    options: &mut CompileOptions,
+) -> Result<Option<Globals<'gc>>> {
+    let mut globals = None;
+    for entry in list_to_vec(alist)? {
+        let (key, value) = uncons(entry).ok_or_else(|| {
+            InterpretError::RuntimeError(format!("{} is not a compile option", entry))
+        })?;
+        match &*key.as_symbol()?.as_str() {
+            "optimization-level" => {
+                let level = value.as_number()?;
+                if !(0.0..=f64::from(u8::MAX)).contains(&level) || level.fract() != 0.0 {
+                    return Err(InterpretError::RuntimeError(format!(
+                        "{} is not an optimization level",
+                        value
+                    )));
+                }
+                options.optimization_level = level as u8;
+            }
+            "emit-debug-info" => options.debug_info = value.is_truthy(),
+            "warnings" => options.warnings = value.is_truthy().then(Default::default),
+            "target-environment" => match value {
+                Value::Symbol(environment) if &*environment.as_str() == "global" => globals = None,
+                _ => {
+                    let environment = environment_arg(value).map_err(|_| {
+                        InterpretError::RuntimeError(format!(
+                            "Can't compile for the environment {}",
+                            value
+                        ))
+                    })?;
+                    globals = Some(environment);
+                }
+            },
+            key => {
+                return Err(InterpretError::RuntimeError(format!(
+                    "Unknown compile option {}",
+                    key
+                )))
+            }
+        }
+    }
+
+    Ok(globals)
+}
+
+/// Describes compiled code as an alist for `compile`
+fn compile_metadata<'gc>(
+    vm: &VirtualMachine<'gc>,
+    function: &object::ObjFunction<'gc>,
+    options: &CompileOptions,
+    mc: MutationContext<'gc, '_>,
+) -> Value<'gc> {
+    let chunk = function.chunk();
+    let lines = chunk
+        .source_lines()
+        .into_iter()
+        .map(|line| Value::Number(line as f64));
+    let file = match chunk.file() {
+        Some(file) => Value::boxed(mc, Object::String(ObjString::from(&**file))),
+        None => Value::Bool(false),
+    };
+    let entries = vec![
+        ("code-size", Value::Number(chunk.code().len() as f64)),
+        ("constants", Value::Number(chunk.constants().len() as f64)),
+        ("file", file),
+        ("lines", list_from(lines.collect::<Vec<_>>(), mc)),
+        (
+            "optimization-level",
+            Value::Number(f64::from(options.optimization_level)),
+        ),
+        ("emit-debug-info", Value::Bool(options.debug_info)),
+    ];
+
+    let entries = entries.into_iter().map(|(key, value)| {
+        let key = vm.intern_symbol(Token::new(mc, key.into()), mc);
+        Value::boxed(mc, Object::Pair(ObjPair::new(Value::Symbol(key), value)))
+    });
+    list_from(entries.collect::<Vec<_>>(), mc)
 }
 
 pub fn load<'gc>(
     vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    load_file(vm, stack, false, mc)
+}
+
+/// Like `load`, but does nothing if the file has already been loaded
+pub fn load_once<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    load_file(vm, stack, true, mc)
+}
+
+/// Loads a library at most once. A symbol names a feature: nothing is loaded if it has already
+/// been provided, and otherwise `<name>.scm` is looked up on the load path. A string is looked
+/// up on the load path as is.
+pub fn require<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let library = stack.read()[1];
+    let file_name = match library {
+        Value::Symbol(feature) if vm.is_provided(&feature.as_str()) => {
+            return Ok(Some(Value::Void))
+        }
+        Value::Symbol(feature) => format!("{}.scm", feature),
+        Value::String(s) => s.as_str().into_owned(),
+        Value::Box(b) => b.read().as_string()?.as_str().into_owned(),
+        _ => {
+            return Err(InterpretError::RuntimeError(format!(
+                "'{}' is not a library name",
+                library
+            )))
+        }
+    };
+
+    let path = find_on_load_path(vm, &file_name).ok_or_else(|| {
+        InterpretError::RuntimeError(format!("Couldn't find {} on the load path", file_name))
+    })?;
+
+    let path = ObjString::from(path.to_string_lossy().into_owned());
+    *stack.write(mc).last_mut().unwrap() = Value::String(Gc::allocate(mc, path));
+    load_file(vm, stack, true, mc)
+}
+
+/// Directories searched for files: the directory of the file being loaded, then the VM's load
+/// path, then the current directory
+fn load_path(vm: &VirtualMachine<'_>) -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+    if let Some((file, _)) = vm.load_context().into_iter().next() {
+        if let Some(directory) = Path::new(&file).parent() {
+            directories.push(directory.to_path_buf());
+        }
+    }
+    directories.extend(vm.load_path());
+    directories.push(PathBuf::from("."));
+    directories
+}
+
+/// Finds the first file called `file_name` in the directories on the load path
+pub(crate) fn find_on_load_path(vm: &VirtualMachine<'_>, file_name: &str) -> Option<PathBuf> {
+    load_path(vm)
+        .into_iter()
+        .map(|directory| directory.join(file_name))
+        .find(|path| path.is_file())
+}
+
+/// Adds a directory to the front of the load path
+pub fn add_to_load_path<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let directory = string_arg(stack.read()[1])?;
+    vm.add_to_load_path(PathBuf::from(directory));
+    Ok(Some(Value::Void))
+}
+
+/// Announces that the given features are available, so later `require`s of them do nothing
+pub fn provide<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    for feature in &stack.read()[1..] {
+        vm.provide(feature.as_symbol()?.as_str().into_owned());
+    }
+
+    Ok(Some(Value::Void))
+}
+
+pub fn is_provided<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let feature = stack.read()[1].as_symbol()?;
+    Ok(Some(Value::Bool(vm.is_provided(&feature.as_str()))))
+}
+
+fn load_file<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    once: bool,
+    mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
     let file_name = stack.write(mc).pop().unwrap();
-    let file_name = match file_name {
+    let path = match file_name {
         Value::String(s) => s.deref().clone(),
         Value::Box(b) => match &*b.read() {
             Object::String(s) => s.clone(),
@@ -165,19 +523,142 @@ pub fn load<'gc>(
         _ => return Err(InterpretError::RuntimeError("Expected string".into())),
     };
 
+    let path = resolve_load_path(vm, &path.as_str());
+    let mut file = File::open(&path)?;
+    let path = fs::canonicalize(path)?;
+    let canonical_name = path.to_string_lossy().into_owned();
+    if !vm.mark_loaded(path.clone()) && once {
+        return Ok(Some(Value::Void));
+    }
+    check_not_loading(vm, &canonical_name)?;
+
+    // Files written by `compile-file` are run as they are, rather than read and compiled
+    let mut header = Vec::new();
+    (&mut file).take(4).read_to_end(&mut header)?;
+    file.seek(SeekFrom::Start(0))?;
+    let reader = if serialize::is_program(&header) {
+        let thunks = serialize::deserialize_program(vm, &fs::read(&path)?, mc)?;
+        for thunk in &thunks {
+            if let Object::Function(function) = &*thunk.as_object()?.read() {
+                vm.notify_compile(function);
+            }
+        }
+        list_from(thunks, mc)
+    } else {
+        let port = ObjReadPort::new(file).with_source(PortSource::File(path));
+        Value::boxed(mc, Object::ReadPort(port))
+    };
+    start_loading(vm, stack, reader, canonical_name, Value::Bool(false), mc)
+}
+
+/// Compiles a file without running it, writing the compiled code to another file that `load`
+/// can run instead. `define-syntax` forms are run as they're compiled, so the rest of the file
+/// can use the macros they define, but any other macros have to be defined beforehand.
+pub fn compile_file<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let output = stack.write(mc).pop().unwrap();
+    let input = stack.write(mc).pop().unwrap();
+    string_arg(output)?;
+    let path = resolve_load_path(vm, &string_arg(input)?);
+    let file = File::open(&path)?;
+    let path = fs::canonicalize(path)?;
+    let canonical_name = path.to_string_lossy().into_owned();
+    check_not_loading(vm, &canonical_name)?;
+
+    let port = ObjReadPort::new(file).with_source(PortSource::File(path));
+    let reader = Value::boxed(mc, Object::ReadPort(port));
+    // The thunks compiled so far are kept in reverse, along with where to write them
+    let compiled = Value::boxed(mc, Object::Pair(ObjPair::new(output, Value::Null)));
+    start_loading(vm, stack, reader, canonical_name, compiled, mc)
+}
+
+/// Looks a relative path that doesn't name a file up on the load path
+fn resolve_load_path(vm: &VirtualMachine<'_>, path: &str) -> PathBuf {
+    let path = Path::new(path).to_path_buf();
+    if path.is_relative() && !path.exists() {
+        find_on_load_path(vm, &path.to_string_lossy()).unwrap_or(path)
+    } else {
+        path
+    }
+}
+
+fn check_not_loading(vm: &VirtualMachine<'_>, canonical_name: &str) -> Result<()> {
+    if vm
+        .load_context()
+        .iter()
+        .any(|(loading, _)| *loading == canonical_name)
+    {
+        return Err(InterpretError::RuntimeError(format!(
+            "Recursive load of {}",
+            canonical_name
+        )));
+    }
+    Ok(())
+}
+
+/// Starts the load thunk chain, which reads each form from `reader`, compiles it and runs it in
+/// turn. `reader` can also be a list of thunks that were compiled already, which are just run.
+/// When `compiled` isn't `#f`, it's where `compile-file` collects the compiled thunks instead
+/// of running them.
+fn start_loading<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    reader: Value<'gc>,
+    file_name: String,
+    compiled: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
     let loader = Value::boxed(
         mc,
-        Object::Native(ObjNative::new(1, false, load_read_thunk, None)),
+        Object::Native(ObjNative::registered(4, false, LOAD_READ_THUNK, None)),
     );
     stack.write(mc).push(loader);
+    stack.write(mc).push(reader);
+    let file_name = Gc::allocate(mc, ObjString::from(file_name));
+    stack.write(mc).push(Value::String(file_name));
+    stack.write(mc).push(Value::Number(1f64));
+    stack.write(mc).push(compiled);
+
+    vm.tail_call_value(loader, stack, 4, mc)?;
+    Ok(None)
+}
+
+/// Records which file and form the load thunk chain is working on, so errors can report it.
+/// Expects the file name and form index at `stack[2]` and `stack[3]`.
+fn enter_load_form<'gc>(vm: &VirtualMachine<'gc>, stack: Stack<'gc>, mc: MutationContext<'gc, '_>) {
+    let form = {
+        let args = stack.read();
+        Value::boxed(mc, Object::Pair(ObjPair::new(args[2], args[3])))
+    };
+    let loading = *vm.loading().read();
+    *vm.loading().write(mc) = Value::boxed(mc, Object::Pair(ObjPair::new(form, loading)));
+}
 
-    let reader = Value::boxed(
+/// Tail calls the load thunk chain again to read the next form
+fn load_next_form<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    form: f64,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let loader = Value::boxed(
         mc,
-        Object::ReadPort(ObjReadPort::new(File::open(file_name.as_str().as_ref())?)),
+        Object::Native(ObjNative::registered(4, false, LOAD_READ_THUNK, None)),
     );
+    let (reader, file_name, compiled) = {
+        let args = stack.read();
+        (args[1], args[2], args[4])
+    };
+    stack.write(mc).push(loader);
     stack.write(mc).push(reader);
+    stack.write(mc).push(file_name);
+    stack.write(mc).push(Value::Number(form));
+    stack.write(mc).push(compiled);
 
-    vm.tail_call_value(loader, stack, 1, mc)?;
+    vm.tail_call_value(loader, stack, 4, mc)?;
     Ok(None)
 }
 
@@ -186,11 +667,21 @@ fn load_read_thunk<'gc>(
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let top = peek(stack, 0);
+    let reader = stack.read()[1];
+
+    // Thunks that were compiled already are run one after the other
+    if reader.is_null() {
+        return finish_loading(vm, Value::Eof, mc);
+    }
+    if let Some((thunk, rest)) = uncons(reader) {
+        stack.write(mc)[1] = rest;
+        stack.write(mc).push(thunk);
+        return load_eval_thunk(vm, stack, mc);
+    }
 
     // Write the procedure that should pick up execution after this procedure call finishes
     *vm.procedure().write(mc) =
-        Procedure::Native(ObjNative::new(1, false, load_compile_thunk, None));
+        Procedure::Native(ObjNative::registered(1, false, LOAD_COMPILE_THUNK, None));
 
     let read = Value::boxed(
         mc,
@@ -202,9 +693,10 @@ fn load_read_thunk<'gc>(
         )),
     );
     stack.write(mc).push(read);
-    stack.write(mc).push(top);
+    stack.write(mc).push(reader);
 
     vm.call_value(read, stack, 1, mc)?;
+    enter_load_form(vm, stack, mc);
     Ok(None)
 }
 
@@ -215,39 +707,33 @@ fn load_compile_thunk<'gc>(
 ) -> Result<Option<Value<'gc>>> {
     let result = peek(stack, 0);
     if result.is_null() {
-        let loader = Value::boxed(
-            mc,
-            Object::Native(ObjNative::new(1, false, load_read_thunk, None)),
-        );
-        let reader = stack.read()[1];
-        stack.write(mc).push(loader);
-        stack.write(mc).push(reader);
-
-        vm.tail_call_value(loader, stack, 1, mc)?;
-        return Ok(None);
+        let form = stack.read()[3].as_number()?;
+        return load_next_form(vm, stack, form, mc);
     }
 
     let result = car(result).unwrap();
+    let compiled = stack.read()[4];
     if result.is_eof() {
-        let frame = *vm.parent_continuation().read();
-        if let Some(frame) = frame {
-            vm.apply_continuation(frame, mc);
-            vm.push_stack(result, mc);
-            return Ok(None);
-        } else {
-            std::process::exit(0);
+        if !compiled.is_falsey() {
+            write_compiled(vm, compiled)?;
         }
+        return finish_loading(vm, result, mc);
     }
 
     // Write the procedure that should pick up execution after this procedure call finishes
-    *vm.procedure().write(mc) = Procedure::Native(ObjNative::new(1, false, load_eval_thunk, None));
+    let next = match compiled {
+        Value::Bool(false) => LOAD_EVAL_THUNK,
+        _ if is_syntax_definition(result) => COMPILE_FILE_EVAL_THUNK,
+        _ => COMPILE_FILE_SAVE_THUNK,
+    };
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(1, false, next, None));
 
     let compile = Value::boxed(
         mc,
-        Object::Native(ObjNative::new(
+        Object::Native(ObjNative::registered(
             1,
             false,
-            compile,
+            ("compile", compile),
             Some(Symbol::uninterned(Token::new(
                 mc,
                 ObjString::from("compile"),
@@ -258,6 +744,7 @@ fn load_compile_thunk<'gc>(
     stack.write(mc).push(result);
 
     vm.call_value(compile, stack, 1, mc)?;
+    enter_load_form(vm, stack, mc);
     Ok(None)
 }
 
@@ -269,42 +756,119 @@ fn load_eval_thunk<'gc>(
     let eval = peek(stack, 0);
 
     // Write the procedure that should pick up execution after this procedure call finishes
-    *vm.procedure().write(mc) =
-        Procedure::Native(ObjNative::new(2, false, load_eval_continuation_thunk, None));
+    *vm.procedure().write(mc) = Procedure::Native(ObjNative::registered(
+        2,
+        false,
+        LOAD_EVAL_CONTINUATION_THUNK,
+        None,
+    ));
 
     vm.call_value(eval, stack, 0, mc)?;
+    enter_load_form(vm, stack, mc);
     Ok(None)
 }
 
+/// Keeps the thunk `compile-file` just compiled, and moves on to the next form without running it
+fn compile_file_save_thunk<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let thunk = stack.write(mc).pop().unwrap();
+    save_compiled(stack, thunk, mc)?;
+    let form = stack.read()[3].as_number()?;
+    load_next_form(vm, stack, form + 1f64, mc)
+}
+
+/// Keeps the thunk `compile-file` just compiled from a syntax definition, and runs it so that
+/// the macro can be used by the forms after it
+fn compile_file_eval_thunk<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    save_compiled(stack, peek(stack, 0), mc)?;
+    load_eval_thunk(vm, stack, mc)
+}
+
+fn save_compiled<'gc>(
+    stack: Stack<'gc>,
+    thunk: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let compiled = stack.read()[4].as_object()?;
+    let mut compiled = borrow_mut(&compiled, mc)?;
+    let compiled = compiled.as_pair_mut()?;
+    let saved = Value::boxed(mc, Object::Pair(ObjPair::new(thunk, compiled.cdr())));
+    compiled.set_cdr(saved);
+    Ok(())
+}
+
+/// Writes out the thunks `compile-file` compiled, to the file it was asked to
+fn write_compiled<'gc>(vm: &VirtualMachine<'gc>, compiled: Value<'gc>) -> Result<()> {
+    let (output, thunks) = {
+        let compiled = compiled.as_object()?;
+        let compiled = compiled.read();
+        let compiled = compiled.as_pair()?;
+        (string_arg(compiled.car())?, list_to_vec(compiled.cdr())?)
+    };
+    let thunks: Vec<_> = thunks.into_iter().rev().collect();
+    fs::write(output, serialize::serialize_program(vm, &thunks)?)?;
+    Ok(())
+}
+
+/// Whether `form` is a `define-syntax`
+fn is_syntax_definition(form: Value<'_>) -> bool {
+    match car(form) {
+        Some(Value::Symbol(keyword)) => keyword.as_str() == "define-syntax",
+        _ => false,
+    }
+}
+
+/// Returns from the load thunk chain with `result`, or exits if nothing called it
+fn finish_loading<'gc>(
+    vm: &VirtualMachine<'gc>,
+    result: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    if vm.resume_caller(mc) {
+        vm.push_stack(result, mc);
+        Ok(None)
+    } else {
+        std::process::exit(0);
+    }
+}
+
 fn load_eval_continuation_thunk<'gc>(
     vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
     let _ = stack.write(mc).pop();
-    let reader = stack.read()[1];
-
-    // Write the procedure that should pick up execution after this procedure call finishes
-    let loader = Value::boxed(
-        mc,
-        Object::Native(ObjNative::new(1, false, load_read_thunk, None)),
-    );
-    stack.write(mc).push(loader);
-    stack.write(mc).push(reader);
+    let form = stack.read()[3].as_number()?;
+    load_next_form(vm, stack, form + 1f64, mc)
+}
 
-    vm.tail_call_value(loader, stack, 1, mc)?;
+/// Root continuation of a VM: once it's reached, the program has finished
+fn halt<'gc>(
+    vm: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    vm.halt();
     Ok(None)
 }
 
 pub fn exit<'gc>(
-    _: &VirtualMachine<'gc>,
+    vm: &VirtualMachine<'gc>,
     _: Stack<'gc>,
     _: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
     // let exit_code = stack.write(mc).pop()
     //     .map(|v| v.as_number().map(|num| num as i32))
     //     .unwrap_or(Ok(0))?;
-    std::process::exit(0);
+    vm.halt();
+    Ok(None)
 }
 
 pub fn disassemble<'gc>(
@@ -0,0 +1,265 @@
+use gc_arena::MutationContext;
+
+use super::{call_step, list_from, list_to_vec, mutable_vector, uncons};
+use crate::object::{ObjNative, ObjPair, ObjVector, Object};
+use crate::value::{TypeError, Value};
+use crate::vm::{borrow_mut, Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    SORT_STEP => "%sort-step", sort_step;
+    SORT_CONTINUATION => "%sort-continuation", sort_continuation;
+}
+
+/// Sorts a list or vector with a `less?` procedure, returning a new sequence of the same kind.
+/// This is a stable merge sort, so elements that compare equal keep their original order.
+pub fn sort<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (sequence, less) = {
+        let args = stack.read();
+        (args[1], args[2])
+    };
+    let (elements, is_vector) = match sequence {
+        Value::Vector(vector) => (vector.as_slice().iter().map(|&e| e.into()).collect(), true),
+        Value::Box(object) if object.read().is_vector() => {
+            (object.read().as_vector()?.as_slice().to_vec(), true)
+        }
+        _ => (
+            list_to_vec(sequence).map_err(|_| not_a_sequence(sequence))?,
+            false,
+        ),
+    };
+
+    start_sort(vm, stack, elements, less, Value::Bool(is_vector), mc)
+}
+
+/// Sorts a mutable vector in place with a `less?` procedure, using the same stable merge sort as
+/// `sort`. The vector isn't changed until the sort is finished.
+pub fn vector_sort<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (vector, less) = {
+        let args = stack.read();
+        (args[1], args[2])
+    };
+    let cell = mutable_vector(vector)?;
+    let elements = cell.read().as_vector()?.as_slice().to_vec();
+
+    start_sort(vm, stack, elements, less, vector, mc)
+}
+
+fn start_sort<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    elements: Vec<Value<'gc>>,
+    less: Value<'gc>,
+    into: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    // Start out with every element in a run of its own
+    let runs = elements
+        .into_iter()
+        .map(|element| list_from(vec![element], mc))
+        .collect::<Vec<_>>();
+    let mut merge = Merge {
+        less,
+        into,
+        todo: list_from(runs, mc),
+        done: Value::Null,
+        left: Value::Null,
+        right: Value::Null,
+        merged: Value::Null,
+    };
+
+    match merge.next_pair(mc) {
+        Some(sorted) => Ok(Some(merge.finish(sorted, mc)?)),
+        None => call_step(vm, stack, SORT_STEP, merge.into_args(), mc),
+    }
+}
+
+/// The state of a bottom-up merge sort, passed from step to step as arguments so that
+/// re-entering a continuation captured by `less?` carries on from the right place
+struct Merge<'gc> {
+    less: Value<'gc>,
+
+    /// What to do with the sorted elements: `#f` to return them as a list, `#t` as a new vector,
+    /// or a mutable vector to put them back into
+    into: Value<'gc>,
+
+    /// Sorted runs that still have to be merged on this pass
+    todo: Value<'gc>,
+
+    /// Runs merged on this pass, most recent first
+    done: Value<'gc>,
+
+    /// The two runs being merged
+    left: Value<'gc>,
+    right: Value<'gc>,
+
+    /// The elements taken from `left` and `right` so far, most recent first
+    merged: Value<'gc>,
+}
+
+impl<'gc> Merge<'gc> {
+    fn from_args(args: &[Value<'gc>]) -> Self {
+        Self {
+            less: args[0],
+            into: args[1],
+            todo: args[2],
+            done: args[3],
+            left: args[4],
+            right: args[5],
+            merged: args[6],
+        }
+    }
+
+    fn into_args(self) -> Vec<Value<'gc>> {
+        vec![
+            self.less,
+            self.into,
+            self.todo,
+            self.done,
+            self.left,
+            self.right,
+            self.merged,
+        ]
+    }
+
+    /// Sets up the next two runs to merge, starting a new pass when this one is over. Returns
+    /// the sorted elements instead once there's only one run left.
+    fn next_pair(&mut self, mc: MutationContext<'gc, '_>) -> Option<Value<'gc>> {
+        loop {
+            match uncons(self.todo) {
+                Some((left, rest)) => match uncons(rest) {
+                    Some((right, rest)) => {
+                        self.left = left;
+                        self.right = right;
+                        self.merged = Value::Null;
+                        self.todo = rest;
+                        return None;
+                    }
+                    None => {
+                        self.done = cons(left, self.done, mc);
+                        self.todo = Value::Null;
+                    }
+                },
+                None => match uncons(self.done) {
+                    None => return Some(Value::Null),
+                    Some((run, Value::Null)) => return Some(run),
+                    Some(_) => {
+                        self.todo = append_reverse(self.done, Value::Null, mc);
+                        self.done = Value::Null;
+                    }
+                },
+            }
+        }
+    }
+
+    fn finish(&self, sorted: Value<'gc>, mc: MutationContext<'gc, '_>) -> Result<Value<'gc>> {
+        match self.into {
+            Value::Box(vector) => {
+                let elements = list_to_vec(sorted)?;
+                borrow_mut(&vector, mc)?
+                    .as_vector_mut()?
+                    .as_slice_mut()
+                    .copy_from_slice(&elements);
+                Ok(Value::Void)
+            }
+            Value::Bool(true) => {
+                let elements = list_to_vec(sorted)?;
+                Ok(Value::boxed(
+                    mc,
+                    Object::Vector(ObjVector::new(elements.into_boxed_slice())),
+                ))
+            }
+            _ => Ok(sorted),
+        }
+    }
+}
+
+/// Calls `less?` on the next two elements to merge, or moves on to the next two runs when one
+/// of them has run out. Expects the stack to look like
+/// `[step, less, into, todo, done, left, right, merged]`.
+fn sort_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut merge = Merge::from_args(&stack.read()[1..]);
+    if let (Some((left, _)), Some((right, _))) = (uncons(merge.left), uncons(merge.right)) {
+        // Write the procedure that should pick up execution after this procedure call finishes
+        *vm.procedure().write(mc) =
+            Procedure::Native(ObjNative::registered(1, false, SORT_CONTINUATION, None));
+
+        // Only take from the right run when it's strictly less, to keep the sort stable
+        stack.write(mc).push(merge.less);
+        stack.write(mc).push(right);
+        stack.write(mc).push(left);
+        vm.call_value(merge.less, stack, 2, mc)?;
+        return Ok(None);
+    }
+
+    let rest = if merge.left.is_null() {
+        merge.right
+    } else {
+        merge.left
+    };
+    let run = append_reverse(merge.merged, rest, mc);
+    merge.done = cons(run, merge.done, mc);
+    match merge.next_pair(mc) {
+        Some(sorted) => Ok(Some(merge.finish(sorted, mc)?)),
+        None => call_step(vm, stack, SORT_STEP, merge.into_args(), mc),
+    }
+}
+
+fn sort_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (mut merge, right_first) = {
+        let args = stack.read();
+        let len = args.len();
+        (
+            Merge::from_args(&args[1..len - 1]),
+            args[len - 1].is_truthy(),
+        )
+    };
+
+    let taken = if right_first {
+        &mut merge.right
+    } else {
+        &mut merge.left
+    };
+    let (element, rest) = uncons(*taken).unwrap();
+    *taken = rest;
+    merge.merged = cons(element, merge.merged, mc);
+    call_step(vm, stack, SORT_STEP, merge.into_args(), mc)
+}
+
+fn cons<'gc>(car: Value<'gc>, cdr: Value<'gc>, mc: MutationContext<'gc, '_>) -> Value<'gc> {
+    Value::boxed(mc, Object::Pair(ObjPair::new(car, cdr)))
+}
+
+/// Conses the elements of `list` onto `tail` in reverse order
+fn append_reverse<'gc>(
+    list: Value<'gc>,
+    tail: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Value<'gc> {
+    let mut result = tail;
+    let mut remaining = list;
+    while let Some((element, rest)) = uncons(remaining) {
+        result = cons(element, result, mc);
+        remaining = rest;
+    }
+    result
+}
+
+fn not_a_sequence(value: Value<'_>) -> TypeError {
+    TypeError(format!("'{}' is not a list or vector", value))
+}
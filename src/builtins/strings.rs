@@ -1,9 +1,10 @@
 use gc_arena::MutationContext;
 
-use crate::memory::{Symbol, Token};
+use super::{checked_index, list_from, list_to_vec};
+use crate::memory::Token;
 use crate::object::{ObjString, Object};
-use crate::value::{TypeError, Value};
-use crate::vm::{Result, Stack, VirtualMachine};
+use crate::value::{Char, DisplayStyle, Print, TypeError, Value};
+use crate::vm::{borrow_mut, InterpretError, Result, Stack, VirtualMachine};
 
 pub fn is_string<'gc>(
     _: &VirtualMachine<'gc>,
@@ -18,23 +19,24 @@ pub fn is_string<'gc>(
     }
 }
 
-/// Creates an uninterned symbol
+/// Gets the interned symbol with the given name, which is the same symbol a literal with that
+/// name reads as
 pub fn string_to_symbol<'gc>(
-    _: &VirtualMachine<'gc>,
+    vm: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
     let string = stack.read()[1];
-    let symbol = match string {
-        Value::String(s) => Symbol::uninterned(Token::from(s)),
+    let token = match string {
+        Value::String(s) => Token::from(s),
         Value::Box(b) => {
             let string = b.read();
-            Symbol::uninterned(Token::new(mc, string.as_string()?.clone()))
+            Token::new(mc, string.as_string()?.clone())
         }
         _ => return Err(TypeError(format!("'{}' is not a string", string)).into()),
     };
 
-    Ok(Some(Value::Symbol(symbol)))
+    Ok(Some(Value::Symbol(vm.intern_symbol(token, mc))))
 }
 
 pub fn string_length<'gc>(
@@ -44,15 +46,32 @@ pub fn string_length<'gc>(
 ) -> Result<Option<Value<'gc>>> {
     let string = stack.read()[1];
     let length = match string {
-        Value::String(s) => s.as_str().chars().count(),
+        Value::String(s) => s.char_count(),
+        Value::Box(b) => b.read().as_string()?.char_count(),
+        _ => return Err(TypeError(format!("'{}' is not a string", string)).into()),
+    };
+
+    Ok(Some(Value::Number(length as f64)))
+}
+
+pub fn string_ref<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let character = match args[1] {
+        Value::String(s) => s.char_at(checked_index(args[2], s.char_count(), "string")?),
         Value::Box(b) => {
             let string = b.read();
-            string.as_string()?.as_str().chars().count()
+            let string = string.as_string()?;
+            string.char_at(checked_index(args[2], string.char_count(), "string")?)
         }
-        _ => return Err(TypeError(format!("'{}' is not a string", string)).into()),
+        string => return Err(TypeError(format!("'{}' is not a string", string)).into()),
     };
 
-    Ok(Some(Value::Number(length as f64)))
+    // Any index below the string's length is one of its characters
+    Ok(Some(Value::Char(Char(character.unwrap()))))
 }
 
 pub fn make_string<'gc>(
@@ -81,3 +100,222 @@ pub fn make_string<'gc>(
         Object::String(ObjString::new(chars)),
     )))
 }
+
+pub fn string_upcase<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let string = string_arg(stack.read()[1])?;
+    Ok(Some(Value::boxed(
+        mc,
+        Object::String(ObjString::from(string.to_uppercase())),
+    )))
+}
+
+pub fn string_downcase<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let string = string_arg(stack.read()[1])?;
+    Ok(Some(Value::boxed(
+        mc,
+        Object::String(ObjString::from(string.to_lowercase())),
+    )))
+}
+
+/// Approximates full Unicode case folding by upcasing and then downcasing, which folds
+/// multi-character expansions like ß -> ss and final sigma -> σ
+pub fn string_foldcase<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let string = string_arg(stack.read()[1])?;
+    Ok(Some(Value::boxed(
+        mc,
+        Object::String(ObjString::from(
+            string.to_uppercase().to_lowercase().replace('ς', "σ"),
+        )),
+    )))
+}
+
+/// Splits a string on a delimiter character or string (extension)
+///
+/// Without a delimiter, splits on runs of whitespace and drops empty fields.
+pub fn string_split<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let string = string_arg(args[1])?;
+    let fields: Vec<_> = match args.get(2) {
+        None => string.split_whitespace().collect(),
+        Some(Value::Char(c)) => string.split(c.0).collect(),
+        Some(delimiter) => {
+            let delimiter = string_arg(*delimiter)?;
+            if delimiter.is_empty() {
+                return Err(InterpretError::RuntimeError(
+                    "Can't split on an empty delimiter".into(),
+                ));
+            }
+            string.split(delimiter.as_str()).collect()
+        }
+    };
+
+    let fields = fields
+        .into_iter()
+        .map(|field| Value::boxed(mc, Object::String(ObjString::from(field))));
+    Ok(Some(list_from(fields.collect::<Vec<_>>(), mc)))
+}
+
+/// Concatenates a list of strings, separated by a delimiter (a single space by default)
+/// (extension)
+pub fn string_join<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let strings = list_to_vec(args[1])?
+        .into_iter()
+        .map(string_arg)
+        .collect::<Result<Vec<_>>>()?;
+    let delimiter = match args.get(2) {
+        None => " ".to_string(),
+        Some(Value::Char(c)) => c.0.to_string(),
+        Some(delimiter) => string_arg(*delimiter)?,
+    };
+
+    Ok(Some(Value::boxed(
+        mc,
+        Object::String(ObjString::from(strings.join(&delimiter))),
+    )))
+}
+
+/// Removes leading and trailing whitespace from a string (extension)
+pub fn string_trim<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let string = string_arg(stack.read()[1])?;
+    Ok(Some(Value::boxed(
+        mc,
+        Object::String(ObjString::from(string.trim())),
+    )))
+}
+
+/// Finds the character index of the first occurrence of a substring, or `#f` if it doesn't
+/// occur (extension)
+pub fn string_contains<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let string = string_arg(args[1])?;
+    let pattern = string_arg(args[2])?;
+    let result = match string.find(pattern.as_str()) {
+        Some(offset) => Value::Number(string[..offset].chars().count() as f64),
+        None => Value::Bool(false),
+    };
+
+    Ok(Some(result))
+}
+
+/// Formats its arguments according to a SRFI-28 style template. The result is returned as a
+/// string when the destination is `#f`, and otherwise written to the given port (or the current
+/// output port for `#t`).
+pub fn format<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let template = string_arg(args[2])?;
+    let mut values = args[3..].iter();
+    let mut next_value = || {
+        values.next().copied().ok_or_else(|| {
+            InterpretError::RuntimeError(format!(
+                "Not enough arguments for format string \"{}\"",
+                template
+            ))
+        })
+    };
+
+    let mut output = String::new();
+    let mut chars = template.chars();
+    while let Some(character) = chars.next() {
+        if character != '~' {
+            output.push(character);
+            continue;
+        }
+
+        match chars.next() {
+            Some('a') | Some('A') => {
+                let value = next_value()?;
+                output.push_str(&value.styled(DisplayStyle::Display).to_string());
+            }
+            Some('s') | Some('S') => {
+                let value = next_value()?;
+                output.push_str(&value.styled(DisplayStyle::Write).to_string());
+            }
+            Some('d') | Some('D') => {
+                let number = next_value()?.as_number()?;
+                output.push_str(&Value::Number(number).to_string());
+            }
+            Some('%') => output.push('\n'),
+            Some('~') => output.push('~'),
+            Some(directive) => {
+                return Err(InterpretError::RuntimeError(format!(
+                    "Unknown format directive '~{}'",
+                    directive
+                )))
+            }
+            None => {
+                return Err(InterpretError::RuntimeError(
+                    "Format string ends in the middle of a directive".to_string(),
+                ))
+            }
+        }
+    }
+
+    if values.next().is_some() {
+        return Err(InterpretError::RuntimeError(format!(
+            "Too many arguments for format string \"{}\"",
+            template
+        )));
+    }
+
+    match args[1] {
+        Value::Bool(false) => Ok(Some(Value::boxed(
+            mc,
+            Object::String(ObjString::from(output)),
+        ))),
+        Value::Bool(true) => {
+            let port = *vm.current_output_port().read();
+            borrow_mut(&port, mc)?
+                .as_write_port_mut()?
+                .write_str(&output)?;
+            Ok(Some(Value::Void))
+        }
+        port => {
+            borrow_mut(&port.as_object()?, mc)?
+                .as_write_port_mut()?
+                .write_str(&output)?;
+            Ok(Some(Value::Void))
+        }
+    }
+}
+
+/// Copies the contents of a (const or boxed) string argument
+pub(crate) fn string_arg(string: Value<'_>) -> Result<String> {
+    match string {
+        Value::String(s) => Ok(s.as_str().into_owned()),
+        Value::Box(b) => Ok(b.read().as_string()?.as_str().into_owned()),
+        _ => Err(TypeError(format!("'{}' is not a string", string)).into()),
+    }
+}
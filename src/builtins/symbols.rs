@@ -2,8 +2,10 @@ use std::ops::Deref;
 
 use gc_arena::{GcCell, MutationContext};
 
-use crate::object::Object;
-use crate::value::Value;
+use super::list_from;
+use crate::memory::{Symbol, Token};
+use crate::object::{ObjString, Object};
+use crate::value::{TypeError, Value};
 use crate::vm::{Result, Stack, VirtualMachine};
 
 pub fn symbol_to_string<'gc>(
@@ -26,3 +28,73 @@ pub fn is_symbol<'gc>(
     let args = stack.read();
     Ok(Some(Value::Bool(args[1].is_symbol())))
 }
+
+/// Checks whether all of its arguments are the same symbol
+pub fn is_symbol_eq<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let symbols = symbol_args(stack)?;
+    Ok(Some(Value::Bool(
+        symbols.windows(2).all(|pair| pair[0] == pair[1]),
+    )))
+}
+
+/// Checks whether the names of its arguments are in strictly increasing order, comparing them a
+/// character at a time like `string<?`
+pub fn is_symbol_lt<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let symbols = symbol_args(stack)?;
+    Ok(Some(Value::Bool(
+        symbols
+            .windows(2)
+            .all(|pair| pair[0].as_bytes() < pair[1].as_bytes()),
+    )))
+}
+
+/// Gets every argument as a symbol, so that a non-symbol is an error even if the comparison
+/// could be decided without looking at it
+fn symbol_args(stack: Stack<'_>) -> Result<Vec<Symbol<'_>>> {
+    let args = stack.read();
+    let mut symbols = Vec::with_capacity(args.len() - 1);
+    for arg in &args[1..] {
+        symbols.push(arg.as_symbol()?);
+    }
+    Ok(symbols)
+}
+
+/// Lists the symbols bound in the global environment, in sorted order
+pub fn global_bindings<'gc>(
+    vm: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let names = vm.global_names().into_iter().map(Value::Symbol);
+    Ok(Some(list_from(names, mc)))
+}
+
+/// Makes a fresh uninterned symbol, which isn't `eq?` to any other symbol even if it's printed
+/// the same way. Its name is a prefix (`g` unless a string or symbol is given) and a number.
+pub fn gensym<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let prefix = match stack.read().get(1) {
+        None => "g".to_string(),
+        Some(Value::Symbol(symbol)) => symbol.as_str().into_owned(),
+        Some(Value::String(string)) => string.as_str().into_owned(),
+        Some(Value::Box(b)) => b.read().as_string()?.as_str().into_owned(),
+        Some(prefix) => {
+            return Err(TypeError(format!("'{}' is not a string or symbol", prefix)).into())
+        }
+    };
+
+    let name = format!("{}{}", prefix, vm.next_gensym());
+    let symbol = Symbol::uninterned(Token::new(mc, ObjString::from(name)));
+    Ok(Some(Value::Symbol(symbol)))
+}
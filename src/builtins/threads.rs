@@ -0,0 +1,157 @@
+//! Green threads and the channels they talk over. See the `threads` module of the VM for how
+//! they're scheduled.
+
+use gc_arena::MutationContext;
+
+use crate::object::{ObjNative, ObjPair, Object};
+use crate::value::Value;
+use crate::vm::{Procedure, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    THREAD_START => "%thread-start", thread_start;
+    THREAD_EXIT => "%thread-exit", thread_exit;
+    THREAD_FAIL => "%thread-fail", thread_fail;
+    THREAD_JOINED => "%thread-joined", thread_joined;
+}
+
+/// `(spawn thunk)` makes a thread that calls `thunk` once the running thread yields or waits
+pub fn spawn<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let thunk = stack.read()[1];
+    Ok(Some(vm.spawn_thread(thunk, mc)))
+}
+
+/// `(yield)` lets the other threads that are ready run before carrying on
+pub fn yield_thread<'gc>(
+    vm: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    vm.yield_thread(mc)?;
+    Ok(None)
+}
+
+/// `(thread-join! thread)` waits for a thread to finish, and returns what its thunk returned. If
+/// the thread raised something it didn't handle instead, `thread-join!` raises it.
+pub fn thread_join<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let thread = stack.read()[1];
+    vm.join_thread(thread, stack, mc)
+}
+
+pub fn is_thread<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let value = stack.read()[1];
+    Ok(Some(Value::Bool(vm.is_thread(value, mc))))
+}
+
+pub fn make_channel<'gc>(
+    vm: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(vm.make_channel(mc)))
+}
+
+pub fn is_channel<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let value = stack.read()[1];
+    Ok(Some(Value::Bool(vm.is_channel(value, mc))))
+}
+
+/// `(channel-send! channel obj)` sends a value on a channel without waiting for it to be received
+pub fn channel_send<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (channel, value) = {
+        let args = stack.read();
+        (args[1], args[2])
+    };
+    vm.channel_send(channel, value, mc)?;
+    Ok(Some(Value::Void))
+}
+
+/// `(channel-receive channel)` takes the oldest value sent on a channel, waiting for one to be
+/// sent if there isn't one yet
+pub fn channel_receive<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let channel = stack.read()[1];
+    vm.channel_receive(channel, mc)
+}
+
+/// Starts a thread off, from the frame `spawn` made for it with the thread and its thunk on the
+/// stack
+fn thread_start<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    // Write the procedure that should pick up execution after the thunk returns
+    *vm.procedure().write(mc) =
+        Procedure::Native(ObjNative::registered(0, false, THREAD_EXIT, None));
+    let thunk = stack.read()[2];
+    stack.write(mc).push(thunk);
+    vm.call_value(thunk, stack, 0, mc)?;
+
+    // Anything raised and not handled in the thread finishes it. Installed after the call, like
+    // `with-exception-handler` does, so that the frame finishing the thread doesn't run with it.
+    let handler = Value::boxed(
+        mc,
+        Object::Native(ObjNative::registered(1, false, THREAD_FAIL, None)),
+    );
+    let handlers = *vm.handlers().read();
+    *vm.handlers().write(mc) = Value::boxed(mc, Object::Pair(ObjPair::new(handler, handlers)));
+    Ok(None)
+}
+
+fn thread_exit<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let (thread, result) = {
+        let args = stack.read();
+        (args[1], args[4])
+    };
+    vm.finish_thread(thread, result, false, mc)?;
+    Ok(None)
+}
+
+/// Exception handler a thread's thunk runs with, which finishes the thread with what was raised
+fn thread_fail<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let obj = stack.read()[1];
+    vm.fail_thread(obj, mc)?;
+    Ok(None)
+}
+
+/// Picks a thread that was waiting in `thread-join!` back up once the thread it joined has
+/// finished, which is on the stack
+fn thread_joined<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let thread = stack.read()[1];
+    vm.join_thread(thread, stack, mc)
+}
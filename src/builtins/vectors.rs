@@ -1,8 +1,18 @@
-use gc_arena::MutationContext;
+use std::ops::Range;
 
-use crate::object::{ObjVector, Object};
+use gc_arena::{GcCell, MutationContext};
+
+use super::{call_on_next, call_step, list_from, list_to_vec};
+use crate::object::{ObjPair, ObjVector, Object, RegisteredNative};
 use crate::value::{TypeError, Value};
-use crate::vm::{InterpretError, Result, Stack, VirtualMachine};
+use crate::vm::{borrow_mut, InterpretError, Result, Stack, VirtualMachine};
+
+internal_natives! {
+    VECTOR_MAP_STEP => "%vector-map-step", vector_map_step;
+    VECTOR_MAP_CONTINUATION => "%vector-map-continuation", vector_map_continuation;
+    VECTOR_FOR_EACH_STEP => "%vector-for-each-step", vector_for_each_step;
+    VECTOR_FOR_EACH_CONTINUATION => "%vector-for-each-continuation", vector_for_each_continuation;
+}
 
 pub fn is_vector<'gc>(
     _: &VirtualMachine<'gc>,
@@ -56,20 +66,29 @@ pub fn vector_length<'gc>(
     Ok(Some(Value::Number(length as f64)))
 }
 
+/// Gets an element of a vector. Elements of constant vectors are converted to the matching
+/// constant values, which share the element's allocation, so fetching the same element twice
+/// gives results that are `eqv?` just like they are for mutable vectors.
 pub fn vector_ref<'gc>(
     _: &VirtualMachine<'gc>,
     stack: Stack<'gc>,
     _: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let vector = stack.read()[1];
-    let offset = stack.read()[2].as_number()? as usize;
+    let (vector, index) = {
+        let args = stack.read();
+        (args[1], args[2])
+    };
     let value = match vector {
-        Value::Vector(v) => Value::from(v.as_slice()[offset]),
+        Value::Vector(v) => {
+            let elements = v.as_slice();
+            Value::from(elements[checked_index(index, elements.len(), "vector")?])
+        }
         Value::Box(b) => {
             let vector = b.read();
-            vector.as_vector()?.as_slice()[offset]
+            let elements = vector.as_vector()?.as_slice();
+            elements[checked_index(index, elements.len(), "vector")?]
         }
-        _ => return Err(TypeError(format!("'{}' is not a string", vector)).into()),
+        _ => return Err(TypeError(format!("'{}' is not a vector", vector)).into()),
     };
 
     Ok(Some(value))
@@ -80,21 +99,338 @@ pub fn vector_set<'gc>(
     stack: Stack<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>> {
-    let vector = stack.read()[1];
-    let offset = stack.read()[2].as_number()? as usize;
-    let obj = stack.read()[3];
-    match vector {
-        Value::Vector(_) => {
-            return Err(InterpretError::RuntimeError(
-                "Expected a mutable vector".into(),
-            ))
+    let (vector, index, obj) = {
+        let args = stack.read();
+        (args[1], args[2], args[3])
+    };
+    let vector = mutable_vector(vector)?;
+    let mut vector = borrow_mut(&vector, mc)?;
+    let elements = vector.as_vector_mut()?.as_slice_mut();
+    elements[checked_index(index, elements.len(), "vector")?] = obj;
+
+    Ok(Some(Value::Void))
+}
+
+/// Makes a mutable vector out of the elements of a list
+pub fn list_to_vector<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let elements = list_to_vec(stack.read()[1])?;
+    Ok(Some(Value::boxed(
+        mc,
+        Object::Vector(ObjVector::new(elements.into_boxed_slice())),
+    )))
+}
+
+/// Makes a list out of the elements of a vector, optionally only those from `start` up to (but
+/// not including) `end`
+pub fn vector_to_list<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let elements = vector_elements(args[1])?;
+    let range = vector_range(&args, 2, args[1], elements.len())?;
+
+    Ok(Some(list_from(elements[range].to_vec(), mc)))
+}
+
+/// Makes a mutable vector out of its arguments
+pub fn vector<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let elements = stack.read()[1..].to_vec();
+    Ok(Some(Value::boxed(
+        mc,
+        Object::Vector(ObjVector::new(elements.into_boxed_slice())),
+    )))
+}
+
+/// Sets every element of a vector to `fill`, optionally only those from `start` up to (but not
+/// including) `end`
+pub fn vector_fill<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let vector = mutable_vector(args[1])?;
+    let fill = args[2];
+
+    let length = vector.read().as_vector()?.as_slice().len();
+    let range = vector_range(&args, 3, args[1], length)?;
+    borrow_mut(&vector, mc)?.as_vector_mut()?.as_slice_mut()[range].fill(fill);
+
+    Ok(Some(Value::Void))
+}
+
+/// Makes a new mutable vector out of the elements of a vector, optionally only those from
+/// `start` up to (but not including) `end`
+pub fn vector_copy<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let elements = vector_elements(args[1])?;
+    let range = vector_range(&args, 2, args[1], elements.len())?;
+
+    Ok(Some(Value::boxed(
+        mc,
+        Object::Vector(ObjVector::new(elements[range].into())),
+    )))
+}
+
+/// Makes a new mutable vector out of the elements of a vector from `start` up to (but not
+/// including) `end`. This is `vector-copy`, but with a range that has to be given.
+pub fn subvector<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    vector_copy(vm, stack, mc)
+}
+
+/// `(vector-copy! to at from [start [end]])` copies the elements of `from` (or those from `start`
+/// up to `end`) into `to`, starting at index `at`. The elements are read out before any are
+/// written, so this works even when `to` and `from` are the same vector.
+pub fn vector_copy_to<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    let to = mutable_vector(args[1])?;
+    let at = args[2].as_number()?;
+    let elements = vector_elements(args[3])?;
+    let range = vector_range(&args, 4, args[3], elements.len())?;
+
+    let mut to = borrow_mut(&to, mc)?;
+    let to = to.as_vector_mut()?.as_slice_mut();
+    if at < 0.0 || at.fract() != 0.0 || at as usize + range.len() > to.len() {
+        return Err(InterpretError::RuntimeError(format!(
+            "Can't copy {} elements to index {} of a vector of length {}",
+            range.len(),
+            at,
+            to.len()
+        )));
+    }
+    let at = at as usize;
+    to[at..at + range.len()].copy_from_slice(&elements[range]);
+
+    Ok(Some(Value::Void))
+}
+
+/// Makes a new mutable vector out of the elements of all of its arguments, in order
+pub fn vector_append<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut elements = Vec::new();
+    for &vector in &stack.read()[1..] {
+        elements.extend(vector_elements(vector)?);
+    }
+
+    Ok(Some(Value::boxed(
+        mc,
+        Object::Vector(ObjVector::new(elements.into_boxed_slice())),
+    )))
+}
+
+/// Applies a procedure elementwise to one or more vectors, stopping at the end of the shortest
+/// one, and returns a new vector of the results
+pub fn vector_map<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    start_vector_mapping(vm, stack, VECTOR_MAP_STEP, mc)
+}
+
+/// Applies a procedure elementwise to one or more vectors for its side effects
+pub fn vector_for_each<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    start_vector_mapping(vm, stack, VECTOR_FOR_EACH_STEP, mc)
+}
+
+/// Hands `[procedure, index, results, vectors...]` over to `step`, which works like the steps
+/// of `map`, except that it keeps track of an index into the vectors rather than what's left of
+/// each list
+fn start_vector_mapping<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    step: RegisteredNative,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut args = stack.read()[1..].to_vec();
+    for &vector in &args[1..] {
+        vector_elements(vector)?;
+    }
+    args.splice(1..1, [Value::Number(0.0), Value::Null]);
+    call_step(vm, stack, step, args, mc)
+}
+
+/// Gets the elements at the step's index, or `None` once it's past the end of any of the vectors
+fn vector_elements_at<'gc>(stack: Stack<'gc>) -> Result<Option<Vec<Value<'gc>>>> {
+    let args = stack.read();
+    let index = args[2].as_number()? as usize;
+    let mut elements = Vec::with_capacity(args.len() - 4);
+    for &vector in &args[4..] {
+        let element = match vector {
+            Value::Vector(v) => v.as_slice().get(index).map(|&element| element.into()),
+            Value::Box(b) => b.read().as_vector()?.as_slice().get(index).copied(),
+            _ => return Err(TypeError(format!("'{}' is not a vector", vector)).into()),
+        };
+        match element {
+            Some(element) => elements.push(element),
+            None => return Ok(None),
         }
-        Value::Box(b) => {
-            let mut vector = b.write(mc);
-            vector.as_vector_mut()?.as_slice_mut()[offset] = obj;
+    }
+
+    Ok(Some(elements))
+}
+
+/// The arguments for the step after the one on `stack`, whose result was `result`
+fn next_vector_step<'gc>(
+    stack: Stack<'gc>,
+    result: Option<Value<'gc>>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Vec<Value<'gc>>> {
+    let args = stack.read();
+    let len = args.len();
+    let results = match result {
+        Some(result) => Value::boxed(mc, Object::Pair(ObjPair::new(result, args[3]))),
+        None => args[3],
+    };
+
+    let mut next = vec![args[1], Value::Number(args[2].as_number()? + 1.0), results];
+    next.extend_from_slice(&args[4..len - 1]);
+    Ok(next)
+}
+
+fn vector_map_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    match vector_elements_at(stack)? {
+        Some(elements) => call_on_next(vm, stack, elements, VECTOR_MAP_CONTINUATION, mc),
+        None => {
+            let mut results = list_to_vec(stack.read()[3])?;
+            results.reverse();
+            Ok(Some(Value::boxed(
+                mc,
+                Object::Vector(ObjVector::new(results.into_boxed_slice())),
+            )))
         }
-        _ => return Err(TypeError(format!("'{}' is not a string", vector)).into()),
+    }
+}
+
+fn vector_map_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let result = *stack.read().last().unwrap();
+    let args = next_vector_step(stack, Some(result), mc)?;
+    call_step(vm, stack, VECTOR_MAP_STEP, args, mc)
+}
+
+fn vector_for_each_step<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    match vector_elements_at(stack)? {
+        Some(elements) => call_on_next(vm, stack, elements, VECTOR_FOR_EACH_CONTINUATION, mc),
+        None => Ok(Some(Value::Void)),
+    }
+}
+
+fn vector_for_each_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = next_vector_step(stack, None, mc)?;
+    call_step(vm, stack, VECTOR_FOR_EACH_STEP, args, mc)
+}
+
+/// Reads the optional `start` and `end` arguments at `first` and the one after it, which pick out
+/// part of `vector`, which has `length` elements
+fn vector_range(
+    args: &[Value<'_>],
+    first: usize,
+    vector: Value<'_>,
+    length: usize,
+) -> Result<Range<usize>> {
+    checked_range(args, first, "vector", vector, length)
+}
+
+/// Like `vector_range`, but for any `kind` of sequence
+pub(crate) fn checked_range(
+    args: &[Value<'_>],
+    first: usize,
+    kind: &str,
+    sequence: Value<'_>,
+    length: usize,
+) -> Result<Range<usize>> {
+    let bound = |i: usize, default: usize| match args.get(i) {
+        Some(bound) => bound.as_number(),
+        None => Ok(default as f64),
     };
+    let start = bound(first, 0)?;
+    let end = bound(first + 1, length)?;
+    let is_integer = start.fract() == 0.0 && end.fract() == 0.0;
+    if !is_integer || start < 0.0 || start > end || end > length as f64 {
+        return Err(InterpretError::RuntimeError(format!(
+            "Range {} to {} is out of range for {} {}",
+            start, end, kind, sequence
+        )));
+    }
 
-    Ok(Some(Value::Void))
+    Ok(start as usize..end as usize)
+}
+
+/// Checks that `index` is an exact integer that can index into a `kind` (such as a vector or
+/// string) with `length` elements
+pub(crate) fn checked_index(index: Value<'_>, length: usize, kind: &str) -> Result<usize> {
+    let k = index.as_number()?;
+    if k < 0.0 || k.fract() != 0.0 || k >= length as f64 {
+        return Err(InterpretError::RuntimeError(format!(
+            "Index {} is out of range for a {} of length {}",
+            index, kind, length
+        )));
+    }
+
+    Ok(k as usize)
+}
+
+/// Copies out the elements of a vector, converting those of constant vectors to values
+pub(crate) fn vector_elements(vector: Value<'_>) -> Result<Vec<Value<'_>>> {
+    match vector {
+        Value::Vector(v) => Ok(v.as_slice().iter().map(|&element| element.into()).collect()),
+        Value::Box(b) => Ok(b.read().as_vector()?.as_slice().to_vec()),
+        _ => Err(TypeError(format!("'{}' is not a vector", vector)).into()),
+    }
+}
+
+/// Gets the object behind a vector that can be modified
+pub(crate) fn mutable_vector<'gc>(vector: Value<'gc>) -> Result<GcCell<'gc, Object<'gc>>> {
+    match vector {
+        Value::Box(b) => Ok(b),
+        Value::Vector(_) => Err(InterpretError::RuntimeError(
+            "Expected a mutable vector".into(),
+        )),
+        vector => Err(TypeError(format!("'{}' is not a vector", vector)).into()),
+    }
 }
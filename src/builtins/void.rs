@@ -0,0 +1,22 @@
+use gc_arena::MutationContext;
+
+use crate::value::Value;
+use crate::vm::{Result, Stack, VirtualMachine};
+
+/// Ignores its arguments and returns the unspecified value
+pub fn void<'gc>(
+    _: &VirtualMachine<'gc>,
+    _: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(Value::Void))
+}
+
+pub fn is_void<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let args = stack.read();
+    Ok(Some(Value::Bool(args[1].is_void())))
+}
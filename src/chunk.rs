@@ -1,8 +1,100 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+use std::mem;
+use std::rc::Rc;
+
+use gc_arena::{Gc, GcCell};
 use gc_arena_derive::Collect;
 use num_enum::{IntoPrimitive, TryFromPrimitive};
 
+use crate::memory::Symbol;
 use crate::value::Value;
 
+/// A table of global variables, like the VM's own or a library's
+pub type Globals<'gc> = GcCell<'gc, GlobalTable<'gc>>;
+
+/// Marks a global variable slot that hasn't been looked up yet
+const UNRESOLVED: usize = usize::MAX;
+
+/// Global variables by name. Each variable keeps the slot it was first defined in for as long as
+/// the table exists, so code can remember where a variable is instead of hashing its name every
+/// time it's used.
+#[derive(Debug, Default, Clone, Collect)]
+#[collect(no_drop)]
+pub struct GlobalTable<'gc> {
+    slots: Vec<(Symbol<'gc>, Value<'gc>)>,
+    index: HashMap<Symbol<'gc>, usize>,
+}
+
+impl<'gc> GlobalTable<'gc> {
+    /// Gets the value of a variable
+    pub fn get(&self, name: &Symbol<'gc>) -> Option<&Value<'gc>> {
+        self.index.get(name).map(|&slot| &self.slots[slot].1)
+    }
+
+    /// Defines a variable (or sets it, if it's already defined), returning its slot
+    pub fn insert(&mut self, name: Symbol<'gc>, value: Value<'gc>) -> usize {
+        match self.index.get(&name) {
+            Some(&slot) => {
+                self.slots[slot].1 = value;
+                slot
+            }
+            None => {
+                self.slots.push((name, value));
+                self.index.insert(name, self.slots.len() - 1);
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    pub fn contains_key(&self, name: &Symbol<'gc>) -> bool {
+        self.index.contains_key(name)
+    }
+
+    /// Gets the names of the variables, in the order they were defined
+    pub fn keys(&self) -> impl Iterator<Item = &Symbol<'gc>> {
+        self.slots.iter().map(|(name, _)| name)
+    }
+
+    /// Gets the slot a variable is in
+    pub fn slot(&self, name: &Symbol<'gc>) -> Option<usize> {
+        self.index.get(name).copied()
+    }
+
+    /// Gets the value in a slot
+    pub fn slot_value(&self, slot: usize) -> Value<'gc> {
+        self.slots[slot].1
+    }
+
+    /// Sets the value in a slot
+    pub fn set_slot_value(&mut self, slot: usize, value: Value<'gc>) {
+        self.slots[slot].1 = value;
+    }
+
+    /// Whether `slot` holds the variable `name`
+    fn is_slot_of(&self, slot: usize, name: &Symbol<'gc>) -> bool {
+        matches!(self.slots.get(slot), Some((slot_name, _)) if slot_name == name)
+    }
+}
+
+impl<'gc> Extend<(Symbol<'gc>, Value<'gc>)> for GlobalTable<'gc> {
+    fn extend<T: IntoIterator<Item = (Symbol<'gc>, Value<'gc>)>>(&mut self, iter: T) {
+        for (name, value) in iter {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl<'gc> FromIterator<(Symbol<'gc>, Value<'gc>)> for GlobalTable<'gc> {
+    fn from_iter<T: IntoIterator<Item = (Symbol<'gc>, Value<'gc>)>>(iter: T) -> Self {
+        let mut table = Self::default();
+        table.extend(iter);
+        table
+    }
+}
+
 /// Represents an opcode that runs on our virtual machine.
 /// Opcodes are 1 byte in length (for now) and represent the
 /// simplest operations our VM can perform (arithmetic, control flow, etc.).
@@ -29,6 +121,53 @@ pub enum OpCode {
     True,
     False,
     Return,
+    Car,
+    Cdr,
+    Cons,
+    IsNull,
+    IsPair,
+    GetLocalLong,
+    SetLocalLong,
+    GetUpvalueLong,
+    SetUpvalueLong,
+    CallLong,
+    TailCallLong,
+    DefineGlobalLong,
+    GetGlobalLong,
+    SetGlobalLong,
+    BoxLocal,
+    BoxLocalLong,
+    Unbox,
+    SetBox,
+}
+
+impl OpCode {
+    /// Gets the form of this instruction that takes a 16-bit operand, for when the operand doesn't
+    /// fit in a byte
+    pub fn long_form(self) -> Option<Self> {
+        match self {
+            Self::GetLocal => Some(Self::GetLocalLong),
+            Self::SetLocal => Some(Self::SetLocalLong),
+            Self::GetUpvalue => Some(Self::GetUpvalueLong),
+            Self::SetUpvalue => Some(Self::SetUpvalueLong),
+            Self::BoxLocal => Some(Self::BoxLocalLong),
+            Self::Call => Some(Self::CallLong),
+            Self::TailCall => Some(Self::TailCallLong),
+            _ => None,
+        }
+    }
+
+    /// Gets the form of this instruction that takes a 24-bit constant offset, like
+    /// `ConstantLong`, for when the offset doesn't fit in a byte
+    pub fn constant_long_form(self) -> Option<Self> {
+        match self {
+            Self::Constant => Some(Self::ConstantLong),
+            Self::DefineGlobal => Some(Self::DefineGlobalLong),
+            Self::GetGlobal => Some(Self::GetGlobalLong),
+            Self::SetGlobal => Some(Self::SetGlobalLong),
+            _ => None,
+        }
+    }
 }
 
 /// Represents a series of instructions that correspond to some piece of high-level code.
@@ -37,7 +176,32 @@ pub enum OpCode {
 pub struct Chunk<'gc> {
     code: Vec<u8>,
     lines: Vec<(isize, usize)>,
+
+    /// Source column the code from each offset on was compiled from, as `(offset, column)`
+    /// pairs in order. Columns start at 1, and 0 means the column isn't known.
+    columns: Vec<(usize, usize)>,
+
     constants: Vec<Value<'gc>>,
+
+    /// Slot of each constant in `constants`, so that adding one that's already there is cheap
+    constant_slots: HashMap<Constant<'gc>, usize>,
+
+    /// File the code was compiled from, if it came from one
+    #[collect(require_static)]
+    file: Option<Rc<str>>,
+
+    /// Where the code's global variables live, if that's not the VM's own table (i.e. the code
+    /// belongs to a library)
+    globals: Option<Globals<'gc>>,
+
+    /// Slot of the global table that each constant naming a global variable was last found in,
+    /// which `GET_GLOBAL` and `SET_GLOBAL` check before looking the name up
+    global_slots: Vec<Cell<usize>>,
+
+    /// How close the chunk is to being compiled to native code, and the code once it is
+    #[cfg(feature = "jit")]
+    #[collect(require_static)]
+    tier: crate::vm::jit::Tier,
 }
 
 impl Chunk<'_> {
@@ -62,11 +226,59 @@ impl Chunk<'_> {
         }
     }
 
+    /// Write a 16-bit operand into this chunk, high byte first
+    pub fn write_short(&mut self, value: u16, line: usize) {
+        for byte in value.to_be_bytes().iter() {
+            self.write(*byte, line);
+        }
+    }
+
+    /// Write an instruction along with its operand, switching to the long form of the instruction
+    /// if the operand doesn't fit in a byte
+    pub fn write_operand(&mut self, opcode: OpCode, operand: usize, line: usize) {
+        match opcode.long_form() {
+            Some(long) if operand > u8::MAX as usize => {
+                self.write(long.into(), line);
+                self.write_short(operand as u16, line);
+            }
+            _ => {
+                self.write(opcode.into(), line);
+                self.write(operand as u8, line);
+            }
+        }
+    }
+
     #[inline(always)]
     pub fn read(&self, offset: usize) -> u8 {
         self.code[offset]
     }
 
+    /// Gets this chunk's bytecode
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    /// Gets the run-length encoded line numbers of this chunk's bytecode, as
+    /// `(instruction bytes, line)` pairs
+    pub fn lines(&self) -> &[(isize, usize)] {
+        &self.lines
+    }
+
+    /// Gets where the source column changes in this chunk's bytecode, as `(offset, column)` pairs
+    pub fn columns(&self) -> &[(usize, usize)] {
+        &self.columns
+    }
+
+    /// Attributes the bytes written from now on to `column` of the source
+    pub fn mark_column(&mut self, column: usize) {
+        let offset = self.code.len();
+        match self.columns.last_mut() {
+            Some((_, last)) if *last == column => {}
+            Some((last_offset, last)) if *last_offset == offset => *last = column,
+            _ => self.columns.push((offset, column)),
+        }
+    }
+
     /// Disassemble this chunk
     pub fn disassemble(&self, name: &str) {
         println!("== {} ==", name);
@@ -77,6 +289,23 @@ impl Chunk<'_> {
         }
     }
 
+    /// Gets the file this chunk was compiled from
+    pub fn file(&self) -> Option<&Rc<str>> {
+        self.file.as_ref()
+    }
+
+    pub(crate) fn set_file(&mut self, file: Option<Rc<str>>) {
+        self.file = file;
+    }
+
+    /// Gets every source line that some instruction in this chunk was compiled from, in order
+    pub fn source_lines(&self) -> Vec<usize> {
+        let mut lines: Vec<usize> = self.lines.iter().map(|(_, line)| *line).collect();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
     pub fn get_line(&self, offset: usize) -> usize {
         let mut current_offset = offset as isize;
         let mut i = 0;
@@ -91,6 +320,20 @@ impl Chunk<'_> {
         current_line
     }
 
+    /// Gets the source column the byte at `offset` was compiled from, or 0 if it isn't known
+    pub fn get_column(&self, offset: usize) -> usize {
+        let index = self.columns.partition_point(|(start, _)| *start <= offset);
+        match index {
+            0 => 0,
+            index => self.columns[index - 1].1,
+        }
+    }
+
+    /// Describes where in the source the byte at `offset` was compiled from, e.g. `file.scm:3:5`
+    pub fn describe_position(&self, offset: usize) -> String {
+        describe_position(self.file(), self.get_line(offset), self.get_column(offset))
+    }
+
     /// Try to disassemble the instruction at the offset in this chunk
     pub fn disassemble_instruction(&self, offset: usize) -> usize {
         print!("{:04} ", offset);
@@ -137,12 +380,12 @@ impl Chunk<'_> {
                 for _ in 0..function.upvalues().len() {
                     let is_local = self.read(offset);
                     offset += 1;
-                    let index = self.read(offset);
-                    offset += 1;
+                    let index = ((self.read(offset) as u16) << 8) | (self.read(offset + 1) as u16);
+                    offset += 2;
                     let is_local = if is_local > 0 { "local" } else { "upvalue" };
                     println!(
                         "{:04}    |                      {} {}",
-                        offset - 2,
+                        offset - 3,
                         is_local,
                         index
                     );
@@ -158,7 +401,51 @@ impl Chunk<'_> {
             OpCode::True => simple_instruction("TRUE", offset),
             OpCode::False => simple_instruction("FALSE", offset),
             OpCode::Return => simple_instruction("RETURN", offset),
+            OpCode::Car => simple_instruction("CAR", offset),
+            OpCode::Cdr => simple_instruction("CDR", offset),
+            OpCode::Cons => simple_instruction("CONS", offset),
+            OpCode::IsNull => simple_instruction("IS_NULL", offset),
+            OpCode::IsPair => simple_instruction("IS_PAIR", offset),
+            OpCode::GetLocalLong => self.short_instruction("GET_LOCAL_LONG", offset),
+            OpCode::SetLocalLong => self.short_instruction("SET_LOCAL_LONG", offset),
+            OpCode::GetUpvalueLong => self.short_instruction("GET_UPVALUE_LONG", offset),
+            OpCode::SetUpvalueLong => self.short_instruction("SET_UPVALUE_LONG", offset),
+            OpCode::CallLong => self.short_instruction("CALL_LONG", offset),
+            OpCode::TailCallLong => self.short_instruction("TAIL_CALL_LONG", offset),
+            OpCode::DefineGlobalLong => {
+                self.constant_long_instruction("DEFINE_GLOBAL_LONG", offset)
+            }
+            OpCode::GetGlobalLong => self.constant_long_instruction("GET_GLOBAL_LONG", offset),
+            OpCode::SetGlobalLong => self.constant_long_instruction("SET_GLOBAL_LONG", offset),
+            OpCode::BoxLocal => self.byte_instruction("BOX_LOCAL", offset),
+            OpCode::BoxLocalLong => self.short_instruction("BOX_LOCAL_LONG", offset),
+            OpCode::Unbox => simple_instruction("UNBOX", offset),
+            OpCode::SetBox => simple_instruction("SET_BOX", offset),
+        }
+    }
+
+    /// Replaces this chunk's bytecode with `code`, each byte of which was compiled from the same
+    /// place in the source as the byte of the old code at the offset `origins` gives for it
+    pub(crate) fn replace_code(&mut self, code: Vec<u8>, origins: &[usize]) {
+        let mut lines: Vec<(isize, usize)> = Vec::new();
+        let mut columns = Vec::new();
+        for (offset, origin) in origins.iter().enumerate() {
+            let line = self.get_line(*origin);
+            match lines.last_mut() {
+                Some((times, last)) if *last == line => *times += 1,
+                _ => lines.push((1, line)),
+            }
+
+            let column = self.get_column(*origin);
+            let last = columns.last().map_or(0, |(_, last)| *last);
+            if column != last {
+                columns.push((offset, column));
+            }
         }
+
+        self.code = code;
+        self.lines = lines;
+        self.columns = columns;
     }
 
     pub fn emit_jump(&mut self, opcode: OpCode, line: usize) -> usize {
@@ -208,6 +495,12 @@ impl Chunk<'_> {
         offset + 2
     }
 
+    fn short_instruction(&self, name: &str, offset: usize) -> usize {
+        let slot = ((self.read(offset + 1) as u16) << 8) | (self.read(offset + 2) as u16);
+        println!("{:16} {:4}", name, slot);
+        offset + 3
+    }
+
     fn jump_instruction(&self, name: &str, sign: isize, offset: usize) -> usize {
         let jump = ((self.read(offset + 1) as u16) << 8) | (self.read(offset + 2) as u16);
         println!(
@@ -221,14 +514,82 @@ impl Chunk<'_> {
 }
 
 impl<'gc> Chunk<'gc> {
+    /// Puts a chunk back together from the parts returned by its getters
+    pub fn from_parts(
+        code: Vec<u8>,
+        lines: Vec<(isize, usize)>,
+        columns: Vec<(usize, usize)>,
+        constants: Vec<Value<'gc>>,
+        file: Option<Rc<str>>,
+    ) -> Self {
+        let global_slots = vec![Cell::new(UNRESOLVED); constants.len()];
+        let mut constant_slots = HashMap::new();
+        for (offset, constant) in constants.iter().enumerate() {
+            constant_slots.entry(Constant(*constant)).or_insert(offset);
+        }
+        Self {
+            code,
+            lines,
+            columns,
+            constants,
+            constant_slots,
+            file,
+            globals: None,
+            global_slots,
+            #[cfg(feature = "jit")]
+            tier: Default::default(),
+        }
+    }
+
+    /// Gets the table this chunk's global variables live in, if it's not the VM's
+    pub fn globals(&self) -> Option<Globals<'gc>> {
+        self.globals
+    }
+
+    pub(crate) fn set_globals(&mut self, globals: Option<Globals<'gc>>) {
+        self.globals = globals;
+    }
+
+    #[cfg(feature = "jit")]
+    pub(crate) fn tier(&self) -> &crate::vm::jit::Tier {
+        &self.tier
+    }
+
+    /// Gets the slot of `globals` holding the variable named by constant `offset`, remembering
+    /// it for next time
+    pub(crate) fn global_slot(&self, offset: usize, globals: &GlobalTable<'gc>) -> Option<usize> {
+        let name = self.constants[offset].as_symbol().ok()?;
+        let cached = &self.global_slots[offset];
+        if globals.is_slot_of(cached.get(), &name) {
+            return Some(cached.get());
+        }
+
+        let slot = globals.slot(&name)?;
+        cached.set(slot);
+        Some(slot)
+    }
+
     #[inline(always)]
     pub fn read_constant(&self, offset: usize) -> Value<'gc> {
         self.constants[offset]
     }
 
-    /// Add a constant to this chunk's constant pool
+    /// Gets this chunk's constant pool
+    pub fn constants(&self) -> &[Value<'gc>] {
+        &self.constants
+    }
+
+    /// Add a constant to this chunk's constant pool, reusing the slot of an earlier constant that's
+    /// the same value
     pub fn add_constant(&mut self, value: Value<'gc>) -> usize {
+        let offset = self.constants.len();
+        let existing = *self.constant_slots.entry(Constant(value)).or_insert(offset);
+        if existing != offset {
+            return existing;
+        }
+
         self.constants.push(value);
+        self.global_slots.push(Cell::new(UNRESOLVED));
         self.constants.len() - 1
     }
 
@@ -236,20 +597,92 @@ impl<'gc> Chunk<'gc> {
     /// it
     pub fn write_constant(&mut self, value: Value<'gc>, line: usize) {
         let offset = self.add_constant(value);
-        if offset > 255 {
-            self.write(OpCode::ConstantLong.into(), line);
-            for byte in offset.to_le_bytes()[0..3].iter() {
-                self.write(*byte, line);
+        self.write_constant_operand(OpCode::Constant, offset, line);
+    }
+
+    /// Write an instruction that takes the offset of a constant, switching to the long form of the
+    /// instruction if the offset doesn't fit in a byte
+    pub fn write_constant_operand(&mut self, opcode: OpCode, offset: usize, line: usize) {
+        match opcode.constant_long_form() {
+            Some(long) if offset > u8::MAX as usize => {
+                self.write(long.into(), line);
+                for byte in offset.to_le_bytes()[0..3].iter() {
+                    self.write(*byte, line);
+                }
+            }
+            _ => {
+                self.write(opcode.into(), line);
+                self.write(offset as u8, line);
             }
-        } else {
-            self.write(OpCode::Constant.into(), line);
-            self.write(offset as u8, line);
         }
     }
 }
 
+/// A constant as a key of a chunk's `constant_slots`, where two constants are equal if they can
+/// share a slot of the constant pool (see `is_same_constant`)
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+struct Constant<'gc>(Value<'gc>);
+
+impl PartialEq for Constant<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        is_same_constant(self.0, other.0)
+    }
+}
+
+impl Eq for Constant<'_> {}
+
+impl Hash for Constant<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use Value::*;
+        mem::discriminant(&self.0).hash(state);
+        match self.0 {
+            Bool(b) => b.hash(state),
+            Char(c) => c.hash(state),
+            Number(n) => n.to_bits().hash(state),
+            Symbol(s) => s.hash(state),
+            Pair(pair) => Gc::as_ptr(pair).hash(state),
+            String(string) => Gc::as_ptr(string).hash(state),
+            Vector(vector) => Gc::as_ptr(vector).hash(state),
+            Box(object) => object.as_ptr().hash(state),
+            Null | Void | Eof => {}
+        }
+    }
+}
+
+/// Whether two constants can share a slot of a constant pool. Numbers, characters, booleans and
+/// the like are compared by value (numbers bit for bit, so `0.0` and `-0.0` stay apart), while
+/// symbols, strings (which are interned) and everything else have to be the same object, so
+/// sharing a slot can't change what `eq?` says about them.
+fn is_same_constant<'gc>(first: Value<'gc>, second: Value<'gc>) -> bool {
+    use Value::*;
+    match (first, second) {
+        (Bool(b1), Bool(b2)) => b1 == b2,
+        (Char(c1), Char(c2)) => c1 == c2,
+        (Number(n1), Number(n2)) => n1.to_bits() == n2.to_bits(),
+        (Null, Null) | (Void, Void) | (Eof, Eof) => true,
+        (Symbol(s1), Symbol(s2)) => s1 == s2,
+        (Pair(pair1), Pair(pair2)) => Gc::ptr_eq(pair1, pair2),
+        (String(string1), String(string2)) => Gc::ptr_eq(string1, string2),
+        (Vector(vector1), Vector(vector2)) => Gc::ptr_eq(vector1, vector2),
+        (Box(object1), Box(object2)) => GcCell::ptr_eq(object1, object2),
+        (_, _) => false,
+    }
+}
+
 /// Print a simple instruction with no operands
 fn simple_instruction(name: &str, offset: usize) -> usize {
     println!("{}", name);
     offset + 1
 }
+
+/// Describes a place in the source, e.g. `file.scm:3:5`, or `line 3` if neither the file nor the
+/// column is known
+pub fn describe_position(file: Option<&Rc<str>>, line: usize, column: usize) -> String {
+    match (file, column) {
+        (Some(file), 0) => format!("{}:{}", file, line),
+        (Some(file), column) => format!("{}:{}:{}", file, line, column),
+        (None, 0) => format!("line {}", line),
+        (None, column) => format!("line {}, column {}", line, column),
+    }
+}
@@ -1,13 +1,26 @@
+use core::cell::RefCell;
 use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use gc_arena::{GcCell, MutationContext};
+use pest::Parser;
 use thiserror::Error;
 
-use super::{CompilerContext, Upvalue};
-use crate::chunk::OpCode;
-use crate::memory::Symbol;
-use crate::object::{ObjFunction, ObjPair, Object};
-use crate::value::{TypeError, Value};
+use super::peephole::optimize;
+use super::verify::verify;
+use super::{read, CompilerContext, Interner, SourceMap, Tables, Upvalue, Upvalues};
+use crate::builtins;
+use crate::chunk::{describe_position, Chunk, GlobalTable, Globals, OpCode};
+use crate::memory::{Symbol, Token};
+use crate::object::{
+    ObjFunction, ObjNative, ObjPair, ObjRecordType, ObjString, Object, RegisteredNative,
+};
+use crate::scanner::{Rule, SchemeParser};
+use crate::value::{Datum, TypeError, Value};
 
 #[derive(Debug, Error)]
 pub enum CompileError {
@@ -16,10 +29,20 @@ pub enum CompileError {
 
     #[error("[compile]: {0}")]
     TypeError(#[from] TypeError),
+
+    /// An error in a form, along with where in the source the form was read from
+    #[error("{error} (at {position})")]
+    At {
+        error: Box<CompileError>,
+        position: String,
+    },
 }
 
 type Result<T> = std::result::Result<T, CompileError>;
 
+/// How many characters of an expression `source_name` names its thunk after
+const SOURCE_NAME_LENGTH: usize = 40;
+
 fn car(value: Value<'_>) -> Result<Value<'_>> {
     match value {
         Value::Pair(p) => Ok(p.car().into()),
@@ -46,20 +69,121 @@ fn cons<'gc>(car: Value<'gc>, cdr: Value<'gc>, mc: MutationContext<'gc, '_>) ->
     Ok(Value::boxed(mc, Object::Pair(ObjPair::new(car, cdr))))
 }
 
-pub fn compile<'gc>(ast: Value<'gc>, mc: MutationContext<'gc, '_>) -> Result<ObjFunction<'gc>> {
-    let cc = GcCell::allocate(mc, CompilerContext::default());
+/// Settings that change the code the compiler generates
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// Wrap procedure calls and special forms (other than `define`, `lambda` and `quote`) so that
+    /// they're printed along with their results as they run, for `--explain`
+    pub explain: bool,
+
+    /// How hard to try to make the generated code faster, with 0 meaning not at all. For now
+    /// anything above 0 runs the peephole optimizer over each procedure's bytecode.
+    pub optimization_level: u8,
+
+    /// Whether to remember the file and lines the code came from, for error messages, coverage
+    /// and the debugging modes
+    pub debug_info: bool,
+
+    /// Where to put warnings about unused variables and unreachable code, if the code should be
+    /// checked for them. They don't stop it compiling.
+    pub warnings: Option<Rc<RefCell<Vec<String>>>>,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            explain: false,
+            optimization_level: 1,
+            debug_info: true,
+            warnings: None,
+        }
+    }
+}
+
+impl CompileOptions {
+    /// Takes the warnings found in the code compiled so far
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.warnings
+            .as_ref()
+            .map(|warnings| warnings.take())
+            .unwrap_or_default()
+    }
+}
+
+/// Compiles `ast` to a thunk that runs it, called `name` if it's given one (see `source_name`)
+pub fn compile<'gc>(
+    ast: Value<'gc>,
+    tables: Tables<'gc>,
+    source: SourceMap,
+    options: &CompileOptions,
+    name: Option<Symbol<'gc>>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<ObjFunction<'gc>> {
+    compile_in(ast, tables, source, options, None, name, mc)
+}
+
+/// Like `compile`, but the code uses the global variables in `globals` rather than the VM's
+pub fn compile_in<'gc>(
+    ast: Value<'gc>,
+    tables: Tables<'gc>,
+    source: SourceMap,
+    options: &CompileOptions,
+    globals: Option<Globals<'gc>>,
+    name: Option<Symbol<'gc>>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<ObjFunction<'gc>> {
+    let source = if options.debug_info {
+        source
+    } else {
+        SourceMap::default()
+    };
+    let line = source.start_line();
+    let cc = GcCell::allocate(mc, CompilerContext::with_source(tables, source));
+    cc.write(mc).line = line;
+    cc.write(mc).explain = options.explain;
+    cc.write(mc).optimize = options.optimization_level > 0;
+    cc.write(mc).warnings = options.warnings.clone();
+    cc.write(mc).chunk.set_globals(globals);
     expression(cc, ast, true, None, mc).map_err(|err| {
         print_code(&cc.read());
         err
     })?;
 
-    cc.write(mc).chunk.write(OpCode::Return.into(), 1);
+    cc.write(mc).chunk.write(OpCode::Return.into(), line);
+    if cc.read().optimize {
+        optimize(&mut cc.write(mc).chunk);
+    }
     let (chunk, upvalues) = {
         let cc = cc.read();
         (cc.chunk.clone(), cc.upvalues.clone())
     };
 
-    Ok(ObjFunction::thunk(mc, chunk, upvalues))
+    let function = ObjFunction::new(mc, 0, false, chunk, upvalues, name);
+    verify(&function)?;
+    Ok(function)
+}
+
+/// Makes a name for the thunk compiled from `ast`, so that it can be told apart from the others
+/// in backtraces and disassembly: the file and line it was read from if it was read from a file,
+/// or else the start of the expression
+pub fn source_name<'gc>(
+    ast: Value<'gc>,
+    source: &SourceMap,
+    mc: MutationContext<'gc, '_>,
+) -> Symbol<'gc> {
+    // The map may be left over from reading something else, so it only counts if it knows `ast`
+    let position = source.file().zip(source.position(ast));
+    let name = match position {
+        Some((file, (line, _))) => format!("{}:{}", file, line),
+        None => {
+            let expression = ast.to_string();
+            match expression.char_indices().nth(SOURCE_NAME_LENGTH) {
+                Some((end, _)) => format!("{}...", &expression[..end]),
+                None => expression,
+            }
+        }
+    };
+    Symbol::uninterned(Token::new(mc, ObjString::from(name)))
 }
 
 fn expression<'gc>(
@@ -77,9 +201,9 @@ fn expression<'gc>(
         Value::Pair(_) => definition_or_expression(cc, current, in_tail_position, name, mc),
         Value::Box(b) => match &*b.read() {
             Object::Pair(_) => definition_or_expression(cc, current, in_tail_position, name, mc),
-            _ => literal(&mut cc.write(mc), current),
+            _ => literal(&mut cc.write(mc), current, mc),
         },
-        _ => literal(&mut cc.write(mc), current),
+        _ => literal(&mut cc.write(mc), current, mc),
     }
 }
 
@@ -89,6 +213,108 @@ fn definition_or_expression<'gc>(
     in_tail_position: bool,
     name: Option<Symbol<'gc>>,
     mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    // Code for a form is attributed to the line and column it was read from, going back to the
+    // enclosing form's once it's done
+    let (outer_line, outer_column) = (cc.read().line, cc.read().column);
+    let position = cc
+        .read()
+        .source
+        .as_ref()
+        .and_then(|source| source.position(current));
+    if let Some((line, column)) = position {
+        cc.write(mc).set_position(line, column);
+    }
+
+    let result = if cc.read().explain && is_explained(current) {
+        explained_form(cc, current, in_tail_position, name, mc)
+    } else {
+        compound_form(cc, current, in_tail_position, name, mc)
+    };
+    cc.write(mc).set_position(outer_line, outer_column);
+
+    // Errors are located at the innermost form that has a position
+    match result {
+        Err(error) if position.is_some() && !matches!(error, CompileError::At { .. }) => {
+            let (line, column) = position.unwrap();
+            let cc = cc.read();
+            let file = cc.source.as_ref().and_then(|source| source.file());
+            Err(CompileError::At {
+                error: Box::new(error),
+                position: describe_position(file, line, column),
+            })
+        }
+        result => result,
+    }
+}
+
+/// Whether `--explain` shows a compound form. Definitions have nothing interesting to show, and
+/// neither do lambdas and quotations, which just evaluate to themselves.
+fn is_explained(current: Value<'_>) -> bool {
+    match car(current) {
+        Ok(Value::Symbol(s)) => !matches!(
+            s.as_str().as_ref(),
+            "define"
+                | "define-syntax"
+                | "define-record-type"
+                | "define-library"
+                | "import"
+                | "include"
+                | "include-ci"
+                | "lambda"
+                | "quote"
+        ),
+        _ => true,
+    }
+}
+
+/// Compiles a compound form as `(%explain 'form (lambda () form))`, which prints the form and its
+/// result around running it
+fn explained_form<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    current: Value<'gc>,
+    in_tail_position: bool,
+    name: Option<Symbol<'gc>>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let line = cc.read().line;
+    let explain = ObjNative::registered(
+        2,
+        false,
+        builtins::EXPLAIN,
+        Some(Symbol::uninterned(Token::new(
+            mc,
+            ObjString::from("explain"),
+        ))),
+    );
+    cc.write(mc)
+        .chunk
+        .write_constant(Value::boxed(mc, Object::Native(explain)), line);
+    literal(&mut cc.write(mc), current, mc)?;
+
+    // The thunk compiles the form itself directly, or it would wrap it all over again
+    let compiler = GcCell::allocate(mc, CompilerContext::with_parent(cc));
+    compound_form(compiler, current, true, name, mc)?;
+    compiler.write(mc).chunk.write(OpCode::Return.into(), line);
+    emit_function(cc, compiler, 0, false, None, line, mc)?;
+
+    let opcode = if in_tail_position {
+        OpCode::TailCall
+    } else {
+        OpCode::Call
+    };
+    cc.write(mc).chunk.write(opcode.into(), line);
+    cc.write(mc).chunk.write(2, line);
+
+    Ok(())
+}
+
+fn compound_form<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    current: Value<'gc>,
+    in_tail_position: bool,
+    name: Option<Symbol<'gc>>,
+    mc: MutationContext<'gc, '_>,
 ) -> Result<()> {
     let head = car(current)?;
     let tail = cdr(current)?;
@@ -99,7 +325,7 @@ fn definition_or_expression<'gc>(
                     let expr = car(cdr(tail)?)?;
                     let global = parse_variable(&mut cc.write(mc), name)?;
                     expression(cc, expr, false, Some(name), mc)?;
-                    define_variable(&mut cc.write(mc), global as u8, 1);
+                    define_variable(&mut cc.write(mc), name, global);
                     Ok(())
                 }
                 Value::Pair(formals) => {
@@ -108,7 +334,7 @@ fn definition_or_expression<'gc>(
                     let bodies = cdr(tail)?;
                     let global = parse_variable(&mut cc.write(mc), name)?;
                     function(cc, formals.into(), bodies, Some(name), false, mc)?;
-                    define_variable(&mut cc.write(mc), global as u8, 1);
+                    define_variable(&mut cc.write(mc), name, global);
 
                     Ok(())
                 }
@@ -119,7 +345,7 @@ fn definition_or_expression<'gc>(
                         let bodies = cdr(tail)?;
                         let global = parse_variable(&mut cc.write(mc), name)?;
                         function(cc, formals, bodies, Some(name), false, mc)?;
-                        define_variable(&mut cc.write(mc), global as u8, 1);
+                        define_variable(&mut cc.write(mc), name, global);
 
                         Ok(())
                     }
@@ -130,30 +356,31 @@ fn definition_or_expression<'gc>(
             "set!" => {
                 let name = car(tail)?.as_symbol()?;
                 let expr = car(cdr(tail)?)?;
-                let end = 1;
+                let end = cc.read().line;
                 expression(cc, expr, false, Some(name), mc)?;
                 named_variable(&mut cc.write(mc), name, true, mc);
                 cc.write(mc).chunk.write(OpCode::Void.into(), end);
                 Ok(())
             }
             "if" => {
+                let line = cc.read().line;
                 let test = car(tail)?;
                 expression(cc, test, false, None, mc)?;
-                let then_jump = cc.write(mc).chunk.emit_jump(OpCode::JumpIfFalse, 1);
-                cc.write(mc).chunk.write(OpCode::Pop.into(), 1);
+                let then_jump = cc.write(mc).chunk.emit_jump(OpCode::JumpIfFalse, line);
+                cc.write(mc).chunk.write(OpCode::Pop.into(), line);
 
                 let consequent = car(cdr(tail)?)?;
 
                 expression(cc, consequent, true, None, mc)?;
-                let else_jump = cc.write(mc).chunk.emit_jump(OpCode::Jump, 1);
+                let else_jump = cc.write(mc).chunk.emit_jump(OpCode::Jump, line);
                 cc.write(mc).chunk.patch_jump(then_jump);
-                cc.write(mc).chunk.write(OpCode::Pop.into(), 1);
+                cc.write(mc).chunk.write(OpCode::Pop.into(), line);
 
                 let alternate = cdr(cdr(tail)?)?;
                 if !alternate.is_null() {
                     expression(cc, car(alternate)?, true, None, mc)?;
                 } else {
-                    cc.write(mc).chunk.write(OpCode::Void.into(), 1);
+                    cc.write(mc).chunk.write(OpCode::Void.into(), line);
                 }
                 cc.write(mc).chunk.patch_jump(else_jump);
 
@@ -167,7 +394,7 @@ fn definition_or_expression<'gc>(
                 Ok(())
             }
             "begin" => {
-                let line = 1;
+                let line = cc.read().line;
                 let formals = Value::Null;
                 function(cc, formals, tail, None, false, mc)?;
 
@@ -186,8 +413,15 @@ fn definition_or_expression<'gc>(
             "quote" => {
                 let lit = car(tail)?;
 
-                literal(&mut cc.write(mc), lit)
+                literal(&mut cc.write(mc), lit, mc)
             }
+            "define-record-type" => record_type_definition(cc, tail, mc),
+            "define-syntax" => syntax_definition(cc, tail, mc),
+            "assert" => assertion(cc, car(tail)?, in_tail_position, mc),
+            "define-library" => library_definition(cc, tail, in_tail_position, mc),
+            "import" => import_declaration(cc, tail, in_tail_position, mc),
+            "include" => inclusion(cc, tail, false, in_tail_position, mc),
+            "include-ci" => inclusion(cc, tail, true, in_tail_position, mc),
             "let" => match car(tail)? {
                 Value::Symbol(s) => let_definition(
                     cc,
@@ -209,25 +443,34 @@ fn definition_or_expression<'gc>(
                 _ => Err(CompileError::Blah("Invalid let expression".into())),
             },
             _ => {
-                let line = 1;
-                named_variable(&mut cc.write(mc), s, false, mc);
+                let line = cc.read().line;
+                let get_op = named_variable(&mut cc.write(mc), s, false, mc);
                 let arg_count = argument_list(cc, tail, mc)?;
 
+                if let Some(opcode) = primitive_opcode(s, get_op, arg_count) {
+                    cc.write(mc).chunk.write(opcode.into(), line);
+                    if in_tail_position {
+                        // Lets the VM see that a call it falls back to is a tail call
+                        cc.write(mc).chunk.write(OpCode::Return.into(), line);
+                    }
+                    return Ok(());
+                }
+
                 let opcode = if in_tail_position {
                     OpCode::TailCall
                 } else {
                     OpCode::Call
                 };
 
-                cc.write(mc).chunk.write(opcode.into(), line);
-
-                cc.write(mc).chunk.write(arg_count, line);
+                cc.write(mc)
+                    .chunk
+                    .write_operand(opcode, arg_count.into(), line);
 
                 Ok(())
             }
         },
         _ => {
-            let line = 1;
+            let line = cc.read().line;
             expression(cc, head, false, None, mc)?;
             let arg_count = argument_list(cc, tail, mc)?;
 
@@ -237,15 +480,563 @@ fn definition_or_expression<'gc>(
                 OpCode::Call
             };
 
-            cc.write(mc).chunk.write(opcode.into(), line);
-
-            cc.write(mc).chunk.write(arg_count, line);
+            cc.write(mc)
+                .chunk
+                .write_operand(opcode, arg_count.into(), line);
 
             Ok(())
         }
     }
 }
 
+/// Compiles `(define-record-type name constructor predicate field...)`. The record type and the
+/// procedures for it are all made here, and the code just binds them to their names. A
+/// constructor can be `(name field...)`, a bare name to take every field in order, or `#f`, and
+/// each field is either `(field accessor [modifier])` or just a field name.
+fn record_type_definition<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    tail: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let invalid = || CompileError::Blah("Invalid define-record-type expression".into());
+    let name = car(tail)?.as_symbol()?;
+    let constructor = car(cdr(tail)?)?;
+    let predicate = car(cdr(cdr(tail)?)?)?;
+
+    let mut fields = Vec::new();
+    let mut procedures = Vec::new();
+    let mut specs = cdr(cdr(cdr(tail)?)?)?;
+    while !specs.is_null() {
+        let spec = car(specs)?;
+        let (field, names) = match spec {
+            Value::Symbol(field) => (field, Value::Null),
+            _ => (car(spec)?.as_symbol()?, cdr(spec)?),
+        };
+        if fields.contains(&field) {
+            return Err(CompileError::Blah(
+                format!("Field {} is defined twice in record type {}", field, name).into(),
+            ));
+        }
+
+        let index = Value::Number(fields.len() as f64);
+        if !names.is_null() {
+            procedures.push(RecordProcedure::Accessor(car(names)?.as_symbol()?, index));
+            let modifier = cdr(names)?;
+            if !modifier.is_null() {
+                procedures.push(RecordProcedure::Modifier(
+                    car(modifier)?.as_symbol()?,
+                    index,
+                ));
+            }
+        }
+        fields.push(field);
+        specs = cdr(specs)?;
+    }
+
+    let constructor = match constructor {
+        Value::Bool(false) => None,
+        Value::Symbol(constructor) => Some((constructor, (1..=fields.len() as u8).collect())),
+        _ => {
+            let mut slots = vec![0; fields.len()];
+            let mut args = cdr(constructor)?;
+            let mut arity = 0;
+            while !args.is_null() {
+                let arg = car(args)?.as_symbol()?;
+                let index = fields
+                    .iter()
+                    .position(|field| *field == arg)
+                    .ok_or_else(|| {
+                        CompileError::Blah(
+                            format!("{} is not a field of record type {}", arg, name).into(),
+                        )
+                    })?;
+                arity += 1;
+                slots[index] = arity;
+                args = cdr(args)?;
+            }
+            Some((car(constructor)?.as_symbol()?, slots))
+        }
+    };
+    if let Some((constructor, slots)) = constructor {
+        procedures.insert(0, RecordProcedure::Constructor(constructor, slots));
+    }
+    match predicate {
+        Value::Symbol(predicate) => procedures.insert(0, RecordProcedure::Predicate(predicate)),
+        Value::Bool(false) => {}
+        _ => return Err(invalid()),
+    }
+
+    let record_type = Value::boxed(
+        mc,
+        Object::RecordType(ObjRecordType::new(name, fields.into_boxed_slice())),
+    );
+    let line = cc.read().line;
+    define_constant(cc, name, record_type, mc)?;
+    for procedure in procedures {
+        // Only the last definition's result is kept. Local definitions don't leave one behind.
+        if cc.read().scope_depth == 0 {
+            cc.write(mc).chunk.write(OpCode::Pop.into(), line);
+        }
+        let (name, procedure) = procedure.build(record_type, line, mc);
+        define_constant(cc, name, procedure, mc)?;
+    }
+
+    Ok(())
+}
+
+/// A procedure `define-record-type` defines, along with its name
+enum RecordProcedure<'gc> {
+    /// Takes the fields whose slots aren't zero as its arguments, from those slots
+    Constructor(Symbol<'gc>, Vec<u8>),
+    Predicate(Symbol<'gc>),
+    Accessor(Symbol<'gc>, Value<'gc>),
+    Modifier(Symbol<'gc>, Value<'gc>),
+}
+
+impl<'gc> RecordProcedure<'gc> {
+    /// Makes the procedure, which hands the record type (and field index) on to a native along
+    /// with its own arguments
+    fn build(
+        self,
+        record_type: Value<'gc>,
+        line: usize,
+        mc: MutationContext<'gc, '_>,
+    ) -> (Symbol<'gc>, Value<'gc>) {
+        let mut chunk = Chunk::new();
+        let (name, native, arity, arg_count): (_, RegisteredNative, _, _) = match &self {
+            RecordProcedure::Constructor(name, slots) => {
+                let arity = slots.iter().filter(|slot| **slot != 0).count();
+                (*name, builtins::MAKE_RECORD, arity, slots.len() + 1)
+            }
+            RecordProcedure::Predicate(name) => (*name, builtins::IS_RECORD, 1, 2),
+            RecordProcedure::Accessor(name, _) => (*name, builtins::RECORD_REF, 1, 3),
+            RecordProcedure::Modifier(name, _) => (*name, builtins::RECORD_SET, 2, 4),
+        };
+        let native = ObjNative::registered(arg_count, false, native, None);
+        chunk.write_constant(Value::boxed(mc, Object::Native(native)), line);
+        chunk.write_constant(record_type, line);
+
+        let get_local = |chunk: &mut Chunk<'gc>, slot: u8| {
+            chunk.write(OpCode::GetLocal.into(), line);
+            chunk.write(slot, line);
+        };
+        match &self {
+            RecordProcedure::Constructor(_, slots) => {
+                for &slot in slots {
+                    match slot {
+                        0 => chunk.write(OpCode::Void.into(), line),
+                        slot => get_local(&mut chunk, slot),
+                    }
+                }
+            }
+            RecordProcedure::Predicate(_) => get_local(&mut chunk, 1),
+            RecordProcedure::Accessor(_, index) => {
+                chunk.write_constant(*index, line);
+                get_local(&mut chunk, 1);
+            }
+            RecordProcedure::Modifier(_, index) => {
+                chunk.write_constant(*index, line);
+                get_local(&mut chunk, 1);
+                get_local(&mut chunk, 2);
+            }
+        }
+        chunk.write(OpCode::TailCall.into(), line);
+        chunk.write(arg_count as u8, line);
+
+        let function = ObjFunction::new(mc, arity, false, chunk, Upvalues::default(), Some(name));
+        (name, Value::boxed(mc, Object::Function(function)))
+    }
+}
+
+/// Compiles `(define-syntax keyword transformer)`, which binds `keyword` to a macro transformer
+/// the way `define` would. Uses of macros are expanded before code is compiled, by looking their
+/// keywords up among the global variables, so macros can only be defined at the top level.
+fn syntax_definition<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    tail: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    if cc.read().parent.is_some() {
+        return Err(CompileError::Blah(
+            "define-syntax is only allowed at the top level of a program".into(),
+        ));
+    }
+
+    let keyword = car(tail)?.as_symbol()?;
+    let transformer = car(cdr(tail)?)?;
+    let global = parse_variable(&mut cc.write(mc), keyword)?;
+    expression(cc, transformer, false, Some(keyword), mc)?;
+    define_variable(&mut cc.write(mc), keyword, global);
+    Ok(())
+}
+
+/// Special forms the compiler knows, which aren't procedure calls even though they look like them
+const SPECIAL_FORMS: &[&str] = &[
+    "define",
+    "define-syntax",
+    "set!",
+    "if",
+    "lambda",
+    "begin",
+    "quote",
+    "define-record-type",
+    "let",
+    "assert",
+    "define-library",
+    "import",
+    "include",
+    "include-ci",
+];
+
+/// Checks whether `name` is the keyword of a special form
+pub fn is_special_form(name: &str) -> bool {
+    SPECIAL_FORMS.contains(&name)
+}
+
+/// Expands `form` once if it's one of the special forms the compiler rewrites into other forms
+/// (only `assert` so far), returning `None` if it isn't
+pub fn expand_once<'gc>(
+    form: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    match car(form) {
+        Ok(Value::Symbol(head)) if &*head.as_str() == "assert" => {
+            Ok(Some(assertion_expansion(car(cdr(form)?)?, mc)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Compiles `(assert expr)`, which raises an error showing `expr` if it turns out false
+fn assertion<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    expr: Value<'gc>,
+    in_tail_position: bool,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let form = assertion_expansion(expr, mc)?;
+    compound_form(cc, form, in_tail_position, None, mc)
+}
+
+/// Rewrites `(assert expr)`. When `expr` is a procedure call, its arguments are evaluated once up
+/// front so that their values can be included in the error too. `(assert (f x y))` becomes
+/// `((lambda (a b) (if (f a b) <void> (error "Assertion failed: (f x y)" a b))) x y)`, with `a`
+/// and `b` uninterned so that they can't shadow anything `f` refers to.
+fn assertion_expansion<'gc>(expr: Value<'gc>, mc: MutationContext<'gc, '_>) -> Result<Value<'gc>> {
+    let message = Value::boxed(
+        mc,
+        Object::String(ObjString::from(format!("Assertion failed: {}", expr))),
+    );
+    let error = Value::boxed(
+        mc,
+        Object::Native(ObjNative::registered(
+            2,
+            true,
+            ("error", builtins::error),
+            None,
+        )),
+    );
+    // Uninterned, but special forms are recognized by name alone
+    let symbol = |name: String| Value::Symbol(Symbol::uninterned(Token::new(mc, name.into())));
+    let list = |values: Vec<Value<'gc>>| -> Result<Value<'gc>> {
+        values
+            .into_iter()
+            .rev()
+            .try_fold(Value::Null, |acc, value| cons(value, acc, mc))
+    };
+
+    let is_call = match car(expr) {
+        Ok(Value::Symbol(head)) => !is_special_form(&head.as_str()),
+        Ok(_) => true,
+        Err(_) => false,
+    };
+    if !is_call {
+        let failure = list(vec![error, message])?;
+        return list(vec![symbol("if".into()), expr, Value::Void, failure]);
+    }
+
+    let procedure = car(expr)?;
+    let mut args = Vec::new();
+    let mut curr = cdr(expr)?;
+    while !curr.is_null() {
+        args.push(car(curr)?);
+        curr = cdr(curr)?;
+    }
+    let temps: Vec<_> = (0..args.len())
+        .map(|i| symbol(format!("arg{}", i)))
+        .collect();
+
+    let test = cons(procedure, list(temps.clone())?, mc)?;
+    let failure = cons(error, cons(message, list(temps.clone())?, mc)?, mc)?;
+    let body = list(vec![symbol("if".into()), test, Value::Void, failure])?;
+    let lambda = list(vec![symbol("lambda".into()), list(temps)?, body])?;
+    cons(lambda, list(args)?, mc)
+}
+
+/// Compiles `(define-library name declaration...)`, where each declaration is an `(export
+/// spec...)`, `(import set...)` or `(begin form...)`. The bodies are compiled into a thunk whose
+/// global variables are the library's own, which is handed to `%define-library` along with the
+/// rest of the declarations to bind the imports in and run.
+fn library_definition<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    tail: Value<'gc>,
+    in_tail_position: bool,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    if cc.read().parent.is_some() {
+        return Err(CompileError::Blah(
+            "define-library is only allowed at the top level of a program".into(),
+        ));
+    }
+
+    let name = car(tail)?;
+    let mut exports = Vec::new();
+    let mut imports = Vec::new();
+    let mut bodies = Vec::new();
+    let mut declarations = cdr(tail)?;
+    while !declarations.is_null() {
+        let declaration = car(declarations)?;
+        let keyword = car(declaration)?.as_symbol()?;
+        let list = match keyword.as_str().as_ref() {
+            "export" => &mut exports,
+            "import" => &mut imports,
+            "begin" => &mut bodies,
+            _ => {
+                return Err(CompileError::Blah(
+                    format!("Unsupported library declaration {}", declaration).into(),
+                ))
+            }
+        };
+        let mut curr = cdr(declaration)?;
+        while !curr.is_null() {
+            list.push(car(curr)?);
+            curr = cdr(curr)?;
+        }
+        declarations = cdr(declarations)?;
+    }
+    if bodies.is_empty() {
+        bodies.push(Value::Void);
+    }
+    let to_list = |values: Vec<Value<'gc>>| -> Result<Value<'gc>> {
+        values
+            .into_iter()
+            .rev()
+            .try_fold(Value::Null, |acc, value| cons(value, acc, mc))
+    };
+
+    let globals = GcCell::allocate(mc, GlobalTable::default());
+    let compiler = GcCell::allocate(mc, CompilerContext::with_globals(cc, globals));
+    parse_bodies(compiler, to_list(bodies)?, mc)?;
+    let body = {
+        let compiler = compiler.read();
+        ObjFunction::thunk(mc, compiler.chunk.clone(), compiler.upvalues.clone())
+    };
+
+    let line = cc.read().line;
+    let native = ObjNative::registered(4, false, builtins::DEFINE_LIBRARY, None);
+    cc.write(mc)
+        .chunk
+        .write_constant(Value::boxed(mc, Object::Native(native)), line);
+    literal(&mut cc.write(mc), name, mc)?;
+    literal(&mut cc.write(mc), to_list(exports)?, mc)?;
+    literal(&mut cc.write(mc), to_list(imports)?, mc)?;
+    literal(
+        &mut cc.write(mc),
+        Value::boxed(mc, Object::Function(body)),
+        mc,
+    )?;
+
+    let opcode = if in_tail_position {
+        OpCode::TailCall
+    } else {
+        OpCode::Call
+    };
+    cc.write(mc).chunk.write(opcode.into(), line);
+    cc.write(mc).chunk.write(4, line);
+
+    Ok(())
+}
+
+/// Compiles `(import set...)`, which binds what each import set brings in as globals. Code that
+/// has its own globals (in a library, or evaluated in an environment) passes them along to import
+/// into.
+fn import_declaration<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    sets: Value<'gc>,
+    in_tail_position: bool,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let line = cc.read().line;
+    let native = ObjNative::registered(3, true, builtins::IMPORT, None);
+    cc.write(mc)
+        .chunk
+        .write_constant(Value::boxed(mc, Object::Native(native)), line);
+    let environment = match cc.read().chunk.globals() {
+        Some(globals) => Value::boxed(mc, Object::Namespace(globals)),
+        None => Value::Bool(false),
+    };
+    literal(&mut cc.write(mc), environment, mc)?;
+    let mut count = 1;
+    let mut curr = sets;
+    while !curr.is_null() {
+        literal(&mut cc.write(mc), car(curr)?, mc)?;
+        if count == u8::MAX {
+            return Err(CompileError::Blah(
+                "Can't have more than 255 import sets".into(),
+            ));
+        }
+        count += 1;
+        curr = cdr(curr)?;
+    }
+
+    let opcode = if in_tail_position {
+        OpCode::TailCall
+    } else {
+        OpCode::Call
+    };
+    cc.write(mc).chunk.write(opcode.into(), line);
+    cc.write(mc).chunk.write(count, line);
+
+    Ok(())
+}
+
+/// Compiles `(include file...)`, which reads every datum in the files and compiles them in place
+/// of the `include`, as if they'd been written there. Relative paths are relative to the file doing
+/// the including. `include-ci` reads the files with their symbols folded to lower case.
+fn inclusion<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    files: Value<'gc>,
+    fold_case: bool,
+    in_tail_position: bool,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let tables = cc
+        .read()
+        .tables()
+        .ok_or_else(|| CompileError::Blah("Can't include files here".into()))?;
+    if !tables.files() {
+        return Err(CompileError::Blah(
+            "Can't include files without the file builtins".into(),
+        ));
+    }
+    let including = cc
+        .read()
+        .source
+        .as_ref()
+        .and_then(|source| source.file().cloned());
+
+    let mut forms = Vec::new();
+    let mut curr = files;
+    while !curr.is_null() {
+        let file = car(curr)?;
+        let name = match file {
+            Value::String(s) => s.as_str().into_owned(),
+            Value::Box(b) => b.read().as_string()?.as_str().into_owned(),
+            _ => {
+                return Err(CompileError::Blah(
+                    format!("'{}' is not a file name", file).into(),
+                ))
+            }
+        };
+        let path = match including
+            .as_deref()
+            .and_then(|file| Path::new(file).parent())
+        {
+            Some(directory) => directory.join(&name),
+            None => PathBuf::from(&name),
+        };
+
+        let couldnt_include = |err: &dyn Display| {
+            CompileError::Blah(format!("Couldn't include {}: {}", name, err).into())
+        };
+        let source = fs::read_to_string(&path).map_err(|err| couldnt_include(&err))?;
+        let pairs =
+            SchemeParser::parse(Rule::program, &source).map_err(|err| couldnt_include(&err))?;
+        let file: Rc<str> = Rc::from(path.to_string_lossy());
+        for pair in pairs {
+            let datum = read(pair, &tables, mc).map_err(|err| couldnt_include(&err))?;
+            if let Datum::Eof = datum {
+                continue;
+            }
+            let form = datum.into_boxed_value(mc);
+            let form = if fold_case {
+                folded(form, &tables, mc)?
+            } else {
+                form
+            };
+            forms.push((file.clone(), form));
+        }
+        curr = cdr(curr)?;
+    }
+    if forms.is_empty() {
+        return literal(&mut cc.write(mc), Value::Void, mc);
+    }
+
+    // Includes in the included code are relative to the file it came from
+    let outer_source = cc.read().source.clone();
+    let line = cc.read().line;
+    let count = forms.len();
+    for (i, (file, form)) in forms.into_iter().enumerate() {
+        cc.write(mc).source = Some(Rc::new(SourceMap::new(Some(file), line)));
+        let result = expression(cc, form, in_tail_position && i == count - 1, None, mc);
+        cc.write(mc).source = outer_source.clone();
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Folds the symbols in a datum read by `include-ci` to lower case
+fn folded<'gc>(
+    value: Value<'gc>,
+    tables: &Tables<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Value<'gc>> {
+    match value {
+        Value::Symbol(symbol) => {
+            let name = symbol.as_str().to_lowercase();
+            Ok(Value::Symbol(
+                tables.intern_symbol(Token::new(mc, name.into()), mc),
+            ))
+        }
+        Value::Box(object) => {
+            let children = match &*object.read() {
+                Object::Pair(pair) => vec![pair.car(), pair.cdr()],
+                Object::Vector(vector) => vector.as_slice().to_vec(),
+                _ => return Ok(value),
+            };
+            let children = children
+                .into_iter()
+                .map(|child| folded(child, tables, mc))
+                .collect::<Result<Vec<_>>>()?;
+            match &mut *object.write(mc) {
+                Object::Pair(pair) => {
+                    pair.set_car(children[0]);
+                    pair.set_cdr(children[1]);
+                }
+                Object::Vector(vector) => vector.as_slice_mut().copy_from_slice(&children),
+                _ => {}
+            }
+            Ok(value)
+        }
+        _ => Ok(value),
+    }
+}
+
+/// Binds `name` to a value that's already known at compile time
+fn define_constant<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    name: Symbol<'gc>,
+    value: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let global = parse_variable(&mut cc.write(mc), name)?;
+    let line = cc.read().line;
+    cc.write(mc).chunk.write_constant(value, line);
+    define_variable(&mut cc.write(mc), name, global);
+    Ok(())
+}
+
 fn let_definition<'gc>(
     cc: GcCell<'gc, CompilerContext<'gc>>,
     name: Option<Symbol<'gc>>,
@@ -254,7 +1045,7 @@ fn let_definition<'gc>(
     in_tail_position: bool,
     mc: MutationContext<'gc, '_>,
 ) -> Result<()> {
-    let line = 1;
+    let line = cc.read().line;
     let mut curr = bindings;
     let mut formals = Value::Null;
     let mut formals_curr = formals;
@@ -300,9 +1091,9 @@ fn let_definition<'gc>(
         OpCode::Call
     };
 
-    cc.write(mc).chunk.write(opcode.into(), line);
-
-    cc.write(mc).chunk.write(arg_count, line);
+    cc.write(mc)
+        .chunk
+        .write_operand(opcode, arg_count.into(), line);
 
     Ok(())
 }
@@ -311,15 +1102,15 @@ fn argument_list<'gc>(
     cc: GcCell<'gc, CompilerContext<'gc>>,
     args: Value<'gc>,
     mc: MutationContext<'gc, '_>,
-) -> Result<u8> {
+) -> Result<u16> {
     let mut arg_count = 0;
     let mut curr = args;
     while !curr.is_null() {
         expression(cc, car(curr)?, false, None, mc)?;
 
-        if arg_count == u8::MAX {
+        if arg_count == u16::MAX {
             return Err(CompileError::Blah(
-                "Can't have more than 255 arguments".to_string().into(),
+                "Can't have more than 65535 arguments".to_string().into(),
             ));
         }
         arg_count += 1;
@@ -339,24 +1130,44 @@ fn function<'gc>(
 ) -> Result<()> {
     let compiler = GcCell::allocate(mc, CompilerContext::with_parent(cc));
 
-    if bind_name {
-        compiler.write(mc).local0 = name;
+    assigned_variables(bodies, &mut compiler.write(mc).assigned);
+    if let Some(name) = name.filter(|_| bind_name) {
+        compiler.write(mc).local0 = Some(name);
+        box_if_assigned(&mut compiler.write(mc), name);
     }
 
     let (arity, variadic) = parse_formals(&mut compiler.write(mc), formals)?;
 
     let last_line = parse_bodies(compiler, bodies, mc)?;
+    compiler.read().warn_unused_locals();
+    if compiler.read().optimize {
+        optimize(&mut compiler.write(mc).chunk);
+    }
+    emit_function(cc, compiler, arity, variadic, name, last_line, mc)
+}
 
-    let object = Object::Function(ObjFunction::new(
+/// Emits the code that makes a procedure out of what `compiler` compiled, closing over any
+/// upvalues it needs
+fn emit_function<'gc>(
+    cc: GcCell<'gc, CompilerContext<'gc>>,
+    compiler: GcCell<'gc, CompilerContext<'gc>>,
+    arity: u8,
+    variadic: bool,
+    name: Option<Symbol<'gc>>,
+    last_line: usize,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let function = ObjFunction::new(
         mc,
         arity as usize,
         variadic,
         compiler.read().chunk.clone(),
         compiler.read().upvalues.clone(),
         name,
-    ));
+    );
+    verify(&function)?;
 
-    let value = Value::boxed(mc, object);
+    let value = Value::boxed(mc, Object::Function(function));
 
     if !compiler.read().upvalues.is_empty() {
         cc.write(mc).chunk.write(OpCode::Closure.into(), last_line);
@@ -368,12 +1179,11 @@ fn function<'gc>(
         for upvalue in compiler.read().upvalues.iter() {
             let is_local = if upvalue.is_local { 1 } else { 0 };
             cc.write(mc).chunk.write(is_local, last_line);
-            cc.write(mc).chunk.write(upvalue.index, last_line);
+            cc.write(mc).chunk.write_short(upvalue.index, last_line);
         }
     } else {
         cc.write(mc).chunk.write_constant(value, last_line);
     }
-
     Ok(())
 }
 
@@ -383,8 +1193,9 @@ fn parse_formals<'gc>(cc: &mut CompilerContext<'gc>, formals: Value<'gc>) -> Res
             let formal = p.car();
 
             // let line = formal.as_span().start_pos().line_col().0;
-            let param_constant = parse_variable(cc, formal.as_symbol()?)?;
-            define_variable(cc, param_constant as u8, 1);
+            let formal = formal.as_symbol()?;
+            let param_constant = parse_variable(cc, formal)?;
+            define_variable(cc, formal, param_constant);
 
             let (mut arity, variadic) = parse_formals(cc, p.cdr().into())?;
             arity += 1;
@@ -401,8 +1212,9 @@ fn parse_formals<'gc>(cc: &mut CompilerContext<'gc>, formals: Value<'gc>) -> Res
                     let formal = p.car();
 
                     // let line = formal.as_span().start_pos().line_col().0;
-                    let param_constant = parse_variable(cc, formal.as_symbol()?)?;
-                    define_variable(cc, param_constant as u8, 1);
+                    let formal = formal.as_symbol()?;
+                    let param_constant = parse_variable(cc, formal)?;
+                    define_variable(cc, formal, param_constant);
 
                     let (mut arity, variadic) = parse_formals(cc, p.cdr())?;
                     arity += 1;
@@ -419,7 +1231,7 @@ fn parse_formals<'gc>(cc: &mut CompilerContext<'gc>, formals: Value<'gc>) -> Res
         Value::Symbol(s) => {
             // let line = formals.as_span().start_pos().line_col().0;
             let param_constant = parse_variable(cc, s)?;
-            define_variable(cc, param_constant as u8, 1);
+            define_variable(cc, s, param_constant);
             Ok((1, true))
         }
         Value::Null => Ok((0, false)),
@@ -433,18 +1245,27 @@ fn parse_bodies<'gc>(
     mc: MutationContext<'gc, '_>,
 ) -> Result<usize> {
     // let mut last_line = body.as_span().end_pos().line_col().0;
-    let mut last_line = 1;
+    let mut last_line = cc.read().line;
     let mut in_tail_position = false;
 
+    // Where the code for the body before this one starts, until it's been checked for tail calls
+    let mut previous = None;
+
     while !in_tail_position {
         // last_line = body.as_span().end_pos().line_col().0;
-        last_line = 1;
+        last_line = cc.read().line;
         let body =
             car(remaining_bodies).map_err(|_| CompileError::Blah("Invalid bodies list".into()))?;
         remaining_bodies =
             cdr(remaining_bodies).map_err(|_| CompileError::Blah("Invalid bodies list".into()))?;
         in_tail_position = remaining_bodies.is_null();
+
+        let start = cc.read().chunk.code().len();
+        if let Some(previous) = previous.take() {
+            cc.read().warn_unreachable(previous..start, body);
+        }
         expression(cc, body, in_tail_position, None, mc)?;
+        previous = Some(start);
     }
 
     cc.write(mc).chunk.write(OpCode::Return.into(), last_line);
@@ -469,9 +1290,13 @@ fn declare_variable<'gc>(cc: &mut CompilerContext<'gc>, name: Symbol<'gc>) -> Re
     add_local(cc, name)
 }
 
-fn literal<'gc>(cc: &mut CompilerContext<'gc>, result: Value<'gc>) -> Result<()> {
+fn literal<'gc>(
+    cc: &mut CompilerContext<'gc>,
+    result: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
     // let line = span.start_pos().line_col().0;
-    let line = 1;
+    let line = cc.line;
     match result {
         Value::Bool(b) => {
             let opcode = if b { OpCode::True } else { OpCode::False };
@@ -480,6 +1305,16 @@ fn literal<'gc>(cc: &mut CompilerContext<'gc>, result: Value<'gc>) -> Result<()>
         }
         Value::Null => cc.chunk.write(OpCode::Null.into(), line),
         Value::Void => cc.chunk.write(OpCode::Void.into(), line),
+        Value::Box(b) => {
+            // String literals are immutable, so identical ones can share an interned constant
+            let constant = match (&*b.read(), cc.strings) {
+                (Object::String(string), Some(strings)) => {
+                    Value::String(strings.write(mc).intern(string.clone(), mc))
+                }
+                _ => result,
+            };
+            cc.chunk.write_constant(constant, line)
+        }
         _ => cc.chunk.write_constant(result, line),
     }
 
@@ -496,21 +1331,50 @@ fn resolve_local<'gc>(cc: &CompilerContext<'gc>, name: Symbol<'gc>) -> Option<us
     cc.locals.get_index_of(&name).map(|val| val + 1)
 }
 
+/// Gets the opcode that stands in for calling one of a handful of list primitives, if `name` is
+/// one of them, called with the right number of arguments, and not bound to a local variable.
+/// The VM checks the global is still the builtin when it runs the opcode, in case it's been
+/// redefined since.
+fn primitive_opcode(name: Symbol<'_>, get_op: OpCode, arg_count: u16) -> Option<OpCode> {
+    if get_op != OpCode::GetGlobal {
+        return None;
+    }
+
+    let (opcode, arity) = match &*name.as_str() {
+        "car" => (OpCode::Car, 1),
+        "cdr" => (OpCode::Cdr, 1),
+        "cons" => (OpCode::Cons, 2),
+        "null?" => (OpCode::IsNull, 1),
+        "pair?" => (OpCode::IsPair, 1),
+        _ => return None,
+    };
+    if arg_count == arity {
+        Some(opcode)
+    } else {
+        None
+    }
+}
+
+/// Emits the code to get or set a variable, and returns the opcode used
 fn named_variable<'gc>(
     cc: &mut CompilerContext<'gc>,
     symbol: Symbol<'gc>,
     is_assign: bool,
     mc: MutationContext<'gc, '_>,
-) {
+) -> OpCode {
     let (arg, get_op, set_op) = {
         let arg = resolve_local(cc, symbol);
         if let Some(arg) = arg {
+            if !is_assign {
+                cc.use_local(arg);
+            }
             (arg, OpCode::GetLocal, OpCode::SetLocal)
         } else if let Some(arg) = resolve_upvalue(cc, symbol, mc) {
             (arg, OpCode::GetUpvalue, OpCode::SetUpvalue)
         } else {
             (
-                cc.chunk.add_constant(Value::Symbol(symbol)),
+                cc.chunk
+                    .add_constant(Value::Symbol(global_symbol(cc, symbol))),
                 OpCode::GetGlobal,
                 OpCode::SetGlobal,
             )
@@ -519,8 +1383,23 @@ fn named_variable<'gc>(
 
     let opcode = if is_assign { set_op } else { get_op };
 
-    cc.chunk.write(opcode.into(), 1);
-    cc.chunk.write(arg as u8, 1);
+    match opcode {
+        OpCode::GetGlobal | OpCode::SetGlobal => {
+            cc.chunk.write_constant_operand(opcode, arg, cc.line)
+        }
+        // Assigned variables are kept in a cell, which is what's in the slot
+        _ if is_boxed(cc, symbol) => {
+            cc.chunk.write_operand(get_op, arg, cc.line);
+            let opcode = if is_assign {
+                OpCode::SetBox
+            } else {
+                OpCode::Unbox
+            };
+            cc.chunk.write(opcode.into(), cc.line);
+        }
+        _ => cc.chunk.write_operand(opcode, arg, cc.line),
+    }
+    opcode
 }
 
 fn resolve_upvalue<'gc>(
@@ -528,9 +1407,12 @@ fn resolve_upvalue<'gc>(
     name: Symbol<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Option<usize> {
-    let local = resolve_local(&cc.parent?.read(), name).map(|local| {
+    let parent = cc.parent?;
+    let local = resolve_local(&parent.read(), name);
+    let local = local.map(|local| {
+        parent.write(mc).use_local(local);
         cc.upvalues.add_upvalue(Upvalue {
-            index: local as u8,
+            index: local as u16,
             is_local: true,
         })
     });
@@ -541,14 +1423,14 @@ fn resolve_upvalue<'gc>(
 
     resolve_upvalue(&mut cc.parent?.write(mc), name, mc).map(|upvalue| {
         cc.upvalues.add_upvalue(Upvalue {
-            index: upvalue as u8,
+            index: upvalue as u16,
             is_local: false,
         })
     })
 }
 
 fn add_local<'gc>(cc: &mut CompilerContext<'gc>, name: Symbol<'gc>) -> Result<()> {
-    if cc.locals.len() > u8::MAX as usize + 1 {
+    if cc.locals.len() >= u16::MAX as usize {
         return Err(CompileError::Blah(
             "Too many local variables in function".into(),
         ));
@@ -564,20 +1446,82 @@ fn add_local<'gc>(cc: &mut CompilerContext<'gc>, name: Symbol<'gc>) -> Result<()
     Ok(())
 }
 
-fn define_variable(cc: &mut CompilerContext<'_>, global: u8, line: usize) {
+/// Emits the code that binds `name` once its value is in place, which for a local that's assigned
+/// to means moving the value into a cell
+fn define_variable<'gc>(cc: &mut CompilerContext<'gc>, name: Symbol<'gc>, global: usize) {
     if cc.scope_depth > 0 {
+        box_if_assigned(cc, name);
         return;
     }
 
-    cc.chunk.write(OpCode::DefineGlobal.into(), line);
-    cc.chunk.write(global, line);
+    let line = cc.line;
+    cc.chunk
+        .write_constant_operand(OpCode::DefineGlobal, global, line);
     cc.chunk.write(OpCode::Void.into(), line); // In case this is the last thing in the chunk
 }
 
+/// Moves local `name` into a cell if it's assigned to anywhere in the procedure
+fn box_if_assigned<'gc>(cc: &mut CompilerContext<'gc>, name: Symbol<'gc>) {
+    if !cc.assigned.contains(&name) {
+        return;
+    }
+    if let Some(slot) = resolve_local(cc, name) {
+        let line = cc.line;
+        cc.chunk.write_operand(OpCode::BoxLocal, slot, line);
+    }
+}
+
+/// Whether the local or upvalue `name` refers to is kept in a cell, because the procedure it's
+/// bound in assigns to it
+fn is_boxed<'gc>(cc: &CompilerContext<'gc>, name: Symbol<'gc>) -> bool {
+    if resolve_local(cc, name).is_some() {
+        return cc.assigned.contains(&name);
+    }
+    match cc.parent {
+        Some(parent) => is_boxed(&parent.read(), name),
+        None => false,
+    }
+}
+
+/// Collects the names `set!` assigns to anywhere in `form`, including in the procedures inside
+/// it. Names that are shadowed by the time they're assigned are collected all the same, which
+/// just means a few more locals are kept in cells than need to be.
+fn assigned_variables<'gc>(form: Value<'gc>, assigned: &mut HashSet<Symbol<'gc>>) {
+    let (head, tail) = match builtins::uncons(form) {
+        Some(pair) => pair,
+        None => return,
+    };
+    match head {
+        Value::Symbol(keyword) if &*keyword.as_str() == "quote" => return,
+        Value::Symbol(keyword) if &*keyword.as_str() == "set!" => {
+            if let Some((Value::Symbol(name), _)) = builtins::uncons(tail) {
+                assigned.insert(name);
+            }
+        }
+        _ => {}
+    }
+
+    let mut rest = form;
+    while let Some((element, next)) = builtins::uncons(rest) {
+        assigned_variables(element, assigned);
+        rest = next;
+    }
+}
+
 fn make_symbol<'gc>(cc: &mut CompilerContext<'gc>, name: Symbol<'gc>) -> usize {
+    let name = global_symbol(cc, name);
     cc.chunk.add_constant(Value::Symbol(name))
 }
 
+/// Gets the symbol a global variable is stored under. Identifiers renamed by a macro are
+/// uninterned so that they can't capture anything, but when they aren't bound locally they refer
+/// to the global variable with the same name.
+fn global_symbol<'gc>(cc: &CompilerContext<'gc>, name: Symbol<'gc>) -> Symbol<'gc> {
+    cc.symbols
+        .and_then(|symbols| symbols.read().get(&name))
+        .unwrap_or(name)
+}
+
 #[inline(always)]
 fn print_code(cc: &CompilerContext<'_>) {
     if cfg!(features = "debug-print-code") {
@@ -0,0 +1,203 @@
+use gc_arena::MutationContext;
+
+use super::Tables;
+use crate::builtins::uncons;
+use crate::chunk::Globals;
+use crate::memory::Symbol;
+use crate::object::{ObjPair, Object};
+use crate::value::Value;
+
+/// A use of a macro somewhere in a form
+#[derive(Debug, Copy, Clone)]
+pub struct MacroUse<'gc> {
+    /// The pair whose car is the use, or `None` if the use is the whole form
+    pub location: Option<Value<'gc>>,
+
+    /// The use itself, e.g. `(swap! a b)`
+    pub form: Value<'gc>,
+
+    /// The procedure that expands the use
+    pub transformer: Value<'gc>,
+}
+
+/// Finds the first use of a macro in `form`, going by the macros bound in `globals`. Quoted data
+/// isn't searched, and neither is anything that binds the macro's keyword locally.
+pub fn find_macro_use<'gc>(
+    form: Value<'gc>,
+    globals: Globals<'gc>,
+    tables: Tables<'gc>,
+) -> Option<MacroUse<'gc>> {
+    let mut walker = Walker {
+        globals,
+        tables,
+        bound: Vec::new(),
+    };
+    walker.form(form, None)
+}
+
+/// Gets the transformer of the macro `form` uses, if its keyword names one in `globals`
+pub fn macro_transformer<'gc>(
+    form: Value<'gc>,
+    globals: Globals<'gc>,
+    tables: Tables<'gc>,
+) -> Option<Value<'gc>> {
+    match uncons(form)? {
+        (Value::Symbol(keyword), _) => transformer(keyword, globals, tables),
+        _ => None,
+    }
+}
+
+/// Copies the pairs of a form, so that expanding the macros in it doesn't change the original
+pub fn copy_tree<'gc>(value: Value<'gc>, mc: MutationContext<'gc, '_>) -> Value<'gc> {
+    let mut elements = Vec::new();
+    let mut rest = value;
+    while let Some((car, cdr)) = uncons(rest) {
+        elements.push(copy_tree(car, mc));
+        rest = cdr;
+    }
+    elements.into_iter().rev().fold(rest, |cdr, car| {
+        Value::boxed(mc, Object::Pair(ObjPair::new(car, cdr)))
+    })
+}
+
+fn transformer<'gc>(
+    keyword: Symbol<'gc>,
+    globals: Globals<'gc>,
+    tables: Tables<'gc>,
+) -> Option<Value<'gc>> {
+    let keyword = tables.symbols.read().get(&keyword).unwrap_or(keyword);
+    match globals.read().get(&keyword)? {
+        Value::Box(object) => object.read().as_macro().ok().copied(),
+        _ => None,
+    }
+}
+
+/// Walks a form the way the compiler would, keeping track of the identifiers bound locally
+struct Walker<'gc> {
+    globals: Globals<'gc>,
+    tables: Tables<'gc>,
+    bound: Vec<Symbol<'gc>>,
+}
+
+impl<'gc> Walker<'gc> {
+    fn form(&mut self, form: Value<'gc>, location: Option<Value<'gc>>) -> Option<MacroUse<'gc>> {
+        let (head, tail) = uncons(form)?;
+        let keyword = match head {
+            Value::Symbol(keyword) if !self.bound.contains(&keyword) => keyword,
+            _ => return self.elements(form),
+        };
+        if let Some(transformer) = transformer(keyword, self.globals, self.tables) {
+            return Some(MacroUse {
+                location,
+                form,
+                transformer,
+            });
+        }
+
+        match &*keyword.as_str() {
+            "quote" | "define-record-type" | "import" | "include" | "include-ci" => None,
+            "lambda" => {
+                let (formals, body) = uncons(tail)?;
+                self.scope(identifiers(formals), body)
+            }
+            "define" | "define-syntax" => {
+                let (target, rest) = uncons(tail)?;
+                match uncons(target) {
+                    Some((_, formals)) => self.scope(identifiers(formals), rest),
+                    None => self.elements(rest),
+                }
+            }
+            "set!" => self.elements(uncons(tail)?.1),
+            "let" => {
+                let (first, rest) = uncons(tail)?;
+                let (name, bindings, body) = match first {
+                    Value::Symbol(name) => {
+                        let (bindings, body) = uncons(rest)?;
+                        (Some(name), bindings, body)
+                    }
+                    _ => (None, first, rest),
+                };
+
+                // The initial values are outside of the scope of the variables
+                let mut names = Vec::new();
+                let mut bindings = bindings;
+                while let Some((binding, rest)) = uncons(bindings) {
+                    let (variable, init) = uncons(binding)?;
+                    names.extend(variable.as_symbol().ok());
+                    if let Some(found) = self.elements(init) {
+                        return Some(found);
+                    }
+                    bindings = rest;
+                }
+                names.extend(name);
+                self.scope(names, body)
+            }
+            "define-library" => {
+                let mut declarations = tail;
+                while let Some((declaration, rest)) = uncons(declarations) {
+                    match uncons(declaration) {
+                        Some((Value::Symbol(keyword), body)) if &*keyword.as_str() == "begin" => {
+                            if let Some(found) = self.elements(body) {
+                                return Some(found);
+                            }
+                        }
+                        _ => {}
+                    }
+                    declarations = rest;
+                }
+                None
+            }
+            _ => self.elements(form),
+        }
+    }
+
+    /// Searches the forms in a list, such as the arguments of a procedure call
+    fn elements(&mut self, list: Value<'gc>) -> Option<MacroUse<'gc>> {
+        let mut rest = list;
+        while let Some((element, cdr)) = uncons(rest) {
+            if let Some(found) = self.form(element, Some(rest)) {
+                return Some(found);
+            }
+            rest = cdr;
+        }
+        None
+    }
+
+    /// Searches a body with `names` bound, along with whatever the body defines
+    fn scope(&mut self, names: Vec<Symbol<'gc>>, body: Value<'gc>) -> Option<MacroUse<'gc>> {
+        let depth = self.bound.len();
+        self.bound.extend(names);
+        let mut forms = body;
+        while let Some((form, rest)) = uncons(forms) {
+            if let Some((Value::Symbol(keyword), tail)) = uncons(form) {
+                if &*keyword.as_str() == "define" {
+                    let name = match uncons(tail) {
+                        Some((Value::Symbol(name), _)) => Some(name),
+                        Some((target, _)) => {
+                            uncons(target).and_then(|(name, _)| name.as_symbol().ok())
+                        }
+                        None => None,
+                    };
+                    self.bound.extend(name);
+                }
+            }
+            forms = rest;
+        }
+
+        let found = self.elements(body);
+        self.bound.truncate(depth);
+        found
+    }
+}
+
+/// Gets the identifiers in a lambda's formals, which can be a list, an improper list or a symbol
+fn identifiers(formals: Value<'_>) -> Vec<Symbol<'_>> {
+    let mut identifiers = Vec::new();
+    let mut rest = formals;
+    while let Some((formal, cdr)) = uncons(rest) {
+        identifiers.extend(formal.as_symbol().ok());
+        rest = cdr;
+    }
+    identifiers.extend(rest.as_symbol().ok());
+    identifiers
+}
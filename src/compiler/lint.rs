@@ -0,0 +1,86 @@
+//! Warnings about code that compiles but probably doesn't do what was meant, for when
+//! `CompileOptions::warnings` asks for them: variables bound by `let`, `lambda` or an internal
+//! `define` that are never used, and forms in a body that come after one that ends the procedure
+//! with a tail call, so they can't run.
+
+use core::ops::Range;
+
+use super::verify::decode;
+use super::CompilerContext;
+use crate::chunk::{describe_position, OpCode};
+use crate::value::Value;
+
+impl<'gc> CompilerContext<'gc> {
+    /// Whether the code is being checked for warnings
+    pub(super) fn is_linting(&self) -> bool {
+        self.warnings.is_some()
+    }
+
+    /// Notes that the code reads (or closes over) the local in `slot`
+    pub(super) fn use_local(&mut self, slot: usize) {
+        if self.is_linting() {
+            self.used_locals.insert(slot);
+        }
+    }
+
+    /// Warns about each local the code never used, once all of it's been compiled. Names starting
+    /// with `_` are meant to go unused, and names a macro renamed weren't written by whoever
+    /// would read the warning.
+    pub(super) fn warn_unused_locals(&self) {
+        if !self.is_linting() {
+            return;
+        }
+
+        for (index, name) in self.locals.0.iter().enumerate() {
+            let renamed = self
+                .symbols
+                .is_some_and(|symbols| symbols.read().get(name) != Some(*name));
+            if !self.used_locals.contains(&(index + 1))
+                && !renamed
+                && !name.as_str().starts_with('_')
+            {
+                self.warn(format!("unused variable {}", name), None);
+            }
+        }
+    }
+
+    /// Warns that `form` can't be reached if the code in `range` of the chunk, which was compiled
+    /// from the form before it in a body, can end the procedure
+    pub(super) fn warn_unreachable(&self, range: Range<usize>, form: Value<'gc>) {
+        if !self.is_linting() {
+            return;
+        }
+
+        let ends_procedure = decode(&self.chunk)
+            .map(|instructions| {
+                instructions.iter().any(|(offset, instruction, ..)| {
+                    range.contains(offset)
+                        && matches!(
+                            instruction,
+                            OpCode::TailCall | OpCode::TailCallLong | OpCode::Return
+                        )
+                })
+            })
+            .unwrap_or(false);
+        if ends_procedure {
+            let position = self
+                .source
+                .as_ref()
+                .and_then(|source| source.position(form));
+            self.warn("unreachable code after a tail call".into(), position);
+        }
+    }
+
+    /// Adds a warning about the code at `position`, or the form being compiled if that's `None`
+    fn warn(&self, message: String, position: Option<(usize, usize)>) {
+        let (line, column) = position.unwrap_or((self.line, self.column));
+        let file = self.source.as_ref().and_then(|source| source.file());
+        if let Some(warnings) = &self.warnings {
+            warnings.borrow_mut().push(format!(
+                "{} (at {})",
+                message,
+                describe_position(file, line, column)
+            ));
+        }
+    }
+}
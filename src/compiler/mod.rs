@@ -1,4 +1,7 @@
+use core::cell::RefCell;
 use core::str::FromStr;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 use gc_arena::{static_collect, Collect, CollectionContext, Gc, GcCell, MutationContext};
 use indexmap::set::{IndexSet, Iter};
@@ -6,24 +9,44 @@ use pest::error::{Error, ErrorVariant};
 use pest::iterators::Pair;
 use pest::{Position, Span};
 
-use crate::chunk::Chunk;
-use crate::memory::{Symbol, Token};
-use crate::object::{ObjPair, ObjString, ObjVector};
+use crate::chunk::{Chunk, Globals};
+use crate::memory::{StringTable, Symbol, SymbolTable, Token};
+use crate::object::{ObjPair, ObjString, ObjVector, Object};
 use crate::scanner::Rule;
-use crate::value::Datum;
+use crate::value::{Datum, Value};
 use crate::vm::VirtualMachine;
 
 pub mod bootstrap;
+pub mod expander;
+mod lint;
+mod peephole;
+pub mod verify;
 
 pub type Result<T> = std::result::Result<T, Error<Rule>>;
 
 /// An Upvalue generated by the compiler
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Upvalue {
-    index: u8,
+    index: u16,
     is_local: bool,
 }
 
+impl Upvalue {
+    pub fn new(index: u16, is_local: bool) -> Self {
+        Self { index, is_local }
+    }
+
+    /// Gets the slot (if local) or enclosing upvalue (if not) this upvalue captures
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Whether this upvalue captures a local of the immediately enclosing function
+    pub fn is_local(&self) -> bool {
+        self.is_local
+    }
+}
+
 /// Struct representing Upvalues generated by the compiler
 #[derive(Debug, Default, Clone)]
 pub struct Upvalues(IndexSet<Upvalue>);
@@ -60,7 +83,7 @@ impl Upvalues {
             return index;
         }
 
-        if upvalue_count == u8::MAX as usize + 1 {
+        if upvalue_count == u16::MAX as usize + 1 {
             panic!("Too many closure variables in function");
         }
 
@@ -102,6 +125,101 @@ unsafe impl<'gc> Collect for Locals<'gc> {
     }
 }
 
+/// Where the lists in a datum were read from, so the code compiled from them can be traced back
+/// to the source. Lists are identified by address, so a map only means anything while the datum
+/// it was recorded from is still alive.
+#[derive(Debug)]
+pub struct SourceMap {
+    file: Option<Rc<str>>,
+    start_line: usize,
+
+    /// Line and column (both starting at 1) of each list
+    positions: HashMap<usize, (usize, usize)>,
+}
+
+impl SourceMap {
+    /// Creates an empty map for a datum starting on `start_line` of `file`
+    pub fn new(file: Option<Rc<str>>, start_line: usize) -> Self {
+        Self {
+            file,
+            start_line,
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Gets the line the datum started on
+    pub fn start_line(&self) -> usize {
+        self.start_line
+    }
+
+    /// Gets the file the datum was read from
+    pub fn file(&self) -> Option<&Rc<str>> {
+        self.file.as_ref()
+    }
+
+    /// Records the line and column of every list in `value`, which was read from `pair`.
+    /// `first_line` is the line that the input `pair` was parsed from started on, and
+    /// `first_column` is how many characters of that line came before the input.
+    pub fn record(
+        &mut self,
+        pair: Pair<'_, Rule>,
+        value: Value<'_>,
+        first_line: usize,
+        first_column: usize,
+    ) {
+        let identity = match value {
+            Value::Box(object) => object.as_ptr() as usize,
+            _ => return,
+        };
+        let rule = pair.as_rule();
+        if !matches!(
+            rule,
+            Rule::proper_list | Rule::improper_list | Rule::abbreviation
+        ) {
+            return;
+        }
+
+        let (line, column) = pair.as_span().start_pos().line_col();
+        let column = if line == 1 {
+            first_column + column
+        } else {
+            column
+        };
+        self.positions
+            .insert(identity, (first_line + line - 1, column));
+        if rule == Rule::abbreviation {
+            return;
+        }
+
+        let mut rest = value;
+        for element in pair.into_inner() {
+            let (car, cdr) = match rest {
+                Value::Box(object) => match &*object.read() {
+                    Object::Pair(pair) => (pair.car(), pair.cdr()),
+                    _ => break,
+                },
+                _ => break,
+            };
+            self.record(element, car, first_line, first_column);
+            rest = cdr;
+        }
+    }
+
+    /// Gets the line and column a list was read from
+    pub fn position(&self, value: Value<'_>) -> Option<(usize, usize)> {
+        match value {
+            Value::Box(object) => self.positions.get(&(object.as_ptr() as usize)).copied(),
+            _ => None,
+        }
+    }
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        Self::new(None, 1)
+    }
+}
+
 #[derive(Debug, Default, Collect)]
 #[collect(no_drop)]
 pub struct CompilerContext<'gc> {
@@ -111,6 +229,45 @@ pub struct CompilerContext<'gc> {
     local0: Option<Symbol<'gc>>,
     chunk: Chunk<'gc>,
     scope_depth: usize,
+    strings: Option<GcCell<'gc, StringTable<'gc>>>,
+
+    /// Where symbols are interned, for reading files with `include` and finding the global
+    /// variables that identifiers renamed by macros refer to
+    symbols: Option<GcCell<'gc, SymbolTable<'gc>>>,
+
+    /// Whether `include` may read files (see `Tables`)
+    files: bool,
+
+    /// Where the code being compiled came from
+    #[collect(require_static)]
+    source: Option<Rc<SourceMap>>,
+
+    /// Source line of the form being compiled
+    line: usize,
+
+    /// Source column of the form being compiled, or 0 if it isn't known
+    column: usize,
+
+    /// Whether to wrap forms so they're printed as they run (see `CompileOptions`)
+    explain: bool,
+
+    /// Whether to run the peephole optimizer over the code once it's compiled (see
+    /// `CompileOptions`)
+    optimize: bool,
+
+    /// Where to put warnings about the code, or `None` if it isn't being checked (see
+    /// `CompileOptions`)
+    #[collect(require_static)]
+    warnings: Option<Rc<RefCell<Vec<String>>>>,
+
+    /// Slots of the locals that the code reads or closes over (see `resolve_local`)
+    #[collect(require_static)]
+    used_locals: HashSet<usize>,
+
+    /// Names that are assigned to with `set!` somewhere in the procedure being compiled. Locals
+    /// with these names are kept in a cell, so that a continuation's copy of the stack shares
+    /// them with the procedure instead of going back to the values they had when it was captured.
+    assigned: HashSet<Symbol<'gc>>,
 }
 
 impl<'gc> CompilerContext<'gc> {
@@ -122,19 +279,162 @@ impl<'gc> CompilerContext<'gc> {
             local0: None,
             chunk: Chunk::default(),
             scope_depth: 0,
+            strings: None,
+            symbols: None,
+            files: true,
+            source: None,
+            line: 1,
+            column: 0,
+            explain: false,
+            optimize: false,
+            warnings: None,
+            used_locals: HashSet::new(),
+            assigned: HashSet::new(),
+        }
+    }
+
+    /// Construct a CompilerContext that interns its string literals into `strings`
+    pub fn with_strings(strings: GcCell<'gc, StringTable<'gc>>) -> Self {
+        Self {
+            strings: Some(strings),
+            ..Self::new()
+        }
+    }
+
+    /// Construct a CompilerContext for code read from `source`, interning its string literals
+    /// (and anything it includes) into `tables`
+    pub fn with_source(tables: Tables<'gc>, source: SourceMap) -> Self {
+        let mut cc = Self::with_strings(tables.strings);
+        cc.symbols = Some(tables.symbols);
+        cc.files = tables.files;
+        cc.chunk.set_file(source.file().cloned());
+        cc.source = Some(Rc::new(source));
+        cc
+    }
+
+    /// Construct a CompilerContext for the top level of a library body inside `parent`, whose
+    /// global variables live in `globals`
+    pub fn with_globals(parent: GcCell<'gc, CompilerContext<'gc>>, globals: Globals<'gc>) -> Self {
+        let parent = parent.read();
+        let mut chunk = Chunk::default();
+        chunk.set_file(parent.chunk.file().cloned());
+        chunk.set_globals(Some(globals));
+        chunk.mark_column(parent.column);
+        Self {
+            strings: parent.strings,
+            symbols: parent.symbols,
+            files: parent.files,
+            source: parent.source.clone(),
+            line: parent.line,
+            column: parent.column,
+            explain: parent.explain,
+            optimize: parent.optimize,
+            warnings: parent.warnings.clone(),
+            chunk,
+            ..Self::new()
         }
     }
 
     pub fn with_parent(parent: GcCell<'gc, CompilerContext<'gc>>) -> Self {
+        let source = parent.read().source.clone();
+        let mut chunk = Chunk::default();
+        chunk.set_file(source.as_ref().and_then(|source| source.file().cloned()));
+        chunk.set_globals(parent.read().chunk.globals());
+        chunk.mark_column(parent.read().column);
         Self {
             parent: Some(parent),
             upvalues: Upvalues::default(),
             locals: Locals::default(),
             local0: None,
-            chunk: Chunk::default(),
+            chunk,
             scope_depth: parent.read().scope_depth + 1,
+            strings: parent.read().strings,
+            symbols: parent.read().symbols,
+            files: parent.read().files,
+            source,
+            line: parent.read().line,
+            column: parent.read().column,
+            explain: parent.read().explain,
+            optimize: parent.read().optimize,
+            warnings: parent.read().warnings.clone(),
+            used_locals: HashSet::new(),
+            assigned: HashSet::new(),
+        }
+    }
+
+    /// Moves on to compiling the form at `line` and `column` of the source
+    fn set_position(&mut self, line: usize, column: usize) {
+        self.line = line;
+        self.column = column;
+        self.chunk.mark_column(column);
+    }
+}
+
+/// Makes the symbols that the reader reads, so that ones that are spelled the same are shared
+pub trait Interner<'gc> {
+    fn intern_symbol(&self, token: Token<'gc>, mc: MutationContext<'gc, '_>) -> Symbol<'gc>;
+}
+
+impl<'gc> Interner<'gc> for VirtualMachine<'gc> {
+    fn intern_symbol(&self, token: Token<'gc>, mc: MutationContext<'gc, '_>) -> Symbol<'gc> {
+        VirtualMachine::intern_symbol(self, token, mc)
+    }
+}
+
+/// The tables a compiler interns into, so that it can read files while compiling
+#[derive(Debug, Copy, Clone)]
+pub struct Tables<'gc> {
+    symbols: GcCell<'gc, SymbolTable<'gc>>,
+    strings: GcCell<'gc, StringTable<'gc>>,
+
+    /// Whether `include` may read files
+    files: bool,
+}
+
+impl<'gc> Tables<'gc> {
+    pub fn new(
+        symbols: GcCell<'gc, SymbolTable<'gc>>,
+        strings: GcCell<'gc, StringTable<'gc>>,
+    ) -> Self {
+        Self {
+            symbols,
+            strings,
+            files: true,
         }
     }
+
+    /// Sets whether `include` may read files
+    pub fn with_files(mut self, files: bool) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// Whether `include` may read files
+    pub fn files(&self) -> bool {
+        self.files
+    }
+
+    /// Gets the table string literals are interned into
+    pub fn strings(&self) -> GcCell<'gc, StringTable<'gc>> {
+        self.strings
+    }
+}
+
+impl<'gc> Interner<'gc> for Tables<'gc> {
+    fn intern_symbol(&self, token: Token<'gc>, mc: MutationContext<'gc, '_>) -> Symbol<'gc> {
+        self.symbols.write(mc).intern(token)
+    }
+}
+
+impl<'gc> CompilerContext<'gc> {
+    /// Gets the tables the code being compiled interns into, if it was given them
+    pub(crate) fn tables(&self) -> Option<Tables<'gc>> {
+        Some(Tables {
+            symbols: self.symbols?,
+            strings: self.strings?,
+            files: self.files,
+        })
+    }
 }
 
 fn error(err: String, span: Span<'_>) -> Error<Rule> {
@@ -147,7 +447,7 @@ fn error_at(err: String, pos: Position<'_>) -> Error<Rule> {
 
 pub fn read_with_lineinfo<'gc>(
     current: Pair<'_, Rule>,
-    vm: &VirtualMachine<'gc>,
+    vm: &dyn Interner<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Datum<'gc>> {
     match current.as_rule() {
@@ -170,7 +470,7 @@ pub fn read_with_lineinfo<'gc>(
 
 pub fn read<'gc>(
     current: Pair<'_, Rule>,
-    vm: &VirtualMachine<'gc>,
+    vm: &dyn Interner<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Datum<'gc>> {
     match current.as_rule() {
@@ -193,7 +493,7 @@ pub fn read<'gc>(
 
 fn read_abbreviation<'gc>(
     current: Pair<'_, Rule>,
-    vm: &VirtualMachine<'gc>,
+    vm: &dyn Interner<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Gc<'gc, ObjPair<Datum<'gc>>>> {
     let span = current.as_span().clone();
@@ -209,25 +509,30 @@ fn read_abbreviation<'gc>(
         .next()
         .ok_or_else(|| error("Couldn't parse the quoted datum".to_string(), span.clone()))?;
 
-    match prefix.as_str() {
-        "'" => {
-            let symbol = vm.intern_symbol(Token::new(mc, "quote".into()), mc);
-            Ok(Gc::allocate(
-                mc,
-                ObjPair::new(
-                    Datum::Symbol(symbol),
-                    Datum::Pair(Gc::allocate(
-                        mc,
-                        ObjPair::new(read(quoted, vm, mc)?, Datum::Null),
-                    )),
-                ),
+    let name = match prefix.as_str() {
+        "'" => "quote",
+        "`" => "quasiquote",
+        "," => "unquote",
+        ",@" => "unquote-splicing",
+        _ => {
+            return Err(error(
+                format!("Tried to parse '{}' as an abbreviation", current_str),
+                span.clone(),
             ))
         }
-        _ => Err(error(
-            format!("Tried to parse '{}' as an abbreviation", current_str),
-            span.clone(),
-        )),
-    }
+    };
+
+    let symbol = vm.intern_symbol(Token::new(mc, name.into()), mc);
+    Ok(Gc::allocate(
+        mc,
+        ObjPair::new(
+            Datum::Symbol(symbol),
+            Datum::Pair(Gc::allocate(
+                mc,
+                ObjPair::new(read(quoted, vm, mc)?, Datum::Null),
+            )),
+        ),
+    ))
 }
 
 fn read_boolean(current: Pair<'_, Rule>) -> Result<bool> {
@@ -267,7 +572,7 @@ fn read_number(current: Pair<'_, Rule>) -> Result<f64> {
 
 fn read_proper_list<'gc>(
     current: Pair<'_, Rule>,
-    vm: &VirtualMachine<'gc>,
+    vm: &dyn Interner<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Datum<'gc>> {
     current
@@ -280,7 +585,7 @@ fn read_proper_list<'gc>(
 
 fn read_improper_list<'gc>(
     current: Pair<'_, Rule>,
-    vm: &VirtualMachine<'gc>,
+    vm: &dyn Interner<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Datum<'gc>> {
     let span = current.as_span().clone();
@@ -302,14 +607,36 @@ fn read_string<'gc>(
     current: Pair<'_, Rule>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Gc<'gc, ObjString>> {
-    let obj_string = ObjString::from(current.into_inner().as_str());
-    let value = Gc::allocate(mc, obj_string);
-    Ok(value)
+    // Strings aren't interned here, since `read` reads data at runtime too and the string table
+    // never lets go of them. The compiler interns the ones that turn out to be literals.
+    let obj_string = ObjString::from(unescape(current.into_inner().as_str()));
+    Ok(Gc::allocate(mc, obj_string))
+}
+
+/// Replaces the escape sequences within a string literal with the characters they denote
+fn unescape(raw: &str) -> String {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(character) = chars.next() {
+        if character != '\\' {
+            result.push(character);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(escaped) => result.push(escaped),
+            None => result.push('\\'),
+        }
+    }
+    result
 }
 
 fn read_symbol<'gc>(
     current: Pair<'_, Rule>,
-    vm: &VirtualMachine<'gc>,
+    vm: &dyn Interner<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Symbol<'gc>> {
     let symbol = vm.intern_symbol(Token::new(mc, current.as_str().into()), mc);
@@ -318,7 +645,7 @@ fn read_symbol<'gc>(
 
 fn read_vector<'gc>(
     current: Pair<'_, Rule>,
-    vm: &VirtualMachine<'gc>,
+    vm: &dyn Interner<'gc>,
     mc: MutationContext<'gc, '_>,
 ) -> Result<Gc<'gc, ObjVector<Datum<'gc>>>> {
     let vector: Result<Vec<_>> = current
@@ -0,0 +1,172 @@
+//! Peephole optimization, which tidies up the bytecode the compiler emits by rewriting short runs
+//! of instructions into shorter ones that do the same thing:
+//!
+//! - A value that's pushed only to be popped straight back off (e.g. `VOID; POP`) is left out
+//! - `TRUE; JUMP_IF_FALSE` never jumps, so the jump is left out, and `FALSE; JUMP_IF_FALSE`
+//!   always does, so it becomes a `JUMP`
+//! - A `JUMP` to the instruction right after it is left out
+//! - Code right after a `JUMP`, `RETURN` or `TAIL_CALL` that nothing jumps to can't run, so it's
+//!   left out
+//!
+//! Each rewrite can make room for others, so they're made until there are none left. Then the
+//! jumps are repatched to wherever the instructions they went to ended up.
+
+use std::collections::HashSet;
+
+use super::verify::{decode, Operand};
+use crate::chunk::{Chunk, OpCode};
+
+/// An instruction of the code being optimized
+struct Instruction {
+    /// Offset of the instruction in the code as it was compiled
+    start: usize,
+
+    opcode: OpCode,
+
+    /// The instruction's bytes, opcode and all
+    bytes: Vec<u8>,
+
+    /// Offset in the code as it was compiled that a jump goes to
+    target: Option<usize>,
+}
+
+/// Optimizes the bytecode of `chunk`. Code that can't be decoded is left alone, for `verify` to
+/// report.
+pub fn optimize(chunk: &mut Chunk<'_>) {
+    let code = chunk.code();
+    let end = code.len();
+    let mut instructions: Vec<_> = match decode(chunk) {
+        Ok(instructions) => instructions
+            .into_iter()
+            .map(|(start, opcode, operand, next)| Instruction {
+                start,
+                opcode,
+                bytes: code[start..next].to_vec(),
+                target: match operand {
+                    Operand::Target(target) => Some(target),
+                    _ => None,
+                },
+            })
+            .collect(),
+        Err(_) => return,
+    };
+
+    let mut changed = false;
+    while rewrite(&mut instructions, end) {
+        changed = true;
+    }
+    if !changed {
+        return;
+    }
+
+    let mut offsets = Vec::with_capacity(instructions.len());
+    let mut length = 0;
+    for instruction in &instructions {
+        offsets.push(length);
+        length += instruction.bytes.len();
+    }
+
+    let mut code = Vec::with_capacity(length);
+    let mut origins = Vec::with_capacity(length);
+    for (offset, instruction) in offsets.iter().zip(&instructions) {
+        let mut bytes = instruction.bytes.clone();
+        if let Some(target) = instruction.target {
+            let target = match following(&instructions, target) {
+                Some(index) => offsets[index],
+                None => length,
+            };
+            let jump = (target - (offset + bytes.len())) as u16;
+            bytes[1..].copy_from_slice(&jump.to_be_bytes());
+        }
+        code.extend(bytes);
+        origins.extend(instruction.start..instruction.start + instruction.bytes.len());
+    }
+    chunk.replace_code(code, &origins);
+}
+
+/// Makes a pass of rewrites over `instructions`, which were compiled from code `end` bytes long,
+/// returning whether anything changed
+fn rewrite(instructions: &mut Vec<Instruction>, end: usize) -> bool {
+    // Jumps to an instruction that was left out go to the next one that wasn't
+    let mut targets = HashSet::new();
+    for index in 0..instructions.len() {
+        if let Some(target) = instructions[index].target {
+            let target =
+                following(instructions, target).map_or(end, |next| instructions[next].start);
+            instructions[index].target = Some(target);
+            targets.insert(target);
+        }
+    }
+    let starts: Vec<_> = instructions
+        .iter()
+        .map(|instruction| instruction.start)
+        .collect();
+
+    let mut changed = false;
+    let mut kept: Vec<(Instruction, bool)> = Vec::with_capacity(instructions.len());
+    // Whether the next instruction is jumped to in place of ones that were left out
+    let mut jumped_to = false;
+    for (index, mut instruction) in instructions.drain(..).enumerate() {
+        let is_target = jumped_to || targets.contains(&instruction.start);
+        jumped_to = false;
+        let next = starts.get(index + 1).copied().unwrap_or(end);
+        let previous = kept.last().map(|(previous, _)| previous.opcode);
+        match (previous, instruction.opcode) {
+            (Some(previous), _) if ends_path(previous) && !is_target => {}
+            (Some(previous), OpCode::Pop) if only_pushes(previous) && !is_target => {
+                let (_, previous_is_target) = kept.pop().unwrap();
+                jumped_to = previous_is_target;
+            }
+            (Some(OpCode::True), OpCode::JumpIfFalse) if !is_target => {}
+            (Some(OpCode::False), OpCode::JumpIfFalse) if !is_target => {
+                instruction.opcode = OpCode::Jump;
+                instruction.bytes[0] = OpCode::Jump.into();
+                kept.push((instruction, false));
+            }
+            (_, OpCode::Jump) if instruction.target == Some(next) => jumped_to = is_target,
+            _ => {
+                kept.push((instruction, is_target));
+                continue;
+            }
+        }
+        changed = true;
+    }
+
+    instructions.extend(kept.into_iter().map(|(instruction, _)| instruction));
+    changed
+}
+
+/// Gets the index of the first instruction at or after `offset` of the code as it was compiled
+fn following(instructions: &[Instruction], offset: usize) -> Option<usize> {
+    let index = instructions.partition_point(|instruction| instruction.start < offset);
+    if index < instructions.len() {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+/// Whether the instruction after `opcode` can only be reached by jumping to it
+fn ends_path(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::Jump | OpCode::Return | OpCode::TailCall | OpCode::TailCallLong
+    )
+}
+
+/// Whether all `opcode` does is push a value
+fn only_pushes(opcode: OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::Void
+            | OpCode::Null
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Constant
+            | OpCode::ConstantLong
+            | OpCode::GetLocal
+            | OpCode::GetLocalLong
+            | OpCode::GetUpvalue
+            | OpCode::GetUpvalueLong
+    )
+}
@@ -0,0 +1,321 @@
+//! Checks a function's bytecode before it can run, so that a malformed chunk (e.g. one put
+//! together by hand, or read back from a damaged file) is an error rather than a panic. Each
+//! instruction has to decode, refer to constants, locals and upvalues that exist, and jump to the
+//! start of another instruction, and every path through the code has to leave enough values on
+//! the stack for each instruction it reaches.
+
+use core::convert::TryFrom;
+
+use super::bootstrap::CompileError;
+use crate::chunk::{Chunk, OpCode};
+use crate::object::ObjFunction;
+use crate::value::Value;
+
+type Result<T> = std::result::Result<T, CompileError>;
+
+/// An instruction's operands, decoded
+pub(super) enum Operand {
+    None,
+
+    /// Index into the constants
+    Constant(usize),
+
+    /// Local slot, upvalue slot, or argument count
+    Index(usize),
+
+    /// Offset of the instruction to jump to
+    Target(usize),
+
+    /// Upvalues a closure captures, as `(is_local, index)`
+    Closure(Vec<(bool, usize)>),
+}
+
+/// Checks the bytecode of `function`
+pub fn verify(function: &ObjFunction<'_>) -> Result<()> {
+    let chunk = function.chunk();
+    let instructions = decode(&chunk)?;
+    let upvalues = function.upvalues().len();
+
+    let mut starts = vec![None; chunk.code().len()];
+    for (index, (start, ..)) in instructions.iter().enumerate() {
+        starts[*start] = Some(index);
+    }
+
+    // The frame starts out with the procedure and its arguments on the stack
+    let mut heights: Vec<Option<usize>> = vec![None; chunk.code().len()];
+    let mut pending = vec![(0, function.arity() + 1)];
+    while let Some((offset, height)) = pending.pop() {
+        let index = starts
+            .get(offset)
+            .ok_or_else(|| invalid(offset, "is past the end of the code".into()))?;
+        let index = index.ok_or_else(|| invalid(offset, "is jumped into the middle of".into()))?;
+        let (_, instruction, operand, next) = &instructions[index];
+        let (instruction, next) = (*instruction, *next);
+        // Code doesn't always clean up after itself (e.g. the values of all but the last
+        // expression in a body stay on the stack until the procedure returns), so paths can
+        // disagree on how many values there are. What matters is how many there are at least.
+        match heights[offset] {
+            Some(seen) if seen <= height => continue,
+            _ => heights[offset] = Some(height),
+        }
+
+        let need = |count: usize| {
+            if height < count {
+                Err(invalid(
+                    offset,
+                    format!(
+                        "needs {} values on the stack but there are only {}",
+                        count, height
+                    ),
+                ))
+            } else {
+                Ok(())
+            }
+        };
+
+        match (instruction, operand) {
+            (OpCode::Constant | OpCode::ConstantLong, Operand::Constant(constant)) => {
+                if upvalue_count(chunk.read_constant(*constant)).unwrap_or(0) > 0 {
+                    return Err(invalid(
+                        offset,
+                        "loads a procedure with upvalues without closing over them".into(),
+                    ));
+                }
+                pending.push((next, height + 1));
+            }
+            (
+                OpCode::DefineGlobal
+                | OpCode::DefineGlobalLong
+                | OpCode::GetGlobal
+                | OpCode::GetGlobalLong
+                | OpCode::SetGlobal
+                | OpCode::SetGlobalLong,
+                Operand::Constant(constant),
+            ) => {
+                if chunk.read_constant(*constant).as_symbol().is_err() {
+                    return Err(invalid(offset, "names a global with a non-symbol".into()));
+                }
+                let height = match instruction {
+                    OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                        need(1)?;
+                        height - 1
+                    }
+                    OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                        need(1)?;
+                        height
+                    }
+                    _ => height + 1,
+                };
+                pending.push((next, height));
+            }
+            (OpCode::GetLocal | OpCode::GetLocalLong, Operand::Index(slot)) => {
+                check_local(offset, *slot, height)?;
+                pending.push((next, height + 1));
+            }
+            (
+                OpCode::SetLocal | OpCode::SetLocalLong | OpCode::BoxLocal | OpCode::BoxLocalLong,
+                Operand::Index(slot),
+            ) => {
+                check_local(offset, *slot, height)?;
+                pending.push((next, height));
+            }
+            (OpCode::GetUpvalue | OpCode::GetUpvalueLong, Operand::Index(slot)) => {
+                check_upvalue(offset, *slot, upvalues)?;
+                pending.push((next, height + 1));
+            }
+            (OpCode::SetUpvalue | OpCode::SetUpvalueLong, Operand::Index(slot)) => {
+                check_upvalue(offset, *slot, upvalues)?;
+                need(1)?;
+                pending.push((next, height));
+            }
+            (OpCode::JumpIfFalse, Operand::Target(target)) => {
+                need(1)?;
+                pending.push((next, height));
+                pending.push((*target, height));
+            }
+            (OpCode::Jump, Operand::Target(target)) => pending.push((*target, height)),
+            (OpCode::Call | OpCode::CallLong, Operand::Index(arg_count)) => {
+                need(arg_count + 1)?;
+                pending.push((next, height - arg_count));
+            }
+            (OpCode::TailCall | OpCode::TailCallLong, Operand::Index(arg_count)) => {
+                need(arg_count + 1)?;
+            }
+            (OpCode::Closure, Operand::Closure(captures)) => {
+                for (is_local, index) in captures {
+                    if *is_local {
+                        check_local(offset, *index, height)?;
+                    } else {
+                        check_upvalue(offset, *index, upvalues)?;
+                    }
+                }
+                pending.push((next, height + 1));
+            }
+            (OpCode::Pop, _) => {
+                need(1)?;
+                pending.push((next, height - 1));
+            }
+            (OpCode::Void | OpCode::Null | OpCode::True | OpCode::False, _) => {
+                pending.push((next, height + 1));
+            }
+            (OpCode::Return, _) => need(1)?,
+            (OpCode::Car | OpCode::Cdr | OpCode::IsNull | OpCode::IsPair, _) => {
+                need(2)?;
+                pending.push((next, height - 1));
+            }
+            (OpCode::Unbox, _) => {
+                need(1)?;
+                pending.push((next, height));
+            }
+            (OpCode::SetBox, _) => {
+                need(2)?;
+                pending.push((next, height - 1));
+            }
+            (OpCode::Cons, _) => {
+                need(3)?;
+                pending.push((next, height - 2));
+            }
+            _ => unreachable!("operands are decoded to match their instructions"),
+        }
+    }
+    Ok(())
+}
+
+/// Splits `chunk` into instructions, as their offset, opcode, operand and the offset of the next
+/// instruction
+pub(super) fn decode(chunk: &Chunk<'_>) -> Result<Vec<(usize, OpCode, Operand, usize)>> {
+    let constants = chunk.constants().len();
+    let mut instructions = Vec::new();
+    let mut reader = Reader {
+        code: chunk.code(),
+        start: 0,
+        offset: 0,
+    };
+    while reader.offset < reader.code.len() {
+        let start = reader.offset;
+        reader.start = start;
+        let instruction = OpCode::try_from(reader.byte()? as u8)
+            .map_err(|err| invalid(start, format!("has unknown opcode {}", err.number)))?;
+
+        let operand = match instruction {
+            OpCode::Constant | OpCode::DefineGlobal | OpCode::GetGlobal | OpCode::SetGlobal => {
+                Operand::Constant(reader.byte()?)
+            }
+            OpCode::ConstantLong
+            | OpCode::DefineGlobalLong
+            | OpCode::GetGlobalLong
+            | OpCode::SetGlobalLong => {
+                // Constant offsets are 24 bits, low byte first
+                let (low, middle, high) = (reader.byte()?, reader.byte()?, reader.byte()?);
+                Operand::Constant(low | middle << 8 | high << 16)
+            }
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::BoxLocal
+            | OpCode::Call
+            | OpCode::TailCall => Operand::Index(reader.byte()?),
+            OpCode::GetLocalLong
+            | OpCode::SetLocalLong
+            | OpCode::GetUpvalueLong
+            | OpCode::SetUpvalueLong
+            | OpCode::BoxLocalLong
+            | OpCode::CallLong
+            | OpCode::TailCallLong => Operand::Index(reader.short()?),
+            OpCode::JumpIfFalse | OpCode::Jump => {
+                let jump = reader.short()?;
+                Operand::Target(reader.offset + jump)
+            }
+            OpCode::Closure => {
+                let constant = reader.byte()?;
+                if constant >= constants {
+                    return Err(invalid(start, format!("has no constant {}", constant)));
+                }
+                let count = upvalue_count(chunk.read_constant(constant)).ok_or_else(|| {
+                    invalid(start, "makes a closure out of a non-procedure".into())
+                })?;
+                let mut captures = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let is_local = reader.byte()? > 0;
+                    captures.push((is_local, reader.short()?));
+                }
+                Operand::Closure(captures)
+            }
+            _ => Operand::None,
+        };
+        if let Operand::Constant(constant) = operand {
+            if constant >= constants {
+                return Err(invalid(start, format!("has no constant {}", constant)));
+            }
+        }
+        instructions.push((start, instruction, operand, reader.offset));
+    }
+    Ok(instructions)
+}
+
+/// Reads the bytes of the instruction at `start`
+struct Reader<'a> {
+    code: &'a [u8],
+    start: usize,
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<usize> {
+        let byte = self
+            .code
+            .get(self.offset)
+            .ok_or_else(|| invalid(self.start, "is cut off by the end of the code".into()))?;
+        self.offset += 1;
+        Ok(*byte as usize)
+    }
+
+    /// Reads a 16-bit operand, high byte first
+    fn short(&mut self) -> Result<usize> {
+        Ok(self.byte()? << 8 | self.byte()?)
+    }
+}
+
+/// Gets how many upvalues a procedure constant closes over, or `None` if it isn't one
+fn upvalue_count(constant: Value<'_>) -> Option<usize> {
+    let object = constant.as_object().ok()?;
+    let object = object.read();
+    object
+        .as_function()
+        .ok()
+        .map(|function| function.upvalues().len())
+}
+
+fn check_local(offset: usize, slot: usize, height: usize) -> Result<()> {
+    if slot >= height {
+        return Err(invalid(
+            offset,
+            format!(
+                "uses local {} but there are only {} on the stack",
+                slot, height
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn check_upvalue(offset: usize, slot: usize, upvalues: usize) -> Result<()> {
+    if slot >= upvalues {
+        return Err(invalid(
+            offset,
+            format!("uses upvalue {} but there are only {}", slot, upvalues),
+        ));
+    }
+    Ok(())
+}
+
+fn invalid(offset: usize, problem: String) -> CompileError {
+    CompileError::Blah(
+        format!(
+            "Invalid bytecode: the instruction at {} {}",
+            offset, problem
+        )
+        .into(),
+    )
+}
@@ -0,0 +1,57 @@
+//! Line coverage for Scheme programs, collected through the VM's hooks
+use core::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::vm::VmHooks;
+
+/// Counts how many times each line of each loaded file was run. Clones share their counts, so
+/// one can be installed on a VM while another is kept to write the report.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage(Rc<RefCell<BTreeMap<String, BTreeMap<usize, u64>>>>);
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the counts collected so far as an LCOV tracefile
+    pub fn write_lcov(&self, out: &mut impl Write) -> io::Result<()> {
+        for (file, lines) in self.0.borrow().iter() {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", file)?;
+            for (line, count) in lines {
+                writeln!(out, "DA:{},{}", line, count)?;
+            }
+            writeln!(out, "LF:{}", lines.len())?;
+            writeln!(
+                out,
+                "LH:{}",
+                lines.values().filter(|count| **count > 0).count()
+            )?;
+            writeln!(out, "end_of_record")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl VmHooks for Coverage {
+    fn on_compile(&self, file: &str, lines: &[usize]) {
+        let mut files = self.0.borrow_mut();
+        let counts = files.entry(file.to_string()).or_default();
+        for line in lines {
+            counts.entry(*line).or_insert(0);
+        }
+    }
+
+    fn on_line(&self, file: &str, line: usize) {
+        let mut files = self.0.borrow_mut();
+        *files
+            .entry(file.to_string())
+            .or_default()
+            .entry(line)
+            .or_insert(0) += 1;
+    }
+}
@@ -0,0 +1,82 @@
+//! Machine-readable diagnostics for external tools, written as one S-expression per event
+use core::cell::RefCell;
+use core::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::object::ObjString;
+use crate::value::{DisplayStyle, Print};
+use crate::vm::{InterpretError, VmHooks};
+
+/// Writes an event for every compile unit, error, warning and garbage collection to an output
+/// (e.g. the file given to `--diagnostics-port`), one per line:
+///
+/// ```text
+/// (compile (file "main.scm") (lines 1 2 3))
+/// (error (message "runtime error: 5 is not a pair") (file "main.scm") (form 3))
+/// (warning (message "unused variable x"))
+/// (gc (allocated 52736))
+/// ```
+///
+/// Clones share the output.
+#[derive(Clone)]
+pub struct Diagnostics(Rc<RefCell<Box<dyn Write>>>);
+
+impl Diagnostics {
+    pub fn new(out: impl Write + 'static) -> Self {
+        Self(Rc::new(RefCell::new(Box::new(out))))
+    }
+
+    /// Writes out an event. Diagnostics are best effort, so failing to write one isn't an error
+    /// for the program being run.
+    fn event(&self, event: fmt::Arguments<'_>) {
+        let mut out = self.0.borrow_mut();
+        let _ = writeln!(out, "{}", event);
+        let _ = out.flush();
+    }
+}
+
+impl fmt::Debug for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Diagnostics").finish()
+    }
+}
+
+/// Renders a Rust string as a Scheme string literal
+fn string(string: &str) -> String {
+    ObjString::from(string)
+        .styled(DisplayStyle::Write)
+        .to_string()
+}
+
+impl VmHooks for Diagnostics {
+    fn on_compile(&self, file: &str, lines: &[usize]) {
+        let lines: Vec<_> = lines.iter().map(|line| line.to_string()).collect();
+        self.event(format_args!(
+            "(compile (file {}) (lines {}))",
+            string(file),
+            lines.join(" ")
+        ));
+    }
+
+    fn on_error(&self, error: &InterpretError) {
+        let message = string(&error.to_string());
+        match error {
+            InterpretError::LoadError { file, form, .. } => self.event(format_args!(
+                "(error (message {}) (file {}) (form {}))",
+                message,
+                string(file),
+                form
+            )),
+            _ => self.event(format_args!("(error (message {}))", message)),
+        }
+    }
+
+    fn on_warning(&self, message: &str) {
+        self.event(format_args!("(warning (message {}))", string(message)));
+    }
+
+    fn on_gc(&self, allocated: usize) {
+        self.event(format_args!("(gc (allocated {}))", allocated));
+    }
+}
@@ -8,8 +8,13 @@ pub mod arena;
 mod builtins;
 pub mod chunk;
 pub mod compiler;
+pub mod coverage;
+pub mod diagnostics;
 pub mod memory;
 pub mod object;
+mod platform;
+mod printer;
 pub mod scanner;
+pub mod serialize;
 pub mod value;
 pub mod vm;
@@ -1,56 +1,204 @@
+use std::fs::File;
+use std::io::BufWriter;
 use std::process::exit;
 
-use cheshire::arena::GcArena;
-use cheshire::vm::VirtualMachine;
+use cheshire::arena::{self, GcArena};
+use cheshire::coverage::Coverage;
+use cheshire::diagnostics::Diagnostics;
+use cheshire::vm::{InterpretError, VirtualMachine, VmHooks};
 use gc_arena::ArenaParameters;
 
+/// Where coverage is written when `--coverage` doesn't name a file
+const DEFAULT_COVERAGE_FILE: &str = "lcov.info";
+
+/// Command line options
+#[derive(Debug, Default)]
+struct Options {
+    /// Program to run, or `None` for the REPL
+    path: Option<String>,
+
+    /// File to write an LCOV coverage report to
+    coverage: Option<String>,
+
+    /// How many procedure calls apart to snapshot the machine for `,back`, if at all
+    time_travel: Option<usize>,
+
+    /// How deeply nested the forms `--explain` shows can be, if it's on
+    explain: Option<usize>,
+
+    /// Whether to warn about unused variables and unreachable code
+    warnings: bool,
+
+    /// File to write machine-readable diagnostics to
+    diagnostics: Option<String>,
+
+    /// How many frames deep calls can nest, if not the default
+    max_depth: Option<usize>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Self::default();
+
+        // `cheshire run <path>` is the same as `cheshire <path>`
+        let args = match args.first().map(String::as_str) {
+            Some("run") => &args[1..],
+            _ => args,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--coverage" {
+                options.coverage = Some(DEFAULT_COVERAGE_FILE.to_string());
+            } else if let Some(file) = arg.strip_prefix("--coverage=") {
+                options.coverage = Some(file.to_string());
+            } else if arg == "--time-travel" {
+                options.time_travel = Some(1);
+            } else if let Some(interval) = arg.strip_prefix("--time-travel=") {
+                options.time_travel = Some(interval.parse().ok()?);
+            } else if arg == "--explain" {
+                options.explain = Some(usize::MAX);
+            } else if let Some(depth) = arg.strip_prefix("--explain=") {
+                options.explain = Some(depth.parse().ok()?);
+            } else if arg == "--warnings" {
+                options.warnings = true;
+            } else if let Some(limit) = arg.strip_prefix("--max-depth=") {
+                options.max_depth = Some(limit.parse().ok()?);
+            } else if arg == "--diagnostics-port" {
+                options.diagnostics = Some(args.next()?.clone());
+            } else if let Some(file) = arg.strip_prefix("--diagnostics-port=") {
+                options.diagnostics = Some(file.to_string());
+            } else if arg.starts_with("--") || options.path.is_some() {
+                return None;
+            } else {
+                options.path = Some(arg.clone());
+            }
+        }
+
+        Some(options)
+    }
+}
+
 pub fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() == 1 {
-        repl();
-    } else if args.len() == 2 {
-        run_file(args[1].clone());
-    } else {
-        eprintln!("Usage: {} [path]", args[0]);
-        exit(64);
+    let options = match Options::parse(&args[1..]) {
+        Some(options) => options,
+        None => {
+            eprintln!(
+                "Usage: {} [run] [--coverage[=file]] [--time-travel[=calls]] [--explain[=depth]] \
+                 [--warnings] [--max-depth=frames] [--diagnostics-port file] [path]",
+                args[0]
+            );
+            exit(64);
+        }
+    };
+
+    let coverage = options.coverage.as_ref().map(|_| Coverage::new());
+    let diagnostics = options
+        .diagnostics
+        .as_ref()
+        .map(|file| match File::create(file) {
+            Ok(out) => Diagnostics::new(out),
+            Err(err) => {
+                eprintln!("Couldn't open {} for diagnostics: {}", file, err);
+                exit(74);
+            }
+        });
+    let hooks = hooks(coverage.clone(), diagnostics);
+    let code = match options.path.clone() {
+        Some(path) => run_file(path, hooks, &options),
+        None => repl(hooks, &options),
+    };
+
+    if let (Some(coverage), Some(file)) = (coverage, options.coverage) {
+        let written =
+            File::create(&file).and_then(|out| coverage.write_lcov(&mut BufWriter::new(out)));
+        if let Err(err) = written {
+            eprintln!("Couldn't write coverage to {}: {}", file, err);
+            exit(74);
+        }
+    }
+
+    exit(code);
+}
+
+/// Combines whichever of coverage and diagnostics were asked for into the VM's hooks
+fn hooks(coverage: Option<Coverage>, diagnostics: Option<Diagnostics>) -> Option<Box<dyn VmHooks>> {
+    match (coverage, diagnostics) {
+        (Some(coverage), Some(diagnostics)) => Some(Box::new((coverage, diagnostics))),
+        (Some(coverage), None) => Some(Box::new(coverage)),
+        (None, Some(diagnostics)) => Some(Box::new(diagnostics)),
+        (None, None) => None,
+    }
+}
+
+fn install_hooks(arena: &mut GcArena, hooks: Option<Box<dyn VmHooks>>) {
+    if hooks.is_some() {
+        arena.mutate(|_, vm| vm.set_hooks(hooks));
     }
 }
 
-fn repl() {
+fn install_debugging(arena: &mut GcArena, options: &Options) {
+    arena.mutate(|_, vm| {
+        vm.interrupt_on_ctrl_c();
+        vm.set_time_travel(options.time_travel);
+        vm.set_explain(options.explain);
+        vm.set_warnings(options.warnings);
+        if let Some(limit) = options.max_depth {
+            vm.set_max_depth(limit);
+        }
+    });
+}
+
+fn repl(hooks: Option<Box<dyn VmHooks>>, options: &Options) -> i32 {
     let mut arena = GcArena::new(ArenaParameters::default(), |mc| VirtualMachine::repl(mc));
+    install_hooks(&mut arena, hooks);
+    install_debugging(&mut arena, options);
     loop {
-        arena.mutate(|mc, vm| {
+        let halted = arena.mutate(|mc, vm| {
             let result = vm.interpret(mc);
             match result {
                 Ok(_) => {}
                 // Err(err) => eprintln!("{}", err),
                 Err(err) => {
-                    eprintln!("{}", err);
+                    vm.report_error(&err, mc);
                     vm.reset_repl(mc);
                 }
             }
+            vm.is_halted()
         });
+        if halted {
+            return 0;
+        }
 
-        arena.collect_debt();
+        arena::collect_debt(&mut arena);
     }
 }
 
-fn run_file(path: String) {
+fn run_file(path: String, hooks: Option<Box<dyn VmHooks>>, options: &Options) -> i32 {
     let mut arena = GcArena::new(ArenaParameters::default(), |mc| {
         VirtualMachine::load_file(path, mc)
     });
+    install_hooks(&mut arena, hooks);
+    install_debugging(&mut arena, options);
     loop {
-        arena.mutate(|mc, vm| {
-            let result = vm.interpret(mc);
-            match result {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("{}", err);
-                    std::process::exit(1);
-                }
+        let result = arena.mutate(|mc, vm| match vm.interpret(mc) {
+            Ok(_) => Ok(vm.is_halted()),
+            Err(err) => {
+                vm.report_error(&err, mc);
+                // Exit the way the shell expects a program stopped by Ctrl+C to
+                Err(match err {
+                    InterpretError::Interrupted => 130,
+                    _ => 1,
+                })
             }
         });
+        match result {
+            Ok(true) => return 0,
+            Ok(false) => {}
+            Err(code) => return code,
+        }
 
-        arena.collect_debt();
+        arena::collect_debt(&mut arena);
     }
 }
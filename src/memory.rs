@@ -1,6 +1,7 @@
 use core::fmt;
 use core::hash::{Hash, Hasher};
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
 
 use gc_arena::{Gc, MutationContext};
@@ -98,6 +99,12 @@ impl<'gc> Token<'gc> {
     }
 }
 
+impl Borrow<ObjString> for Token<'_> {
+    fn borrow(&self) -> &ObjString {
+        &self.0
+    }
+}
+
 impl<'gc> From<Gc<'gc, ObjString>> for Token<'gc> {
     fn from(value: Gc<'gc, ObjString>) -> Self {
         Self(value)
@@ -115,4 +122,35 @@ impl<'gc> SymbolTable<'gc> {
             .entry(token)
             .or_insert_with(|| Symbol::uninterned(token))
     }
+
+    /// Gets the interned symbol spelled `name`, if there is one
+    pub fn get(&self, name: &ObjString) -> Option<Symbol<'gc>> {
+        self.0.get(name).copied()
+    }
+}
+
+/// Pool of immutable string constants, so that identical literals share a single allocation
+#[derive(Clone, Collect, Debug, Default)]
+#[collect(no_drop)]
+pub struct StringTable<'gc>(HashSet<Token<'gc>>);
+
+impl<'gc> StringTable<'gc> {
+    pub fn intern(
+        &mut self,
+        string: ObjString,
+        mc: MutationContext<'gc, '_>,
+    ) -> Gc<'gc, ObjString> {
+        if let Some(token) = self.0.get(&string) {
+            return token.0;
+        }
+
+        let token = Token::new(mc, string);
+        self.0.insert(token);
+        token.0
+    }
+
+    /// Whether a string spelled like `string` has been interned
+    pub fn contains(&self, string: &ObjString) -> bool {
+        self.0.contains(string)
+    }
 }
@@ -1,11 +1,12 @@
 use core::convert::TryFrom;
 use core::fmt;
+use core::ops::Range;
 
-use gc_arena::GcCell;
+use gc_arena::{GcCell, MutationContext};
 use gc_arena_derive::Collect;
 
 use super::{ObjClosure, ObjFunction, ObjNative, Object};
-use crate::value::TypeError;
+use crate::value::{TypeError, Value};
 use crate::vm::Stack;
 
 /// Represents an ongoing execution of a procedure
@@ -32,6 +33,16 @@ pub enum Procedure<'gc> {
     Native(ObjNative<'gc>),
 }
 
+impl fmt::Display for Procedure<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closure { closure, .. } => write!(f, "{}", closure),
+            Self::Function { function, .. } => write!(f, "{}", function),
+            Self::Native(native) => write!(f, "{}", native),
+        }
+    }
+}
+
 /// Representation of a function invokation, a currently executing function
 #[derive(Debug, Clone, Collect)]
 #[collect(no_drop)]
@@ -42,9 +53,12 @@ pub struct ObjContinuation<'gc> {
     /// Currently executing procedure
     procedure: Procedure<'gc>,
 
-    /// Local stack
+    /// Stack the procedure runs on, which frames running bytecode share with their caller
     stack: Stack<'gc>,
 
+    /// Slot of the stack holding the procedure, where its frame starts
+    stack_base: usize,
+
     /// Top of the stack
     stack_top: usize,
 
@@ -53,24 +67,156 @@ pub struct ObjContinuation<'gc> {
 
     /// Current output port
     current_output_port: GcCell<'gc, Object<'gc>>,
+
+    /// Current error port
+    current_error_port: GcCell<'gc, Object<'gc>>,
+
+    /// Installed exception handlers, innermost first
+    handlers: Value<'gc>,
+
+    /// Files being loaded, innermost first
+    loading: Value<'gc>,
+
+    /// Tag of the prompt installed by this frame, if it belongs to `call-with-prompt`
+    prompt: Option<Value<'gc>>,
+
+    /// Port opened by this frame's `call-with-input-file` (or one of its relatives), which is
+    /// closed if the frame is escaped from instead of returned to
+    port: Option<GcCell<'gc, Object<'gc>>>,
+
+    /// Whether this is a delimited continuation captured by `abort-to-prompt`, which runs on top
+    /// of the continuation it's applied in instead of replacing it
+    delimited: bool,
+}
+
+/// The current input, output and error ports, which a continuation goes back to when it's applied
+#[derive(Clone, Copy, Debug)]
+pub struct CurrentPorts<'gc> {
+    pub input: GcCell<'gc, Object<'gc>>,
+    pub output: GcCell<'gc, Object<'gc>>,
+    pub error: GcCell<'gc, Object<'gc>>,
 }
 
 impl<'gc> ObjContinuation<'gc> {
-    /// Creates a new continuation
+    /// Creates a new continuation, for a frame using the `region` of `stack`
     pub fn new(
         frames: Option<GcCell<'gc, ObjContinuation<'gc>>>,
         procedure: Procedure<'gc>,
         stack: Stack<'gc>,
-        current_input_port: GcCell<'gc, Object<'gc>>,
-        current_output_port: GcCell<'gc, Object<'gc>>,
+        region: Range<usize>,
+        ports: CurrentPorts<'gc>,
+        handlers: Value<'gc>,
+        loading: Value<'gc>,
     ) -> Self {
         Self {
             frames,
             procedure,
             stack,
-            stack_top: stack.read().len(),
-            current_input_port,
-            current_output_port,
+            stack_base: region.start,
+            stack_top: region.end,
+            current_input_port: ports.input,
+            current_output_port: ports.output,
+            current_error_port: ports.error,
+            handlers,
+            loading,
+            prompt: None,
+            port: None,
+            delimited: false,
+        }
+    }
+
+    /// Copies this continuation and every frame below it, so that running it can't disturb the
+    /// stacks of the original. First-class continuations are captured and applied this way so they
+    /// can be re-entered any number of times.
+    pub fn snapshot(&self, mc: MutationContext<'gc, '_>) -> Self {
+        self.snapshot_onto(None, mc)
+    }
+
+    /// Like `snapshot`, but the outermost copied frame returns to `parent` when it finishes
+    /// instead of its original parent. Delimited continuations are applied this way.
+    pub fn snapshot_onto(
+        &self,
+        parent: Option<GcCell<'gc, ObjContinuation<'gc>>>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Self {
+        let mut chain = Vec::new();
+        let mut frame = self.frames;
+        while let Some(current) = frame {
+            chain.push(current);
+            frame = current.read().frames;
+        }
+
+        let tops = chain.iter().map(|frame| frame.read().live_stack());
+        let stacks = copy_stacks(tops.chain([self.live_stack()]), mc);
+
+        // Rebuild from the outermost frame inwards so each copy can point at its copied parent
+        match chain.pop() {
+            Some(outermost) => {
+                let outermost = GcCell::allocate(mc, outermost.read().copy_frame(parent, &stacks));
+                let frames = chain.into_iter().rev().fold(outermost, |parent, frame| {
+                    GcCell::allocate(mc, frame.read().copy_frame(Some(parent), &stacks))
+                });
+                self.copy_frame(Some(frames), &stacks)
+            }
+            None => self.copy_frame(parent, &stacks),
+        }
+    }
+
+    /// Captures the frames from `frame` up to (but not including) the `prompt` frame as a
+    /// delimited continuation, or returns `None` if there are no frames in between
+    pub fn delimit(
+        frame: GcCell<'gc, ObjContinuation<'gc>>,
+        prompt: GcCell<'gc, ObjContinuation<'gc>>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Option<Self> {
+        let mut chain = Vec::new();
+        let mut current = Some(frame);
+        while let Some(frame) = current {
+            if GcCell::ptr_eq(frame, prompt) {
+                break;
+            }
+            chain.push(frame);
+            current = frame.read().frames;
+        }
+
+        let (innermost, rest) = chain.split_first()?;
+        let stacks = copy_stacks(chain.iter().map(|frame| frame.read().live_stack()), mc);
+        let frames = rest.iter().rev().fold(None, |parent, frame| {
+            Some(GcCell::allocate(
+                mc,
+                frame.read().copy_frame(parent, &stacks),
+            ))
+        });
+        let mut delimited = innermost.read().copy_frame(frames, &stacks);
+        delimited.delimited = true;
+        Some(delimited)
+    }
+
+    /// Copies just this frame, switching it over to its stack's copy in `stacks`. The copy is an
+    /// ordinary frame even if this is a delimited continuation.
+    fn copy_frame(
+        &self,
+        frames: Option<GcCell<'gc, ObjContinuation<'gc>>>,
+        stacks: &[(Stack<'gc>, Stack<'gc>)],
+    ) -> Self {
+        let stack = stacks
+            .iter()
+            .find(|(original, _)| GcCell::ptr_eq(*original, self.stack))
+            .map_or(self.stack, |(_, copy)| *copy);
+        Self {
+            frames,
+            procedure: self.procedure.clone(),
+            stack,
+            stack_base: self.stack_base,
+            stack_top: self.stack_top,
+            current_input_port: self.current_input_port,
+            current_output_port: self.current_output_port,
+            current_error_port: self.current_error_port,
+            handlers: self.handlers,
+            loading: self.loading,
+            prompt: self.prompt,
+            port: self.port,
+            delimited: false,
         }
     }
 
@@ -79,6 +225,12 @@ impl<'gc> ObjContinuation<'gc> {
         self.frames
     }
 
+    /// Sets the frames this continuation returns to, for a frame moving onto the heap from the
+    /// VM's frame list
+    pub(crate) fn set_frames(&mut self, frames: Option<GcCell<'gc, ObjContinuation<'gc>>>) {
+        self.frames = frames;
+    }
+
     /// Gets the procedure associated with this continuation
     pub fn procedure(&self) -> &Procedure<'gc> {
         &self.procedure
@@ -89,11 +241,21 @@ impl<'gc> ObjContinuation<'gc> {
         self.stack
     }
 
+    /// Gets the slot of the stack where this frame starts
+    pub fn stack_base(&self) -> usize {
+        self.stack_base
+    }
+
     /// Gets the length of the stack at the time the continuation was created
     pub fn stack_top(&self) -> usize {
         self.stack_top
     }
 
+    /// Gets the stack along with how much of it this frame uses
+    fn live_stack(&self) -> (Stack<'gc>, usize) {
+        (self.stack, self.stack_top)
+    }
+
     /// Gets the current input port
     pub fn current_input_port(&self) -> GcCell<'gc, Object<'gc>> {
         self.current_input_port
@@ -103,6 +265,86 @@ impl<'gc> ObjContinuation<'gc> {
     pub fn current_output_port(&self) -> GcCell<'gc, Object<'gc>> {
         self.current_output_port
     }
+
+    /// Gets the current error port
+    pub fn current_error_port(&self) -> GcCell<'gc, Object<'gc>> {
+        self.current_error_port
+    }
+
+    /// Gets the installed exception handlers
+    pub fn handlers(&self) -> Value<'gc> {
+        self.handlers
+    }
+
+    /// Gets the files being loaded
+    pub fn loading(&self) -> Value<'gc> {
+        self.loading
+    }
+
+    /// Gets the tag of the prompt installed by this frame, if any
+    pub fn prompt(&self) -> Option<Value<'gc>> {
+        self.prompt
+    }
+
+    /// Marks this frame as the prompt with the given tag
+    pub fn set_prompt(&mut self, tag: Value<'gc>) {
+        self.prompt = Some(tag);
+    }
+
+    /// Gets the port this frame closes when it's escaped from, if any
+    pub fn port(&self) -> Option<GcCell<'gc, Object<'gc>>> {
+        self.port
+    }
+
+    /// Marks this frame as the owner of a port, which is closed if the frame is escaped from
+    pub fn set_port(&mut self, port: GcCell<'gc, Object<'gc>>) {
+        self.port = Some(port);
+    }
+
+    /// Whether this is a delimited continuation
+    pub fn is_delimited(&self) -> bool {
+        self.delimited
+    }
+
+    /// Sets the parts of a continuation that `new` doesn't take, when putting one back together
+    /// after it was serialized
+    pub(crate) fn restore(
+        &mut self,
+        prompt: Option<Value<'gc>>,
+        port: Option<GcCell<'gc, Object<'gc>>>,
+        delimited: bool,
+    ) {
+        self.prompt = prompt;
+        self.port = port;
+        self.delimited = delimited;
+    }
+}
+
+/// Copies the live part of each stack the frames use, pairing it with the original. Frames running
+/// bytecode share their caller's stack, so each stack is only copied once, as far up as the frame
+/// using the most of it.
+fn copy_stacks<'gc>(
+    frames: impl Iterator<Item = (Stack<'gc>, usize)>,
+    mc: MutationContext<'gc, '_>,
+) -> Vec<(Stack<'gc>, Stack<'gc>)> {
+    let mut live: Vec<(Stack<'gc>, usize)> = Vec::new();
+    for (stack, top) in frames {
+        match live
+            .iter_mut()
+            .find(|(seen, _)| GcCell::ptr_eq(*seen, stack))
+        {
+            Some((_, live_top)) => *live_top = (*live_top).max(top),
+            None => live.push((stack, top)),
+        }
+    }
+
+    live.into_iter()
+        .map(|(stack, top)| {
+            let values = stack.read();
+            let len = top.min(values.len());
+            (stack, GcCell::allocate(mc, values[..len].to_vec()))
+        })
+        .collect()
 }
 
 impl<'gc> From<ObjContinuation<'gc>> for Object<'gc> {
@@ -1,31 +1,86 @@
 use core::convert::TryFrom;
 use core::fmt;
 
-use gc_arena::MutationContext;
+use gc_arena::{GcCell, MutationContext};
 use gc_arena_derive::Collect;
 
 use super::Object;
 use crate::value::{TypeError, Value};
 use crate::vm::Stack;
 
+/// Where a captured variable lives. It stays on the stack of the frame that bound it for as long
+/// as that frame is running, then moves into the upvalue itself once the frame's slots are given
+/// back.
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
-pub struct Upvalue<'gc> {
-    stack: Stack<'gc>,
-    offset: usize,
+pub enum UpvalueState<'gc> {
+    /// The variable is still in a slot of `stack`
+    Open { stack: Stack<'gc>, offset: usize },
+
+    /// The variable outlived its frame
+    Closed(Value<'gc>),
 }
 
+/// A variable captured by a closure. Every closure that captures the same variable shares the
+/// same upvalue, so they all see each other's assignments.
+#[derive(Debug, Copy, Clone, Collect)]
+#[collect(no_drop)]
+pub struct Upvalue<'gc>(GcCell<'gc, UpvalueState<'gc>>);
+
 impl<'gc> Upvalue<'gc> {
-    pub fn new(stack: Stack<'gc>, offset: usize) -> Self {
-        Upvalue { stack, offset }
+    /// Creates an upvalue for the variable in slot `offset` of `stack`
+    pub fn new(stack: Stack<'gc>, offset: usize, mc: MutationContext<'gc, '_>) -> Self {
+        Self::from_state(UpvalueState::Open { stack, offset }, mc)
+    }
+
+    pub(crate) fn from_state(state: UpvalueState<'gc>, mc: MutationContext<'gc, '_>) -> Self {
+        Upvalue(GcCell::allocate(mc, state))
+    }
+
+    /// Gets where the captured variable lives
+    pub fn state(&self) -> UpvalueState<'gc> {
+        *self.0.read()
+    }
+
+    pub(crate) fn set_state(&self, state: UpvalueState<'gc>, mc: MutationContext<'gc, '_>) {
+        *self.0.write(mc) = state;
+    }
+
+    /// Gets the address of the upvalue, which is the same for every closure sharing it
+    pub fn as_ptr(&self) -> *mut UpvalueState<'gc> {
+        self.0.as_ptr()
+    }
+
+    /// Whether the captured variable is still in slot `offset` or above of `stack`
+    pub fn is_open_above(&self, stack: Stack<'gc>, offset: usize) -> bool {
+        match *self.0.read() {
+            UpvalueState::Open {
+                stack: open,
+                offset: slot,
+            } => GcCell::ptr_eq(open, stack) && slot >= offset,
+            UpvalueState::Closed(_) => false,
+        }
+    }
+
+    /// Moves the captured variable off of its stack, so the slot it was in can be reused
+    pub fn close(&self, mc: MutationContext<'gc, '_>) {
+        let value = self.location();
+        *self.0.write(mc) = UpvalueState::Closed(value);
     }
 
     pub fn location(&self) -> Value<'gc> {
-        self.stack.read()[self.offset]
+        match *self.0.read() {
+            UpvalueState::Open { stack, offset } => stack.read()[offset],
+            UpvalueState::Closed(value) => value,
+        }
     }
 
     pub fn set_location(&self, value: Value<'gc>, mc: MutationContext<'gc, '_>) {
-        self.stack.write(mc)[self.offset] = value;
+        let state = *self.0.read();
+        match state {
+            UpvalueState::Open { stack, offset } => stack.write(mc)[offset] = value,
+            UpvalueState::Closed(_) => *self.0.write(mc) = UpvalueState::Closed(value),
+        }
     }
 }
 
@@ -43,8 +98,8 @@ impl<'gc> ObjEnvironment<'gc> {
     }
 
     /// Captures an `Upvalue` into this environment
-    pub fn capture_upvalue(&mut self, stack: Stack<'gc>, offset: usize) {
-        self.upvalues.push(Upvalue { stack, offset });
+    pub fn capture_upvalue(&mut self, upvalue: Upvalue<'gc>) {
+        self.upvalues.push(upvalue);
     }
 
     pub(crate) fn upvalues(&self) -> &[Upvalue<'gc>] {
@@ -0,0 +1,57 @@
+use gc_arena_derive::Collect;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+use crate::value::Value;
+
+/// What an error object is about, for `file-error?` and `read-error?`
+#[derive(Collect, Copy, Clone, Debug, PartialEq, Eq, Default, IntoPrimitive, TryFromPrimitive)]
+#[collect(require_static)]
+#[repr(u8)]
+pub enum ErrorKind {
+    /// Made by `error`, or a failure of any other sort
+    #[default]
+    General,
+
+    /// Opening, reading or writing a file or other port failed
+    File,
+
+    /// Input couldn't be parsed
+    Read,
+}
+
+/// Represents an error object made by `error`, or raised for an error a handler can react to
+#[derive(Collect, Clone, Debug)]
+#[collect(no_drop)]
+pub struct ObjError<'gc> {
+    kind: ErrorKind,
+    message: Value<'gc>,
+    irritants: Box<[Value<'gc>]>,
+}
+
+impl<'gc> ObjError<'gc> {
+    pub fn new(message: Value<'gc>, irritants: Box<[Value<'gc>]>) -> Self {
+        Self {
+            kind: ErrorKind::default(),
+            message,
+            irritants,
+        }
+    }
+
+    pub fn with_kind(self, kind: ErrorKind) -> Self {
+        Self { kind, ..self }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Gets the message describing what went wrong
+    pub fn message(&self) -> Value<'gc> {
+        self.message
+    }
+
+    /// Gets the values the error is about, in the order they were given
+    pub fn irritants(&self) -> &[Value<'gc>] {
+        &self.irritants
+    }
+}
@@ -37,6 +37,23 @@ impl<'gc> ObjFunction<'gc> {
         }
     }
 
+    /// Like `new`, but shares an already allocated chunk and set of upvalues
+    pub fn from_parts(
+        arity: usize,
+        variadic: bool,
+        chunk: Gc<'gc, Chunk<'gc>>,
+        upvalues: Gc<'gc, Upvalues>,
+        name: Option<Symbol<'gc>>,
+    ) -> Self {
+        Self {
+            arity,
+            variadic,
+            upvalues,
+            chunk,
+            name,
+        }
+    }
+
     pub fn thunk(mc: MutationContext<'gc, '_>, chunk: Chunk<'gc>, upvalues: Upvalues) -> Self {
         Self::new(mc, 0, false, chunk, upvalues, None)
     }
@@ -2,26 +2,38 @@ use core::fmt;
 
 use gc_arena_derive::Collect;
 
-use crate::value::{TypeError, Value};
+use crate::chunk::{Chunk, Globals};
+use crate::printer;
+use crate::value::{DisplayStyle, Print, TypeError, Value};
 
 mod closure;
 mod continuation;
 mod environment;
+mod error;
 mod function;
 mod native;
 mod pair;
 mod port;
+mod record;
+#[cfg(feature = "regex")]
+mod regex;
 mod string;
+mod transcoder;
 mod vector;
 
+#[cfg(feature = "regex")]
+pub use self::regex::ObjRegex;
 pub use closure::ObjClosure;
-pub use continuation::{ObjContinuation, Procedure};
-pub use environment::{ObjEnvironment, Upvalue};
+pub use continuation::{CurrentPorts, ObjContinuation, Procedure};
+pub use environment::{ObjEnvironment, Upvalue, UpvalueState};
+pub use error::{ErrorKind, ObjError};
 pub use function::ObjFunction;
-pub use native::ObjNative;
+pub use native::{Native, NativeRegistry, ObjNative, RegisteredNative};
 pub use pair::ObjPair;
-pub use port::{ObjReadPort, ObjWritePort};
+pub use port::{Buffering, ObjReadPort, ObjWritePort, PortBackend, PortSource};
+pub use record::{ObjRecord, ObjRecordType};
 pub use string::ObjString;
+pub use transcoder::{DecodeErrorMode, Encoding};
 pub use vector::ObjVector;
 
 /// Represents (mutable) boxed objects that live on the heap
@@ -57,6 +69,34 @@ pub enum Object<'gc> {
 
     /// Output port
     WritePort(ObjWritePort),
+
+    /// Bytecode being put together by hand, before it's made into a procedure
+    Chunk(Chunk<'gc>),
+
+    /// Record type
+    RecordType(ObjRecordType<'gc>),
+
+    /// Record
+    Record(ObjRecord<'gc>),
+
+    /// Bytevector
+    Bytevector(ObjVector<u8>),
+
+    /// Mutable cell made by `box`
+    Cell(Value<'gc>),
+
+    /// Error object made by `error`
+    Error(ObjError<'gc>),
+
+    /// Table of global variables that code can be evaluated in, made by `environment` and friends
+    Namespace(Globals<'gc>),
+
+    /// Macro transformer made by `er-macro-transformer`, wrapping the procedure that expands uses
+    Macro(Value<'gc>),
+
+    /// Compiled regular expression
+    #[cfg(feature = "regex")]
+    Regex(ObjRegex),
 }
 
 macro_rules! as_type {
@@ -144,6 +184,72 @@ impl<'gc> Object<'gc> {
     pub fn as_write_port_mut(&mut self) -> Result<&mut ObjWritePort, TypeError> {
         as_type!(WritePort, self)
     }
+
+    /// Tries to turn this `Object` into a `Chunk`
+    pub fn as_chunk(&self) -> Result<&Chunk<'gc>, TypeError> {
+        as_type!(Chunk, self)
+    }
+
+    /// Tries to turn this `Object` into a mutable `Chunk`
+    pub fn as_chunk_mut(&mut self) -> Result<&mut Chunk<'gc>, TypeError> {
+        as_type!(Chunk, self)
+    }
+
+    /// Tries to turn this `Object` into a `RecordType`
+    pub fn as_record_type(&self) -> Result<&ObjRecordType<'gc>, TypeError> {
+        as_type!(RecordType, self)
+    }
+
+    /// Tries to turn this `Object` into a `Record`
+    pub fn as_record(&self) -> Result<&ObjRecord<'gc>, TypeError> {
+        as_type!(Record, self)
+    }
+
+    /// Tries to turn this `Object` into a mutable `Record`
+    pub fn as_record_mut(&mut self) -> Result<&mut ObjRecord<'gc>, TypeError> {
+        as_type!(Record, self)
+    }
+
+    /// Tries to turn this `Object` into a `Bytevector`
+    pub fn as_bytevector(&self) -> Result<&ObjVector<u8>, TypeError> {
+        as_type!(Bytevector, self)
+    }
+
+    /// Tries to turn this `Object` into a mutable `Bytevector`
+    pub fn as_bytevector_mut(&mut self) -> Result<&mut ObjVector<u8>, TypeError> {
+        as_type!(Bytevector, self)
+    }
+
+    /// Tries to get the contents of this `Object` as a `Cell`
+    pub fn as_cell(&self) -> Result<&Value<'gc>, TypeError> {
+        as_type!(Cell, self)
+    }
+
+    /// Tries to get the contents of this `Object` as a mutable `Cell`
+    pub fn as_cell_mut(&mut self) -> Result<&mut Value<'gc>, TypeError> {
+        as_type!(Cell, self)
+    }
+
+    /// Tries to turn this `Object` into an `Error`
+    pub fn as_error(&self) -> Result<&ObjError<'gc>, TypeError> {
+        as_type!(Error, self)
+    }
+
+    /// Tries to get the global variables of this `Object` as a `Namespace`
+    pub fn as_namespace(&self) -> Result<&Globals<'gc>, TypeError> {
+        as_type!(Namespace, self)
+    }
+
+    /// Tries to get the procedure behind this `Object` as a `Macro`
+    pub fn as_macro(&self) -> Result<&Value<'gc>, TypeError> {
+        as_type!(Macro, self)
+    }
+
+    /// Tries to turn this `Object` into a `Regex`
+    #[cfg(feature = "regex")]
+    pub fn as_regex(&self) -> Result<&ObjRegex, TypeError> {
+        as_type!(Regex, self)
+    }
 }
 
 /// Predicates
@@ -194,21 +300,83 @@ impl Object<'_> {
     pub fn is_write_port(&self) -> bool {
         matches!(self, Object::WritePort(_))
     }
+
+    pub fn is_chunk(&self) -> bool {
+        matches!(self, Object::Chunk(_))
+    }
+
+    pub fn is_record_type(&self) -> bool {
+        matches!(self, Object::RecordType(_))
+    }
+
+    pub fn is_record(&self) -> bool {
+        matches!(self, Object::Record(_))
+    }
+
+    pub fn is_bytevector(&self) -> bool {
+        matches!(self, Object::Bytevector(_))
+    }
+
+    pub fn is_cell(&self) -> bool {
+        matches!(self, Object::Cell(_))
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Object::Error(_))
+    }
+
+    pub fn is_namespace(&self) -> bool {
+        matches!(self, Object::Namespace(_))
+    }
+
+    pub fn is_macro(&self) -> bool {
+        matches!(self, Object::Macro(_))
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn is_regex(&self) -> bool {
+        matches!(self, Object::Regex(_))
+    }
 }
 
 impl fmt::Display for Object<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.print(f, DisplayStyle::Write)
+    }
+}
+
+impl Print for Object<'_> {
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result {
         match self {
             Self::Closure(closure) => write!(f, "{}", closure),
             Self::Continuation(continuation) => write!(f, "{}", continuation),
             Self::Environment(environment) => write!(f, "{}", environment),
             Self::Function(function) => write!(f, "{}", function),
             Self::Native(native) => write!(f, "{}", native),
-            Self::String(string) => write!(f, "{}", string),
-            Self::Pair(pair) => write!(f, "{}", pair),
-            Self::Vector(vector) => write!(f, "{}", vector),
+            Self::String(string) => string.print(f, style),
+            Self::Pair(pair) => pair.print(f, style),
+            Self::Vector(vector) => printer::print_vector(vector, f, style),
             Self::ReadPort(port) => write!(f, "{}", port),
             Self::WritePort(port) => write!(f, "{}", port),
+            Self::Chunk(_) => write!(f, "#<chunk>"),
+            Self::RecordType(record_type) => write!(f, "{}", record_type),
+            Self::Record(record) => printer::print_record(record, f, style),
+            Self::Bytevector(bytevector) => {
+                write!(f, "#u8(")?;
+                for (i, byte) in bytevector.as_slice().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", byte)?;
+                }
+                write!(f, ")")
+            }
+            Self::Cell(value) => printer::print_cell(*value, f, style),
+            Self::Error(error) => printer::print_error(error, f, style),
+            Self::Namespace(_) => write!(f, "#<environment>"),
+            Self::Macro(_) => write!(f, "#<macro>"),
+            #[cfg(feature = "regex")]
+            Self::Regex(regex) => write!(f, "{}", regex),
         }
     }
 }
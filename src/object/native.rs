@@ -1,4 +1,5 @@
 use core::fmt;
+use std::collections::HashMap;
 
 use gc_arena::{Collect, MutationContext};
 
@@ -6,12 +7,16 @@ use crate::memory::Symbol;
 use crate::value::Value;
 use crate::vm::{InterpretError, Stack, VirtualMachine};
 
-type Native = for<'gc> fn(
+/// Signature of the Rust functions behind native procedures
+pub type Native = for<'gc> fn(
     &VirtualMachine<'gc>,
     Stack<'gc>,
     MutationContext<'gc, '_>,
 ) -> Result<Option<Value<'gc>>, InterpretError>;
 
+/// A native function along with the name it's registered under (see `NativeRegistry`)
+pub type RegisteredNative = (&'static str, Native);
+
 /// Representation of a native function
 #[derive(Copy, Clone, Collect)]
 #[collect(require_static)]
@@ -44,6 +49,11 @@ pub struct ObjNative<'gc> {
     variadic: bool,
     function: NativeFn,
     name: Option<Symbol<'gc>>,
+
+    /// Name the function is registered under, which is how it's written out when it's
+    /// serialized. Natives made by `new` don't have one, so they can't be.
+    #[collect(require_static)]
+    registered_name: Option<&'static str>,
 }
 
 impl ObjNative<'_> {
@@ -63,6 +73,16 @@ impl ObjNative<'_> {
     ) -> Result<Option<Value<'gc>>, InterpretError> {
         self.function.call(vm, args, mc)
     }
+
+    /// Gets the Rust function behind this native
+    pub fn function(&self) -> Native {
+        self.function.0
+    }
+
+    /// Gets the name the function behind this native is registered under, if it is
+    pub fn registered_name(&self) -> Option<&'static str> {
+        self.registered_name
+    }
 }
 
 impl<'gc> ObjNative<'gc> {
@@ -72,8 +92,26 @@ impl<'gc> ObjNative<'gc> {
             variadic,
             function: NativeFn(function),
             name,
+            registered_name: None,
         }
     }
+
+    /// Makes a native out of a registered function, so that it can be serialized
+    pub fn registered(
+        arity: usize,
+        variadic: bool,
+        (registered_name, function): RegisteredNative,
+        name: Option<Symbol<'gc>>,
+    ) -> Self {
+        Self {
+            registered_name: Some(registered_name),
+            ..Self::new(arity, variadic, function, name)
+        }
+    }
+
+    pub fn name(&self) -> Option<Symbol<'gc>> {
+        self.name
+    }
 }
 
 impl fmt::Display for ObjNative<'_> {
@@ -85,3 +123,30 @@ impl fmt::Display for ObjNative<'_> {
         }
     }
 }
+
+/// Names for the Rust functions behind native procedures, so that references to them can be
+/// written out and linked back up again when they're read in (possibly by another process)
+#[derive(Default)]
+pub struct NativeRegistry {
+    functions: HashMap<&'static str, Native>,
+}
+
+impl NativeRegistry {
+    /// Registers `function` under `name`. The first function registered under a name wins.
+    pub fn register(&mut self, name: &'static str, function: Native) {
+        self.functions.entry(name).or_insert(function);
+    }
+
+    /// Gets the function registered under `name`, along with the name
+    pub fn get(&self, name: &str) -> Option<RegisteredNative> {
+        self.functions
+            .get_key_value(name)
+            .map(|(name, function)| (*name, *function))
+    }
+}
+
+impl fmt::Debug for NativeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.functions.keys()).finish()
+    }
+}
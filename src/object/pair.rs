@@ -4,7 +4,8 @@ use core::fmt;
 use gc_arena_derive::Collect;
 
 use super::Object;
-use crate::value::{Datum, TypeError, Value};
+use crate::printer;
+use crate::value::{Datum, DisplayStyle, Print, TypeError, Value};
 
 #[derive(Debug, Clone, Collect, PartialEq, Eq)]
 #[collect(no_drop)]
@@ -62,44 +63,38 @@ impl<'gc> TryFrom<Object<'gc>> for ObjPair<Value<'gc>> {
 
 impl fmt::Display for ObjPair<Value<'_>> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut cdr = self.cdr();
-        write!(f, "({}", self.car())?;
-        while !cdr.is_null() {
-            match cdr {
-                Value::Box(object) => match &*object.read() {
-                    Object::Pair(pair) => {
-                        write!(f, " {}", pair.car())?;
-                        cdr = pair.cdr();
-                        continue;
-                    }
-                    _ => {
-                        write!(f, " . {}", cdr)?;
-                        break;
-                    }
-                },
-                _ => {
-                    write!(f, " . {}", cdr)?;
-                    break;
-                }
-            }
-        }
-        write!(f, ")")
+        self.print(f, DisplayStyle::Write)
+    }
+}
+
+impl Print for ObjPair<Value<'_>> {
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result {
+        printer::print_pair(self, f, style)
     }
 }
 
 impl fmt::Display for ObjPair<Datum<'_>> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.print(f, DisplayStyle::Write)
+    }
+}
+
+impl Print for ObjPair<Datum<'_>> {
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result {
         let mut cdr = self.cdr();
-        write!(f, "({}", self.car())?;
+        write!(f, "(")?;
+        self.car().print(f, style)?;
         while !cdr.is_null() {
             match cdr {
                 Datum::Pair(pair) => {
-                    write!(f, " {}", pair.car())?;
+                    write!(f, " ")?;
+                    pair.car().print(f, style)?;
                     cdr = pair.cdr();
                     continue;
                 }
                 _ => {
-                    write!(f, " . {}", cdr)?;
+                    write!(f, " . ")?;
+                    cdr.print(f, style)?;
                     break;
                 }
             }
@@ -1,13 +1,316 @@
 use core::fmt;
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use core::str::FromStr;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
 
 use gc_arena::{static_collect, Collect};
 
-use crate::vm::Result;
+use super::transcoder::{DecodeErrorMode, Decoder, Encoding};
+use crate::platform::{self, RawHandle};
+use crate::vm::{InterpretError, Result};
+
+/// Where a port's data comes from or goes to, kept so the port can be opened again somewhere
+/// else (e.g. when a serialized continuation is read back in)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortSource {
+    /// The process' standard input or output
+    Console,
+
+    /// The process' standard error
+    ErrorConsole,
+
+    /// A file on disk
+    File(PathBuf),
+
+    /// An in-memory string (the contents are only kept for input ports)
+    String(Rc<[u8]>),
+
+    /// Anything else, which can't be reopened
+    Other,
+}
+
+/// When an output port passes what's written to it on to whatever it writes to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Buffering {
+    /// Pass everything on as soon as it's written
+    None,
+
+    /// Hold output back until a line is finished
+    Line,
+
+    /// Hold output back until the buffer fills up or the port is flushed (the default)
+    #[default]
+    Full,
+}
+
+impl Buffering {
+    /// Gets the name of this buffering mode, as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Line => "line",
+            Self::Full => "full",
+        }
+    }
+}
+
+impl FromStr for Buffering {
+    type Err = String;
+
+    fn from_str(name: &str) -> core::result::Result<Self, Self::Err> {
+        match name {
+            "none" => Ok(Self::None),
+            "line" => Ok(Self::Line),
+            "full" | "block" => Ok(Self::Full),
+            _ => Err(format!("Unknown buffering mode '{}'", name)),
+        }
+    }
+}
+
+/// An I/O channel that an embedder exposes to Scheme as a port, like an in-game console or a
+/// network stream
+///
+/// Ports buffer (and textual ports decode) on top of their backend, which is also what peeking
+/// reads into, so input ports only need `read` and output ports only need `write`.
+pub trait PortBackend {
+    /// Reads some bytes into `buf`, returning how many were read (0 at the end of the input)
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let _ = buf;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "backend can't be read from",
+        ))
+    }
+
+    /// Writes some of `buf`, returning how many bytes were written
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = buf;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "backend can't be written to",
+        ))
+    }
+
+    /// Passes on anything the backend is holding back
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether `read` would return straight away instead of blocking
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    /// Lets go of the channel, once the port using it is closed
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What an input port reads from, which may be able to move to another position
+trait Source: Read {
+    /// Moves to `position` bytes from the start of the input
+    fn seek_to(&mut self, position: u64) -> io::Result<()>;
+
+    /// Whether input can be read without blocking, if this source can tell
+    fn is_ready(&self) -> Option<bool> {
+        None
+    }
+
+    /// Lets go of whatever this reads from
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What an output port writes to, which may be able to move to another position
+trait Sink: Write {
+    /// Gets the position of the next byte to be written
+    fn position(&mut self) -> io::Result<u64>;
+
+    /// Moves to `position` bytes from the start of the output
+    fn seek_to(&mut self, position: u64) -> io::Result<()>;
+
+    /// Lets go of whatever this writes to
+    fn close(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader or writer that passes everything on to an embedder's backend
+struct Backend(Box<dyn PortBackend>);
+
+impl Read for Backend {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Backend {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Source for Backend {
+    fn seek_to(&mut self, _: u64) -> io::Result<()> {
+        Err(unseekable())
+    }
+
+    fn is_ready(&self) -> Option<bool> {
+        Some(self.0.is_ready())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.0.close()
+    }
+}
+
+impl Sink for Backend {
+    fn position(&mut self) -> io::Result<u64> {
+        Err(unseekable())
+    }
+
+    fn seek_to(&mut self, _: u64) -> io::Result<()> {
+        Err(unseekable())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.0.close()
+    }
+}
+
+/// Reader or writer that can be repositioned (e.g. a file)
+struct Seekable<T>(T);
+
+/// Reader or writer that can only go forwards (e.g. the console)
+struct Unseekable<T>(T);
+
+impl<R: Read> Read for Seekable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: Read> Read for Unseekable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<W: Write> Write for Seekable<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Write for Unseekable<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<R: Read + Seek> Source for Seekable<R> {
+    fn seek_to(&mut self, position: u64) -> io::Result<()> {
+        self.0.seek(SeekFrom::Start(position)).map(|_| ())
+    }
+}
+
+impl<R: Read> Source for Unseekable<R> {
+    fn seek_to(&mut self, _: u64) -> io::Result<()> {
+        Err(unseekable())
+    }
+}
+
+impl<W: Write + Seek> Sink for Seekable<W> {
+    fn position(&mut self) -> io::Result<u64> {
+        self.0.stream_position()
+    }
+
+    fn seek_to(&mut self, position: u64) -> io::Result<()> {
+        self.0.seek(SeekFrom::Start(position)).map(|_| ())
+    }
+}
+
+impl<W: Write> Sink for Unseekable<W> {
+    fn position(&mut self) -> io::Result<u64> {
+        Err(unseekable())
+    }
+
+    fn seek_to(&mut self, _: u64) -> io::Result<()> {
+        Err(unseekable())
+    }
+}
+
+/// Decoded positions don't line up with positions in the raw input, so decoded input can only
+/// be rewound to its start
+impl Source for Decoder<Box<dyn Source>> {
+    fn seek_to(&mut self, position: u64) -> io::Result<()> {
+        if position != 0 {
+            return Err(unseekable());
+        }
+        self.get_mut().seek_to(0)?;
+        self.reset();
+        Ok(())
+    }
+
+    fn is_ready(&self) -> Option<bool> {
+        self.get_ref().is_ready()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.get_mut().close()
+    }
+}
+
+fn unseekable() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "port can't be repositioned")
+}
 
 /// Input port
 pub struct ObjReadPort {
-    resource: BufReader<Box<dyn Read>>,
+    resource: BufReader<Box<dyn Source>>,
+
+    /// OS handle backing the resource, if it can be polled for readiness
+    handle: Option<RawHandle>,
+
+    /// Where the resource came from
+    source: PortSource,
+
+    /// Encoding the resource is decoded from
+    encoding: Encoding,
+
+    /// How invalid input is handled while decoding
+    mode: DecodeErrorMode,
+
+    /// Whether this port reads raw bytes instead of characters
+    binary: bool,
+
+    /// Whether this port has been closed
+    closed: bool,
+
+    /// Number of (decoded) bytes consumed so far
+    offset: usize,
+
+    /// Line of the next character to be read (starting at 1)
+    line: usize,
+
+    /// Column of the next character to be read (starting at 0)
+    column: usize,
 }
 
 static_collect!(ObjReadPort);
@@ -15,9 +318,221 @@ static_collect!(ObjReadPort);
 impl ObjReadPort {
     /// Construct a ObjReadPort
     pub fn new<R: Read + 'static>(reader: R) -> Self {
+        Self::with_encoding(reader, Encoding::Utf8)
+    }
+
+    /// Construct a ObjReadPort that decodes its input from the given encoding
+    pub fn with_encoding<R: Read + 'static>(reader: R, encoding: Encoding) -> Self {
+        Self::with_transcoder(reader, encoding, DecodeErrorMode::default())
+    }
+
+    /// Construct a ObjReadPort that decodes its input from the given encoding, handling invalid
+    /// input according to `mode`
+    pub fn with_transcoder<R: Read + 'static>(
+        reader: R,
+        encoding: Encoding,
+        mode: DecodeErrorMode,
+    ) -> Self {
+        Self::decoding(Box::new(Unseekable(reader)), encoding, mode)
+    }
+
+    /// Construct a ObjReadPort like `with_transcoder`, whose position can be set
+    pub fn seekable<R: Read + Seek + 'static>(
+        reader: R,
+        encoding: Encoding,
+        mode: DecodeErrorMode,
+    ) -> Self {
+        Self::decoding(Box::new(Seekable(reader)), encoding, mode)
+    }
+
+    fn decoding(source: Box<dyn Source>, encoding: Encoding, mode: DecodeErrorMode) -> Self {
         Self {
-            resource: BufReader::new(Box::new(reader)),
+            resource: BufReader::new(Box::new(Decoder::new(source, encoding, mode))),
+            handle: None,
+            source: PortSource::Other,
+            encoding,
+            mode,
+            binary: false,
+            closed: false,
+            offset: 0,
+            line: 1,
+            column: 0,
+        }
+    }
+
+    /// Construct a ObjReadPort that decodes UTF-8 read from an embedder's backend
+    pub fn backend<B: PortBackend + 'static>(backend: B) -> Self {
+        Self::decoding(
+            Box::new(Backend(Box::new(backend))),
+            Encoding::Utf8,
+            DecodeErrorMode::default(),
+        )
+    }
+
+    /// Construct a binary ObjReadPort that reads raw bytes from an embedder's backend
+    pub fn binary_backend<B: PortBackend + 'static>(backend: B) -> Self {
+        Self {
+            resource: BufReader::new(Box::new(Backend(Box::new(backend)))),
+            binary: true,
+            ..Self::new(io::empty())
+        }
+    }
+
+    /// Construct a ObjReadPort that reads from the process' standard input
+    pub fn stdin() -> Self {
+        let port = Self::new(io::stdin()).with_source(PortSource::Console);
+        match platform::stdin_handle() {
+            Some(handle) => port.with_handle(handle),
+            None => port,
+        }
+    }
+
+    /// Construct a ObjReadPort that reads from an in-memory string
+    pub fn string(string: &[u8]) -> Self {
+        Self {
+            source: PortSource::String(string.into()),
+            ..Self::seekable(
+                Cursor::new(string.to_vec()),
+                Encoding::Utf8,
+                DecodeErrorMode::default(),
+            )
+        }
+    }
+
+    /// Construct a binary ObjReadPort, which reads the raw bytes of its input
+    pub fn binary<R: Read + 'static>(reader: R) -> Self {
+        Self {
+            resource: BufReader::new(Box::new(Unseekable(reader))),
+            binary: true,
+            ..Self::new(io::empty())
+        }
+    }
+
+    /// Construct a binary ObjReadPort whose position can be set
+    pub fn binary_seekable<R: Read + Seek + 'static>(reader: R) -> Self {
+        Self {
+            resource: BufReader::new(Box::new(Seekable(reader))),
+            binary: true,
+            ..Self::new(io::empty())
+        }
+    }
+
+    /// Construct a binary ObjReadPort that reads from an in-memory bytevector
+    pub fn bytevector(bytes: &[u8]) -> Self {
+        Self {
+            source: PortSource::String(bytes.into()),
+            ..Self::binary_seekable(Cursor::new(bytes.to_vec()))
+        }
+    }
+
+    /// Records where this port's input comes from
+    pub fn with_source(self, source: PortSource) -> Self {
+        Self { source, ..self }
+    }
+
+    /// Records the OS handle this port reads from, so that readiness can be polled
+    pub(crate) fn with_handle(self, handle: RawHandle) -> Self {
+        Self {
+            handle: Some(handle),
+            ..self
+        }
+    }
+
+    /// Is this a binary port, which reads bytes instead of characters?
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Is this port still open?
+    pub fn is_open(&self) -> bool {
+        !self.closed
+    }
+
+    /// Closes this port, letting go of whatever it was reading from. Anything but closing it
+    /// again is an error from then on.
+    pub fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+
+        let mut resource = std::mem::replace(
+            &mut self.resource,
+            BufReader::new(Box::new(Unseekable(io::empty()))),
+        );
+        self.handle = None;
+        self.closed = true;
+        resource.get_mut().close()?;
+        Ok(())
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.closed {
+            return Err(InterpretError::RuntimeError(format!(
+                "'{}' is closed",
+                self
+            )));
+        }
+        Ok(())
+    }
+
+    /// Makes sure this port is open and reads characters
+    pub fn check_textual(&self) -> Result<()> {
+        self.check_open()?;
+        if self.binary {
+            return Err(InterpretError::RuntimeError(format!(
+                "'{}' is not a textual port",
+                self
+            )));
         }
+        Ok(())
+    }
+
+    /// Makes sure this port is open and reads bytes
+    pub fn check_binary(&self) -> Result<()> {
+        self.check_open()?;
+        if !self.binary {
+            return Err(InterpretError::RuntimeError(format!(
+                "'{}' is not a binary port",
+                self
+            )));
+        }
+        Ok(())
+    }
+
+    /// Read a byte from a binary port
+    pub fn read_u8(&mut self) -> Result<Option<u8>> {
+        let result = self.peek_u8()?;
+        if result.is_some() {
+            self.consume(1);
+        }
+        Ok(result)
+    }
+
+    /// Peek a byte from a binary port
+    pub fn peek_u8(&mut self) -> Result<Option<u8>> {
+        self.check_binary()?;
+        Ok(self.fill_buf()?.first().copied())
+    }
+
+    /// Read up to `count` bytes from a binary port, or `None` if the input is already at its end
+    pub fn read_bytes(&mut self, count: usize) -> Result<Option<Vec<u8>>> {
+        self.check_binary()?;
+        let mut bytes = Vec::new();
+        while bytes.len() < count {
+            let buf = self.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+
+            let size = buf.len().min(count - bytes.len());
+            bytes.extend_from_slice(&buf[..size]);
+            self.consume(size);
+        }
+
+        if count > 0 && bytes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(bytes))
     }
 
     /// Read a character from the input
@@ -31,23 +546,205 @@ impl ObjReadPort {
 
     /// Peek a character from the input
     pub fn peek_char(&mut self) -> Result<Option<char>> {
+        self.check_textual()?;
         let buf = self.fill_buf()?;
         let character = core::str::from_utf8(buf)?.chars().next();
         Ok(character)
     }
 
-    /// Is a character ready from the input?
-    pub fn is_char_ready(&self) -> bool {
-        !self.resource.buffer().is_empty()
+    /// Read the rest of the current line from the input, without its line ending, or `None` if
+    /// the input is already at its end
+    pub fn read_line(&mut self) -> Result<Option<String>> {
+        self.check_textual()?;
+        let mut bytes = Vec::new();
+        let mut found_newline = false;
+        while !found_newline {
+            let buf = self.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+
+            let size = match buf.iter().position(|byte| *byte == b'\n') {
+                Some(newline) => {
+                    found_newline = true;
+                    bytes.extend_from_slice(&buf[..newline]);
+                    newline + 1
+                }
+                None => {
+                    bytes.extend_from_slice(buf);
+                    buf.len()
+                }
+            };
+            self.consume(size);
+        }
+
+        if !found_newline && bytes.is_empty() {
+            return Ok(None);
+        }
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+        Ok(Some(core::str::from_utf8(&bytes)?.to_string()))
+    }
+
+    /// Read up to `count` characters from the input, or `None` if the input is already at its
+    /// end
+    pub fn read_string(&mut self, count: usize) -> Result<Option<String>> {
+        self.check_textual()?;
+        let mut bytes = Vec::new();
+        let mut chars = 0;
+        loop {
+            let buf = self.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+
+            // Stop at the leading byte of the first character past `count`, so the continuation
+            // bytes of the last character are still taken if it straddles two buffers
+            let size = buf
+                .iter()
+                .position(|byte| {
+                    let is_leading = (*byte & 0xc0) != 0x80;
+                    if is_leading && chars == count {
+                        return true;
+                    }
+                    if is_leading {
+                        chars += 1;
+                    }
+                    false
+                })
+                .unwrap_or(buf.len());
+            bytes.extend_from_slice(&buf[..size]);
+            let done = size < buf.len();
+            self.consume(size);
+            if done {
+                break;
+            }
+        }
+
+        if count > 0 && bytes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(core::str::from_utf8(&bytes)?.to_string()))
+    }
+
+    /// Can input be read without blocking? Ports without a pollable OS handle (files, strings)
+    /// never block, even at the end of their input.
+    pub fn is_char_ready(&self) -> Result<bool> {
+        self.poll(Duration::ZERO)
+    }
+
+    /// Waits up to `timeout` for a character to become available, returning whether one
+    /// can be read without blocking. Ports without a pollable OS handle never block.
+    pub fn wait_for_char(&self, timeout: Duration) -> Result<bool> {
+        self.check_textual()?;
+        self.poll(timeout)
+    }
+
+    fn poll(&self, timeout: Duration) -> Result<bool> {
+        if !self.resource.buffer().is_empty() {
+            return Ok(true);
+        }
+        // Backends can only say whether they're ready right now
+        if let Some(ready) = self.resource.get_ref().is_ready() {
+            return Ok(ready);
+        }
+
+        match self.handle {
+            Some(handle) => Ok(platform::poll_readable(handle, Some(timeout))?),
+            None => Ok(true),
+        }
     }
 
     pub(crate) fn fill_buf(&mut self) -> io::Result<&[u8]> {
         self.resource.fill_buf()
     }
 
+    /// Gets where this port's input comes from
+    pub fn source(&self) -> &PortSource {
+        &self.source
+    }
+
+    /// Gets the encoding this port decodes its input from
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Gets how this port handles invalid input
+    pub fn decode_error_mode(&self) -> DecodeErrorMode {
+        self.mode
+    }
+
+    /// Gets the number of (decoded) bytes read so far
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Gets the line of the next character to be read, starting at 1
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Gets the column of the next character to be read, starting at 0
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
     pub(crate) fn consume(&mut self, size: usize) {
+        let buffer = self.resource.buffer();
+        for byte in &buffer[..size.min(buffer.len())] {
+            if *byte == b'\n' {
+                self.line += 1;
+                self.column = 0;
+            } else if (*byte & 0xc0) != 0x80 {
+                // Only count the leading byte of each UTF-8 sequence
+                self.column += 1;
+            }
+        }
+        self.offset += size.min(buffer.len());
         self.resource.consume(size);
     }
+
+    /// Gets the position of the next byte to be read, counting decoded bytes for textual ports
+    pub fn position(&self) -> Result<usize> {
+        self.check_open()?;
+        Ok(self.offset)
+    }
+
+    /// Moves to `position` bytes from the start of the input. Binary ports go straight there,
+    /// while textual ports (where positions count decoded bytes) are rewound and read forward.
+    pub fn set_position(&mut self, position: usize) -> Result<()> {
+        self.check_open()?;
+        let target = if self.binary { position } else { 0 };
+        if let Err(err) = self.resource.get_mut().seek_to(target as u64) {
+            return Err(reposition_error(err, self));
+        }
+
+        // Whatever was buffered came from the old position
+        let buffered = self.resource.buffer().len();
+        self.resource.consume(buffered);
+        self.offset = target;
+        self.line = 1;
+        self.column = 0;
+        self.skip(position - target)?;
+        Ok(())
+    }
+
+    /// Reads and throws away `count` (decoded) bytes, stopping early at the end of the input
+    pub(crate) fn skip(&mut self, count: usize) -> io::Result<()> {
+        let mut remaining = count;
+        while remaining > 0 {
+            let available = self.fill_buf()?.len();
+            if available == 0 {
+                break;
+            }
+
+            let size = available.min(remaining);
+            self.consume(size);
+            remaining -= size;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for ObjReadPort {
@@ -65,9 +762,33 @@ impl fmt::Debug for ObjReadPort {
     }
 }
 
+/// Where the output of an `ObjWritePort` ends up
+enum WriteResource {
+    /// An external resource (file, stdout, etc.)
+    Writer(BufWriter<Box<dyn Sink>>),
+
+    /// An in-memory buffer that can be read back out later
+    Buffer(Vec<u8>),
+}
+
 /// Output port
 pub struct ObjWritePort {
-    resource: BufWriter<Box<dyn Write>>,
+    resource: WriteResource,
+
+    /// Where the output ends up
+    source: PortSource,
+
+    /// Encoding characters are written out in
+    encoding: Encoding,
+
+    /// Whether this port writes raw bytes instead of characters
+    binary: bool,
+
+    /// When output is passed on to the resource
+    buffering: Buffering,
+
+    /// Whether this port has been closed
+    closed: bool,
 }
 
 static_collect!(ObjWritePort);
@@ -75,28 +796,299 @@ static_collect!(ObjWritePort);
 impl ObjWritePort {
     /// Construct a ObjWritePort
     pub fn new<W: Write + 'static>(writer: W) -> Self {
+        Self::writing(Box::new(Unseekable(writer)))
+    }
+
+    /// Construct a ObjWritePort that encodes its output in the given encoding, and whose position
+    /// can be set
+    pub fn seekable<W: Write + Seek + 'static>(writer: W, encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            ..Self::writing(Box::new(Seekable(writer)))
+        }
+    }
+
+    fn writing(sink: Box<dyn Sink>) -> Self {
         Self {
-            resource: BufWriter::new(Box::new(writer)),
+            resource: WriteResource::Writer(BufWriter::new(sink)),
+            source: PortSource::Other,
+            encoding: Encoding::Utf8,
+            binary: false,
+            buffering: Buffering::default(),
+            closed: false,
         }
     }
 
+    /// Construct a ObjWritePort that encodes its output in the given encoding
+    pub fn with_encoding<W: Write + 'static>(writer: W, encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            ..Self::new(writer)
+        }
+    }
+
+    /// Construct a ObjWritePort that writes to an embedder's backend a line at a time
+    pub fn backend<B: PortBackend + 'static>(backend: B) -> Self {
+        Self {
+            buffering: Buffering::Line,
+            ..Self::writing(Box::new(Backend(Box::new(backend))))
+        }
+    }
+
+    /// Construct a ObjWritePort that writes to the process' standard output a line at a time
+    pub fn stdout() -> Self {
+        Self {
+            source: PortSource::Console,
+            buffering: Buffering::Line,
+            ..Self::new(io::stdout())
+        }
+    }
+
+    /// Construct a ObjWritePort that writes to the process' standard error as soon as it's
+    /// written to
+    pub fn stderr() -> Self {
+        Self {
+            source: PortSource::ErrorConsole,
+            buffering: Buffering::None,
+            ..Self::new(io::stderr())
+        }
+    }
+
+    /// Construct a ObjWritePort that accumulates its output in memory
+    pub fn string() -> Self {
+        Self::with_contents(Vec::new())
+    }
+
+    /// Construct an in-memory ObjWritePort that already holds `contents`
+    pub fn with_contents(contents: Vec<u8>) -> Self {
+        Self {
+            resource: WriteResource::Buffer(contents),
+            source: PortSource::String(Rc::from(&[][..])),
+            encoding: Encoding::Utf8,
+            binary: false,
+            buffering: Buffering::default(),
+            closed: false,
+        }
+    }
+
+    /// Construct a binary ObjWritePort, which writes raw bytes
+    pub fn binary<W: Write + 'static>(writer: W) -> Self {
+        Self {
+            binary: true,
+            ..Self::new(writer)
+        }
+    }
+
+    /// Construct a binary ObjWritePort that accumulates its output in memory
+    pub fn bytevector() -> Self {
+        Self {
+            binary: true,
+            ..Self::string()
+        }
+    }
+
+    /// Turns this port into a binary one
+    pub fn into_binary(self) -> Self {
+        Self {
+            binary: true,
+            ..self
+        }
+    }
+
+    /// Sets when this port passes its output on
+    pub fn with_buffering(self, buffering: Buffering) -> Self {
+        Self { buffering, ..self }
+    }
+
+    /// Gets when this port passes its output on
+    pub fn buffering(&self) -> Buffering {
+        self.buffering
+    }
+
+    /// Is this a binary port, which writes bytes instead of characters?
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Is this port still open?
+    pub fn is_open(&self) -> bool {
+        !self.closed
+    }
+
+    /// Closes this port, flushing and letting go of whatever it was writing to. Anything written
+    /// to an in-memory port so far can still be gotten back out.
+    pub fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+
+        if let WriteResource::Writer(writer) = &mut self.resource {
+            writer.flush()?;
+            let mut sink =
+                std::mem::replace(writer, BufWriter::new(Box::new(Unseekable(io::sink()))));
+            sink.get_mut().close()?;
+        }
+        self.closed = true;
+        Ok(())
+    }
+
+    fn check_open(&self) -> Result<()> {
+        if self.closed {
+            return Err(InterpretError::RuntimeError(format!(
+                "'{}' is closed",
+                self
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_textual(&self) -> Result<()> {
+        self.check_open()?;
+        if self.binary {
+            return Err(InterpretError::RuntimeError(format!(
+                "'{}' is not a textual port",
+                self
+            )));
+        }
+        Ok(())
+    }
+
+    /// Write a byte to a binary port
+    pub fn write_u8(&mut self, byte: u8) -> Result<()> {
+        self.write_bytes(&[byte])
+    }
+
+    /// Write raw bytes to a binary port
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.check_open()?;
+        if !self.binary {
+            return Err(InterpretError::RuntimeError(format!(
+                "'{}' is not a binary port",
+                self
+            )));
+        }
+
+        self.write_raw(bytes)
+    }
+
+    /// Passes everything written so far on to the resource
+    pub fn flush(&mut self) -> Result<()> {
+        self.check_open()?;
+        if let WriteResource::Writer(writer) = &mut self.resource {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes already encoded output, flushing it if the buffering mode calls for it
+    fn write_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        match &mut self.resource {
+            WriteResource::Writer(writer) => {
+                // Everything up to here gets passed on, and the rest is held back
+                let flushed = match self.buffering {
+                    Buffering::None => bytes.len(),
+                    Buffering::Line => bytes
+                        .iter()
+                        .rposition(|byte| *byte == b'\n')
+                        .map_or(0, |newline| newline + 1),
+                    Buffering::Full => 0,
+                };
+                if flushed > 0 {
+                    writer.write_all(&bytes[..flushed])?;
+                    writer.flush()?;
+                }
+                writer.write_all(&bytes[flushed..])?;
+            }
+            WriteResource::Buffer(buffer) => buffer.extend_from_slice(bytes),
+        }
+        Ok(())
+    }
+
+    /// Records where this port's output ends up
+    pub fn with_source(self, source: PortSource) -> Self {
+        Self { source, ..self }
+    }
+
     /// Write a single character to the write buffer
-    pub fn write_char(&mut self, character: char) -> io::Result<usize> {
+    pub fn write_char(&mut self, character: char) -> Result<usize> {
+        self.check_textual()?;
         let buf = &mut [0; 4];
-        let result = character.encode_utf8(buf).len();
-        let result = self.resource.write(&buf[0..result]);
-        // TODO: fix this - this is pretty inefficient
-        self.resource.flush()?;
-        result
+        let len = self.encoding.encode(character, buf)?;
+        self.write_raw(&buf[..len])?;
+        Ok(len)
+    }
+
+    /// Write a whole string to the write buffer
+    pub fn write_str(&mut self, string: &str) -> Result<()> {
+        self.check_textual()?;
+        let mut encoded = Vec::with_capacity(string.len());
+        let buf = &mut [0; 4];
+        for character in string.chars() {
+            let len = self.encoding.encode(character, buf)?;
+            encoded.extend_from_slice(&buf[..len]);
+        }
+        self.write_raw(&encoded)
+    }
+
+    /// Gets the position of the next byte to be written
+    pub fn position(&mut self) -> Result<usize> {
+        self.check_open()?;
+        let position = match &mut self.resource {
+            WriteResource::Writer(writer) => {
+                writer.flush().and_then(|_| writer.get_mut().position())
+            }
+            WriteResource::Buffer(buffer) => Ok(buffer.len() as u64),
+        };
+        match position {
+            Ok(position) => Ok(position as usize),
+            Err(err) => Err(reposition_error(err, self)),
+        }
+    }
+
+    /// Moves to `position` bytes from the start of the output, so that what's written next
+    /// overwrites what's there
+    pub fn set_position(&mut self, position: usize) -> Result<()> {
+        self.check_open()?;
+        let result = match &mut self.resource {
+            WriteResource::Writer(writer) => writer
+                .flush()
+                .and_then(|_| writer.get_mut().seek_to(position as u64)),
+            WriteResource::Buffer(_) => Err(unseekable()),
+        };
+        result.map_err(|err| reposition_error(err, self))
+    }
+
+    /// Gets where this port's output ends up
+    pub fn source(&self) -> &PortSource {
+        &self.source
+    }
+
+    /// Gets the encoding characters are written out in
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Gets everything written so far to an in-memory port, or `None` if this port writes to
+    /// an external resource
+    pub fn contents(&self) -> Option<&[u8]> {
+        match &self.resource {
+            WriteResource::Writer(_) => None,
+            WriteResource::Buffer(buffer) => Some(buffer),
+        }
     }
 }
 
 // This is dumb, but it's better than redefining Read and Write traits
 impl fmt::Debug for ObjWritePort {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("ObjWritePort")
-            .field("resource", &(&self.resource as *const dyn Write))
-            .finish()
+        let mut debug = f.debug_struct("ObjWritePort");
+        match &self.resource {
+            WriteResource::Writer(writer) => {
+                debug.field("resource", &(writer as *const BufWriter<Box<dyn Sink>>))
+            }
+            WriteResource::Buffer(buffer) => debug.field("buffer", buffer),
+        };
+        debug.finish()
     }
 }
 
@@ -105,3 +1097,13 @@ impl fmt::Display for ObjWritePort {
         write!(f, "#<output port {:p}>", self)
     }
 }
+
+/// Describes a failure to get or set a port's position, naming the port if it's one that
+/// can't be repositioned at all
+fn reposition_error(err: io::Error, port: &dyn fmt::Display) -> InterpretError {
+    if err.kind() == io::ErrorKind::Unsupported {
+        InterpretError::RuntimeError(format!("'{}' can't be repositioned", port))
+    } else {
+        err.into()
+    }
+}
@@ -0,0 +1,85 @@
+use core::fmt;
+
+use gc_arena::GcCell;
+use gc_arena_derive::Collect;
+
+use super::Object;
+use crate::memory::Symbol;
+use crate::value::Value;
+
+/// Represents a record type made by `define-record-type`
+#[derive(Collect, Clone, Debug)]
+#[collect(no_drop)]
+pub struct ObjRecordType<'gc> {
+    name: Symbol<'gc>,
+    fields: Box<[Symbol<'gc>]>,
+}
+
+impl<'gc> ObjRecordType<'gc> {
+    pub fn new(name: Symbol<'gc>, fields: Box<[Symbol<'gc>]>) -> Self {
+        Self { name, fields }
+    }
+
+    pub fn name(&self) -> Symbol<'gc> {
+        self.name
+    }
+
+    /// Gets the names of the fields of records of this type, in order
+    pub fn fields(&self) -> &[Symbol<'gc>] {
+        &self.fields
+    }
+
+    /// Gets the name records of this type are printed with, which leaves off the angle brackets
+    /// record type names are conventionally written with
+    pub fn record_name(&self) -> String {
+        let name = self.name.as_str();
+        match name
+            .strip_prefix('<')
+            .and_then(|name| name.strip_suffix('>'))
+        {
+            Some(inner) if !inner.is_empty() => inner.to_string(),
+            _ => name.into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for ObjRecordType<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<record-type {}>", self.name)
+    }
+}
+
+/// Represents an instance of a record type
+#[derive(Collect, Clone, Debug)]
+#[collect(no_drop)]
+pub struct ObjRecord<'gc> {
+    record_type: GcCell<'gc, Object<'gc>>,
+    fields: Box<[Value<'gc>]>,
+}
+
+impl<'gc> ObjRecord<'gc> {
+    /// Makes a record of `record_type`, which should be an `Object::RecordType`
+    pub fn new(record_type: GcCell<'gc, Object<'gc>>, fields: Box<[Value<'gc>]>) -> Self {
+        Self {
+            record_type,
+            fields,
+        }
+    }
+
+    pub fn record_type(&self) -> GcCell<'gc, Object<'gc>> {
+        self.record_type
+    }
+
+    /// Checks whether this record is of the given type
+    pub fn is_a(&self, record_type: GcCell<'gc, Object<'gc>>) -> bool {
+        GcCell::ptr_eq(self.record_type, record_type)
+    }
+
+    pub fn fields(&self) -> &[Value<'gc>] {
+        &self.fields
+    }
+
+    pub fn fields_mut(&mut self) -> &mut [Value<'gc>] {
+        &mut self.fields
+    }
+}
@@ -0,0 +1,33 @@
+use core::fmt;
+
+use gc_arena::{static_collect, Collect};
+use regex::Regex;
+
+/// Represents a compiled regular expression
+#[derive(Debug, Clone)]
+pub struct ObjRegex {
+    regex: Regex,
+}
+
+static_collect!(ObjRegex);
+
+impl ObjRegex {
+    pub fn new(regex: Regex) -> Self {
+        Self { regex }
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// Gets the pattern this regular expression was compiled from
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+}
+
+impl fmt::Display for ObjRegex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<regexp {:?}>", self.regex.as_str())
+    }
+}
@@ -1,24 +1,80 @@
+use core::cmp::Ordering;
 use core::convert::TryFrom;
 use core::fmt;
+use core::hash::{Hash, Hasher};
 use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::string::FromUtf8Error;
 
 use gc_arena_derive::Collect;
 
 use super::{ObjVector, Object};
-use crate::value::TypeError;
+use crate::value::{DisplayStyle, Print, TypeError};
+
+/// Number of characters between the byte offsets recorded in a `CharIndex`
+const CHECKPOINT_INTERVAL: usize = 32;
+
+/// Maps character positions to byte offsets so that random access doesn't rescan the string
+#[derive(Clone, Debug)]
+struct CharIndex {
+    /// Number of characters in the string
+    len: usize,
+
+    /// Byte offset of every `CHECKPOINT_INTERVAL`th character. Empty for ASCII strings, where
+    /// character positions and byte offsets coincide.
+    checkpoints: Box<[usize]>,
+}
+
+impl CharIndex {
+    /// Indexes `bytes` as `String::from_utf8_lossy` decodes them, so each invalid sequence
+    /// counts as the single replacement character it becomes
+    fn new(bytes: &[u8]) -> Self {
+        if bytes.is_ascii() {
+            return Self {
+                len: bytes.len(),
+                checkpoints: Box::default(),
+            };
+        }
+
+        let mut len = 0;
+        let mut checkpoints = Vec::new();
+        let mut offset = 0;
+        for chunk in bytes.utf8_chunks() {
+            let valid = chunk.valid().char_indices().map(|(i, _)| offset + i);
+            let invalid =
+                Some(offset + chunk.valid().len()).filter(|_| !chunk.invalid().is_empty());
+            for start in valid.chain(invalid) {
+                if len % CHECKPOINT_INTERVAL == 0 {
+                    checkpoints.push(start);
+                }
+                len += 1;
+            }
+            offset += chunk.valid().len() + chunk.invalid().len();
+        }
+
+        Self {
+            len,
+            checkpoints: checkpoints.into_boxed_slice(),
+        }
+    }
+}
 
 /// Represents an allocated string in the VM
-#[derive(Collect, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Collect, Clone, Debug)]
 #[collect(no_drop)]
 pub struct ObjString {
     chars: ObjVector<u8>,
+
+    /// Built on the first indexed access and thrown away whenever the bytes change
+    #[collect(require_static)]
+    index: OnceCell<CharIndex>,
 }
 
 impl ObjString {
     pub fn new(chars: Box<[u8]>) -> Self {
         Self {
             chars: ObjVector::new(chars),
+            index: OnceCell::new(),
         }
     }
 
@@ -27,19 +83,99 @@ impl ObjString {
     }
 
     pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self.index.take();
         self.chars.as_slice_mut()
     }
 
     pub fn as_str(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(self.as_bytes())
     }
+
+    fn char_index(&self) -> &CharIndex {
+        self.index.get_or_init(|| CharIndex::new(self.as_bytes()))
+    }
+
+    /// Gets the number of characters in the string
+    pub fn char_count(&self) -> usize {
+        self.char_index().len
+    }
+
+    /// Gets the character at position `k`, without scanning the string from the start
+    pub fn char_at(&self, k: usize) -> Option<char> {
+        let index = self.char_index();
+        if k >= index.len {
+            return None;
+        }
+
+        let bytes = self.as_bytes();
+        if index.checkpoints.is_empty() {
+            return Some(bytes[k] as char);
+        }
+
+        let checkpoint = k / CHECKPOINT_INTERVAL;
+        let start = index.checkpoints[checkpoint];
+        let end = index
+            .checkpoints
+            .get(checkpoint + 1)
+            .copied()
+            .unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[start..end])
+            .chars()
+            .nth(k % CHECKPOINT_INTERVAL)
+    }
+}
+
+impl PartialEq for ObjString {
+    fn eq(&self, other: &Self) -> bool {
+        self.chars == other.chars
+    }
+}
+
+impl Eq for ObjString {}
+
+impl PartialOrd for ObjString {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ObjString {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.chars.cmp(&other.chars)
+    }
+}
+
+impl Hash for ObjString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.chars.hash(state);
+    }
 }
 
 impl fmt::Display for ObjString {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let bytes = self.as_bytes();
-        let string = String::from_utf8_lossy(bytes);
-        write!(f, "\"{}\"", string)
+        self.print(f, DisplayStyle::Write)
+    }
+}
+
+impl Print for ObjString {
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result {
+        let string = self.as_str();
+        if style == DisplayStyle::Display {
+            return write!(f, "{}", string);
+        }
+
+        write!(f, "\"")?;
+        for character in string.chars() {
+            match character {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\t' => write!(f, "\\t")?,
+                '\r' => write!(f, "\\r")?,
+                character => write!(f, "{}", character)?,
+            }
+        }
+        write!(f, "\"")
     }
 }
 
@@ -0,0 +1,295 @@
+use core::fmt;
+use core::str::FromStr;
+use std::io::{self, Read};
+
+/// Character encoding used by a textual port
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// UTF-8 (the default)
+    #[default]
+    Utf8,
+
+    /// ISO-8859-1, where every byte is the codepoint of the same value
+    Latin1,
+
+    /// Little-endian UTF-16
+    Utf16Le,
+
+    /// Big-endian UTF-16
+    Utf16Be,
+}
+
+impl Encoding {
+    /// Gets the name of this encoding, as accepted by `FromStr`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Latin1 => "latin-1",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+        }
+    }
+
+    /// Encodes a character into `buf`, returning the encoded bytes
+    pub fn encode(&self, character: char, buf: &mut [u8; 4]) -> io::Result<usize> {
+        match self {
+            Self::Utf8 => Ok(character.encode_utf8(buf).len()),
+            Self::Latin1 => match u8::try_from(character as u32) {
+                Ok(byte) => {
+                    buf[0] = byte;
+                    Ok(1)
+                }
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("'{}' can't be encoded as {}", character, self.name()),
+                )),
+            },
+            Self::Utf16Le | Self::Utf16Be => {
+                let mut units = [0; 2];
+                let units = character.encode_utf16(&mut units);
+                for (i, unit) in units.iter().enumerate() {
+                    let bytes = if *self == Self::Utf16Le {
+                        unit.to_le_bytes()
+                    } else {
+                        unit.to_be_bytes()
+                    };
+                    buf[(i * 2)..(i * 2 + 2)].copy_from_slice(&bytes);
+                }
+                Ok(units.len() * 2)
+            }
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Self::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Ok(Self::Latin1),
+            "utf-16le" => Ok(Self::Utf16Le),
+            "utf-16" | "utf-16be" => Ok(Self::Utf16Be),
+            _ => Err(format!("Unknown encoding '{}'", name)),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// What a decoder does when it runs into input that isn't valid in its encoding
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DecodeErrorMode {
+    /// Replace each invalid sequence with U+FFFD (the default)
+    #[default]
+    Replace,
+
+    /// Drop the invalid sequence and report an error from the read
+    Raise,
+}
+
+impl FromStr for DecodeErrorMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "replace" => Ok(Self::Replace),
+            "raise" => Ok(Self::Raise),
+            _ => Err(format!("Unknown decoding error mode '{}'", name)),
+        }
+    }
+}
+
+/// Reader adapter that decodes its input from some encoding into (always valid) UTF-8
+pub struct Decoder<R> {
+    inner: R,
+    encoding: Encoding,
+    mode: DecodeErrorMode,
+
+    /// Set when invalid input was dropped in `Raise` mode and the error hasn't been reported yet
+    pending_error: bool,
+
+    /// Raw bytes that have been read but not decoded yet (e.g. half of a UTF-16 code unit)
+    raw: Vec<u8>,
+
+    /// Decoded UTF-8 that hasn't been handed out yet
+    decoded: Vec<u8>,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(inner: R, encoding: Encoding, mode: DecodeErrorMode) -> Self {
+        Self {
+            inner,
+            encoding,
+            mode,
+            pending_error: false,
+            raw: Vec::new(),
+            decoded: Vec::new(),
+        }
+    }
+
+    /// Gets the reader being decoded
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Gets the reader being decoded
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Forgets any input that's been read but not handed out yet, for when the reader being
+    /// decoded has been moved somewhere else
+    pub fn reset(&mut self) {
+        self.pending_error = false;
+        self.raw.clear();
+        self.decoded.clear();
+    }
+
+    /// Handles an invalid sequence according to the error mode. Returns whether decoding should
+    /// stop so the error is reported at the right position.
+    fn invalid(&mut self, text: &mut String) -> bool {
+        match self.mode {
+            DecodeErrorMode::Replace => {
+                text.push(char::REPLACEMENT_CHARACTER);
+                false
+            }
+            DecodeErrorMode::Raise => {
+                self.pending_error = true;
+                true
+            }
+        }
+    }
+
+    /// Decodes as much of the raw input as possible, stopping after an invalid sequence in `Raise`
+    /// mode. When `eof` is set, any incomplete trailing sequence is treated as invalid.
+    fn decode(&mut self, eof: bool) {
+        let mut text = String::new();
+        let consumed = match self.encoding {
+            Encoding::Utf8 => {
+                let mut offset = 0;
+                loop {
+                    match core::str::from_utf8(&self.raw[offset..]) {
+                        Ok(valid) => {
+                            text.push_str(valid);
+                            offset = self.raw.len();
+                            break;
+                        }
+                        Err(err) => {
+                            let valid_end = offset + err.valid_up_to();
+                            // SAFETY: `from_utf8` just validated this range
+                            text.push_str(unsafe {
+                                core::str::from_utf8_unchecked(&self.raw[offset..valid_end])
+                            });
+                            match err.error_len() {
+                                Some(len) => {
+                                    offset = valid_end + len;
+                                    if self.invalid(&mut text) {
+                                        break;
+                                    }
+                                }
+                                // An incomplete sequence at the end might be finished by the
+                                // next read
+                                None => {
+                                    offset = valid_end;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                offset
+            }
+            Encoding::Latin1 => {
+                text.extend(self.raw.iter().map(|byte| *byte as char));
+                self.raw.len()
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units: Vec<u16> = self
+                    .raw
+                    .chunks_exact(2)
+                    .map(|pair| {
+                        if self.encoding == Encoding::Utf16Le {
+                            u16::from_le_bytes([pair[0], pair[1]])
+                        } else {
+                            u16::from_be_bytes([pair[0], pair[1]])
+                        }
+                    })
+                    .collect();
+
+                // Hold back a trailing high surrogate, its pair may not have been read yet
+                let mut complete = units.len();
+                if !eof && matches!(units.last(), Some(0xd800..=0xdbff)) {
+                    complete -= 1;
+                }
+
+                let mut decoded_units = 0;
+                for result in char::decode_utf16(units[..complete].iter().copied()) {
+                    match result {
+                        Ok(character) => {
+                            text.push(character);
+                            decoded_units += character.len_utf16();
+                        }
+                        Err(_) => {
+                            decoded_units += 1;
+                            if self.invalid(&mut text) {
+                                break;
+                            }
+                        }
+                    }
+                }
+                decoded_units * 2
+            }
+        };
+
+        self.raw.drain(..consumed);
+        if eof && !self.pending_error && !self.raw.is_empty() {
+            self.raw.clear();
+            self.invalid(&mut text);
+        }
+        self.decoded.extend_from_slice(text.as_bytes());
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.decoded.is_empty() {
+            if self.pending_error {
+                self.pending_error = false;
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("input is not valid {}", self.encoding),
+                ));
+            }
+
+            // Input left over from stopping at an error is decoded before reading any more
+            if !self.raw.is_empty() {
+                self.decode(false);
+                if !self.decoded.is_empty() || self.pending_error {
+                    continue;
+                }
+            }
+
+            let mut chunk = [0; 4096];
+            let read = self.inner.read(&mut chunk)?;
+            self.raw.extend_from_slice(&chunk[..read]);
+            self.decode(read == 0);
+            if read == 0 && !self.pending_error {
+                break;
+            }
+        }
+
+        // Only hand out whole characters so readers never see a partial sequence
+        let mut len = buf.len().min(self.decoded.len());
+        while len > 0 && len < self.decoded.len() && (self.decoded[len] & 0xc0) == 0x80 {
+            len -= 1;
+        }
+        buf[..len].copy_from_slice(&self.decoded[..len]);
+        self.decoded.drain(..len);
+        Ok(len)
+    }
+}
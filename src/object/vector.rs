@@ -4,7 +4,7 @@ use core::fmt;
 use gc_arena_derive::Collect;
 
 use super::Object;
-use crate::value::{TypeError, Value};
+use crate::value::{DisplayStyle, Print, TypeError, Value};
 
 /// Represents an allocated vector in the VM
 #[derive(Collect, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -27,14 +27,21 @@ impl<T> ObjVector<T> {
     }
 }
 
-impl<T: fmt::Display> fmt::Display for ObjVector<T> {
+impl<T: Print> fmt::Display for ObjVector<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.print(f, DisplayStyle::Write)
+    }
+}
+
+impl<T: Print> Print for ObjVector<T> {
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result {
         let mut items = self.as_slice().iter();
         write!(f, "#(")?;
         if let Some(item) = items.next() {
-            write!(f, "{}", item)?;
+            item.print(f, style)?;
             for item in items {
-                write!(f, " {}", item)?;
+                write!(f, " ")?;
+                item.print(f, style)?;
             }
         }
         write!(f, ")")
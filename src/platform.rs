@@ -0,0 +1,165 @@
+//! Small platform layer for the handful of OS facilities the interpreter needs beyond `std`
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Set when Ctrl+C is pressed, once `catch_interrupts` has been called
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Native handle of an OS-level I/O resource
+#[cfg(unix)]
+pub type RawHandle = std::os::unix::io::RawFd;
+
+/// Native handle of an OS-level I/O resource
+#[cfg(windows)]
+pub type RawHandle = std::os::windows::io::RawHandle;
+
+/// Native handle of an OS-level I/O resource
+#[cfg(not(any(unix, windows)))]
+pub type RawHandle = ();
+
+/// Gets the native handle of the process' standard input
+#[cfg(unix)]
+pub fn stdin_handle() -> Option<RawHandle> {
+    use std::os::unix::io::AsRawFd;
+
+    Some(io::stdin().as_raw_fd())
+}
+
+/// Gets the native handle of the process' standard input
+#[cfg(windows)]
+pub fn stdin_handle() -> Option<RawHandle> {
+    use std::os::windows::io::AsRawHandle;
+
+    Some(io::stdin().as_raw_handle())
+}
+
+/// Gets the native handle of the process' standard input
+#[cfg(not(any(unix, windows)))]
+pub fn stdin_handle() -> Option<RawHandle> {
+    None
+}
+
+/// Waits up to `timeout` for the handle to have data available to read, returning whether
+/// it became readable. A `timeout` of `None` waits indefinitely.
+#[cfg(unix)]
+pub fn poll_readable(handle: RawHandle, timeout: Option<Duration>) -> io::Result<bool> {
+    let timeout = match timeout {
+        Some(timeout) => timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int,
+        None => -1,
+    };
+    let mut fd = libc::pollfd {
+        fd: handle,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    loop {
+        // SAFETY: `fd` is a single valid pollfd that outlives the call
+        let result = unsafe { libc::poll(&mut fd, 1, timeout) };
+        if result >= 0 {
+            // Hangups and errors count as readable, since a read won't block either
+            return Ok(result > 0 && fd.revents != 0);
+        }
+
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Waits up to `timeout` for the handle to have data available to read, returning whether
+/// it became readable. A `timeout` of `None` waits indefinitely.
+///
+/// Console handles are signalled for any input event, so this can report a console as readable
+/// when only e.g. a mouse or focus event is pending.
+#[cfg(windows)]
+pub fn poll_readable(handle: RawHandle, timeout: Option<Duration>) -> io::Result<bool> {
+    const WAIT_OBJECT_0: u32 = 0;
+    const WAIT_TIMEOUT: u32 = 0x102;
+    const INFINITE: u32 = u32::MAX;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn WaitForSingleObject(handle: RawHandle, milliseconds: u32) -> u32;
+    }
+
+    let timeout = match timeout {
+        Some(timeout) => timeout.as_millis().min((INFINITE - 1) as u128) as u32,
+        None => INFINITE,
+    };
+
+    // SAFETY: the handle belongs to an open standard stream for the life of the process
+    match unsafe { WaitForSingleObject(handle, timeout) } {
+        WAIT_OBJECT_0 => Ok(true),
+        WAIT_TIMEOUT => Ok(false),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Waits up to `timeout` for the handle to have data available to read, returning whether
+/// it became readable. Readiness can't be polled on this platform, so this always reports the
+/// handle as readable (and the subsequent read may block).
+#[cfg(not(any(unix, windows)))]
+pub fn poll_readable(_: RawHandle, _: Option<Duration>) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Makes Ctrl+C set a flag instead of killing the process, returning the flag if the handler could
+/// be installed. Pressing it again before the flag is cleared still exits, for when whatever is
+/// running never gets around to checking it.
+#[cfg(unix)]
+pub fn catch_interrupts() -> Option<&'static AtomicBool> {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            // SAFETY: `_exit` is async-signal-safe
+            unsafe { libc::_exit(130) }
+        }
+    }
+
+    let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only touches an atomic and exits, which are fine to do in a handler
+    match unsafe { libc::signal(libc::SIGINT, handler) } {
+        libc::SIG_ERR => None,
+        _ => Some(&INTERRUPTED),
+    }
+}
+
+/// Makes Ctrl+C set a flag instead of killing the process, returning the flag if the handler could
+/// be installed. Pressing it again before the flag is cleared still exits, for when whatever is
+/// running never gets around to checking it.
+#[cfg(windows)]
+pub fn catch_interrupts() -> Option<&'static AtomicBool> {
+    const CTRL_C_EVENT: u32 = 0;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+
+    // Console control handlers run on a thread of their own, so they can exit normally
+    extern "system" fn on_interrupt(ctrl_type: u32) -> i32 {
+        if ctrl_type != CTRL_C_EVENT {
+            return 0;
+        }
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        1
+    }
+
+    // SAFETY: the handler is a plain function that lives for the whole process
+    match unsafe { SetConsoleCtrlHandler(on_interrupt, 1) } {
+        0 => None,
+        _ => Some(&INTERRUPTED),
+    }
+}
+
+/// Makes Ctrl+C set a flag instead of killing the process, returning the flag if the handler could
+/// be installed. There's no way to catch it on this platform, so this never can.
+#[cfg(not(any(unix, windows)))]
+pub fn catch_interrupts() -> Option<&'static AtomicBool> {
+    None
+}
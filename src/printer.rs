@@ -0,0 +1,306 @@
+//! Printing of mutable pairs, vectors, records, boxes and error objects, which `set-cdr!` and friends can make refer
+//! back to themselves. Cycles are found before anything is printed, and each object that's part of one is
+//! written with a datum label the first time it's reached (`#0=(a . #0#)`) and as a reference to
+//! that label after that, so printing always terminates. Structure that's shared without forming
+//! a cycle is printed out in full wherever it appears, like `write` does in R7RS.
+
+use core::fmt;
+use std::collections::HashMap;
+
+use gc_arena::GcCell;
+
+use crate::object::{ObjError, ObjPair, ObjRecord, ObjVector, Object};
+use crate::value::{DisplayStyle, Print, Value};
+
+/// Prints a boxed object
+pub(crate) fn print_object<'gc>(
+    object: GcCell<'gc, Object<'gc>>,
+    f: &mut fmt::Formatter<'_>,
+    style: DisplayStyle,
+) -> fmt::Result {
+    Printer::new(&[Value::Box(object)], style).object(object, f)
+}
+
+/// Prints a mutable pair that isn't at hand as a boxed object
+pub(crate) fn print_pair(
+    pair: &ObjPair<Value<'_>>,
+    f: &mut fmt::Formatter<'_>,
+    style: DisplayStyle,
+) -> fmt::Result {
+    Printer::new(&[pair.car(), pair.cdr()], style).pair(pair, f)
+}
+
+/// Prints a mutable vector that isn't at hand as a boxed object
+pub(crate) fn print_vector(
+    vector: &ObjVector<Value<'_>>,
+    f: &mut fmt::Formatter<'_>,
+    style: DisplayStyle,
+) -> fmt::Result {
+    Printer::new(vector.as_slice(), style).vector(vector, f)
+}
+
+/// Prints a record that isn't at hand as a boxed object
+pub(crate) fn print_record(
+    record: &ObjRecord<'_>,
+    f: &mut fmt::Formatter<'_>,
+    style: DisplayStyle,
+) -> fmt::Result {
+    Printer::new(record.fields(), style).record(record, f)
+}
+
+/// Prints the contents of a box made by `box` that isn't at hand as a boxed object
+pub(crate) fn print_cell(
+    value: Value<'_>,
+    f: &mut fmt::Formatter<'_>,
+    style: DisplayStyle,
+) -> fmt::Result {
+    write!(f, "#&")?;
+    Printer::new(&[value], style).value(value, f)
+}
+
+/// Prints an error object that isn't at hand as a boxed object
+pub(crate) fn print_error(
+    error: &ObjError<'_>,
+    f: &mut fmt::Formatter<'_>,
+    style: DisplayStyle,
+) -> fmt::Result {
+    let mut roots = vec![error.message()];
+    roots.extend_from_slice(error.irritants());
+    Printer::new(&roots, style).error(error, f)
+}
+
+/// An error object printed the way it's reported when nothing handles it: the message as
+/// `display` would, followed by the irritants as `write` would
+pub(crate) struct ErrorReport<'a, 'gc>(pub &'a ObjError<'gc>);
+
+impl fmt::Display for ErrorReport<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let error = self.0;
+        Printer::new(&[error.message()], DisplayStyle::Display).value(error.message(), f)?;
+        let mut printer = Printer::new(error.irritants(), DisplayStyle::Write);
+        for irritant in error.irritants() {
+            write!(f, " ")?;
+            printer.value(*irritant, f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Identifies a boxed object by its address
+fn key<'gc>(object: GcCell<'gc, Object<'gc>>) -> usize {
+    object.as_ptr() as usize
+}
+
+/// The boxed pairs, vectors, records, boxes and errors directly inside of an object, which are the only things
+/// that can lead back to it
+fn containers<'gc>(object: &Object<'gc>) -> Vec<GcCell<'gc, Object<'gc>>> {
+    let children = match object {
+        Object::Pair(pair) => vec![pair.car(), pair.cdr()],
+        Object::Vector(vector) => vector.as_slice().to_vec(),
+        Object::Record(record) => record.fields().to_vec(),
+        Object::Cell(value) => vec![*value],
+        Object::Error(error) => {
+            let mut children = vec![error.message()];
+            children.extend_from_slice(error.irritants());
+            children
+        }
+        _ => Vec::new(),
+    };
+
+    children
+        .into_iter()
+        .filter_map(|child| match child {
+            Value::Box(object) if is_container(object) => Some(object),
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_container<'gc>(object: GcCell<'gc, Object<'gc>>) -> bool {
+    match object.try_read() {
+        Ok(object) => {
+            object.is_pair()
+                || object.is_vector()
+                || object.is_record()
+                || object.is_cell()
+                || object.is_error()
+        }
+        Err(_) => false,
+    }
+}
+
+enum Visit<'gc> {
+    Enter(GcCell<'gc, Object<'gc>>),
+    Exit(usize),
+}
+
+/// Finds the objects reachable from `roots` that can be reached again from inside themselves. The
+/// search keeps its own stack, since long lists would be too deep to recurse through.
+fn find_cycles(roots: &[Value<'_>]) -> HashMap<usize, Option<usize>> {
+    // Objects map to whether they're still being searched, i.e. on the path to the current one
+    let mut searching = HashMap::new();
+    let mut cycles = HashMap::new();
+    let mut visits: Vec<_> = roots
+        .iter()
+        .filter_map(|root| match root {
+            Value::Box(object) if is_container(*object) => Some(Visit::Enter(*object)),
+            _ => None,
+        })
+        .collect();
+
+    while let Some(visit) = visits.pop() {
+        let object = match visit {
+            Visit::Enter(object) => object,
+            Visit::Exit(key) => {
+                searching.insert(key, false);
+                continue;
+            }
+        };
+
+        match searching.get(&key(object)) {
+            Some(true) => {
+                cycles.insert(key(object), None);
+                continue;
+            }
+            Some(false) => continue,
+            None => {}
+        }
+
+        searching.insert(key(object), true);
+        visits.push(Visit::Exit(key(object)));
+        let children = match object.try_read() {
+            Ok(object) => containers(&object),
+            Err(_) => Vec::new(),
+        };
+        visits.extend(children.into_iter().map(Visit::Enter));
+    }
+
+    cycles
+}
+
+struct Printer {
+    /// Objects that need a label, mapped to the label once it's been written
+    labels: HashMap<usize, Option<usize>>,
+    next_label: usize,
+    style: DisplayStyle,
+}
+
+impl Printer {
+    fn new(roots: &[Value<'_>], style: DisplayStyle) -> Self {
+        Self {
+            labels: find_cycles(roots),
+            next_label: 0,
+            style,
+        }
+    }
+
+    fn value(&mut self, value: Value<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match value {
+            Value::Box(object) => self.object(object, f),
+            _ => value.print(f, self.style),
+        }
+    }
+
+    fn object<'gc>(
+        &mut self,
+        object: GcCell<'gc, Object<'gc>>,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        if let Some(label) = self.labels.get_mut(&key(object)) {
+            match *label {
+                Some(label) => return write!(f, "#{}#", label),
+                None => {
+                    *label = Some(self.next_label);
+                    write!(f, "#{}=", self.next_label)?;
+                    self.next_label += 1;
+                }
+            }
+        }
+
+        // The object may be in the middle of being modified, e.g. when an error about it is
+        // being reported
+        let object = match object.try_read() {
+            Ok(object) => object,
+            Err(_) => return write!(f, "#<object in use>"),
+        };
+        match &*object {
+            Object::Pair(pair) => self.pair(pair, f),
+            Object::Vector(vector) => self.vector(vector, f),
+            Object::Record(record) => self.record(record, f),
+            Object::Cell(value) => {
+                write!(f, "#&")?;
+                self.value(*value, f)
+            }
+            Object::Error(error) => self.error(error, f),
+            object => object.print(f, self.style),
+        }
+    }
+
+    fn pair(&mut self, pair: &ObjPair<Value<'_>>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(")?;
+        self.value(pair.car(), f)?;
+        let mut cdr = pair.cdr();
+        loop {
+            // Keep going in list notation until the list ends, or reaches a labelled pair that
+            // has to be written out separately
+            let next = match cdr {
+                Value::Null => break,
+                Value::Pair(pair) => Some((pair.car().into(), pair.cdr().into())),
+                Value::Box(object) if !self.labels.contains_key(&key(object)) => {
+                    match object.try_read().as_deref() {
+                        Ok(Object::Pair(pair)) => Some((pair.car(), pair.cdr())),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+
+            match next {
+                Some((car, rest)) => {
+                    write!(f, " ")?;
+                    self.value(car, f)?;
+                    cdr = rest;
+                }
+                None => {
+                    write!(f, " . ")?;
+                    self.value(cdr, f)?;
+                    break;
+                }
+            }
+        }
+        write!(f, ")")
+    }
+
+    fn vector(&mut self, vector: &ObjVector<Value<'_>>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#(")?;
+        for (i, item) in vector.as_slice().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            self.value(*item, f)?;
+        }
+        write!(f, ")")
+    }
+
+    fn record(&mut self, record: &ObjRecord<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match record.record_type().try_read().as_deref() {
+            Ok(Object::RecordType(record_type)) => write!(f, "#<{}", record_type.record_name())?,
+            _ => write!(f, "#<record")?,
+        }
+        for field in record.fields() {
+            write!(f, " ")?;
+            self.value(*field, f)?;
+        }
+        write!(f, ">")
+    }
+
+    fn error(&mut self, error: &ObjError<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#<error ")?;
+        self.value(error.message(), f)?;
+        for irritant in error.irritants() {
+            write!(f, " ")?;
+            self.value(*irritant, f)?;
+        }
+        write!(f, ">")
+    }
+}
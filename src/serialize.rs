@@ -0,0 +1,1197 @@
+//! Experimental serialization of captured continuations and compiled programs
+//!
+//! A continuation is written out along with everything it can reach: its frames, their stacks,
+//! the closures and code running in them and the data they refer to. This is enough for a
+//! long-running script to checkpoint itself with `save-continuation` and carry on from the same
+//! place with `load-continuation`, even in another process. Globals aren't part of a
+//! continuation, so the program that resumes one has to define them again (usually by loading
+//! the same file).
+//!
+//! A compiled program is the thunks compiled from each top-level form of a file, in order, which
+//! `compile-file` writes out and `load` reads back in and runs instead of the source. Their code
+//! is written the same way as a continuation's: chunks with their constants, with symbols by
+//! name and the procedures they make written out in turn.
+//!
+//! Things that can't be written out are written as placeholders that get re-linked when they're
+//! read back in. Natives are written by the name they were registered under, which they carry
+//! from when they're made (see [`ObjNative::registered`] and
+//! [`VirtualMachine::register_native`]), and ports by where they read from or write to: file
+//! ports are reopened (input ports carry on from the same position), string ports are recreated
+//! and any other port is linked to the current input or output port of the VM doing the reading.
+//!
+//! Symbols are always interned again when they're read. The format is versioned, but there are
+//! no promises that what's written by one version of the interpreter can be read by another.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use gc_arena::{Gc, GcCell, MutationContext};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+
+use crate::builtins;
+use crate::chunk::{Chunk, Globals};
+use crate::compiler::verify::verify;
+use crate::compiler::{Upvalue as CompilerUpvalue, Upvalues};
+use crate::memory::{Symbol, Token};
+use crate::object::{
+    Buffering, CurrentPorts, DecodeErrorMode, Encoding, ErrorKind, ObjClosure, ObjContinuation,
+    ObjEnvironment, ObjError, ObjFunction, ObjNative, ObjPair, ObjReadPort, ObjRecord,
+    ObjRecordType, ObjString, ObjVector, ObjWritePort, Object, PortSource, Procedure, Upvalue,
+    UpvalueState,
+};
+use crate::value::{Char, Datum, Value};
+use crate::vm::{InterpretError, Result, Stack, VirtualMachine};
+
+/// Marks the start of a serialized continuation
+const MAGIC: &[u8; 4] = b"CHSK";
+
+/// Marks the start of a compiled program
+const PROGRAM_MAGIC: &[u8; 4] = b"CHSP";
+
+/// Bumped whenever the format changes
+const VERSION: u8 = 14;
+
+/// Identifies what's written next
+#[derive(Debug, Copy, Clone, IntoPrimitive, TryFromPrimitive, PartialEq, Eq)]
+#[repr(u8)]
+enum Tag {
+    False,
+    True,
+    Char,
+    Number,
+    Pair,
+    String,
+    Symbol,
+    Vector,
+    Eof,
+    Null,
+    Void,
+    Object,
+    Closure,
+    Continuation,
+    Environment,
+    Function,
+    Native,
+    ReadPort,
+    WritePort,
+    Console,
+    File,
+    Other,
+    Chunk,
+    RecordType,
+    Record,
+    Bytevector,
+    Cell,
+    Regex,
+    ErrorConsole,
+    Error,
+    Namespace,
+    Macro,
+}
+
+/// Writes out `continuation` and everything it refers to
+pub fn serialize_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    continuation: Value<'gc>,
+) -> Result<Vec<u8>> {
+    let is_continuation = continuation
+        .as_object()
+        .map(|object| object.read().is_continuation())
+        .unwrap_or(false);
+    if !is_continuation {
+        return Err(InterpretError::RuntimeError(format!(
+            "'{}' is not a continuation",
+            continuation
+        )));
+    }
+
+    let mut writer = Writer::new(vm);
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.byte(VERSION);
+    writer.value(continuation)?;
+    Ok(writer.bytes)
+}
+
+/// Reads a continuation written by `serialize_continuation` back in, re-linking its natives and
+/// ports to the ones in `vm`
+pub fn deserialize_continuation<'gc>(
+    vm: &VirtualMachine<'gc>,
+    bytes: &[u8],
+    mc: MutationContext<'gc, '_>,
+) -> Result<Value<'gc>> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(InterpretError::RuntimeError(
+            "Not a serialized continuation".into(),
+        ));
+    }
+
+    let mut reader = Reader::new(vm, &bytes[MAGIC.len()..], mc);
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(InterpretError::RuntimeError(format!(
+            "Unsupported serialized continuation version {}",
+            version
+        )));
+    }
+
+    let continuation = reader.value()?;
+    let is_continuation = continuation
+        .as_object()
+        .map(|object| object.read().is_continuation())
+        .unwrap_or(false);
+    if !is_continuation || reader.position != reader.bytes.len() {
+        return Err(corrupt());
+    }
+    Ok(continuation)
+}
+
+/// Writes out the thunks compiled from the forms of a program, and everything they refer to
+pub fn serialize_program<'gc>(vm: &VirtualMachine<'gc>, thunks: &[Value<'gc>]) -> Result<Vec<u8>> {
+    let mut writer = Writer::new(vm);
+    writer.bytes.extend_from_slice(PROGRAM_MAGIC);
+    writer.byte(VERSION);
+    writer.usize(thunks.len());
+    for thunk in thunks {
+        writer.value(*thunk)?;
+    }
+    Ok(writer.bytes)
+}
+
+/// Whether `bytes` start out like a program written by `serialize_program`
+pub fn is_program(bytes: &[u8]) -> bool {
+    bytes.starts_with(PROGRAM_MAGIC)
+}
+
+/// Reads the thunks of a program written by `serialize_program` back in, checking their bytecode
+/// and linking their natives to the ones in `vm`
+pub fn deserialize_program<'gc>(
+    vm: &VirtualMachine<'gc>,
+    bytes: &[u8],
+    mc: MutationContext<'gc, '_>,
+) -> Result<Vec<Value<'gc>>> {
+    if !is_program(bytes) {
+        return Err(InterpretError::RuntimeError(
+            "Not a compiled program".into(),
+        ));
+    }
+
+    let mut reader = Reader::new(vm, &bytes[PROGRAM_MAGIC.len()..], mc);
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(InterpretError::RuntimeError(format!(
+            "Unsupported compiled program version {}",
+            version
+        )));
+    }
+
+    let thunks = (0..reader.usize()?)
+        .map(|_| reader.value())
+        .collect::<Result<Vec<_>>>()?;
+    let is_thunk = |thunk: &Value<'gc>| {
+        thunk
+            .as_object()
+            .map(|object| matches!(&*object.read(), Object::Function(function) if function.arity() == 0))
+            .unwrap_or(false)
+    };
+    if !thunks.iter().all(is_thunk) || reader.position != reader.bytes.len() {
+        return Err(corrupt());
+    }
+    Ok(thunks)
+}
+
+fn corrupt() -> InterpretError {
+    InterpretError::RuntimeError("Serialized data is corrupt".into())
+}
+
+/// Assigns ids to things that are shared by reference, so each is only written out once. An id
+/// equal to the number of things seen so far introduces a new one, with its contents following.
+struct Ids(HashMap<usize, usize>);
+
+impl Ids {
+    /// Gets the id of the thing at `address`, and whether it's being seen for the first time
+    fn get(&mut self, address: usize) -> (usize, bool) {
+        let next = self.0.len();
+        let id = *self.0.entry(address).or_insert(next);
+        (id, id == next)
+    }
+}
+
+struct Writer<'a, 'gc> {
+    vm: &'a VirtualMachine<'gc>,
+    bytes: Vec<u8>,
+    objects: Ids,
+    stacks: Ids,
+    upvalues: Ids,
+    frames: Ids,
+    chunks: Ids,
+}
+
+impl<'a, 'gc> Writer<'a, 'gc> {
+    fn new(vm: &'a VirtualMachine<'gc>) -> Self {
+        Self {
+            vm,
+            bytes: Vec::new(),
+            objects: Ids(HashMap::new()),
+            stacks: Ids(HashMap::new()),
+            upvalues: Ids(HashMap::new()),
+            frames: Ids(HashMap::new()),
+            chunks: Ids(HashMap::new()),
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn tag(&mut self, tag: Tag) {
+        self.byte(tag.into());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.byte(value as u8);
+    }
+
+    /// Writes an unsigned LEB128 number
+    fn usize(&mut self, mut value: usize) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.byte(byte);
+                return;
+            }
+            self.byte(byte | 0x80);
+        }
+    }
+
+    fn number(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn slice(&mut self, bytes: &[u8]) {
+        self.usize(bytes.len());
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn symbol(&mut self, symbol: Symbol<'_>) {
+        self.slice(symbol.as_bytes());
+    }
+
+    fn value(&mut self, value: Value<'gc>) -> Result<()> {
+        match value {
+            Value::Bool(false) => self.tag(Tag::False),
+            Value::Bool(true) => self.tag(Tag::True),
+            Value::Char(character) => {
+                self.tag(Tag::Char);
+                self.usize(character.0 as usize);
+            }
+            Value::Number(number) => {
+                self.tag(Tag::Number);
+                self.number(number);
+            }
+            Value::Pair(pair) => {
+                self.tag(Tag::Pair);
+                self.value(pair.car().into())?;
+                self.value(pair.cdr().into())?;
+            }
+            Value::String(string) => {
+                self.tag(Tag::String);
+                self.slice(string.as_bytes());
+            }
+            Value::Symbol(symbol) => {
+                self.tag(Tag::Symbol);
+                self.symbol(symbol);
+            }
+            Value::Vector(vector) => {
+                self.tag(Tag::Vector);
+                self.usize(vector.as_slice().len());
+                for element in vector.as_slice() {
+                    self.value((*element).into())?;
+                }
+            }
+            Value::Box(object) => {
+                self.tag(Tag::Object);
+                self.object(object)?;
+            }
+            Value::Eof => self.tag(Tag::Eof),
+            Value::Null => self.tag(Tag::Null),
+            Value::Void => self.tag(Tag::Void),
+        }
+        Ok(())
+    }
+
+    fn object(&mut self, object: GcCell<'gc, Object<'gc>>) -> Result<()> {
+        let (id, is_new) = self.objects.get(object.as_ptr() as usize);
+        self.usize(id);
+        if !is_new {
+            return Ok(());
+        }
+
+        match &*object.read() {
+            Object::Closure(closure) => {
+                self.tag(Tag::Closure);
+                self.closure(closure)?;
+            }
+            Object::Continuation(continuation) => {
+                self.tag(Tag::Continuation);
+                self.continuation(continuation)?;
+            }
+            Object::Environment(environment) => {
+                self.tag(Tag::Environment);
+                self.environment(environment)?;
+            }
+            Object::Function(function) => {
+                self.tag(Tag::Function);
+                self.function(function)?;
+            }
+            Object::Native(native) => {
+                self.tag(Tag::Native);
+                self.native(native)?;
+            }
+            Object::String(string) => {
+                self.tag(Tag::String);
+                self.slice(string.as_bytes());
+            }
+            Object::Pair(pair) => {
+                self.tag(Tag::Pair);
+                self.value(pair.car())?;
+                self.value(pair.cdr())?;
+            }
+            Object::Vector(vector) => {
+                self.tag(Tag::Vector);
+                self.usize(vector.as_slice().len());
+                for element in vector.as_slice() {
+                    self.value(*element)?;
+                }
+            }
+            Object::ReadPort(port) => {
+                self.tag(Tag::ReadPort);
+                self.port_source(port.source());
+                self.bool(port.is_binary());
+                self.bool(port.is_open());
+                self.slice(port.encoding().name().as_bytes());
+                self.bool(port.decode_error_mode() == DecodeErrorMode::Raise);
+                self.usize(port.offset());
+            }
+            Object::WritePort(port) => {
+                self.tag(Tag::WritePort);
+                self.port_source(port.source());
+                self.bool(port.is_binary());
+                self.bool(port.is_open());
+                self.slice(port.encoding().name().as_bytes());
+                self.slice(port.contents().unwrap_or_default());
+                self.slice(port.buffering().name().as_bytes());
+            }
+            Object::Chunk(chunk) => {
+                self.tag(Tag::Chunk);
+                self.chunk_contents(chunk)?;
+            }
+            Object::RecordType(record_type) => {
+                self.tag(Tag::RecordType);
+                self.symbol(record_type.name());
+                self.usize(record_type.fields().len());
+                for field in record_type.fields() {
+                    self.symbol(*field);
+                }
+            }
+            Object::Record(record) => {
+                self.tag(Tag::Record);
+                self.object(record.record_type())?;
+                self.usize(record.fields().len());
+                for field in record.fields() {
+                    self.value(*field)?;
+                }
+            }
+            Object::Bytevector(bytevector) => {
+                self.tag(Tag::Bytevector);
+                self.slice(bytevector.as_slice());
+            }
+            Object::Cell(value) => {
+                self.tag(Tag::Cell);
+                self.value(*value)?;
+            }
+            Object::Error(error) => {
+                self.tag(Tag::Error);
+                self.byte(error.kind().into());
+                self.value(error.message())?;
+                self.usize(error.irritants().len());
+                for irritant in error.irritants() {
+                    self.value(*irritant)?;
+                }
+            }
+            Object::Namespace(globals) => {
+                self.tag(Tag::Namespace);
+                self.globals(*globals)?;
+            }
+            Object::Macro(transformer) => {
+                self.tag(Tag::Macro);
+                self.value(*transformer)?;
+            }
+            #[cfg(feature = "regex")]
+            Object::Regex(regex) => {
+                self.tag(Tag::Regex);
+                self.slice(regex.as_str().as_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    fn port_source(&mut self, source: &PortSource) {
+        match source {
+            PortSource::Console => self.tag(Tag::Console),
+            PortSource::ErrorConsole => self.tag(Tag::ErrorConsole),
+            PortSource::File(path) => {
+                self.tag(Tag::File);
+                self.slice(path.to_string_lossy().as_bytes());
+            }
+            PortSource::String(string) => {
+                self.tag(Tag::String);
+                self.slice(string);
+            }
+            PortSource::Other => self.tag(Tag::Other),
+        }
+    }
+
+    fn continuation(&mut self, continuation: &ObjContinuation<'gc>) -> Result<()> {
+        match continuation.frames() {
+            Some(frame) => {
+                self.bool(true);
+                self.frame(frame)?;
+            }
+            None => self.bool(false),
+        }
+        self.procedure(continuation.procedure())?;
+        self.stack(continuation.stack())?;
+        self.usize(continuation.stack_base());
+        self.usize(continuation.stack_top());
+        self.object(continuation.current_input_port())?;
+        self.object(continuation.current_output_port())?;
+        self.object(continuation.current_error_port())?;
+        self.value(continuation.handlers())?;
+        self.value(continuation.loading())?;
+        match continuation.prompt() {
+            Some(tag) => {
+                self.bool(true);
+                self.value(tag)?;
+            }
+            None => self.bool(false),
+        }
+        match continuation.port() {
+            Some(port) => {
+                self.bool(true);
+                self.object(port)?;
+            }
+            None => self.bool(false),
+        }
+        self.bool(continuation.is_delimited());
+        Ok(())
+    }
+
+    fn frame(&mut self, frame: GcCell<'gc, ObjContinuation<'gc>>) -> Result<()> {
+        let (id, is_new) = self.frames.get(frame.as_ptr() as usize);
+        self.usize(id);
+        if is_new {
+            self.continuation(&frame.read())?;
+        }
+        Ok(())
+    }
+
+    fn procedure(&mut self, procedure: &Procedure<'gc>) -> Result<()> {
+        match procedure {
+            Procedure::Closure { closure, ip } => {
+                self.tag(Tag::Closure);
+                self.closure(closure)?;
+                self.usize(*ip);
+            }
+            Procedure::Function { function, ip } => {
+                self.tag(Tag::Function);
+                self.function(function)?;
+                self.usize(*ip);
+            }
+            Procedure::Native(native) => {
+                self.tag(Tag::Native);
+                self.native(native)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn stack(&mut self, stack: Stack<'gc>) -> Result<()> {
+        let (id, is_new) = self.stacks.get(stack.as_ptr() as usize);
+        self.usize(id);
+        if is_new {
+            let values = stack.read().clone();
+            self.usize(values.len());
+            for value in values {
+                self.value(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn closure(&mut self, closure: &ObjClosure<'gc>) -> Result<()> {
+        self.function(closure.function())?;
+        self.environment(&closure.environment())
+    }
+
+    fn environment(&mut self, environment: &ObjEnvironment<'gc>) -> Result<()> {
+        self.usize(environment.upvalues().len());
+        for upvalue in environment.upvalues() {
+            self.upvalue(upvalue)?;
+        }
+        Ok(())
+    }
+
+    /// Writes an upvalue once, so that closures sharing it still share it when they're read back
+    fn upvalue(&mut self, upvalue: &Upvalue<'gc>) -> Result<()> {
+        let (id, is_new) = self.upvalues.get(upvalue.as_ptr() as usize);
+        self.usize(id);
+        if is_new {
+            match upvalue.state() {
+                UpvalueState::Open { stack, offset } => {
+                    self.bool(true);
+                    self.stack(stack)?;
+                    self.usize(offset);
+                }
+                UpvalueState::Closed(value) => {
+                    self.bool(false);
+                    self.value(value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, function: &ObjFunction<'gc>) -> Result<()> {
+        self.usize(function.arity());
+        self.bool(function.is_variadic());
+        self.name(function.name());
+        self.usize(function.upvalues().len());
+        for upvalue in function.upvalues().iter() {
+            self.usize(upvalue.index().into());
+            self.bool(upvalue.is_local());
+        }
+        self.chunk(function.chunk())
+    }
+
+    fn chunk(&mut self, chunk: Gc<'gc, Chunk<'gc>>) -> Result<()> {
+        let (id, is_new) = self.chunks.get(Gc::as_ptr(chunk) as usize);
+        self.usize(id);
+        if !is_new {
+            return Ok(());
+        }
+        self.chunk_contents(&chunk)
+    }
+
+    fn chunk_contents(&mut self, chunk: &Chunk<'gc>) -> Result<()> {
+        self.slice(chunk.code());
+        self.usize(chunk.lines().len());
+        for (times, line) in chunk.lines() {
+            self.usize(*times as usize);
+            self.usize(*line);
+        }
+        self.usize(chunk.columns().len());
+        for (offset, column) in chunk.columns() {
+            self.usize(*offset);
+            self.usize(*column);
+        }
+        self.usize(chunk.constants().len());
+        for constant in chunk.constants() {
+            self.value(*constant)?;
+        }
+        match chunk.file() {
+            Some(file) => {
+                self.bool(true);
+                self.slice(file.as_bytes());
+            }
+            None => self.bool(false),
+        }
+        match chunk.globals() {
+            Some(globals) => {
+                self.bool(true);
+                self.globals(globals)?;
+            }
+            None => self.bool(false),
+        }
+        Ok(())
+    }
+
+    /// Writes out a table of global variables by what it belongs to: the VM itself or a library,
+    /// which it's linked back up to when it's read in
+    fn globals(&mut self, globals: Globals<'gc>) -> Result<()> {
+        if GcCell::ptr_eq(globals, self.vm.globals()) {
+            self.bool(false);
+            return Ok(());
+        }
+        let name = self.vm.library_with_globals(globals).ok_or_else(|| {
+            InterpretError::RuntimeError(
+                "Can't serialize an environment that doesn't belong to a library".into(),
+            )
+        })?;
+        self.bool(true);
+        self.slice(name.as_bytes());
+        Ok(())
+    }
+
+    fn native(&mut self, native: &ObjNative<'gc>) -> Result<()> {
+        let name = native.registered_name().ok_or_else(|| {
+            InterpretError::RuntimeError(format!(
+                "Can't serialize {}, it isn't a registered native",
+                native
+            ))
+        })?;
+        self.slice(name.as_bytes());
+
+        self.usize(native.arity());
+        self.bool(native.is_variadic());
+        self.name(native.name());
+        Ok(())
+    }
+
+    fn name(&mut self, name: Option<Symbol<'gc>>) {
+        match name {
+            Some(name) => {
+                self.bool(true);
+                self.symbol(name);
+            }
+            None => self.bool(false),
+        }
+    }
+}
+
+struct Reader<'a, 'gc> {
+    vm: &'a VirtualMachine<'gc>,
+    mc: MutationContext<'gc, 'a>,
+    bytes: &'a [u8],
+    position: usize,
+    objects: Vec<GcCell<'gc, Object<'gc>>>,
+    stacks: Vec<Stack<'gc>>,
+    upvalues: Vec<Upvalue<'gc>>,
+    frames: Vec<GcCell<'gc, ObjContinuation<'gc>>>,
+
+    /// Chunks are immutable, so they can't be allocated before their contents are read. They're
+    /// `None` until then.
+    chunks: Vec<Option<Gc<'gc, Chunk<'gc>>>>,
+}
+
+impl<'a, 'gc> Reader<'a, 'gc> {
+    fn new(vm: &'a VirtualMachine<'gc>, bytes: &'a [u8], mc: MutationContext<'gc, 'a>) -> Self {
+        Self {
+            vm,
+            mc,
+            bytes,
+            position: 0,
+            objects: Vec::new(),
+            stacks: Vec::new(),
+            upvalues: Vec::new(),
+            frames: Vec::new(),
+            chunks: Vec::new(),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self.bytes.get(self.position).ok_or_else(corrupt)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn tag(&mut self) -> Result<Tag> {
+        Tag::try_from(self.byte()?).map_err(|_| corrupt())
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(corrupt()),
+        }
+    }
+
+    fn usize(&mut self) -> Result<usize> {
+        let mut value = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= usize::BITS {
+                return Err(corrupt());
+            }
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn number(&mut self) -> Result<f64> {
+        let end = self.position + 8;
+        let bytes = self.bytes.get(self.position..end).ok_or_else(corrupt)?;
+        self.position = end;
+        Ok(f64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn slice(&mut self) -> Result<&'a [u8]> {
+        let len = self.usize()?;
+        let end = self.position.checked_add(len).ok_or_else(corrupt)?;
+        let bytes = self.bytes.get(self.position..end).ok_or_else(corrupt)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        String::from_utf8(self.slice()?.to_vec()).map_err(|_| corrupt())
+    }
+
+    fn symbol(&mut self) -> Result<Symbol<'gc>> {
+        let name = self.string()?;
+        Ok(self
+            .vm
+            .intern_symbol(Token::new(self.mc, name.into()), self.mc))
+    }
+
+    /// Reads an id introducing a new shared thing or referring to one read earlier
+    fn id(&mut self, seen: usize) -> Result<Option<usize>> {
+        match self.usize()? {
+            id if id < seen => Ok(Some(id)),
+            id if id == seen => Ok(None),
+            _ => Err(corrupt()),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value<'gc>> {
+        match self.tag()? {
+            Tag::Void => Ok(Value::Void),
+            Tag::Object => Ok(Value::Box(self.object()?)),
+            tag => Ok(self.datum(tag)?.into()),
+        }
+    }
+
+    /// Reads the rest of an immutable datum, which starts with `tag`
+    fn datum(&mut self, tag: Tag) -> Result<Datum<'gc>> {
+        let datum = match tag {
+            Tag::False => Datum::Bool(false),
+            Tag::True => Datum::Bool(true),
+            Tag::Char => {
+                let code = u32::try_from(self.usize()?).map_err(|_| corrupt())?;
+                Datum::Char(Char(char::from_u32(code).ok_or_else(corrupt)?))
+            }
+            Tag::Number => Datum::Number(self.number()?),
+            Tag::Pair => {
+                let car = self.next_datum()?;
+                let cdr = self.next_datum()?;
+                Datum::Pair(Gc::allocate(self.mc, ObjPair::new(car, cdr)))
+            }
+            Tag::String => {
+                let string = ObjString::new(self.slice()?.into());
+                Datum::String(self.vm.intern_string(string, self.mc))
+            }
+            Tag::Symbol => Datum::Symbol(self.symbol()?),
+            Tag::Vector => {
+                let len = self.usize()?;
+                let elements = (0..len)
+                    .map(|_| self.next_datum())
+                    .collect::<Result<Vec<_>>>()?;
+                let vector = ObjVector::new(elements.into_boxed_slice());
+                Datum::Vector(Gc::allocate(self.mc, vector))
+            }
+            Tag::Eof => Datum::Eof,
+            Tag::Null => Datum::Null,
+            _ => return Err(corrupt()),
+        };
+        Ok(datum)
+    }
+
+    fn next_datum(&mut self) -> Result<Datum<'gc>> {
+        let tag = self.tag()?;
+        self.datum(tag)
+    }
+
+    fn object(&mut self) -> Result<GcCell<'gc, Object<'gc>>> {
+        if let Some(id) = self.id(self.objects.len())? {
+            return Ok(self.objects[id]);
+        }
+
+        // Ports might be linked to one of the VM's own, so they're allocated once they're read
+        let kind = self.tag()?;
+        if kind == Tag::ReadPort || kind == Tag::WritePort {
+            let port = self.port(kind)?;
+            self.objects.push(port);
+            return Ok(port);
+        }
+
+        // Anything else can (indirectly) refer to itself, so it needs to be allocated first
+        let placeholder = Object::Pair(ObjPair::new(Value::Void, Value::Void));
+        let cell = GcCell::allocate(self.mc, placeholder);
+        self.objects.push(cell);
+        let object = match kind {
+            Tag::Closure => Object::Closure(self.closure()?),
+            Tag::Continuation => Object::Continuation(self.continuation()?),
+            Tag::Environment => Object::Environment(self.environment()?),
+            Tag::Function => Object::Function(self.function()?),
+            Tag::Native => Object::Native(self.native()?),
+            Tag::String => Object::String(ObjString::new(self.slice()?.into())),
+            Tag::Pair => {
+                let car = self.value()?;
+                let cdr = self.value()?;
+                Object::Pair(ObjPair::new(car, cdr))
+            }
+            Tag::Vector => {
+                let len = self.usize()?;
+                let elements = (0..len).map(|_| self.value()).collect::<Result<Vec<_>>>()?;
+                Object::Vector(ObjVector::new(elements.into_boxed_slice()))
+            }
+            Tag::Chunk => Object::Chunk(self.chunk_contents()?),
+            Tag::RecordType => {
+                let name = self.symbol()?;
+                let len = self.usize()?;
+                let fields = (0..len)
+                    .map(|_| self.symbol())
+                    .collect::<Result<Vec<_>>>()?;
+                Object::RecordType(ObjRecordType::new(name, fields.into_boxed_slice()))
+            }
+            Tag::Record => {
+                let record_type = self.object()?;
+                let len = self.usize()?;
+                let fields = (0..len).map(|_| self.value()).collect::<Result<Vec<_>>>()?;
+                Object::Record(ObjRecord::new(record_type, fields.into_boxed_slice()))
+            }
+            Tag::Bytevector => Object::Bytevector(ObjVector::new(self.slice()?.into())),
+            Tag::Cell => Object::Cell(self.value()?),
+            Tag::Namespace => Object::Namespace(self.globals()?),
+            Tag::Macro => Object::Macro(self.value()?),
+            Tag::Error => {
+                let kind = ErrorKind::try_from(self.byte()?).map_err(|_| corrupt())?;
+                let message = self.value()?;
+                let len = self.usize()?;
+                let irritants = (0..len).map(|_| self.value()).collect::<Result<Vec<_>>>()?;
+                Object::Error(ObjError::new(message, irritants.into_boxed_slice()).with_kind(kind))
+            }
+            #[cfg(feature = "regex")]
+            Tag::Regex => {
+                let pattern = std::str::from_utf8(self.slice()?).map_err(|_| corrupt())?;
+                let regex = regex::Regex::new(pattern).map_err(|_| corrupt())?;
+                Object::Regex(crate::object::ObjRegex::new(regex))
+            }
+            _ => return Err(corrupt()),
+        };
+        *cell.write(self.mc) = object;
+        Ok(cell)
+    }
+
+    fn port(&mut self, kind: Tag) -> Result<GcCell<'gc, Object<'gc>>> {
+        let source = self.port_source()?;
+        let binary = self.bool()?;
+        let is_open = self.bool()?;
+        let encoding: Encoding = self
+            .string()?
+            .parse()
+            .map_err(InterpretError::RuntimeError)?;
+
+        let port = if kind == Tag::ReadPort {
+            let mode = if self.bool()? {
+                DecodeErrorMode::Raise
+            } else {
+                DecodeErrorMode::Replace
+            };
+            let offset = self.usize()?;
+            let mut port = match source {
+                // Closed ports don't need (and may no longer be able) to reopen what they read
+                source if !is_open => {
+                    let port = if binary {
+                        ObjReadPort::binary(io::empty())
+                    } else {
+                        ObjReadPort::new(io::empty())
+                    };
+                    let mut port = port.with_source(source);
+                    port.close()?;
+                    port
+                }
+                PortSource::File(path) if binary => {
+                    ObjReadPort::binary_seekable(File::open(&path)?)
+                        .with_source(PortSource::File(path))
+                }
+                PortSource::File(path) => ObjReadPort::seekable(File::open(&path)?, encoding, mode)
+                    .with_source(PortSource::File(path)),
+                PortSource::String(bytes) if binary => ObjReadPort::bytevector(&bytes),
+                PortSource::String(string) => ObjReadPort::string(&string),
+                PortSource::Console | PortSource::ErrorConsole | PortSource::Other => {
+                    return Ok(*self.vm.current_input_port().read())
+                }
+            };
+            port.skip(offset)?;
+            Object::ReadPort(port)
+        } else {
+            let contents = self.slice()?;
+            let buffering: Buffering = self
+                .string()?
+                .parse()
+                .map_err(InterpretError::RuntimeError)?;
+            let mut port = match source {
+                PortSource::File(path) if !is_open => {
+                    ObjWritePort::new(io::sink()).with_source(PortSource::File(path))
+                }
+                PortSource::File(path) => {
+                    // Picks up after whatever was written before, like appending would
+                    let mut file = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(false)
+                        .open(&path)?;
+                    file.seek(SeekFrom::End(0))?;
+                    ObjWritePort::seekable(file, encoding).with_source(PortSource::File(path))
+                }
+                PortSource::String(_) => ObjWritePort::with_contents(contents.to_vec()),
+                PortSource::ErrorConsole => return Ok(*self.vm.current_error_port().read()),
+                PortSource::Console | PortSource::Other => {
+                    return Ok(*self.vm.current_output_port().read())
+                }
+            };
+            if !is_open {
+                port.close()?;
+            }
+            let port = port.with_buffering(buffering);
+            Object::WritePort(if binary { port.into_binary() } else { port })
+        };
+        Ok(GcCell::allocate(self.mc, port))
+    }
+
+    fn port_source(&mut self) -> Result<PortSource> {
+        match self.tag()? {
+            Tag::Console => Ok(PortSource::Console),
+            Tag::ErrorConsole => Ok(PortSource::ErrorConsole),
+            Tag::File => Ok(PortSource::File(PathBuf::from(self.string()?))),
+            Tag::String => Ok(PortSource::String(Rc::from(self.slice()?))),
+            Tag::Other => Ok(PortSource::Other),
+            _ => Err(corrupt()),
+        }
+    }
+
+    fn continuation(&mut self) -> Result<ObjContinuation<'gc>> {
+        let frames = if self.bool()? {
+            Some(self.frame()?)
+        } else {
+            None
+        };
+        let procedure = self.procedure()?;
+        let stack = self.stack()?;
+        let stack_base = self.usize()?;
+        let stack_top = self.usize()?;
+        let current_input_port = self.object()?;
+        let current_output_port = self.object()?;
+        let current_error_port = self.object()?;
+        let handlers = self.value()?;
+        let loading = self.value()?;
+        let prompt = if self.bool()? {
+            Some(self.value()?)
+        } else {
+            None
+        };
+        let port = if self.bool()? {
+            Some(self.object()?)
+        } else {
+            None
+        };
+        let delimited = self.bool()?;
+
+        let mut continuation = ObjContinuation::new(
+            frames,
+            procedure,
+            stack,
+            stack_base..stack_top,
+            CurrentPorts {
+                input: current_input_port,
+                output: current_output_port,
+                error: current_error_port,
+            },
+            handlers,
+            loading,
+        );
+        continuation.restore(prompt, port, delimited);
+        Ok(continuation)
+    }
+
+    fn frame(&mut self) -> Result<GcCell<'gc, ObjContinuation<'gc>>> {
+        if let Some(id) = self.id(self.frames.len())? {
+            return Ok(self.frames[id]);
+        }
+
+        // Stand in for the frame until it's been read, in case something in it refers back to it
+        let halt = Procedure::Native(ObjNative::registered(0, false, builtins::HALT, None));
+        let placeholder = ObjContinuation::new(
+            None,
+            halt,
+            GcCell::allocate(self.mc, Vec::new()),
+            0..0,
+            self.vm.current_ports(),
+            Value::Null,
+            Value::Null,
+        );
+        let frame = GcCell::allocate(self.mc, placeholder);
+        self.frames.push(frame);
+        let continuation = self.continuation()?;
+        *frame.write(self.mc) = continuation;
+        Ok(frame)
+    }
+
+    fn procedure(&mut self) -> Result<Procedure<'gc>> {
+        match self.tag()? {
+            Tag::Closure => {
+                let closure = self.closure()?;
+                let ip = self.usize()?;
+                Ok(Procedure::Closure { closure, ip })
+            }
+            Tag::Function => {
+                let function = self.function()?;
+                let ip = self.usize()?;
+                Ok(Procedure::Function { function, ip })
+            }
+            Tag::Native => Ok(Procedure::Native(self.native()?)),
+            _ => Err(corrupt()),
+        }
+    }
+
+    fn stack(&mut self) -> Result<Stack<'gc>> {
+        if let Some(id) = self.id(self.stacks.len())? {
+            return Ok(self.stacks[id]);
+        }
+
+        let stack = GcCell::allocate(self.mc, Vec::new());
+        self.stacks.push(stack);
+        let len = self.usize()?;
+        let values = (0..len).map(|_| self.value()).collect::<Result<Vec<_>>>()?;
+        *stack.write(self.mc) = values;
+        Ok(stack)
+    }
+
+    fn closure(&mut self) -> Result<ObjClosure<'gc>> {
+        let function = self.function()?;
+        let environment = self.environment()?;
+        Ok(ObjClosure::new(
+            function,
+            Gc::allocate(self.mc, environment),
+        ))
+    }
+
+    fn environment(&mut self) -> Result<ObjEnvironment<'gc>> {
+        let len = self.usize()?;
+        let upvalues = (0..len)
+            .map(|_| self.upvalue())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ObjEnvironment::new(upvalues))
+    }
+
+    fn upvalue(&mut self) -> Result<Upvalue<'gc>> {
+        if let Some(id) = self.id(self.upvalues.len())? {
+            return Ok(self.upvalues[id]);
+        }
+
+        // Stand in for the upvalue until it's been read, in case its value refers back to it
+        let upvalue = Upvalue::from_state(UpvalueState::Closed(Value::Void), self.mc);
+        self.upvalues.push(upvalue);
+        let state = if self.bool()? {
+            UpvalueState::Open {
+                stack: self.stack()?,
+                offset: self.usize()?,
+            }
+        } else {
+            UpvalueState::Closed(self.value()?)
+        };
+        upvalue.set_state(state, self.mc);
+        Ok(upvalue)
+    }
+
+    fn function(&mut self) -> Result<ObjFunction<'gc>> {
+        let arity = self.usize()?;
+        let variadic = self.bool()?;
+        let name = self.name()?;
+        let mut upvalues = Upvalues::default();
+        for _ in 0..self.usize()? {
+            let index = u16::try_from(self.usize()?).map_err(|_| corrupt())?;
+            let is_local = self.bool()?;
+            upvalues.insert(CompilerUpvalue::new(index, is_local));
+        }
+        let chunk = self.chunk()?;
+        let function = ObjFunction::from_parts(
+            arity,
+            variadic,
+            chunk,
+            Gc::allocate(self.mc, upvalues),
+            name,
+        );
+        verify(&function)?;
+        Ok(function)
+    }
+
+    fn chunk(&mut self) -> Result<Gc<'gc, Chunk<'gc>>> {
+        if let Some(id) = self.id(self.chunks.len())? {
+            // A chunk that's still being read can't contain itself
+            return self.chunks[id].ok_or_else(corrupt);
+        }
+
+        let id = self.chunks.len();
+        self.chunks.push(None);
+        let chunk = Gc::allocate(self.mc, self.chunk_contents()?);
+        self.chunks[id] = Some(chunk);
+        Ok(chunk)
+    }
+
+    fn chunk_contents(&mut self) -> Result<Chunk<'gc>> {
+        let code = self.slice()?.to_vec();
+        let lines = (0..self.usize()?)
+            .map(|_| Ok((self.usize()? as isize, self.usize()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let columns = (0..self.usize()?)
+            .map(|_| Ok((self.usize()?, self.usize()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let constants = (0..self.usize()?)
+            .map(|_| self.value())
+            .collect::<Result<Vec<_>>>()?;
+        let file = if self.bool()? {
+            Some(Rc::from(self.string()?))
+        } else {
+            None
+        };
+        let globals = if self.bool()? {
+            Some(self.globals()?)
+        } else {
+            None
+        };
+
+        let mut chunk = Chunk::from_parts(code, lines, columns, constants, file);
+        chunk.set_globals(globals);
+        Ok(chunk)
+    }
+
+    fn globals(&mut self) -> Result<Globals<'gc>> {
+        if !self.bool()? {
+            return Ok(self.vm.globals());
+        }
+        let name = self.string()?;
+        self.vm.library_globals(&name).ok_or_else(|| {
+            InterpretError::RuntimeError(format!("No library named {} is defined", name))
+        })
+    }
+
+    fn native(&mut self) -> Result<ObjNative<'gc>> {
+        let name = self.string()?;
+        let function = self.vm.natives().get(&name).ok_or_else(|| {
+            InterpretError::RuntimeError(format!("No native named '{}' is registered", name))
+        })?;
+        let arity = self.usize()?;
+        let variadic = self.bool()?;
+        let name = self.name()?;
+        Ok(ObjNative::registered(arity, variadic, function, name))
+    }
+
+    fn name(&mut self) -> Result<Option<Symbol<'gc>>> {
+        if self.bool()? {
+            Ok(Some(self.symbol()?))
+        } else {
+            Ok(None)
+        }
+    }
+}
@@ -8,6 +8,7 @@ use thiserror::Error;
 
 use crate::memory::Symbol;
 use crate::object::{ObjPair, ObjString, ObjVector, Object};
+use crate::printer;
 use crate::vm::InterpretError;
 
 #[derive(Collect, Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -28,6 +29,50 @@ impl fmt::Display for Char {
     }
 }
 
+impl Print for Char {
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result {
+        match (style, self.0) {
+            (DisplayStyle::Display, character) => write!(f, "{}", character),
+            (DisplayStyle::Write, ' ') => write!(f, "#\\space"),
+            (DisplayStyle::Write, '\n') => write!(f, "#\\newline"),
+            (DisplayStyle::Write, character) => write!(f, "#\\{}", character),
+        }
+    }
+}
+
+/// How a value should be rendered when it's printed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisplayStyle {
+    /// Human-readable output as produced by `display` - strings and characters are printed
+    /// as their raw contents
+    Display,
+
+    /// Machine-readable external representation as produced by `write`, which the reader can
+    /// parse back in
+    Write,
+}
+
+/// Something that can be printed in either `display` or `write` style
+pub trait Print {
+    /// Print this value in the given style
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result;
+
+    /// Adapts this value so that it can be used with `format!` and friends in the given style
+    fn styled(&self, style: DisplayStyle) -> Styled<'_, Self> {
+        Styled(self, style)
+    }
+}
+
+/// A value paired with the style it should be printed in
+#[derive(Debug)]
+pub struct Styled<'a, T: ?Sized>(&'a T, DisplayStyle);
+
+impl<T: Print + ?Sized> fmt::Display for Styled<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.print(f, self.1)
+    }
+}
+
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub enum Datum<'gc> {
@@ -181,6 +226,9 @@ impl<'gc> From<Gc<'gc, ObjVector<Datum<'gc>>>> for Datum<'gc> {
 }
 
 /// Represents a value within the virtual machine
+///
+/// A value is a tag and one word. It isn't NaN-boxed into a single word, since gc-arena has no
+/// way to turn raw bits back into the `Gc` and `GcCell` pointers the heap variants hold.
 #[derive(Debug, Copy, Clone, Collect)]
 #[collect(no_drop)]
 pub enum Value<'gc> {
@@ -359,6 +407,12 @@ impl<'gc> From<Datum<'gc>> for Value<'gc> {
 
 impl fmt::Display for Value<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.print(f, DisplayStyle::Write)
+    }
+}
+
+impl Print for Value<'_> {
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result {
         match *self {
             Self::Bool(_b @ true) => {
                 write!(f, "#t")
@@ -366,21 +420,13 @@ impl fmt::Display for Value<'_> {
             Self::Bool(_b @ false) => {
                 write!(f, "#f")
             }
-            Self::Pair(pair) => write!(f, "{}", *pair),
-            Self::String(string) => write!(f, "{}", *string),
-            Self::Box(object) => write!(f, "{}", object.read()),
-            Self::Char(Char(_c @ ' ')) => {
-                write!(f, "#\\space")
-            }
-            Self::Char(Char(_c @ '\n')) => {
-                write!(f, "#\\newline")
-            }
-            Self::Char(Char(character)) => {
-                write!(f, "#\\{}", character)
-            }
+            Self::Pair(pair) => pair.print(f, style),
+            Self::String(string) => string.print(f, style),
+            Self::Box(object) => printer::print_object(object, f, style),
+            Self::Char(character) => character.print(f, style),
             Self::Number(number) => write!(f, "{}", number),
             Self::Symbol(symbol) => write!(f, "{}", symbol),
-            Self::Vector(vector) => write!(f, "{}", *vector),
+            Self::Vector(vector) => vector.print(f, style),
             Self::Eof => write!(f, "#<eof>"),
             Self::Null => write!(f, "()"),
             Self::Void => write!(f, "#<void>"),
@@ -390,6 +436,12 @@ impl fmt::Display for Value<'_> {
 
 impl fmt::Display for Datum<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.print(f, DisplayStyle::Write)
+    }
+}
+
+impl Print for Datum<'_> {
+    fn print(&self, f: &mut fmt::Formatter<'_>, style: DisplayStyle) -> fmt::Result {
         match *self {
             Self::Bool(_b @ true) => {
                 write!(f, "#t")
@@ -397,20 +449,12 @@ impl fmt::Display for Datum<'_> {
             Self::Bool(_b @ false) => {
                 write!(f, "#f")
             }
-            Self::Pair(pair) => write!(f, "{}", *pair),
-            Self::String(string) => write!(f, "{}", *string),
-            Self::Char(Char(_c @ ' ')) => {
-                write!(f, "#\\space")
-            }
-            Self::Char(Char(_c @ '\n')) => {
-                write!(f, "#\\newline")
-            }
-            Self::Char(Char(character)) => {
-                write!(f, "#\\{}", character)
-            }
+            Self::Pair(pair) => pair.print(f, style),
+            Self::String(string) => string.print(f, style),
+            Self::Char(character) => character.print(f, style),
             Self::Number(number) => write!(f, "{}", number),
             Self::Symbol(symbol) => write!(f, "{}", symbol),
-            Self::Vector(vector) => write!(f, "{}", *vector),
+            Self::Vector(vector) => vector.print(f, style),
             Self::Null => write!(f, "()"),
             Self::Eof => write!(f, "#<eof>"),
         }
@@ -1,8 +1,10 @@
-use core::cell::Cell;
+use core::cell::{Cell, Ref, RefCell, RefMut};
 use core::convert::TryFrom;
 use core::str::Utf8Error;
-use std::collections::HashMap;
-use std::io;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{env, io};
 
 use gc_arena::{Gc, GcCell, MutationContext};
 use gc_arena_derive::Collect;
@@ -10,16 +12,36 @@ use pest::error::Error;
 use thiserror::Error;
 
 use crate::builtins;
-use crate::chunk::{Chunk, OpCode};
-use crate::compiler::bootstrap;
-use crate::memory::{Symbol, SymbolTable, Token};
+use crate::chunk::{Chunk, GlobalTable, Globals, OpCode};
+use crate::compiler::{bootstrap, SourceMap, Tables};
+use crate::memory::{StringTable, Symbol, SymbolTable, Token};
 use crate::object::{
-    self, ObjClosure, ObjContinuation, ObjEnvironment, ObjFunction, ObjNative, ObjPair,
-    ObjReadPort, ObjString, ObjWritePort, Object, Upvalue,
+    self, CurrentPorts, Native, NativeRegistry, ObjClosure, ObjContinuation, ObjEnvironment,
+    ObjFunction, ObjNative, ObjPair, ObjReadPort, ObjString, ObjWritePort, Object, PortBackend,
+    Upvalue, UpvalueState,
 };
+use crate::platform;
 use crate::scanner::Rule;
-use crate::value::{TypeError, Value};
-
+use crate::value::{DisplayStyle, Print, TypeError, Value};
+
+mod config;
+mod hooks;
+#[cfg(feature = "jit")]
+pub(crate) mod jit;
+mod libraries;
+mod profile;
+mod snapshots;
+mod threads;
+
+pub use config::{BuiltinGroup, VmConfig};
+pub use hooks::VmHooks;
+pub use snapshots::MAX_SNAPSHOTS;
+
+pub(crate) use libraries::{imported_library, library_files, library_name, Library};
+use profile::Profile;
+use snapshots::TimeTravel;
+
+/// How many values the stack has room for before it has to grow, unless the embedder says otherwise
 const STACK_MAX: usize = u8::MAX as usize + 1;
 
 #[derive(Debug, Clone, Collect)]
@@ -47,8 +69,27 @@ impl<'gc> TryFrom<Value<'gc>> for Procedure<'gc> {
     }
 }
 
+/// What the running procedure needs to run, taken out of it each step
+enum Active<'gc> {
+    /// A chunk of bytecode, along with the upvalues of the closure running it (if any)
+    Chunk(Gc<'gc, Chunk<'gc>>, Option<Gc<'gc, ObjEnvironment<'gc>>>),
+
+    /// A native
+    Native(Native),
+}
+
 pub(crate) type Stack<'gc> = GcCell<'gc, Vec<Value<'gc>>>;
 
+/// Seed every VM's random number generator starts from, so runs are reproducible
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// How many frames deep calls can nest before they fail, unless the embedder says otherwise
+pub const DEFAULT_MAX_DEPTH: usize = 100_000;
+
+/// How many frames past the limit handlers get for dealing with the error about it, before calls
+/// fail again
+const DEPTH_HEADROOM: usize = 100;
+
 /// Represents the VM that our language executes on
 #[derive(Debug, Collect)]
 #[collect(no_drop)]
@@ -56,6 +97,24 @@ pub struct VirtualMachine<'gc> {
     /// Parent continuation (frame)
     parent_continuation: GcCell<'gc, Option<GcCell<'gc, ObjContinuation<'gc>>>>,
 
+    /// Frames of the current continuation recorded by calls since it was last needed as an
+    /// object, innermost last. They sit on top of `parent_continuation`, and only move onto the
+    /// heap (see `reify_frames`) when something like `call/cc` captures them.
+    frames: GcCell<'gc, Vec<ObjContinuation<'gc>>>,
+
+    /// How many frames the current continuation has, recorded and on the heap
+    depth: Cell<usize>,
+
+    /// How many frames deep calls can nest
+    max_depth: Cell<usize>,
+
+    /// How many more instructions can run, or `None` for no limit
+    fuel: Cell<Option<u64>>,
+
+    /// Flag that stops the program when it's set, checked before every instruction
+    #[collect(require_static)]
+    interrupt: Cell<Option<&'static AtomicBool>>,
+
     /// Currently executing procedure
     procedure: GcCell<'gc, Procedure<'gc>>,
 
@@ -65,17 +124,124 @@ pub struct VirtualMachine<'gc> {
     /// Stack
     stack: GcCell<'gc, Stack<'gc>>,
 
+    /// Slot of the stack holding the running procedure, where its frame starts. Procedures
+    /// written in bytecode run on their caller's stack, so their locals are relative to this.
+    base: Cell<usize>,
+
+    /// Upvalues whose variables are still on a stack, which get closed when their frame ends
+    open_upvalues: GcCell<'gc, Vec<Upvalue<'gc>>>,
+
     /// Symbol pool
     symbol_pool: GcCell<'gc, SymbolTable<'gc>>,
 
+    /// String constant pool
+    string_pool: GcCell<'gc, StringTable<'gc>>,
+
     /// Global variable table
-    globals: GcCell<'gc, HashMap<Symbol<'gc>, Value<'gc>>>,
+    globals: Globals<'gc>,
+
+    /// The global variables as an environment object, for `interaction-environment`
+    environment: Value<'gc>,
+
+    /// Libraries made by `define-library` (and the standard ones), by name
+    libraries: GcCell<'gc, HashMap<String, Library<'gc>>>,
 
     /// Current input port
     current_input_port: GcCell<'gc, GcCell<'gc, Object<'gc>>>,
 
     /// Current output port
     current_output_port: GcCell<'gc, GcCell<'gc, Object<'gc>>>,
+
+    /// Current error port
+    current_error_port: GcCell<'gc, GcCell<'gc, Object<'gc>>>,
+
+    /// Installed exception handlers, innermost first
+    handlers: GcCell<'gc, Value<'gc>>,
+
+    /// Files being loaded, innermost first, each paired with the index of the form being run
+    loading: GcCell<'gc, Value<'gc>>,
+
+    /// What the macro transformer being run has renamed so far, as an alist from each identifier
+    /// to the alias it was renamed to
+    renames: GcCell<'gc, Value<'gc>>,
+
+    /// Every file that has been loaded so far, for `load-once`
+    #[collect(require_static)]
+    loaded: RefCell<HashSet<PathBuf>>,
+
+    /// Directories searched for files to load and libraries to import, from `CHESHIRE_PATH` and
+    /// `add-to-load-path`
+    #[collect(require_static)]
+    load_path: RefCell<Vec<PathBuf>>,
+
+    /// Features announced with `provide`
+    #[collect(require_static)]
+    features: RefCell<HashSet<String>>,
+
+    /// State of the pseudo-random number generator behind `random`
+    rng: Cell<u64>,
+
+    /// Set once the outermost continuation has returned
+    halted: Cell<bool>,
+
+    /// Hooks the embedder installed to observe the VM
+    #[collect(require_static)]
+    hooks: RefCell<Option<Box<dyn VmHooks>>>,
+
+    /// Whether hooks are installed, checked before every instruction
+    has_hooks: Cell<bool>,
+
+    /// Calls recorded since profiling started, or `None` if it's off
+    #[collect(require_static)]
+    profile: RefCell<Option<Profile>>,
+
+    /// Whether profiling is on, checked on every call and return
+    profiling: Cell<bool>,
+
+    /// Chunk and line of the last instruction reported to the hooks
+    last_line: Cell<(usize, usize)>,
+
+    /// Where the datum most recently read by the loader came from, until it's compiled
+    #[collect(require_static)]
+    source_map: RefCell<SourceMap>,
+
+    /// Names of the natives that serialized continuations can refer to
+    #[collect(require_static)]
+    natives: RefCell<NativeRegistry>,
+
+    /// Snapshots recorded for time-travel debugging, oldest first
+    snapshots: GcCell<'gc, Vec<GcCell<'gc, ObjContinuation<'gc>>>>,
+
+    /// Settings and position of time-travel debugging
+    #[collect(require_static)]
+    time_travel: Cell<TimeTravel>,
+
+    /// How deeply nested the forms shown by `--explain` can be, or `None` if it's off
+    explain: Cell<Option<usize>>,
+
+    /// How many explained forms are running inside each other
+    explain_nesting: Cell<usize>,
+
+    /// Whether code compiled from now on is checked for unused variables and unreachable code
+    warnings: Cell<bool>,
+
+    /// Number given to the next symbol made by `gensym`
+    gensym_counter: Cell<usize>,
+
+    /// Green threads that are ready to run, in the order they'll run, each with the frame it
+    /// carries on from and the value it gets back there
+    ready_threads: GcCell<'gc, VecDeque<(GcCell<'gc, ObjContinuation<'gc>>, Value<'gc>)>>,
+
+    /// Record types of green threads and channels, made the first time either is needed
+    thread_types: GcCell<'gc, Option<(GcCell<'gc, Object<'gc>>, GcCell<'gc, Object<'gc>>)>>,
+
+    /// Builtins in the groups the VM was configured without, which are never defined
+    #[collect(require_static)]
+    disabled_builtins: HashSet<&'static str>,
+
+    /// Groups of builtins the VM was configured with
+    #[collect(require_static)]
+    builtin_groups: HashSet<BuiltinGroup>,
 }
 
 /// Represents an error from the interpreter
@@ -97,6 +263,28 @@ pub enum InterpretError {
 
     #[error("{0}")]
     CompilerError(#[from] bootstrap::CompileError),
+
+    /// Error that happened while loading a file
+    #[error("{source}\n  in form {form} of {file}")]
+    LoadError {
+        file: String,
+        form: usize,
+        source: Box<InterpretError>,
+    },
+
+    /// An exception was raised with no handler installed
+    #[error("uncaught exception: {0}")]
+    UncaughtException(String),
+
+    /// The VM ran as many instructions as its fuel allowed. It stops before the next instruction,
+    /// so giving it more fuel and interpreting again carries on from there.
+    #[error("out of fuel")]
+    FuelExhausted,
+
+    /// The interrupt flag was set (e.g. by Ctrl+C). Like running out of fuel, the VM stops
+    /// before the next instruction.
+    #[error("interrupted")]
+    Interrupted,
 }
 
 /// Represents the result of executing the interpreter on an expression
@@ -104,53 +292,198 @@ pub type Result<T> = std::result::Result<T, InterpretError>;
 
 macro_rules! define_native {
     ($vm:ident, $mc:ident, $name:literal, $native:expr, $arity:literal, $variadic:literal) => {
-        let name = $vm.intern_symbol(Token::new($mc, $name.into()), $mc);
-        $vm.define_global(
-            name,
-            Value::boxed(
+        if !$vm.disabled_builtins.contains($name) {
+            let name = $vm.intern_symbol(Token::new($mc, $name.into()), $mc);
+            $vm.register_native($name, $native);
+            $vm.define_global(
+                name,
+                Value::boxed(
+                    $mc,
+                    Object::Native(ObjNative::registered(
+                        $arity,
+                        $variadic,
+                        ($name, $native),
+                        Some(name),
+                    )),
+                ),
                 $mc,
-                Object::Native(ObjNative::new($arity, $variadic, $native, Some(name))),
-            ),
-            $mc,
-        );
+            );
+        }
     };
 }
 
 impl<'gc> VirtualMachine<'gc> {
     /// Construct a new VM
     pub fn new(mc: MutationContext<'gc, '_>) -> Self {
+        Self::from_config(VmConfig::default(), mc)
+    }
+
+    /// Constructs a VM with the settings in `config`, but without any builtins defined yet
+    fn from_config(config: VmConfig, mc: MutationContext<'gc, '_>) -> Self {
+        let disabled_builtins = config.disabled_builtins();
+        let builtin_groups = config.builtins.clone();
+        let mut natives = NativeRegistry::default();
+        for (name, native) in builtins::internal_natives() {
+            natives.register(name, native);
+        }
+
+        let globals = GcCell::allocate(mc, GlobalTable::default());
         Self {
             parent_continuation: GcCell::allocate(mc, None),
+            frames: GcCell::allocate(mc, Vec::new()),
+            depth: Cell::new(0),
+            max_depth: Cell::new(config.max_depth),
+            fuel: Cell::new(config.fuel),
+            interrupt: Cell::new(None),
             procedure: GcCell::allocate(
                 mc,
-                Procedure::Native(ObjNative::new(0, false, builtins::exit, None)),
+                Procedure::Native(ObjNative::registered(0, false, builtins::HALT, None)),
             ),
             ip: Cell::new(0),
-            stack: GcCell::allocate(mc, GcCell::allocate(mc, Vec::with_capacity(STACK_MAX))),
+            stack: GcCell::allocate(
+                mc,
+                GcCell::allocate(mc, Vec::with_capacity(config.stack_capacity)),
+            ),
+            base: Cell::new(0),
+            open_upvalues: GcCell::allocate(mc, Vec::new()),
             symbol_pool: GcCell::allocate(mc, SymbolTable::default()),
-            globals: GcCell::allocate(mc, HashMap::default()),
+            string_pool: GcCell::allocate(mc, StringTable::default()),
+            globals,
+            environment: Value::boxed(mc, Object::Namespace(globals)),
+            libraries: GcCell::allocate(mc, HashMap::default()),
             current_input_port: GcCell::allocate(
                 mc,
-                GcCell::allocate(mc, Object::ReadPort(ObjReadPort::new(io::stdin()))),
+                GcCell::allocate(
+                    mc,
+                    Object::ReadPort(config.input_port.unwrap_or_else(ObjReadPort::stdin)),
+                ),
             ),
             current_output_port: GcCell::allocate(
                 mc,
-                GcCell::allocate(mc, Object::WritePort(ObjWritePort::new(io::stdout()))),
+                GcCell::allocate(
+                    mc,
+                    Object::WritePort(config.output_port.unwrap_or_else(ObjWritePort::stdout)),
+                ),
+            ),
+            current_error_port: GcCell::allocate(
+                mc,
+                GcCell::allocate(
+                    mc,
+                    Object::WritePort(config.error_port.unwrap_or_else(ObjWritePort::stderr)),
+                ),
+            ),
+            handlers: GcCell::allocate(mc, Value::Null),
+            loading: GcCell::allocate(mc, Value::Null),
+            renames: GcCell::allocate(mc, Value::Null),
+            loaded: RefCell::default(),
+            load_path: RefCell::new(
+                env::var_os("CHESHIRE_PATH")
+                    .map(|paths| env::split_paths(&paths).collect())
+                    .unwrap_or_default(),
             ),
+            features: RefCell::default(),
+            rng: Cell::new(DEFAULT_SEED),
+            halted: Cell::new(false),
+            hooks: RefCell::new(None),
+            has_hooks: Cell::new(false),
+            profile: RefCell::new(None),
+            profiling: Cell::new(false),
+            last_line: Cell::new((0, 0)),
+            source_map: RefCell::new(SourceMap::default()),
+            natives: RefCell::new(natives),
+            snapshots: GcCell::allocate(mc, Vec::new()),
+            time_travel: Cell::default(),
+            explain: Cell::new(None),
+            explain_nesting: Cell::new(0),
+            warnings: Cell::new(false),
+            gensym_counter: Cell::new(0),
+            ready_threads: GcCell::allocate(mc, VecDeque::new()),
+            thread_types: GcCell::allocate(mc, None),
+            disabled_builtins,
+            builtin_groups,
         }
     }
 
     pub fn default(mc: MutationContext<'gc, '_>) -> Self {
-        let vm = Self::new(mc);
+        Self::with_config(VmConfig::default(), mc)
+    }
+
+    /// Constructs a VM with the builtins and settings `config` asks for
+    pub fn with_config(config: VmConfig, mc: MutationContext<'gc, '_>) -> Self {
+        let vm = Self::from_config(config, mc);
 
         define_native!(vm, mc, "pair?", builtins::is_pair, 1, false);
+        define_native!(vm, mc, "null?", builtins::is_null, 1, false);
+        define_native!(vm, mc, "list?", builtins::is_list, 1, false);
         define_native!(vm, mc, "cons", builtins::cons, 2, false);
         define_native!(vm, mc, "car", builtins::car, 1, false);
         define_native!(vm, mc, "cdr", builtins::cdr, 1, false);
         define_native!(vm, mc, "set-car!", builtins::set_car, 2, false);
         define_native!(vm, mc, "set-cdr!", builtins::set_cdr, 2, false);
+        define_native!(vm, mc, "caar", builtins::caar, 1, false);
+        define_native!(vm, mc, "cadr", builtins::cadr, 1, false);
+        define_native!(vm, mc, "cdar", builtins::cdar, 1, false);
+        define_native!(vm, mc, "cddr", builtins::cddr, 1, false);
+        define_native!(vm, mc, "caaar", builtins::caaar, 1, false);
+        define_native!(vm, mc, "caadr", builtins::caadr, 1, false);
+        define_native!(vm, mc, "cadar", builtins::cadar, 1, false);
+        define_native!(vm, mc, "caddr", builtins::caddr, 1, false);
+        define_native!(vm, mc, "cdaar", builtins::cdaar, 1, false);
+        define_native!(vm, mc, "cdadr", builtins::cdadr, 1, false);
+        define_native!(vm, mc, "cddar", builtins::cddar, 1, false);
+        define_native!(vm, mc, "cdddr", builtins::cdddr, 1, false);
+        define_native!(vm, mc, "caaaar", builtins::caaaar, 1, false);
+        define_native!(vm, mc, "caaadr", builtins::caaadr, 1, false);
+        define_native!(vm, mc, "caadar", builtins::caadar, 1, false);
+        define_native!(vm, mc, "caaddr", builtins::caaddr, 1, false);
+        define_native!(vm, mc, "cadaar", builtins::cadaar, 1, false);
+        define_native!(vm, mc, "cadadr", builtins::cadadr, 1, false);
+        define_native!(vm, mc, "caddar", builtins::caddar, 1, false);
+        define_native!(vm, mc, "cadddr", builtins::cadddr, 1, false);
+        define_native!(vm, mc, "cdaaar", builtins::cdaaar, 1, false);
+        define_native!(vm, mc, "cdaadr", builtins::cdaadr, 1, false);
+        define_native!(vm, mc, "cdadar", builtins::cdadar, 1, false);
+        define_native!(vm, mc, "cdaddr", builtins::cdaddr, 1, false);
+        define_native!(vm, mc, "cddaar", builtins::cddaar, 1, false);
+        define_native!(vm, mc, "cddadr", builtins::cddadr, 1, false);
+        define_native!(vm, mc, "cdddar", builtins::cdddar, 1, false);
+        define_native!(vm, mc, "cddddr", builtins::cddddr, 1, false);
+        define_native!(vm, mc, "list-tail", builtins::list_tail, 2, false);
+        define_native!(vm, mc, "list-ref", builtins::list_ref, 2, false);
+        define_native!(vm, mc, "list-set!", builtins::list_set, 3, false);
+        define_native!(vm, mc, "list-copy", builtins::list_copy, 1, false);
+        define_native!(vm, mc, "append!", builtins::append_mut, 1, true);
+        define_native!(vm, mc, "reverse!", builtins::reverse_mut, 1, false);
+        define_native!(vm, mc, "map", builtins::map, 3, true);
+        define_native!(vm, mc, "for-each", builtins::for_each, 3, true);
+        define_native!(vm, mc, "filter", builtins::filter, 2, false);
+        define_native!(vm, mc, "fold-left", builtins::fold_left, 4, true);
+        define_native!(vm, mc, "fold-right", builtins::fold_right, 4, true);
+        define_native!(vm, mc, "reduce", builtins::reduce, 3, false);
+        define_native!(vm, mc, "sort", builtins::sort, 2, false);
+        define_native!(vm, mc, "vector-sort!", builtins::vector_sort, 2, false);
+        define_native!(vm, mc, "memq", builtins::memq, 2, false);
+        define_native!(vm, mc, "memv", builtins::memv, 2, false);
+        define_native!(vm, mc, "member", builtins::member, 3, true);
+        define_native!(vm, mc, "assq", builtins::assq, 2, false);
+        define_native!(vm, mc, "assv", builtins::assv, 2, false);
+        define_native!(vm, mc, "assoc", builtins::assoc, 3, true);
+        define_native!(vm, mc, "boolean?", builtins::is_boolean, 1, false);
+        define_native!(vm, mc, "not", builtins::not, 1, false);
         define_native!(vm, mc, "number?", builtins::is_number, 1, false);
+        define_native!(vm, mc, "random", builtins::random, 1, false);
+        define_native!(vm, mc, "random-seed!", builtins::random_seed, 1, false);
         define_native!(vm, mc, "symbol?", builtins::is_symbol, 1, false);
+        define_native!(vm, mc, "symbol=?", builtins::is_symbol_eq, 3, true);
+        define_native!(vm, mc, "symbol<?", builtins::is_symbol_lt, 3, true);
+        define_native!(
+            vm,
+            mc,
+            "global-bindings",
+            builtins::global_bindings,
+            0,
+            false
+        );
         define_native!(vm, mc, "char?", builtins::is_char, 1, false);
         define_native!(vm, mc, "string?", builtins::is_string, 1, false);
         define_native!(vm, mc, "vector?", builtins::is_vector, 1, false);
@@ -166,6 +499,7 @@ impl<'gc> VirtualMachine<'gc> {
         define_native!(vm, mc, ">=", builtins::gte_number, 3, true);
         define_native!(vm, mc, "eqv?", builtins::is_eqv, 2, false);
         define_native!(vm, mc, "eq?", builtins::is_eq, 2, false);
+        define_native!(vm, mc, "equal?", builtins::is_equal, 2, false);
         define_native!(vm, mc, "char=?", builtins::is_char_eq, 2, false);
         define_native!(vm, mc, "char<?", builtins::is_char_lt, 2, false);
         define_native!(vm, mc, "char>?", builtins::is_char_gt, 2, false);
@@ -206,6 +540,10 @@ impl<'gc> VirtualMachine<'gc> {
         );
         define_native!(vm, mc, "char-upcase", builtins::char_upcase, 1, false);
         define_native!(vm, mc, "char-downcase", builtins::char_downcase, 1, false);
+        define_native!(vm, mc, "char-foldcase", builtins::char_foldcase, 1, false);
+        define_native!(vm, mc, "char->integer", builtins::char_to_integer, 1, false);
+        define_native!(vm, mc, "integer->char", builtins::integer_to_char, 1, false);
+        define_native!(vm, mc, "digit-value", builtins::digit_value, 1, false);
         define_native!(
             vm,
             mc,
@@ -222,13 +560,130 @@ impl<'gc> VirtualMachine<'gc> {
             1,
             false
         );
+        define_native!(vm, mc, "gensym", builtins::gensym, 1, true);
+        define_native!(
+            vm,
+            mc,
+            "generate-uninterned-symbol",
+            builtins::gensym,
+            1,
+            true
+        );
+        define_native!(vm, mc, "string-ref", builtins::string_ref, 2, false);
         define_native!(vm, mc, "make-string", builtins::make_string, 2, true);
         define_native!(vm, mc, "string-length", builtins::string_length, 1, false);
+        define_native!(vm, mc, "string-upcase", builtins::string_upcase, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "string-downcase",
+            builtins::string_downcase,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "string-foldcase",
+            builtins::string_foldcase,
+            1,
+            false
+        );
+        define_native!(vm, mc, "string-split", builtins::string_split, 2, true);
+        define_native!(vm, mc, "string-join", builtins::string_join, 2, true);
+        define_native!(vm, mc, "string-trim", builtins::string_trim, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "string-contains",
+            builtins::string_contains,
+            2,
+            false
+        );
         define_native!(vm, mc, "make-vector", builtins::make_vector, 2, true);
+        define_native!(vm, mc, "vector", builtins::vector, 1, true);
         define_native!(vm, mc, "vector-length", builtins::vector_length, 1, false);
         define_native!(vm, mc, "vector-ref", builtins::vector_ref, 2, false);
         define_native!(vm, mc, "vector-set!", builtins::vector_set, 3, false);
+        define_native!(vm, mc, "vector-fill!", builtins::vector_fill, 3, true);
+        define_native!(vm, mc, "list->vector", builtins::list_to_vector, 1, false);
+        define_native!(vm, mc, "vector->list", builtins::vector_to_list, 2, true);
+        define_native!(vm, mc, "vector-copy", builtins::vector_copy, 2, true);
+        define_native!(vm, mc, "subvector", builtins::subvector, 3, false);
+        define_native!(vm, mc, "vector-copy!", builtins::vector_copy_to, 4, true);
+        define_native!(vm, mc, "vector-append", builtins::vector_append, 1, true);
+        define_native!(vm, mc, "vector-map", builtins::vector_map, 3, true);
+        define_native!(
+            vm,
+            mc,
+            "vector-for-each",
+            builtins::vector_for_each,
+            3,
+            true
+        );
+        define_native!(vm, mc, "box", builtins::make_box, 1, false);
+        define_native!(vm, mc, "box?", builtins::is_box, 1, false);
+        define_native!(vm, mc, "unbox", builtins::unbox, 1, false);
+        define_native!(vm, mc, "set-box!", builtins::set_box, 2, false);
+        define_native!(vm, mc, "bytevector?", builtins::is_bytevector, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "make-bytevector",
+            builtins::make_bytevector,
+            2,
+            true
+        );
+        define_native!(vm, mc, "bytevector", builtins::bytevector, 1, true);
+        define_native!(
+            vm,
+            mc,
+            "bytevector-length",
+            builtins::bytevector_length,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "bytevector-u8-ref",
+            builtins::bytevector_u8_ref,
+            2,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "bytevector-u8-set!",
+            builtins::bytevector_u8_set,
+            3,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "bytevector-copy",
+            builtins::bytevector_copy,
+            2,
+            true
+        );
+        define_native!(
+            vm,
+            mc,
+            "bytevector-append",
+            builtins::bytevector_append,
+            1,
+            true
+        );
+        define_native!(vm, mc, "utf8->string", builtins::utf8_to_string, 2, true);
+        define_native!(vm, mc, "string->utf8", builtins::string_to_utf8, 2, true);
+        define_native!(vm, mc, "format", builtins::format, 3, true);
+        define_native!(vm, mc, "void", builtins::void, 1, true);
+        define_native!(vm, mc, "void?", builtins::is_void, 1, false);
         define_native!(vm, mc, "apply", builtins::apply, 2, true);
+        define_native!(vm, mc, "identity", builtins::identity, 1, false);
+        define_native!(vm, mc, "const", builtins::constantly, 1, false);
+        define_native!(vm, mc, "compose", builtins::compose, 1, true);
         define_native!(
             vm,
             mc,
@@ -237,6 +692,90 @@ impl<'gc> VirtualMachine<'gc> {
             1,
             false
         );
+        define_native!(
+            vm,
+            mc,
+            "save-continuation",
+            builtins::save_continuation,
+            2,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "load-continuation",
+            builtins::load_continuation,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "call-with-prompt",
+            builtins::call_with_prompt,
+            3,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "abort-to-prompt",
+            builtins::abort_to_prompt,
+            2,
+            true
+        );
+        define_native!(
+            vm,
+            mc,
+            "with-exception-handler",
+            builtins::with_exception_handler,
+            2,
+            false
+        );
+        define_native!(vm, mc, "raise", builtins::raise, 1, false);
+        define_native!(vm, mc, "error", builtins::error, 2, true);
+        define_native!(vm, mc, "error-object?", builtins::is_error_object, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "error-object-message",
+            builtins::error_object_message,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "error-object-irritants",
+            builtins::error_object_irritants,
+            1,
+            false
+        );
+        define_native!(vm, mc, "file-error?", builtins::is_file_error, 1, false);
+        define_native!(vm, mc, "read-error?", builtins::is_read_error, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "raise-continuable",
+            builtins::raise_continuable,
+            1,
+            false
+        );
+        define_native!(vm, mc, "spawn", builtins::spawn, 1, false);
+        define_native!(vm, mc, "yield", builtins::yield_thread, 0, false);
+        define_native!(vm, mc, "thread-join!", builtins::thread_join, 1, false);
+        define_native!(vm, mc, "thread?", builtins::is_thread, 1, false);
+        define_native!(vm, mc, "make-channel", builtins::make_channel, 0, false);
+        define_native!(vm, mc, "channel?", builtins::is_channel, 1, false);
+        define_native!(vm, mc, "channel-send!", builtins::channel_send, 2, false);
+        define_native!(
+            vm,
+            mc,
+            "channel-receive",
+            builtins::channel_receive,
+            1,
+            false
+        );
         define_native!(vm, mc, "values", builtins::values, 1, true);
         define_native!(
             vm,
@@ -248,6 +787,40 @@ impl<'gc> VirtualMachine<'gc> {
         );
         define_native!(vm, mc, "input-port?", builtins::is_input_port, 1, false);
         define_native!(vm, mc, "output-port?", builtins::is_output_port, 1, false);
+        define_native!(vm, mc, "port?", builtins::is_port, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "input-port-open?",
+            builtins::is_input_port_open,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "output-port-open?",
+            builtins::is_output_port_open,
+            1,
+            false
+        );
+        define_native!(vm, mc, "close-port", builtins::close_port, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "close-input-port",
+            builtins::close_input_port,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "close-output-port",
+            builtins::close_output_port,
+            1,
+            false
+        );
         define_native!(
             vm,
             mc,
@@ -264,162 +837,1078 @@ impl<'gc> VirtualMachine<'gc> {
             0,
             false
         );
-        define_native!(vm, mc, "read-char", builtins::read_char, 0, true);
-        define_native!(vm, mc, "peek-char", builtins::peek_char, 0, true);
-        define_native!(vm, mc, "eof-object?", builtins::is_eof_object, 1, false);
-        define_native!(vm, mc, "char-ready?", builtins::is_char_ready, 0, true);
-        define_native!(vm, mc, "write-char", builtins::write_char, 1, true);
-        define_native!(vm, mc, "read", builtins::read, 0, true);
-        define_native!(vm, mc, "compile", builtins::compile, 1, false);
-        define_native!(vm, mc, "load", builtins::load, 1, false);
-        define_native!(vm, mc, "exit", builtins::exit, 0, false);
-        define_native!(vm, mc, "disassemble", builtins::disassemble, 1, false);
-        vm
-    }
-
-    pub fn repl(mc: MutationContext<'gc, '_>) -> Self {
-        let vm = Self::default(mc);
-
-        let repl = Value::boxed(
+        define_native!(
+            vm,
             mc,
-            Object::Native(ObjNative::new(0, false, builtins::read_thunk, None)),
+            "current-error-port",
+            builtins::current_error_port,
+            0,
+            false
         );
-
-        let stack = *vm.stack.read();
-        stack.write(mc).push(repl);
-        vm.call_value(repl, stack, 0, mc)
-            .expect("Failed to call the repl");
-        vm
+        define_native!(
+            vm,
+            mc,
+            "open-input-file",
+            builtins::open_input_file,
+            2,
+            true
+        );
+        define_native!(
+            vm,
+            mc,
+            "open-output-file",
+            builtins::open_output_file,
+            2,
+            true
+        );
+        define_native!(vm, mc, "port-encoding", builtins::port_encoding, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "flush-output-port",
+            builtins::flush_output_port,
+            1,
+            true
+        );
+        define_native!(vm, mc, "port-position", builtins::port_position, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "set-port-position!",
+            builtins::set_port_position,
+            2,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "open-input-string",
+            builtins::open_input_string,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "open-output-string",
+            builtins::open_output_string,
+            0,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "get-output-string",
+            builtins::get_output_string,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "with-output-to-string",
+            builtins::with_output_to_string,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "with-input-from-string",
+            builtins::with_input_from_string,
+            2,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "call-with-input-file",
+            builtins::call_with_input_file,
+            2,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "call-with-output-file",
+            builtins::call_with_output_file,
+            2,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "with-input-from-file",
+            builtins::with_input_from_file,
+            2,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "with-output-to-file",
+            builtins::with_output_to_file,
+            2,
+            false
+        );
+        define_native!(vm, mc, "read-char", builtins::read_char, 0, true);
+        define_native!(vm, mc, "peek-char", builtins::peek_char, 0, true);
+        define_native!(vm, mc, "read-line", builtins::read_line, 0, true);
+        define_native!(vm, mc, "read-u8", builtins::read_u8, 0, true);
+        define_native!(vm, mc, "peek-u8", builtins::peek_u8, 0, true);
+        define_native!(vm, mc, "u8-ready?", builtins::is_u8_ready, 0, true);
+        define_native!(
+            vm,
+            mc,
+            "read-bytevector",
+            builtins::read_bytevector,
+            2,
+            true
+        );
+        define_native!(vm, mc, "write-u8", builtins::write_u8, 2, true);
+        define_native!(
+            vm,
+            mc,
+            "write-bytevector",
+            builtins::write_bytevector,
+            2,
+            true
+        );
+        define_native!(vm, mc, "binary-port?", builtins::is_binary_port, 1, false);
+        define_native!(vm, mc, "textual-port?", builtins::is_textual_port, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "open-binary-input-file",
+            builtins::open_binary_input_file,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "open-binary-output-file",
+            builtins::open_binary_output_file,
+            2,
+            true
+        );
+        define_native!(
+            vm,
+            mc,
+            "open-input-bytevector",
+            builtins::open_input_bytevector,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "open-output-bytevector",
+            builtins::open_output_bytevector,
+            0,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "get-output-bytevector",
+            builtins::get_output_bytevector,
+            1,
+            false
+        );
+        define_native!(vm, mc, "read-string", builtins::read_string, 2, true);
+        define_native!(vm, mc, "eof-object?", builtins::is_eof_object, 1, false);
+        define_native!(vm, mc, "char-ready?", builtins::is_char_ready, 0, true);
+        define_native!(
+            vm,
+            mc,
+            "read-char/timeout",
+            builtins::read_char_timeout,
+            2,
+            false
+        );
+        define_native!(vm, mc, "write-char", builtins::write_char, 1, true);
+        define_native!(vm, mc, "write-string", builtins::write_string, 2, true);
+        define_native!(vm, mc, "write", builtins::write, 2, true);
+        define_native!(vm, mc, "display", builtins::display, 2, true);
+        define_native!(vm, mc, "newline", builtins::newline, 1, true);
+        define_native!(vm, mc, "read", builtins::read, 0, true);
+        define_native!(vm, mc, "port-line", builtins::port_line, 1, false);
+        define_native!(vm, mc, "port-column", builtins::port_column, 1, false);
+        define_native!(vm, mc, "compile", builtins::compile, 2, true);
+        define_native!(vm, mc, "eval", builtins::eval, 2, true);
+        define_native!(vm, mc, "macroexpand", builtins::macroexpand, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "er-macro-transformer",
+            builtins::er_macro_transformer,
+            1,
+            false
+        );
+        define_native!(vm, mc, "macroexpand-1", builtins::macroexpand_1, 1, false);
+        define_native!(vm, mc, "environment", builtins::environment, 1, true);
+        define_native!(
+            vm,
+            mc,
+            "interaction-environment",
+            builtins::interaction_environment,
+            0,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "scheme-report-environment",
+            builtins::scheme_report_environment,
+            1,
+            false
+        );
+        define_native!(
+            vm,
+            mc,
+            "null-environment",
+            builtins::null_environment,
+            1,
+            false
+        );
+        define_native!(vm, mc, "environment?", builtins::is_environment, 1, false);
+        define_native!(vm, mc, "load", builtins::load, 1, false);
+        define_native!(vm, mc, "load-once", builtins::load_once, 1, false);
+        define_native!(vm, mc, "compile-file", builtins::compile_file, 2, false);
+        define_native!(vm, mc, "require", builtins::require, 1, false);
+        define_native!(
+            vm,
+            mc,
+            "add-to-load-path",
+            builtins::add_to_load_path,
+            1,
+            false
+        );
+        define_native!(vm, mc, "provide", builtins::provide, 1, true);
+        define_native!(vm, mc, "provided?", builtins::is_provided, 1, false);
+        define_native!(vm, mc, "exit", builtins::exit, 0, false);
+        define_native!(vm, mc, "disassemble", builtins::disassemble, 1, false);
+        define_native!(vm, mc, "profile-start!", builtins::profile_start, 0, false);
+        define_native!(vm, mc, "profile-report", builtins::profile_report, 0, false);
+
+        #[cfg(feature = "self-hosting")]
+        {
+            define_native!(vm, mc, "make-chunk", builtins::make_chunk, 0, false);
+            define_native!(vm, mc, "chunk-emit!", builtins::chunk_emit, 3, true);
+            define_native!(
+                vm,
+                mc,
+                "chunk-add-constant!",
+                builtins::chunk_add_constant,
+                2,
+                false
+            );
+            define_native!(
+                vm,
+                mc,
+                "make-procedure-from-chunk",
+                builtins::make_procedure_from_chunk,
+                3,
+                true
+            );
+        }
+        #[cfg(feature = "regex")]
+        {
+            define_native!(vm, mc, "regexp", builtins::regexp, 1, false);
+            define_native!(vm, mc, "regexp?", builtins::is_regexp, 1, false);
+            define_native!(vm, mc, "regexp-match", builtins::regexp_match, 2, false);
+            define_native!(
+                vm,
+                mc,
+                "regexp-match-positions",
+                builtins::regexp_match_positions,
+                2,
+                false
+            );
+            define_native!(vm, mc, "regexp-replace", builtins::regexp_replace, 3, false);
+            define_native!(
+                vm,
+                mc,
+                "regexp-replace*",
+                builtins::regexp_replace_all,
+                3,
+                false
+            );
+            define_native!(vm, mc, "regexp-split", builtins::regexp_split, 2, false);
+        }
+        vm.define_standard_libraries(mc);
+        vm
+    }
+
+    pub fn repl(mc: MutationContext<'gc, '_>) -> Self {
+        let vm = Self::default(mc);
+
+        let repl = Value::boxed(
+            mc,
+            Object::Native(ObjNative::registered(0, false, builtins::READ_THUNK, None)),
+        );
+
+        let stack = *vm.stack.read();
+        stack.write(mc).push(repl);
+        vm.call_value(repl, stack, 0, mc)
+            .expect("Failed to call the repl");
+        vm
+    }
+
+    pub fn reset_repl(&self, mc: MutationContext<'gc, '_>) {
+        // The outermost frame belongs to the REPL itself, so go back to the ports it was using
+        self.reify_frames(mc);
+        let mut root = *self.parent_continuation.read();
+        while let Some(frame) = root.and_then(|frame| frame.read().frames()) {
+            root = Some(frame);
+        }
+        if let Some(root) = root {
+            *self.current_input_port.write(mc) = root.read().current_input_port();
+            *self.current_output_port.write(mc) = root.read().current_output_port();
+            *self.current_error_port.write(mc) = root.read().current_error_port();
+        }
+
+        // Stepping back after an error should start from where it happened
+        self.reset_time_travel();
+        self.explain_nesting.set(0);
+
+        // Nothing is coming back for the files that were open when the error happened
+        let _ = self.close_abandoned_ports(None, mc);
+        self.close_all_upvalues(mc);
+
+        *self.parent_continuation.write(mc) = None;
+        self.depth.set(0);
+        self.clear_threads(mc);
+        *self.handlers.write(mc) = Value::Null;
+        *self.loading.write(mc) = Value::Null;
+        *self.procedure.write(mc) =
+            Procedure::Native(ObjNative::registered(0, false, builtins::HALT, None));
+        self.base.set(0);
+
+        let repl = Value::boxed(
+            mc,
+            Object::Native(ObjNative::registered(0, false, builtins::READ_THUNK, None)),
+        );
+
+        let stack = *self.stack.read();
+        stack.write(mc).push(repl);
+        self.call_value(repl, stack, 0, mc)
+            .expect("Failed to call the repl");
+    }
+
+    pub fn load_file(path: String, mc: MutationContext<'gc, '_>) -> Self {
+        Self::load_file_with_config(path, VmConfig::default(), mc)
+    }
+
+    /// Like `load_file`, but with the builtins and settings `config` asks for. The file is loaded
+    /// even if builtins for working with files were left out.
+    pub fn load_file_with_config(
+        path: String,
+        config: VmConfig,
+        mc: MutationContext<'gc, '_>,
+    ) -> Self {
+        let vm = Self::with_config(config, mc);
+
+        let load_symbol = vm.symbol_pool.write(mc).intern(Token::new(mc, ObjString::from("load")));
+        vm.register_native("load", builtins::load);
+        let load = Value::boxed(
+            mc,
+            Object::Native(ObjNative::registered(
+                1,
+                false,
+                ("load", builtins::load),
+                Some(load_symbol),
+            )),
+        );
+
+        let stack = *vm.stack.read();
+        stack.write(mc).push(load);
+        stack
+            .write(mc)
+            .push(Value::String(Gc::allocate(mc, ObjString::from(path))));
+
+        vm.call_value(load, stack, 1, mc)
+            .expect("Failed to load program");
+        vm
+    }
+
+    /// Leaves the running procedure where it stopped, as the parent of a new frame with an empty
+    /// stack, which is returned. Errors are raised from a frame like this so that the procedure
+    /// they happened in still shows up in backtraces.
+    pub(crate) fn suspend_procedure(&self, mc: MutationContext<'gc, '_>) -> Stack<'gc> {
+        let top = self.stack.read().read().len();
+        self.record_frame(top, mc);
+        let stack = GcCell::allocate(mc, Vec::new());
+        *self.stack.write(mc) = stack;
+        self.base.set(0);
+        stack
+    }
+
+    /// Records the running procedure as the frame the procedure it calls returns to, which resumes
+    /// with the stack cut back to `top`. This is done on every call, so the frame goes in the
+    /// frame list rather than being allocated as an object.
+    fn record_frame(&self, top: usize, mc: MutationContext<'gc, '_>) {
+        let frame = self.save_current_continuation(top);
+        self.frames.write(mc).push(frame);
+        self.depth.set(self.depth.get() + 1);
+    }
+
+    /// Fails if a call would nest frames deeper than the limit. Only the call that reaches the
+    /// limit fails at first, so that exception handlers have some room to run in, but calls fail
+    /// again once that's used up too.
+    fn check_depth(&self) -> Result<()> {
+        let depth = self.depth.get();
+        let limit = self.max_depth.get();
+        if depth == limit || depth >= limit.saturating_add(DEPTH_HEADROOM) {
+            return Err(InterpretError::RuntimeError(format!(
+                "Maximum recursion depth exceeded ({} frames)",
+                limit
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sets how many frames deep calls can nest before they fail
+    pub fn set_max_depth(&self, limit: usize) {
+        self.max_depth.set(limit);
+    }
+
+    /// Gets how many frames deep calls can nest before they fail
+    pub fn max_depth(&self) -> usize {
+        self.max_depth.get()
+    }
+
+    /// Sets how many more instructions can run before interpreting stops with
+    /// `InterpretError::FuelExhausted`, or `None` to run without a limit
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.fuel.set(fuel);
+    }
+
+    /// Gets how many more instructions can run, if there's a limit
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel.get()
+    }
+
+    /// Makes interpreting stop with `InterpretError::Interrupted` soon after `flag` is set, clearing
+    /// it again. `None` stops checking for interrupts.
+    pub fn set_interrupt_flag(&self, flag: Option<&'static AtomicBool>) {
+        self.interrupt.set(flag);
+    }
+
+    /// Makes Ctrl+C interrupt the program instead of killing the process, returning whether it
+    /// could
+    pub fn interrupt_on_ctrl_c(&self) -> bool {
+        let flag = platform::catch_interrupts();
+        if flag.is_some() {
+            self.set_interrupt_flag(flag);
+        }
+        flag.is_some()
+    }
+
+    /// Fails if the interrupt flag was set since it was last checked
+    fn check_interrupt(&self) -> Result<()> {
+        match self.interrupt.get() {
+            Some(flag) if flag.load(Ordering::Relaxed) => {
+                flag.store(false, Ordering::Relaxed);
+                Err(InterpretError::Interrupted)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Uses up the fuel for one instruction, failing if there's none left
+    fn burn_fuel(&self) -> Result<()> {
+        match self.fuel.get() {
+            Some(0) => Err(InterpretError::FuelExhausted),
+            Some(fuel) => {
+                self.fuel.set(Some(fuel - 1));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the frames recorded by calls onto the heap as continuation objects, below
+    /// `parent_continuation`, for when the current continuation is needed as a whole
+    fn reify_frames(&self, mc: MutationContext<'gc, '_>) {
+        if self.frames.read().is_empty() {
+            return;
+        }
+
+        let frames = core::mem::take(&mut *self.frames.write(mc));
+        let mut parent = *self.parent_continuation.read();
+        for mut frame in frames {
+            frame.set_frames(parent);
+            parent = Some(GcCell::allocate(mc, frame));
+        }
+        *self.parent_continuation.write(mc) = parent;
+    }
+
+    /// Saves the running procedure as a frame, which resumes with the stack cut back to `top`.
+    /// Its parent is `parent_continuation`, so the recorded frames have to be reified first if it
+    /// needs to be complete.
+    fn save_current_continuation(&self, top: usize) -> ObjContinuation<'gc> {
+        let procedure = match &*self.procedure.read() {
+            Procedure::Closure(closure) => object::Procedure::Closure {
+                closure: closure.clone(),
+                ip: self.ip.get(),
+            },
+            Procedure::Function(function) => object::Procedure::Function {
+                function: function.clone(),
+                ip: self.ip.get(),
+            },
+            Procedure::Native(native) => object::Procedure::Native(native.clone()),
+        };
+
+        ObjContinuation::new(
+            *self.parent_continuation.read(),
+            procedure,
+            *self.stack.read(),
+            self.base.get()..top,
+            self.current_ports(),
+            *self.handlers.read(),
+            *self.loading.read(),
+        )
+    }
+
+    /// Goes back to the frame that called the running procedure, returning `false` if there isn't
+    /// one because the outermost procedure is finishing
+    pub(crate) fn resume_caller(&self, mc: MutationContext<'gc, '_>) -> bool {
+        let recorded = self.frames.write(mc).pop();
+        let frame = *self.parent_continuation.read();
+        let resumed = match (recorded, frame) {
+            (Some(recorded), _) => {
+                self.resume_frame(&recorded, mc);
+                true
+            }
+            (None, Some(frame)) => {
+                self.switch_to(frame, mc);
+                true
+            }
+            (None, None) => false,
+        };
+        if resumed {
+            self.depth.set(self.depth.get() - 1);
+            self.profile_return();
+        }
+        resumed
+    }
+
+    pub fn apply_continuation(
+        &self,
+        frame: GcCell<'gc, ObjContinuation<'gc>>,
+        mc: MutationContext<'gc, '_>,
+    ) {
+        self.switch_to(frame, mc);
+        self.depth.set(continuation_depth(Some(frame)) - 1);
+        self.profile_return();
+    }
+
+    /// Makes `frame` the running procedure, and its frames the current continuation
+    fn switch_to(&self, frame: GcCell<'gc, ObjContinuation<'gc>>, mc: MutationContext<'gc, '_>) {
+        // Whatever was recorded on top of the current continuation is being left behind
+        self.frames.write(mc).clear();
+        *self.parent_continuation.write(mc) = frame.read().frames();
+        self.resume_frame(&frame.read(), mc);
+    }
+
+    /// Picks up running the procedure of `frame`, without changing the frames below it
+    fn resume_frame(&self, frame: &ObjContinuation<'gc>, mc: MutationContext<'gc, '_>) {
+        match frame.procedure() {
+            object::Procedure::Closure { closure, ip } => {
+                *self.procedure.write(mc) = Procedure::Closure(closure.clone());
+                self.ip.set(*ip);
+            }
+            object::Procedure::Function { function, ip } => {
+                *self.procedure.write(mc) = Procedure::Function(function.clone());
+                self.ip.set(*ip);
+            }
+            object::Procedure::Native(native) => {
+                *self.procedure.write(mc) = Procedure::Native(native.clone());
+            }
+        }
+
+        let stack = frame.stack();
+        let top = frame.stack_top();
+        self.close_upvalues(stack, top, mc);
+        stack.write(mc).truncate(top);
+        *self.stack.write(mc) = stack;
+        self.base.set(frame.stack_base());
+        *self.current_input_port.write(mc) = frame.current_input_port();
+        *self.current_output_port.write(mc) = frame.current_output_port();
+        *self.current_error_port.write(mc) = frame.current_error_port();
+        *self.handlers.write(mc) = frame.handlers();
+        *self.loading.write(mc) = frame.loading();
+    }
+
+    /// Gets the upvalue for slot `offset` of `stack`, so that every closure capturing the same
+    /// variable shares it
+    fn capture_upvalue(
+        &self,
+        stack: Stack<'gc>,
+        offset: usize,
+        mc: MutationContext<'gc, '_>,
+    ) -> Upvalue<'gc> {
+        let existing = self
+            .open_upvalues
+            .read()
+            .iter()
+            .rev()
+            .find(|upvalue| {
+                matches!(upvalue.state(), UpvalueState::Open { stack: open, offset: slot }
+                    if GcCell::ptr_eq(open, stack) && slot == offset)
+            })
+            .copied();
+        existing.unwrap_or_else(|| {
+            let upvalue = Upvalue::new(stack, offset, mc);
+            self.open_upvalues.write(mc).push(upvalue);
+            upvalue
+        })
+    }
+
+    /// Closes the upvalues for slot `from` of `stack` and above, before those slots are reused
+    fn close_upvalues(&self, stack: Stack<'gc>, from: usize, mc: MutationContext<'gc, '_>) {
+        if self.open_upvalues.read().is_empty() {
+            return;
+        }
+        self.open_upvalues.write(mc).retain(|upvalue| {
+            let is_open = upvalue.is_open_above(stack, from);
+            if is_open {
+                upvalue.close(mc);
+            }
+            !is_open
+        });
+    }
+
+    /// Closes every open upvalue, for when the stacks they're on are all being abandoned
+    fn close_all_upvalues(&self, mc: MutationContext<'gc, '_>) {
+        for upvalue in self.open_upvalues.write(mc).drain(..) {
+            upvalue.close(mc);
+        }
+    }
+
+    /// Closes the ports owned by frames of the current continuation that aren't also frames of
+    /// `target`, since jumping there abandons them
+    pub(crate) fn close_abandoned_ports(
+        &self,
+        target: Option<GcCell<'gc, ObjContinuation<'gc>>>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Result<()> {
+        let kept = owned_ports(target);
+        self.reify_frames(mc);
+        for port in owned_ports(*self.parent_continuation.read()) {
+            if !kept.iter().any(|kept| GcCell::ptr_eq(*kept, port)) {
+                builtins::close_port_object(port, mc)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Core interpreter method that executes bytecode
+    pub fn interpret(&self, mc: MutationContext<'gc, '_>) -> Result<()> {
+        self.step(mc).map_err(|err| {
+            // Running out of fuel or being interrupted only pauses the program, so the host gets
+            // those as they are
+            if let InterpretError::FuelExhausted | InterpretError::Interrupted = err {
+                return err;
+            }
+            let err = self.in_load_context(err);
+            if let Some(hooks) = &*self.hooks.borrow() {
+                hooks.on_error(&err);
+            }
+            err
+        })
+    }
+
+    /// Wraps an error with the files (and forms within them) that were being loaded
+    fn in_load_context(&self, err: InterpretError) -> InterpretError {
+        self.load_context()
+            .into_iter()
+            .fold(err, |err, (file, form)| InterpretError::LoadError {
+                file,
+                form,
+                source: Box::new(err),
+            })
+    }
+
+    /// Gets the files being loaded and the index of the form running in each, innermost first
+    pub(crate) fn load_context(&self) -> Vec<(String, usize)> {
+        let mut context = Vec::new();
+        let mut loading = *self.loading.read();
+        while let Value::Box(entry) = loading {
+            let entry = entry.read();
+            let entry = match entry.as_pair() {
+                Ok(entry) => entry,
+                Err(_) => break,
+            };
+            if let Value::Box(form) = entry.car() {
+                if let Ok(form) = form.read().as_pair() {
+                    context.push((
+                        form.car().styled(DisplayStyle::Display).to_string(),
+                        form.cdr().as_number().unwrap_or_default() as usize,
+                    ));
+                }
+            }
+            loading = entry.cdr();
+        }
+
+        context
+    }
+
+    fn step(&self, mc: MutationContext<'gc, '_>) -> Result<()> {
+        // Errors are raised to the program's exception handlers, and only reach the host when
+        // there aren't any
+        self.step_procedure(mc)
+            .or_else(|err| builtins::raise_interpret_error(self, err, mc))
+    }
+
+    fn step_procedure(&self, mc: MutationContext<'gc, '_>) -> Result<()> {
+        let stack = *self.stack.read();
+        let ip = self.ip.get();
+
+        // Only take what's needed to run the procedure, so we don't hold a borrow on it
+        let active = match &*self.procedure.read() {
+            Procedure::Closure(closure) => {
+                Active::Chunk(closure.function().chunk(), Some(closure.environment()))
+            }
+            Procedure::Function(function) => Active::Chunk(function.chunk(), None),
+            Procedure::Native(native) => Active::Native(native.function()),
+        };
+        match active {
+            Active::Chunk(chunk, environment) => {
+                if ip == 0 {
+                    self.record_snapshot(mc);
+                }
+                self.interpret_chunk(mc, chunk, environment, stack, ip)
+            }
+            Active::Native(native) => self.run_native(native, stack, mc).map(|_| ()),
+        }
+    }
+
+    /// Runs `callee` straight away if it's a native that was just called, returning whether it
+    /// gave its result back to the frame that called it (and that frame is running again)
+    fn run_called_native(&self, callee: Value<'gc>, mc: MutationContext<'gc, '_>) -> Result<bool> {
+        let native = match callee {
+            Value::Box(object) => object.read().as_native().map(ObjNative::function),
+            _ => return Ok(false),
+        };
+        let native = match native {
+            Ok(native) => native,
+            Err(_) => return Ok(false),
+        };
+
+        // The call recorded the caller's frame last, so it's running again if the native took
+        // that frame back off of the list
+        let depth = self.frames.read().len();
+        let stack = *self.stack.read();
+        let returned = self.run_native(native, stack, mc)?;
+        Ok(returned && self.frames.read().len() + 1 == depth)
+    }
+
+    /// Runs the native on `stack`. If it returns a value instead of arranging for something else
+    /// to run, the value goes back to the frame below. Returns whether that frame was one
+    /// recorded in the frame list.
+    fn run_native(
+        &self,
+        native: Native,
+        stack: Stack<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Result<bool> {
+        let result = match native(self, stack, mc)? {
+            Some(result) => result,
+            None => return Ok(false),
+        };
+        let recorded = !self.frames.read().is_empty();
+        if self.resume_caller(mc) {
+            self.push_stack(result, mc);
+        } else {
+            self.halt();
+        }
+        Ok(recorded)
+    }
+
+    pub fn current_input_port(&self) -> GcCell<'gc, GcCell<'gc, Object<'gc>>> {
+        self.current_input_port
+    }
+
+    pub fn current_output_port(&self) -> GcCell<'gc, GcCell<'gc, Object<'gc>>> {
+        self.current_output_port
+    }
+
+    pub fn current_error_port(&self) -> GcCell<'gc, GcCell<'gc, Object<'gc>>> {
+        self.current_error_port
+    }
+
+    pub(crate) fn current_ports(&self) -> CurrentPorts<'gc> {
+        CurrentPorts {
+            input: *self.current_input_port.read(),
+            output: *self.current_output_port.read(),
+            error: *self.current_error_port.read(),
+        }
+    }
+
+    /// Passes on anything the current output port is holding back, e.g. before waiting for input
+    /// or reporting an error. A port that can't be flushed right now is left alone.
+    pub fn flush_output(&self, mc: MutationContext<'gc, '_>) {
+        let port = *self.current_output_port.read();
+        if let Ok(mut port) = borrow_mut(&port, mc) {
+            if let Ok(port) = port.as_write_port_mut() {
+                let _ = port.flush();
+            }
+        };
+    }
+
+    /// Writes a line to the current error port, falling back on stderr if that can't be written
+    /// to (e.g. because it's in use)
+    pub fn write_error_line(&self, line: &str, mc: MutationContext<'gc, '_>) {
+        // Anything written before the error should show up before it
+        self.flush_output(mc);
+
+        let port = *self.current_error_port.read();
+        let written = match borrow_mut(&port, mc) {
+            Ok(mut port) => match port.as_write_port_mut() {
+                Ok(port) => port.write_str(&format!("{}\n", line)).is_ok(),
+                Err(_) => false,
+            },
+            Err(_) => false,
+        };
+        if !written {
+            eprintln!("{}", line);
+        }
+    }
+
+    /// Writes an error that stopped the VM to the current error port, followed by a backtrace of
+    /// the procedures that were running when it happened
+    pub fn report_error(&self, err: &InterpretError, mc: MutationContext<'gc, '_>) {
+        let mut report = err.to_string();
+        for frame in self.backtrace() {
+            report.push_str("\n  in ");
+            report.push_str(&frame);
+        }
+        self.write_error_line(&report, mc);
+    }
+
+    /// Describes the procedures that are running, innermost first, with the line each one is at
+    /// when it has one. Unnamed natives only glue other procedures together, so they're left out.
+    pub fn backtrace(&self) -> Vec<String> {
+        let current = match &*self.procedure.read() {
+            Procedure::Closure(closure) => object::Procedure::Closure {
+                closure: closure.clone(),
+                ip: self.ip.get(),
+            },
+            Procedure::Function(function) => object::Procedure::Function {
+                function: function.clone(),
+                ip: self.ip.get(),
+            },
+            Procedure::Native(native) => object::Procedure::Native(native.clone()),
+        };
+
+        let mut frames: Vec<_> = describe_frame(&current).into_iter().collect();
+        for frame in self.frames.read().iter().rev() {
+            frames.extend(describe_frame(frame.procedure()));
+        }
+        let mut parent = *self.parent_continuation.read();
+        while let Some(frame) = parent {
+            let frame = frame.read();
+            frames.extend(describe_frame(frame.procedure()));
+            parent = frame.frames();
+        }
+        frames
+    }
+
+    pub fn handlers(&self) -> GcCell<'gc, Value<'gc>> {
+        self.handlers
+    }
+
+    pub fn loading(&self) -> GcCell<'gc, Value<'gc>> {
+        self.loading
+    }
+
+    pub(crate) fn renames(&self) -> GcCell<'gc, Value<'gc>> {
+        self.renames
+    }
+
+    /// Stops the VM once the outermost continuation has returned
+    pub(crate) fn halt(&self) {
+        self.halted.set(true);
+    }
+
+    /// Installs hooks to be told about what the VM is doing, replacing any that were there
+    pub fn set_hooks(&self, hooks: Option<Box<dyn VmHooks>>) {
+        self.has_hooks.set(hooks.is_some());
+        *self.hooks.borrow_mut() = hooks;
+    }
+
+    /// Turns on `--explain` for code compiled from now on, which prints each procedure call and
+    /// special form nested up to `depth` deep along with its result as it runs. `None` turns it
+    /// off again.
+    pub fn set_explain(&self, depth: Option<usize>) {
+        self.explain.set(depth);
+    }
+
+    /// How deeply nested the forms shown by `--explain` can be, or `None` if it's off
+    pub fn explain(&self) -> Option<usize> {
+        self.explain.get()
+    }
+
+    /// Notes that an explained form has started running, returning how deeply it's nested
+    pub(crate) fn enter_explained(&self) -> usize {
+        let nesting = self.explain_nesting.get();
+        self.explain_nesting.set(nesting + 1);
+        nesting
+    }
+
+    /// Notes that an explained form has finished running, returning how deeply it was nested
+    pub(crate) fn leave_explained(&self) -> usize {
+        let nesting = self.explain_nesting.get().saturating_sub(1);
+        self.explain_nesting.set(nesting);
+        nesting
+    }
+
+    /// Turns on `--warnings` for code compiled from now on, which reports variables that are
+    /// never used and code that can't be reached
+    pub fn set_warnings(&self, on: bool) {
+        self.warnings.set(on);
+    }
+
+    /// Whether `--warnings` is on
+    pub fn warnings(&self) -> bool {
+        self.warnings.get()
+    }
+
+    /// Reports something suspicious but not fatal on the current error port, and to the installed
+    /// hooks
+    pub fn warn(&self, message: &str, mc: MutationContext<'gc, '_>) {
+        self.write_error_line(&format!("warning: {}", message), mc);
+        if let Some(hooks) = &*self.hooks.borrow() {
+            hooks.on_warning(message);
+        }
+    }
+
+    /// Lets the installed hooks know the garbage collector has run
+    pub fn notify_gc(&self, allocated: usize) {
+        if let Some(hooks) = &*self.hooks.borrow() {
+            hooks.on_gc(allocated);
+        }
+    }
+
+    /// Lets the installed hooks know which source lines a freshly compiled function (and the
+    /// functions nested in it) came from
+    pub(crate) fn notify_compile(&self, function: &ObjFunction<'gc>) {
+        if let Some(hooks) = &*self.hooks.borrow() {
+            let mut chunks = vec![function.chunk()];
+            while let Some(chunk) = chunks.pop() {
+                if let Some(file) = chunk.file() {
+                    hooks.on_compile(file, &chunk.source_lines());
+                }
+                for constant in chunk.constants() {
+                    if let Value::Box(object) = constant {
+                        if let Object::Function(function) = &*object.read() {
+                            chunks.push(function.chunk());
+                        }
+                    }
+                }
+            }
+        }
     }
 
-    pub fn reset_repl(&self, mc: MutationContext<'gc, '_>) {
-        *self.parent_continuation.write(mc) = None;
-        *self.procedure.write(mc) = Procedure::Native(ObjNative::new(0, false, builtins::exit, None));
+    /// Lets the installed hooks know when execution moves on to a new source line
+    fn notify_line(&self, chunk: Gc<'gc, Chunk<'gc>>, ip: usize) {
+        let line = chunk.get_line(ip);
+        let position = (Gc::as_ptr(chunk) as usize, line);
+        if self.last_line.replace(position) == position {
+            return;
+        }
 
-        let repl = Value::boxed(
-            mc,
-            Object::Native(ObjNative::new(0, false, builtins::read_thunk, None)),
-        );
+        if let (Some(hooks), Some(file)) = (&*self.hooks.borrow(), chunk.file()) {
+            hooks.on_line(file, line);
+        }
+    }
 
-        let stack = *self.stack.read();
-        stack.write(mc).push(repl);
-        self.call_value(repl, stack, 0, mc)
-            .expect("Failed to call the repl");
+    /// Keeps track of where the datum the loader just read came from, for the compiler
+    pub(crate) fn set_source_map(&self, source: SourceMap) {
+        *self.source_map.borrow_mut() = source;
     }
 
-    pub fn load_file(path: String, mc: MutationContext<'gc, '_>) -> Self {
-        let vm = Self::default(mc);
+    /// Takes the map of where the datum the loader just read came from
+    pub(crate) fn take_source_map(&self) -> SourceMap {
+        self.source_map.take()
+    }
 
-        let load_symbol = vm.symbol_pool.write(mc).intern(Token::new(mc, ObjString::from("load")));
-        let load = *vm.globals.read().get(&load_symbol).unwrap();
+    fn notify_call(&self, callee: Value<'gc>, stack: Stack<'gc>, arg_count: usize) {
+        if let Some(hooks) = &*self.hooks.borrow() {
+            let args = stack.read();
+            hooks.on_call(callee, &args[args.len() - arg_count..]);
+        }
+    }
 
-        let stack = *vm.stack.read();
-        stack.write(mc).push(load);
-        stack
-            .write(mc)
-            .push(Value::String(Gc::allocate(mc, ObjString::from(path))));
+    /// Checks whether the program the VM was running has finished
+    pub fn is_halted(&self) -> bool {
+        self.halted.get()
+    }
 
-        vm.call_value(load, stack, 1, mc)
-            .expect("Failed to load program");
-        vm
+    /// Reseeds the random number generator
+    pub(crate) fn seed_random(&self, seed: u64) {
+        // xorshift gets stuck on zero
+        self.rng.set(if seed == 0 { DEFAULT_SEED } else { seed });
     }
 
-    fn save_current_continuation(&self) -> ObjContinuation<'gc> {
-        let procedure = match &*self.procedure.read() {
-            Procedure::Closure(closure) => object::Procedure::Closure {
-                closure: closure.clone(),
-                ip: self.ip.get(),
-            },
-            Procedure::Function(function) => object::Procedure::Function {
-                function: function.clone(),
-                ip: self.ip.get(),
-            },
-            Procedure::Native(native) => object::Procedure::Native(native.clone()),
-        };
+    /// Gets the next pseudo-random number, uniformly distributed in `[0, 1)`
+    pub(crate) fn next_random(&self) -> f64 {
+        // xorshift64*
+        let mut state = self.rng.get();
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        self.rng.set(state);
+        (state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+    }
 
-        ObjContinuation::new(
-            *self.parent_continuation.read(),
-            procedure,
-            *self.stack.read(),
-            *self.current_input_port.read(),
-            *self.current_output_port.read(),
-        )
+    /// Gets the number for the next symbol made by `gensym`
+    pub(crate) fn next_gensym(&self) -> usize {
+        let n = self.gensym_counter.get();
+        self.gensym_counter.set(n + 1);
+        n
     }
 
-    pub fn apply_continuation(
-        &self,
-        frame: GcCell<'gc, ObjContinuation<'gc>>,
-        mc: MutationContext<'gc, '_>,
-    ) {
-        let parent_frame = frame.read().frames();
-        match frame.read().procedure() {
-            object::Procedure::Closure { closure, ip } => {
-                *self.procedure.write(mc) = Procedure::Closure(closure.clone());
-                self.ip.set(*ip);
-            }
-            object::Procedure::Function { function, ip } => {
-                *self.procedure.write(mc) = Procedure::Function(function.clone());
-                self.ip.set(*ip);
-            }
-            object::Procedure::Native(native) => {
-                *self.procedure.write(mc) = Procedure::Native(native.clone());
-            }
-        }
+    /// Records that a feature has been provided
+    pub(crate) fn provide(&self, feature: String) {
+        self.features.borrow_mut().insert(feature);
+    }
 
-        *self.parent_continuation.write(mc) = parent_frame;
-        let stack = frame.read().stack();
-        stack.write(mc).truncate(frame.read().stack_top());
-        *self.stack.write(mc) = stack;
-        *self.current_input_port.write(mc) = frame.read().current_input_port();
-        *self.current_output_port.write(mc) = frame.read().current_output_port();
+    /// Checks whether a feature has been provided
+    pub(crate) fn is_provided(&self, feature: &str) -> bool {
+        self.features.borrow().contains(feature)
     }
 
-    /// Core interpreter method that executes bytecode
-    pub fn interpret(&self, mc: MutationContext<'gc, '_>) -> Result<()> {
-        // Preemptively clone this so we don't hold a borrow on it
-        let proc = self.procedure.read().clone();
-        let chunk: Gc<'gc, Chunk<'gc>>;
-        let environment: Option<Gc<'gc, ObjEnvironment<'gc>>>;
-        let stack = *self.stack.read();
-        let ip = self.ip.get();
-        match proc {
-            Procedure::Closure(closure) => {
-                chunk = closure.function().chunk();
-                environment = Some(closure.environment());
-                self.interpret_chunk(mc, chunk, environment, stack, ip)
-            }
-            Procedure::Function(function) => {
-                chunk = function.chunk();
-                environment = None;
-                self.interpret_chunk(mc, chunk, environment, stack, ip)
-            }
-            Procedure::Native(native) => {
-                let result = native.call(self, stack, mc)?;
-                if let Some(result) = result {
-                    let frame = *self.parent_continuation.read();
-                    if let Some(frame) = frame {
-                        self.apply_continuation(frame, mc);
-                        self.push_stack(result, mc);
-                        Ok(())
-                    } else {
-                        std::process::exit(0);
-                    }
-                } else {
-                    Ok(())
-                }
-            }
-        }
+    /// Records that a file has been loaded. Returns whether this is the first time.
+    pub(crate) fn mark_loaded(&self, path: PathBuf) -> bool {
+        self.loaded.borrow_mut().insert(path)
     }
 
-    pub fn current_input_port(&self) -> GcCell<'gc, GcCell<'gc, Object<'gc>>> {
-        self.current_input_port
+    /// Checks whether a file has been loaded before
+    pub(crate) fn is_loaded(&self, path: &Path) -> bool {
+        self.loaded.borrow().contains(path)
     }
 
-    pub fn current_output_port(&self) -> GcCell<'gc, GcCell<'gc, Object<'gc>>> {
-        self.current_output_port
+    /// Adds a directory to the front of the load path, so it's searched before the others
+    pub fn add_to_load_path(&self, directory: PathBuf) {
+        self.load_path.borrow_mut().insert(0, directory);
     }
 
-    pub fn parent_continuation(&self) -> GcCell<'gc, Option<GcCell<'gc, ObjContinuation<'gc>>>> {
+    /// Gets the directories on the load path, in the order they're searched
+    pub fn load_path(&self) -> Vec<PathBuf> {
+        self.load_path.borrow().clone()
+    }
+
+    /// Gets the continuation of the running procedure, moving the frames recorded by calls onto
+    /// the heap first so that it's complete
+    pub fn parent_continuation(
+        &self,
+        mc: MutationContext<'gc, '_>,
+    ) -> GcCell<'gc, Option<GcCell<'gc, ObjContinuation<'gc>>>> {
+        self.reify_frames(mc);
         self.parent_continuation
     }
 
@@ -435,7 +1924,41 @@ impl<'gc> VirtualMachine<'gc> {
         stack: Stack<'gc>,
         mut ip: usize,
     ) -> Result<()> {
+        let result = self.run_chunk(mc, chunk, environment, stack, &mut ip);
+        if result.is_err() {
+            // Keep where the error happened, for the backtrace
+            self.ip.set(ip);
+        }
+        result
+    }
+
+    fn run_chunk(
+        &self,
+        mc: MutationContext<'gc, '_>,
+        chunk: Gc<'gc, Chunk<'gc>>,
+        environment: Option<Gc<'gc, ObjEnvironment<'gc>>>,
+        stack: Stack<'gc>,
+        ip: &mut usize,
+    ) -> Result<()> {
+        // Code from inside a library uses the library's global variables
+        let globals = chunk.globals().unwrap_or(self.globals);
+        let base = self.base.get();
+        #[cfg(feature = "jit")]
+        let mut compiled = self.compiled_chunk(&chunk).map(|compiled| {
+            let frame = jit::Frame::new(&chunk, environment, stack, globals, base, mc);
+            (compiled, frame)
+        });
         loop {
+            self.check_interrupt()?;
+            self.burn_fuel()?;
+
+            // Native code runs as far as it can, leaving the instruction it stopped at to the
+            // interpreter
+            #[cfg(feature = "jit")]
+            if let Some((compiled, frame)) = &mut compiled {
+                compiled.run(frame, ip)?;
+            }
+
             if cfg!(feature = "debug-trace-execution") {
                 let stack = stack.read();
 
@@ -447,87 +1970,135 @@ impl<'gc> VirtualMachine<'gc> {
                     println!();
                 }
 
-                chunk.disassemble_instruction(ip);
+                chunk.disassemble_instruction(*ip);
+            }
+
+            if self.has_hooks.get() {
+                self.notify_line(chunk, *ip);
             }
 
-            let instruction = OpCode::try_from(read_byte(&chunk, &mut ip)).unwrap();
+            let instruction = OpCode::try_from(read_byte(&chunk, ip)).unwrap();
 
             match instruction {
                 OpCode::ConstantLong => {
-                    let constant = read_constant_long(&chunk, &mut ip);
+                    let constant = read_constant_long(&chunk, ip);
                     stack.write(mc).push(constant);
                 }
                 OpCode::Constant => {
-                    let constant = read_constant(&chunk, &mut ip);
+                    let constant = read_constant(&chunk, ip);
                     stack.write(mc).push(constant);
                 }
-                OpCode::DefineGlobal => {
-                    let name = read_constant(&chunk, &mut ip);
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                    let long = instruction == OpCode::DefineGlobalLong;
+                    let name = chunk.read_constant(read_constant_offset(&chunk, ip, long));
                     let name = name.as_symbol().unwrap();
-                    self.define_global(name, peek(stack, 0), mc);
+                    let value = peek(stack, 0);
+                    globals.write(mc).insert(name, value);
+                    if let Some(hooks) = &*self.hooks.borrow() {
+                        hooks.on_define(&name.as_str(), value);
+                    }
                     stack.write(mc).pop();
                 }
-                OpCode::GetGlobal => {
-                    let name = read_constant(&chunk, &mut ip);
-                    let name = name.as_symbol().unwrap();
-                    let value = self.globals.read().get(&name).copied();
-                    let value = value.ok_or_else(|| {
-                        InterpretError::RuntimeError(format!("Undefined variable {}", name))
-                    })?;
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let long = instruction == OpCode::GetGlobalLong;
+                    let offset = read_constant_offset(&chunk, ip, long);
+                    let slot = chunk.global_slot(offset, &globals.read());
+                    let slot = slot.ok_or_else(|| undefined_variable(&chunk, offset))?;
+                    let value = globals.read().slot_value(slot);
                     stack.write(mc).push(value);
                 }
-                OpCode::SetGlobal => {
-                    let name = read_constant(&chunk, &mut ip);
-                    let name = name.as_symbol().unwrap();
-                    if self.globals.read().contains_key(&name) {
-                        self.define_global(name, peek(stack, 0), mc);
-                    } else {
-                        return Err(InterpretError::RuntimeError(format!(
-                            "Undefined variable {}",
-                            name
-                        )));
-                    }
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let long = instruction == OpCode::SetGlobalLong;
+                    let offset = read_constant_offset(&chunk, ip, long);
+                    let slot = chunk.global_slot(offset, &globals.read());
+                    let slot = slot.ok_or_else(|| undefined_variable(&chunk, offset))?;
+                    globals.write(mc).set_slot_value(slot, peek(stack, 0));
                 }
-                OpCode::GetLocal => {
-                    let slot = read_byte(&chunk, &mut ip) as usize;
-                    let value = stack.read()[slot];
+                OpCode::GetLocal | OpCode::GetLocalLong => {
+                    let slot = read_operand(&chunk, ip, instruction == OpCode::GetLocalLong);
+                    let value = stack.read()[base + slot];
                     stack.write(mc).push(value);
                 }
-                OpCode::SetLocal => {
-                    let slot = read_byte(&chunk, &mut ip) as usize;
-                    stack.write(mc)[slot] = peek(stack, 0);
+                OpCode::SetLocal | OpCode::SetLocalLong => {
+                    let slot = read_operand(&chunk, ip, instruction == OpCode::SetLocalLong);
+                    stack.write(mc)[base + slot] = peek(stack, 0);
                 }
-                OpCode::GetUpvalue => {
-                    let slot = read_byte(&chunk, &mut ip) as usize;
-                    let value = environment.unwrap().upvalues()[slot].location();
+                OpCode::GetUpvalue | OpCode::GetUpvalueLong => {
+                    let slot = read_operand(&chunk, ip, instruction == OpCode::GetUpvalueLong);
+                    let value = upvalue(environment, slot)?.location();
                     stack.write(mc).push(value);
                 }
-                OpCode::SetUpvalue => {
-                    let slot = read_byte(&chunk, &mut ip) as usize;
+                OpCode::SetUpvalue | OpCode::SetUpvalueLong => {
+                    let slot = read_operand(&chunk, ip, instruction == OpCode::SetUpvalueLong);
                     let value = peek(stack, 0);
-                    environment.unwrap().upvalues()[slot].set_location(value, mc);
+                    upvalue(environment, slot)?.set_location(value, mc);
+                }
+                OpCode::BoxLocal | OpCode::BoxLocalLong => {
+                    let slot = read_operand(&chunk, ip, instruction == OpCode::BoxLocalLong);
+                    let value = stack.read()[base + slot];
+                    stack.write(mc)[base + slot] = Value::boxed(mc, Object::Cell(value));
+                }
+                OpCode::Unbox => {
+                    let value = cell_contents(peek(stack, 0))?;
+                    *stack.write(mc).last_mut().unwrap() = value;
+                }
+                OpCode::SetBox => {
+                    let cell = stack.write(mc).pop().unwrap();
+                    set_cell_contents(cell, peek(stack, 0), mc)?;
                 }
                 OpCode::JumpIfFalse => {
-                    let offset = read_short(&chunk, &mut ip);
+                    let offset = read_short(&chunk, ip);
                     if peek(stack, 0).is_falsey() {
-                        ip += offset as usize;
+                        *ip += offset as usize;
                     }
                 }
                 OpCode::Jump => {
-                    let offset = read_short(&chunk, &mut ip);
-                    ip += offset as usize;
+                    let offset = read_short(&chunk, ip);
+                    *ip += offset as usize;
                 }
-                OpCode::Call => {
-                    let arg_count = read_byte(&chunk, &mut ip);
-                    let function = peek(stack, arg_count.into());
-                    self.ip.set(ip);
-                    self.call_value(function, stack, arg_count as usize, mc)?;
-                    return Ok(());
+                OpCode::Call | OpCode::CallLong => {
+                    let arg_count = read_operand(&chunk, ip, instruction == OpCode::CallLong);
+                    let function = peek(stack, arg_count);
+                    self.ip.set(*ip);
+                    self.call_value(function, stack, arg_count, mc)?;
+
+                    // Most natives hand their result straight back, in which case this frame can
+                    // carry on from here rather than being picked up again by `interpret`
+                    if !self.run_called_native(function, mc)? {
+                        return Ok(());
+                    }
+                }
+                OpCode::Car | OpCode::Cdr | OpCode::Cons | OpCode::IsNull | OpCode::IsPair => {
+                    let arg_count = if instruction == OpCode::Cons { 2 } else { 1 };
+                    let function = peek(stack, arg_count);
+                    let result = {
+                        let stack = stack.read();
+                        let args = &stack[stack.len() - arg_count..];
+                        inline_primitive(instruction, function, args, mc)
+                    };
+
+                    if let Some(result) = result {
+                        let mut stack = stack.write(mc);
+                        let callee = stack.len() - arg_count - 1;
+                        stack.truncate(callee);
+                        stack.push(result);
+                    } else if chunk.read(*ip) == u8::from(OpCode::Return) {
+                        self.tail_call_value(function, stack, arg_count, mc)?;
+                        return Ok(());
+                    } else {
+                        // The primitive was redefined or can't handle its arguments, so it's
+                        // called like any other procedure, errors and all
+                        self.ip.set(*ip);
+                        self.call_value(function, stack, arg_count, mc)?;
+                        if !self.run_called_native(function, mc)? {
+                            return Ok(());
+                        }
+                    }
                 }
-                OpCode::TailCall => {
-                    let arg_count = read_byte(&chunk, &mut ip);
-                    let function = peek(stack, arg_count.into());
-                    self.tail_call_value(function, stack, arg_count as usize, mc)?;
+                OpCode::TailCall | OpCode::TailCallLong => {
+                    let arg_count = read_operand(&chunk, ip, instruction == OpCode::TailCallLong);
+                    let function = peek(stack, arg_count);
+                    self.tail_call_value(function, stack, arg_count, mc)?;
                     return Ok(());
                 }
                 OpCode::Pop => {
@@ -538,15 +2109,15 @@ impl<'gc> VirtualMachine<'gc> {
                 OpCode::True => stack.write(mc).push(Value::Bool(true)),
                 OpCode::False => stack.write(mc).push(Value::Bool(false)),
                 OpCode::Closure => {
-                    let function = read_constant(&chunk, &mut ip);
+                    let function = read_constant(&chunk, ip);
                     if let Value::Box(object) = function {
                         if let Object::Function(function) = &*object.read() {
                             let mut upvalues = Vec::new();
                             for _ in 0..function.upvalues().len() {
-                                let is_local = read_byte(&chunk, &mut ip);
-                                let index = read_byte(&chunk, &mut ip) as usize;
+                                let is_local = read_byte(&chunk, ip);
+                                let index = read_short(&chunk, ip) as usize;
                                 if is_local > 0 {
-                                    upvalues.push(Upvalue::new(stack, index));
+                                    upvalues.push(self.capture_upvalue(stack, base + index, mc));
                                 } else {
                                     upvalues.push(environment.unwrap().upvalues()[index])
                                 }
@@ -569,15 +2140,14 @@ impl<'gc> VirtualMachine<'gc> {
                 }
                 OpCode::Return => {
                     let result = stack.write(mc).pop().unwrap_or(Value::Void);
+                    self.close_upvalues(stack, base, mc);
 
-                    let frame = *self.parent_continuation.read();
-                    if let Some(frame) = frame {
-                        self.apply_continuation(frame, mc);
+                    if self.resume_caller(mc) {
                         self.push_stack(result, mc);
-                        return Ok(());
                     } else {
-                        std::process::exit(0);
+                        self.halt();
                     }
+                    return Ok(());
                 }
             }
         }
@@ -590,13 +2160,21 @@ impl<'gc> VirtualMachine<'gc> {
         arg_count: usize,
         mc: MutationContext<'gc, '_>,
     ) -> Result<()> {
+        self.notify_call(callee, stack, arg_count);
         if let Value::Box(object) = callee {
             match &*object.read() {
                 Object::Closure(closure) => self.call_closure(closure, stack, arg_count, mc),
+                Object::Continuation(continuation) if continuation.is_delimited() => {
+                    self.call_delimited(continuation, stack, arg_count, false, mc);
+                    Ok(())
+                }
                 Object::Continuation(continuation) => {
                     let length = stack.read().len() - arg_count;
                     let mut result = stack.write(mc).split_off(length);
-                    self.apply_continuation(GcCell::allocate(mc, continuation.clone()), mc);
+                    let frame = GcCell::allocate(mc, continuation.snapshot(mc));
+                    self.close_abandoned_ports(Some(frame), mc)?;
+                    self.close_all_upvalues(mc);
+                    self.apply_continuation(frame, mc);
                     self.stack.read().write(mc).append(&mut result);
                     Ok(())
                 }
@@ -613,6 +2191,33 @@ impl<'gc> VirtualMachine<'gc> {
         }
     }
 
+    /// Runs the frames of a delimited continuation on top of the current continuation, so that
+    /// when they finish their result goes to wherever the continuation was called from
+    fn call_delimited(
+        &self,
+        continuation: &ObjContinuation<'gc>,
+        stack: Stack<'gc>,
+        arg_count: usize,
+        tail: bool,
+        mc: MutationContext<'gc, '_>,
+    ) {
+        let split = stack.read().len() - arg_count;
+        let mut args = stack.write(mc).split_off(split);
+        stack.write(mc).pop();
+
+        self.reify_frames(mc);
+        let parent = if tail {
+            self.close_upvalues(stack, self.base.get(), mc);
+            *self.parent_continuation.read()
+        } else {
+            let top = stack.read().len();
+            Some(GcCell::allocate(mc, self.save_current_continuation(top)))
+        };
+        let frame = continuation.snapshot_onto(parent, mc);
+        self.apply_continuation(GcCell::allocate(mc, frame), mc);
+        self.stack.read().write(mc).append(&mut args);
+    }
+
     fn call_native(
         &self,
         native: &ObjNative<'gc>,
@@ -634,17 +2239,21 @@ impl<'gc> VirtualMachine<'gc> {
             )));
         }
 
-        // Save current continuation
-        let current_continuation = self.save_current_continuation();
+        self.check_depth()?;
 
-        self.parent_continuation
-            .write(mc)
-            .replace(GcCell::allocate(mc, current_continuation));
-        *self.procedure.write(mc) = Procedure::Native(native.clone());
-        self.ip.set(0);
         let split = stack.read().len() - arg_count;
         let args = stack.write(mc).split_off(split - 1);
+
+        // Save current continuation, after the arguments are gone so that it resumes with just
+        // the result on top of the stack
+        self.record_frame(split - 1, mc);
+        *self.procedure.write(mc) = Procedure::Native(native.clone());
+        self.ip.set(0);
+        self.profile_native(native, false);
+
+        // Natives index their arguments from the start of the stack, so they get one of their own
         *self.stack.write(mc) = GcCell::allocate(mc, args);
+        self.base.set(0);
 
         Ok(())
     }
@@ -662,10 +2271,17 @@ impl<'gc> VirtualMachine<'gc> {
                 "Expected {} arguments but got {}",
                 arity, arg_count
             )));
+        } else if closure.is_variadic() && arity > (arg_count + 1) {
+            return Err(InterpretError::RuntimeError(format!(
+                "Expected at least {} arguments but got {}",
+                arity - 1,
+                arg_count
+            )));
         }
+        self.check_depth()?;
 
         if closure.is_variadic() {
-            let count = arg_count - arity + 1;
+            let count = arg_count + 1 - arity;
             stack.write(mc).push(Value::Null);
             for _ in 0..count {
                 let acc = stack.write(mc).pop().unwrap();
@@ -676,17 +2292,14 @@ impl<'gc> VirtualMachine<'gc> {
             }
         }
 
-        // Save current continuation
-        let current_continuation = self.save_current_continuation();
-
-        self.parent_continuation
-            .write(mc)
-            .replace(GcCell::allocate(mc, current_continuation));
+        // The callee's frame starts where it is on the stack, and the caller resumes from there
+        let base = stack.read().len() - arity - 1;
+        self.record_frame(base, mc);
         *self.procedure.write(mc) = Procedure::Closure(closure.clone());
         self.ip.set(0);
-        let length = stack.read().len();
-        let args = stack.write(mc).split_off(length - arity - 1);
-        *self.stack.write(mc) = GcCell::allocate(mc, args);
+        self.profile_function(closure.function(), false);
+        *self.stack.write(mc) = stack;
+        self.base.set(base);
 
         Ok(())
     }
@@ -704,10 +2317,17 @@ impl<'gc> VirtualMachine<'gc> {
                 "Expected {} arguments but got {}",
                 arity, arg_count
             )));
+        } else if function.is_variadic() && arity > (arg_count + 1) {
+            return Err(InterpretError::RuntimeError(format!(
+                "Expected at least {} arguments but got {}",
+                arity - 1,
+                arg_count
+            )));
         }
+        self.check_depth()?;
 
         if function.is_variadic() {
-            let count = arg_count - arity + 1;
+            let count = arg_count + 1 - arity;
             stack.write(mc).push(Value::Null);
             for _ in 0..count {
                 let acc = stack.write(mc).pop().unwrap();
@@ -718,17 +2338,14 @@ impl<'gc> VirtualMachine<'gc> {
             }
         }
 
-        // Save current continuation
-        let current_continuation = self.save_current_continuation();
-
-        self.parent_continuation
-            .write(mc)
-            .replace(GcCell::allocate(mc, current_continuation));
+        // The callee's frame starts where it is on the stack, and the caller resumes from there
+        let base = stack.read().len() - arity - 1;
+        self.record_frame(base, mc);
         *self.procedure.write(mc) = Procedure::Function(function.clone());
         self.ip.set(0);
-        let length = stack.read().len();
-        let args = stack.write(mc).split_off(length - arity - 1);
-        *self.stack.write(mc) = GcCell::allocate(mc, args);
+        self.profile_function(function, false);
+        *self.stack.write(mc) = stack;
+        self.base.set(base);
 
         Ok(())
     }
@@ -740,13 +2357,21 @@ impl<'gc> VirtualMachine<'gc> {
         arg_count: usize,
         mc: MutationContext<'gc, '_>,
     ) -> Result<()> {
+        self.notify_call(callee, stack, arg_count);
         if let Value::Box(object) = callee {
             match &*object.read() {
                 Object::Closure(closure) => self.tail_call_closure(closure, stack, arg_count, mc),
+                Object::Continuation(continuation) if continuation.is_delimited() => {
+                    self.call_delimited(continuation, stack, arg_count, true, mc);
+                    Ok(())
+                }
                 Object::Continuation(continuation) => {
                     let length = stack.read().len() - arg_count;
                     let mut result = stack.write(mc).split_off(length);
-                    self.apply_continuation(GcCell::allocate(mc, continuation.clone()), mc);
+                    let frame = GcCell::allocate(mc, continuation.snapshot(mc));
+                    self.close_abandoned_ports(Some(frame), mc)?;
+                    self.close_all_upvalues(mc);
+                    self.apply_continuation(frame, mc);
                     self.stack.read().write(mc).append(&mut result);
                     Ok(())
                 }
@@ -787,11 +2412,29 @@ impl<'gc> VirtualMachine<'gc> {
         }
         let split = stack.read().len() - arg_count;
         let args = stack.write(mc).split_off(split - 1);
+
+        // Nothing returns to the slots of the frame being replaced
+        let base = self.base.get();
+        self.close_upvalues(stack, base, mc);
+        stack.write(mc).truncate(base);
+
         *self.procedure.write(mc) = Procedure::Native(native.clone());
         *self.stack.write(mc) = GcCell::allocate(mc, args);
+        self.base.set(0);
+        self.profile_native(native, true);
         Ok(())
     }
 
+    /// Moves a tail call's callee and its `arity` arguments down to where the running frame
+    /// starts, so the callee takes over the frame instead of growing the stack
+    fn replace_frame(&self, stack: Stack<'gc>, arity: usize, mc: MutationContext<'gc, '_>) {
+        let base = self.base.get();
+        let callee = stack.read().len() - arity - 1;
+        self.close_upvalues(stack, base, mc);
+        stack.write(mc).drain(base..callee);
+        *self.stack.write(mc) = stack;
+    }
+
     fn tail_call_function(
         &self,
         function: &ObjFunction<'gc>,
@@ -805,10 +2448,16 @@ impl<'gc> VirtualMachine<'gc> {
                 "Expected {} arguments but got {}",
                 arity, arg_count
             )));
+        } else if function.is_variadic() && arity > (arg_count + 1) {
+            return Err(InterpretError::RuntimeError(format!(
+                "Expected at least {} arguments but got {}",
+                arity - 1,
+                arg_count
+            )));
         }
 
         if function.is_variadic() {
-            let count = arg_count - arity + 1;
+            let count = arg_count + 1 - arity;
             stack.write(mc).push(Value::Null);
             for _ in 0..count {
                 let acc = stack.write(mc).pop().unwrap();
@@ -821,9 +2470,8 @@ impl<'gc> VirtualMachine<'gc> {
 
         *self.procedure.write(mc) = Procedure::Function(function.clone());
         self.ip.set(0);
-        let top = stack.read().len();
-        let args = stack.write(mc).split_off(top - arity - 1);
-        *self.stack.write(mc) = GcCell::allocate(mc, args);
+        self.replace_frame(stack, arity, mc);
+        self.profile_function(function, true);
 
         Ok(())
     }
@@ -841,10 +2489,16 @@ impl<'gc> VirtualMachine<'gc> {
                 "Expected {} arguments but got {}",
                 arity, arg_count
             )));
+        } else if closure.is_variadic() && arity > (arg_count + 1) {
+            return Err(InterpretError::RuntimeError(format!(
+                "Expected at least {} arguments but got {}",
+                arity - 1,
+                arg_count
+            )));
         }
 
         if closure.is_variadic() {
-            let count = arg_count - arity + 1;
+            let count = arg_count + 1 - arity;
             stack.write(mc).push(Value::Null);
             for _ in 0..count {
                 let acc = stack.write(mc).pop().unwrap();
@@ -857,9 +2511,8 @@ impl<'gc> VirtualMachine<'gc> {
 
         *self.procedure.write(mc) = Procedure::Closure(closure.clone());
         self.ip.set(0);
-        let top = stack.read().len();
-        let args = stack.write(mc).split_off(top - arity - 1);
-        *self.stack.write(mc) = GcCell::allocate(mc, args);
+        self.replace_frame(stack, arity, mc);
+        self.profile_function(closure.function(), true);
 
         Ok(())
     }
@@ -872,6 +2525,39 @@ impl<'gc> VirtualMachine<'gc> {
         self.symbol_pool.write(mc).intern(token)
     }
 
+    /// Gets the symbol and string tables, for compiling code that reads files
+    pub(crate) fn tables(&self) -> Tables<'gc> {
+        Tables::new(self.symbol_pool, self.string_pool)
+            .with_files(self.has_builtins(BuiltinGroup::Files))
+    }
+
+    /// Whether the VM was configured with the builtins in `group`. Without `BuiltinGroup::Files`,
+    /// code can't read files any other way either, such as with `include` or `import`.
+    pub fn has_builtins(&self, group: BuiltinGroup) -> bool {
+        self.builtin_groups.contains(&group)
+    }
+
+    /// Registers `native` under `name`, so that serialized continuations that refer to it can be
+    /// read back in. Every native the VM defines itself is registered already. Only natives made
+    /// with `ObjNative::registered` can be written out.
+    pub fn register_native(&self, name: &'static str, native: Native) {
+        self.natives.borrow_mut().register(name, native);
+    }
+
+    /// Gets the registered natives
+    pub(crate) fn natives(&self) -> Ref<'_, NativeRegistry> {
+        self.natives.borrow()
+    }
+
+    /// Returns the shared copy of an immutable string constant
+    pub(crate) fn intern_string(
+        &self,
+        string: ObjString,
+        mc: MutationContext<'gc, '_>,
+    ) -> Gc<'gc, ObjString> {
+        self.string_pool.write(mc).intern(string, mc)
+    }
+
     /// Define a global bindings
     #[inline(always)]
     pub fn define_global(
@@ -883,12 +2569,136 @@ impl<'gc> VirtualMachine<'gc> {
         self.globals.write(mc).insert(name, value);
     }
 
+    /// Gets the table of global variables programs run with
+    pub(crate) fn globals(&self) -> Globals<'gc> {
+        self.globals
+    }
+
+    /// Gets the global variables programs run with as an environment object
+    pub fn interaction_environment(&self) -> Value<'gc> {
+        self.environment
+    }
+
+    /// Looks up the value of a global binding by name
+    pub fn global(&self, name: &str, mc: MutationContext<'gc, '_>) -> Option<Value<'gc>> {
+        let name = self.intern_symbol(Token::new(mc, name.into()), mc);
+        self.globals.read().get(&name).copied()
+    }
+
+    /// Binds `name` to a new textual input port that reads from an embedder's backend
+    pub fn define_input_port<B: PortBackend + 'static>(
+        &self,
+        name: &str,
+        backend: B,
+        mc: MutationContext<'gc, '_>,
+    ) -> Value<'gc> {
+        self.define_port(name, Object::ReadPort(ObjReadPort::backend(backend)), mc)
+    }
+
+    /// Binds `name` to a new textual output port that writes to an embedder's backend
+    pub fn define_output_port<B: PortBackend + 'static>(
+        &self,
+        name: &str,
+        backend: B,
+        mc: MutationContext<'gc, '_>,
+    ) -> Value<'gc> {
+        self.define_port(name, Object::WritePort(ObjWritePort::backend(backend)), mc)
+    }
+
+    fn define_port(
+        &self,
+        name: &str,
+        port: Object<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Value<'gc> {
+        let port = Value::Box(GcCell::allocate(mc, port));
+        let name = self.intern_symbol(Token::new(mc, name.into()), mc);
+        self.define_global(name, port, mc);
+        port
+    }
+
+    /// Gets the names of every global binding, sorted so the order is stable between runs
+    pub fn global_names(&self) -> Vec<Symbol<'gc>> {
+        let mut names: Vec<_> = self.globals.read().keys().copied().collect();
+        names.sort();
+        names
+    }
+
     /// Push a value onto the VM's value stack
     pub(crate) fn push_stack(&self, value: Value<'gc>, mc: MutationContext<'gc, '_>) {
         self.stack.read().write(mc).push(value);
     }
 }
 
+/// Describes a frame of a backtrace by the name of its procedure and where it's at
+fn describe_frame(procedure: &object::Procedure<'_>) -> Option<String> {
+    let (function, ip) = match procedure {
+        object::Procedure::Closure { closure, ip } => (closure.function(), *ip),
+        object::Procedure::Function { function, ip } => (function, *ip),
+        object::Procedure::Native(native) => return native.name().map(|name| name.to_string()),
+    };
+
+    let name = function.name().map_or_else(
+        || "anonymous procedure".to_string(),
+        |name| name.to_string(),
+    );
+    let chunk = function.chunk();
+    // The offset is just past the instruction being run (or the call being returned from)
+    if ip == 0 || ip > chunk.code().len() {
+        return Some(name);
+    }
+    Some(format!("{} ({})", name, chunk.describe_position(ip - 1)))
+}
+
+/// The error for using the global variable named by constant `offset` of `chunk` before it's
+/// defined
+fn undefined_variable(chunk: &Chunk<'_>, offset: usize) -> InterpretError {
+    InterpretError::RuntimeError(format!(
+        "Undefined variable {}",
+        chunk.read_constant(offset)
+    ))
+}
+
+/// Gets upvalue `slot` of the running procedure. Only malformed bytecode (e.g. from a corrupted
+/// compiled file) uses one the procedure doesn't have.
+fn upvalue<'gc>(
+    environment: Option<Gc<'gc, ObjEnvironment<'gc>>>,
+    slot: usize,
+) -> Result<Upvalue<'gc>> {
+    environment
+        .and_then(|environment| environment.upvalues().get(slot).copied())
+        .ok_or_else(|| InterpretError::RuntimeError(format!("There is no upvalue {}", slot)))
+}
+
+/// Gets what's in the cell a local variable that's assigned to is kept in (see `BOX_LOCAL`)
+fn cell_contents(cell: Value<'_>) -> Result<Value<'_>> {
+    match cell {
+        Value::Box(object) if object.read().is_cell() => Ok(*object.read().as_cell()?),
+        _ => Err(InterpretError::RuntimeError(format!(
+            "'{}' is not a variable's cell",
+            cell
+        ))),
+    }
+}
+
+/// Assigns `value` to the variable kept in `cell`
+fn set_cell_contents<'gc>(
+    cell: Value<'gc>,
+    value: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    match cell {
+        Value::Box(object) if object.read().is_cell() => {
+            *borrow_mut(&object, mc)?.as_cell_mut()? = value;
+            Ok(())
+        }
+        _ => Err(InterpretError::RuntimeError(format!(
+            "'{}' is not a variable's cell",
+            cell
+        ))),
+    }
+}
+
 /// Peek `distance` from the top of the stack
 #[inline(always)]
 pub fn peek(stack: Stack<'_>, distance: usize) -> Value<'_> {
@@ -896,8 +2706,85 @@ pub fn peek(stack: Stack<'_>, distance: usize) -> Value<'_> {
     stack[stack.len() - distance - 1]
 }
 
+/// Mutably borrow a heap object for a builtin. Builtins should copy what they need out of the
+/// stack and any other cells before calling this, and let the borrow go before calling back into
+/// the VM, so an aliased argument (e.g. a port that's also the current input port) reports an
+/// error instead of panicking.
+pub fn borrow_mut<'a, 'gc>(
+    object: &'a GcCell<'gc, Object<'gc>>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<RefMut<'a, Object<'gc>>> {
+    object
+        .try_write(mc)
+        .map_err(|_| InterpretError::RuntimeError("Object is already in use".to_string()))
+}
+
+/// Counts the frames in a continuation, itself included
+fn continuation_depth<'gc>(frame: Option<GcCell<'gc, ObjContinuation<'gc>>>) -> usize {
+    let mut depth = 0;
+    let mut current = frame;
+    while let Some(frame) = current {
+        depth += 1;
+        current = frame.read().frames();
+    }
+    depth
+}
+
 /// Read a u8 of data from the chunk at the current IP and update IP
 #[inline(always)]
+/// Gets the ports owned by `frame` and every frame below it
+fn owned_ports<'gc>(
+    mut frame: Option<GcCell<'gc, ObjContinuation<'gc>>>,
+) -> Vec<GcCell<'gc, Object<'gc>>> {
+    let mut ports = Vec::new();
+    while let Some(current) = frame {
+        ports.extend(current.read().port());
+        frame = current.read().frames();
+    }
+    ports
+}
+
+/// Runs one of the list primitives that have their own opcode without calling it, as long as
+/// `callee` is still the builtin the opcode stands for. Returns `None` if the call has to be made
+/// after all, which is also how errors (like taking the `car` of a non-pair) are left to the
+/// builtin to report.
+fn inline_primitive<'gc>(
+    instruction: OpCode,
+    callee: Value<'gc>,
+    args: &[Value<'gc>],
+    mc: MutationContext<'gc, '_>,
+) -> Option<Value<'gc>> {
+    let builtin: Native = match instruction {
+        OpCode::Car => builtins::car,
+        OpCode::Cdr => builtins::cdr,
+        OpCode::Cons => builtins::cons,
+        OpCode::IsNull => builtins::is_null,
+        OpCode::IsPair => builtins::is_pair,
+        _ => return None,
+    };
+    let is_builtin = match callee {
+        Value::Box(object) => match &*object.read() {
+            Object::Native(native) => native.function() as usize == builtin as usize,
+            _ => false,
+        },
+        _ => false,
+    };
+    if !is_builtin {
+        return None;
+    }
+
+    match instruction {
+        OpCode::Car => builtins::uncons(args[0]).map(|(car, _)| car),
+        OpCode::Cdr => builtins::uncons(args[0]).map(|(_, cdr)| cdr),
+        OpCode::Cons => Some(Value::boxed(
+            mc,
+            Object::Pair(ObjPair::new(args[0], args[1])),
+        )),
+        OpCode::IsNull => Some(Value::Bool(args[0].is_null())),
+        _ => Some(Value::Bool(builtins::uncons(args[0]).is_some())),
+    }
+}
+
 fn read_byte(chunk: &Chunk<'_>, ip: &mut usize) -> u8 {
     let result = chunk.read(*ip);
     *ip += 1;
@@ -910,6 +2797,16 @@ fn read_short(chunk: &Chunk<'_>, ip: &mut usize) -> u16 {
     ((read_byte(chunk, ip) as u16) << 8) | (read_byte(chunk, ip) as u16)
 }
 
+/// Read the operand of an instruction at the current IP, which is a u16 for the long forms of
+/// instructions and a byte otherwise
+fn read_operand(chunk: &Chunk<'_>, ip: &mut usize, long: bool) -> usize {
+    if long {
+        read_short(chunk, ip) as usize
+    } else {
+        read_byte(chunk, ip) as usize
+    }
+}
+
 /// Read a constant from the chunk's contant table denoted by the current IP
 #[inline(always)]
 fn read_constant<'gc>(chunk: &Chunk<'gc>, ip: &mut usize) -> Value<'gc> {
@@ -920,11 +2817,24 @@ fn read_constant<'gc>(chunk: &Chunk<'gc>, ip: &mut usize) -> Value<'gc> {
 /// Read a constant from the chunk's contant table denoted by the current IP
 #[inline(always)]
 fn read_constant_long<'gc>(chunk: &Chunk<'gc>, ip: &mut usize) -> Value<'gc> {
+    chunk.read_constant(read_constant_offset(chunk, ip, true))
+}
+
+/// Read the offset of a constant at the current IP, which is 24 bits for the long forms of
+/// instructions and a byte otherwise
+fn read_constant_offset(chunk: &Chunk<'_>, ip: &mut usize, long: bool) -> usize {
+    if !long {
+        return read_byte(chunk, ip) as usize;
+    }
+
     // `offset` is a 24-bit uint, but we'll hold it in a u32
     let mut offset: u32 = 0;
     for i in 0..3 {
         let offset_bit = read_byte(chunk, ip) as u32;
         offset |= offset_bit << (8 * i);
     }
-    chunk.read_constant(offset as usize)
+    offset as usize
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+
+use super::{DEFAULT_MAX_DEPTH, STACK_MAX};
+use crate::object::{ObjReadPort, ObjWritePort};
+
+/// Groups of builtins that a VM can be made without, e.g. to keep untrusted code away from the
+/// file system
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum BuiltinGroup {
+    /// Opening files, loading code from them (including with `include` and `import`), and saving
+    /// continuations to them
+    Files,
+
+    /// Exiting the process
+    Process,
+
+    /// Compiling and evaluating code at runtime, and the environments it's evaluated in
+    Eval,
+
+    /// Green threads and channels
+    Threads,
+}
+
+impl BuiltinGroup {
+    /// Every group of builtins
+    pub const ALL: [BuiltinGroup; 4] = [Self::Files, Self::Process, Self::Eval, Self::Threads];
+
+    /// Names of the builtins in the group
+    pub fn names(self) -> &'static [&'static str] {
+        match self {
+            Self::Files => &[
+                "open-input-file",
+                "open-output-file",
+                "open-binary-input-file",
+                "open-binary-output-file",
+                "call-with-input-file",
+                "call-with-output-file",
+                "with-input-from-file",
+                "with-output-to-file",
+                "load",
+                "load-once",
+                "compile-file",
+                "require",
+                "add-to-load-path",
+                "save-continuation",
+                "load-continuation",
+            ],
+            Self::Process => &["exit"],
+            Self::Eval => &[
+                "compile",
+                "eval",
+                "environment",
+                "interaction-environment",
+                "scheme-report-environment",
+                "null-environment",
+                "global-bindings",
+            ],
+            Self::Threads => &[
+                "spawn",
+                "yield",
+                "thread-join!",
+                "thread?",
+                "make-channel",
+                "channel?",
+                "channel-send!",
+                "channel-receive",
+            ],
+        }
+    }
+}
+
+/// Settings for making a VM with `VirtualMachine::with_config`. Anything that isn't set is the
+/// same as for `VirtualMachine::default`.
+#[derive(Debug)]
+pub struct VmConfig {
+    pub(super) stack_capacity: usize,
+    pub(super) max_depth: usize,
+    pub(super) fuel: Option<u64>,
+    pub(super) input_port: Option<ObjReadPort>,
+    pub(super) output_port: Option<ObjWritePort>,
+    pub(super) error_port: Option<ObjWritePort>,
+    pub(super) builtins: HashSet<BuiltinGroup>,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            stack_capacity: STACK_MAX,
+            max_depth: DEFAULT_MAX_DEPTH,
+            fuel: None,
+            input_port: None,
+            output_port: None,
+            error_port: None,
+            builtins: BuiltinGroup::ALL.iter().copied().collect(),
+        }
+    }
+}
+
+impl VmConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many values the stack has room for before it has to grow
+    pub fn with_stack_capacity(mut self, capacity: usize) -> Self {
+        self.stack_capacity = capacity;
+        self
+    }
+
+    /// Sets how many frames deep calls can nest before they fail
+    pub fn with_max_depth(mut self, limit: usize) -> Self {
+        self.max_depth = limit;
+        self
+    }
+
+    /// Sets how many instructions can run before interpreting stops with
+    /// `InterpretError::FuelExhausted`
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Sets the port the current input port starts out as, instead of standard input
+    pub fn with_input_port(mut self, port: ObjReadPort) -> Self {
+        self.input_port = Some(port);
+        self
+    }
+
+    /// Sets the port the current output port starts out as, instead of standard output
+    pub fn with_output_port(mut self, port: ObjWritePort) -> Self {
+        self.output_port = Some(port);
+        self
+    }
+
+    /// Sets the port the current error port starts out as, instead of standard error
+    pub fn with_error_port(mut self, port: ObjWritePort) -> Self {
+        self.error_port = Some(port);
+        self
+    }
+
+    /// Sets which groups of builtins are defined. Builtins that aren't in any group always are.
+    pub fn with_builtins(mut self, groups: &[BuiltinGroup]) -> Self {
+        self.builtins = groups.iter().copied().collect();
+        self
+    }
+
+    /// Names of the builtins in the groups that were left out
+    pub(super) fn disabled_builtins(&self) -> HashSet<&'static str> {
+        BuiltinGroup::ALL
+            .iter()
+            .filter(|group| !self.builtins.contains(group))
+            .flat_map(|group| group.names().iter().copied())
+            .collect()
+    }
+}
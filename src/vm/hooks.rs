@@ -0,0 +1,70 @@
+use core::fmt;
+
+use super::InterpretError;
+use crate::value::Value;
+
+/// Callbacks an embedder can install on a VM to observe what it's doing, e.g. for tracing,
+/// coverage or auditing. Every method does nothing by default, so implementations only need to
+/// provide the events they care about.
+pub trait VmHooks: fmt::Debug {
+    /// Called when a top-level `define` binds a global variable
+    fn on_define(&self, _name: &str, _value: Value<'_>) {}
+
+    /// Called just before a procedure is applied to its arguments
+    fn on_call(&self, _callee: Value<'_>, _args: &[Value<'_>]) {}
+
+    /// Called when code read from a file has been compiled, with every line it was compiled from
+    fn on_compile(&self, _file: &str, _lines: &[usize]) {}
+
+    /// Called when execution moves on to code compiled from a different line of a file
+    fn on_line(&self, _file: &str, _line: usize) {}
+
+    /// Called when running the VM fails with an error
+    fn on_error(&self, _error: &InterpretError) {}
+
+    /// Called when something suspicious but not fatal is found, e.g. by the compiler
+    fn on_warning(&self, _message: &str) {}
+
+    /// Called after the garbage collector has done some work, with the number of bytes still
+    /// allocated
+    fn on_gc(&self, _allocated: usize) {}
+}
+
+/// Both sets of hooks are told about everything, so that e.g. coverage and diagnostics can be
+/// collected at the same time
+impl<A: VmHooks, B: VmHooks> VmHooks for (A, B) {
+    fn on_define(&self, name: &str, value: Value<'_>) {
+        self.0.on_define(name, value);
+        self.1.on_define(name, value);
+    }
+
+    fn on_call(&self, callee: Value<'_>, args: &[Value<'_>]) {
+        self.0.on_call(callee, args);
+        self.1.on_call(callee, args);
+    }
+
+    fn on_compile(&self, file: &str, lines: &[usize]) {
+        self.0.on_compile(file, lines);
+        self.1.on_compile(file, lines);
+    }
+
+    fn on_line(&self, file: &str, line: usize) {
+        self.0.on_line(file, line);
+        self.1.on_line(file, line);
+    }
+
+    fn on_error(&self, error: &InterpretError) {
+        self.0.on_error(error);
+        self.1.on_error(error);
+    }
+
+    fn on_warning(&self, message: &str) {
+        self.0.on_warning(message);
+        self.1.on_warning(message);
+    }
+
+    fn on_gc(&self, allocated: usize) {
+        self.0.on_gc(allocated);
+        self.1.on_gc(allocated);
+    }
+}
@@ -0,0 +1,589 @@
+//! A baseline tier that compiles chunks which run often to native code with Cranelift. Each
+//! instruction is lowered to a call into a helper that does what the interpreter would, so there's
+//! no decoding or dispatching, and jumps become branches. Anything the helpers can't do on their
+//! own (calls, returns, making closures, and inlined primitives that need calling after all) stops
+//! the native code at that instruction, which the interpreter then runs before carrying on with
+//! native code again. Jumps only go forwards, so native code never runs for long.
+
+use core::cell::{Cell, RefCell};
+use core::convert::TryFrom;
+use core::fmt;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use cranelift::frontend::Switch;
+use cranelift::jit::{JITBuilder, JITModule};
+use cranelift::module::{default_libcall_names, Linkage, Module};
+use cranelift::prelude::*;
+use gc_arena::{Gc, MutationContext};
+
+use super::{
+    cell_contents, inline_primitive, read_byte, read_constant_offset, read_operand, read_short,
+    set_cell_contents, undefined_variable, upvalue, InterpretError, Result, Stack, VirtualMachine,
+};
+use crate::chunk::{Chunk, Globals, OpCode};
+use crate::object::{ObjEnvironment, Object};
+use crate::value::Value;
+
+/// How many times a chunk starts running before it's compiled
+const HOT_THRESHOLD: u32 = 1_000;
+
+/// What `is_falsey` returns if it stopped with an error instead of testing a value
+const FAILED: u64 = 2;
+
+/// Native code for a chunk, which runs from the instruction at the offset it's given and returns
+/// the offset of the instruction it stopped at
+type Entry = unsafe extern "C" fn(*mut Frame<'_, '_>, u64) -> u64;
+
+/// Helpers take the frame and the instruction's operand, and return nonzero to stop. They can't
+/// panic, since that can't unwind out of native code, so they stop with an error instead.
+type Helper = extern "C" fn(&mut Frame<'_, '_>, u64) -> u64;
+
+/// Where a chunk is at in being compiled, kept with the chunk
+#[derive(Default)]
+pub(crate) struct Tier {
+    /// How many times the chunk has started running
+    runs: Cell<u32>,
+
+    /// The chunk's native code, once it's been compiled
+    compiled: RefCell<Option<Rc<Compiled>>>,
+
+    /// Set if the chunk couldn't be compiled, so it isn't tried again
+    unsupported: Cell<bool>,
+}
+
+impl Tier {
+    /// Whether the chunk has been compiled
+    pub(crate) fn is_compiled(&self) -> bool {
+        self.compiled.borrow().is_some()
+    }
+}
+
+/// Copies of a chunk start over, since they might be changed before they run
+impl Clone for Tier {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for Tier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tier")
+            .field("runs", &self.runs.get())
+            .field("compiled", &self.is_compiled())
+            .finish()
+    }
+}
+
+/// A chunk's native code, along with the module that owns the memory it's in
+pub(crate) struct Compiled {
+    module: Option<JITModule>,
+    entry: Entry,
+}
+
+impl Compiled {
+    /// Runs native code from `ip` until it reaches an instruction the interpreter has to run,
+    /// leaving `ip` there
+    pub(super) fn run(&self, frame: &mut Frame<'_, '_>, ip: &mut usize) -> Result<()> {
+        // Safety: the code was compiled from the chunk the frame is running, and only reads the
+        // frame through the helpers
+        let stopped = unsafe { (self.entry)(frame, *ip as u64) } as usize;
+        match frame.error.take() {
+            Some(error) => {
+                // Errors are reported from just past the instruction that raised them, like the
+                // interpreter does
+                *ip = stopped + 1;
+                Err(error)
+            }
+            None => {
+                *ip = stopped;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for Compiled {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Safety: the code is only reachable through `entry`, which goes with it
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+/// What native code runs on: the parts of the interpreter's state the helpers need
+pub(super) struct Frame<'a, 'gc> {
+    chunk: &'a Chunk<'gc>,
+    environment: Option<Gc<'gc, ObjEnvironment<'gc>>>,
+    stack: Stack<'gc>,
+    globals: Globals<'gc>,
+    base: usize,
+    mc: MutationContext<'gc, 'a>,
+
+    /// Error raised by a helper, which stops the native code
+    error: Option<InterpretError>,
+}
+
+impl<'a, 'gc> Frame<'a, 'gc> {
+    pub(super) fn new(
+        chunk: &'a Chunk<'gc>,
+        environment: Option<Gc<'gc, ObjEnvironment<'gc>>>,
+        stack: Stack<'gc>,
+        globals: Globals<'gc>,
+        base: usize,
+        mc: MutationContext<'gc, 'a>,
+    ) -> Self {
+        Self {
+            chunk,
+            environment,
+            stack,
+            globals,
+            base,
+            mc,
+            error: None,
+        }
+    }
+
+    fn push(&mut self, value: Value<'gc>) {
+        self.stack.write(self.mc).push(value);
+    }
+
+    fn top(&self) -> Result<Value<'gc>> {
+        self.stack
+            .read()
+            .last()
+            .copied()
+            .ok_or_else(|| InterpretError::RuntimeError("The stack is empty".into()))
+    }
+
+    /// Gets where local `slot` of the running procedure is on the stack
+    fn local(&self, slot: u64) -> Result<usize> {
+        let slot = self.base + slot as usize;
+        if slot < self.stack.read().len() {
+            Ok(slot)
+        } else {
+            Err(InterpretError::RuntimeError(format!(
+                "There is no local variable in slot {}",
+                slot
+            )))
+        }
+    }
+
+    /// Stops the native code with `error`, if there is one
+    fn stop_on_error(&mut self, result: Result<()>) -> u64 {
+        match result {
+            Ok(()) => 0,
+            Err(error) => {
+                self.error = Some(error);
+                1
+            }
+        }
+    }
+}
+
+impl<'gc> VirtualMachine<'gc> {
+    /// Counts a run of `chunk`, compiling it once it's run often enough, and returns its native
+    /// code if it has any. Nothing runs natively while anything needs to see every instruction
+    /// (hooks, fuel, or tracing execution).
+    pub(super) fn compiled_chunk(&self, chunk: &Chunk<'gc>) -> Option<Rc<Compiled>> {
+        if self.has_hooks.get()
+            || self.fuel.get().is_some()
+            || cfg!(feature = "debug-trace-execution")
+        {
+            return None;
+        }
+
+        let tier = chunk.tier();
+        if let Some(compiled) = &*tier.compiled.borrow() {
+            return Some(compiled.clone());
+        }
+        if tier.unsupported.get() {
+            return None;
+        }
+
+        let runs = tier.runs.get() + 1;
+        tier.runs.set(runs);
+        if runs < HOT_THRESHOLD {
+            return None;
+        }
+        match compile(chunk) {
+            Some(compiled) => {
+                let compiled = Rc::new(compiled);
+                *tier.compiled.borrow_mut() = Some(compiled.clone());
+                Some(compiled)
+            }
+            None => {
+                tier.unsupported.set(true);
+                None
+            }
+        }
+    }
+}
+
+/// How native code handles an instruction
+enum Lowering {
+    /// Calls a helper with an operand
+    Call(Helper, u64),
+
+    /// Branches to the instruction at an offset if the value on top of the stack is false
+    JumpIfFalse(usize),
+
+    /// Branches to the instruction at an offset
+    Jump(usize),
+
+    /// Stops, for the interpreter to run the instruction
+    Stop,
+}
+
+/// Works out how each instruction in `chunk` is handled, by its offset and the offset of the
+/// instruction after it. Returns `None` if the bytecode can't be made sense of.
+fn lower(chunk: &Chunk<'_>) -> Option<Vec<(usize, usize, Lowering)>> {
+    let mut instructions = Vec::new();
+    let mut ip = 0;
+    while ip < chunk.code().len() {
+        let start = ip;
+        let instruction = OpCode::try_from(read_byte(chunk, &mut ip)).ok()?;
+        let lowering = match instruction {
+            OpCode::Constant | OpCode::ConstantLong => {
+                let long = instruction == OpCode::ConstantLong;
+                let offset = read_constant_offset(chunk, &mut ip, long);
+                Lowering::Call(push_constant, offset as u64)
+            }
+            OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                let long = instruction == OpCode::GetGlobalLong;
+                let offset = read_constant_offset(chunk, &mut ip, long);
+                Lowering::Call(get_global, offset as u64)
+            }
+            OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                let long = instruction == OpCode::SetGlobalLong;
+                let offset = read_constant_offset(chunk, &mut ip, long);
+                Lowering::Call(set_global, offset as u64)
+            }
+            OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                read_constant_offset(chunk, &mut ip, instruction == OpCode::DefineGlobalLong);
+                Lowering::Stop
+            }
+            OpCode::GetLocal | OpCode::GetLocalLong => {
+                let slot = read_operand(chunk, &mut ip, instruction == OpCode::GetLocalLong);
+                Lowering::Call(get_local, slot as u64)
+            }
+            OpCode::SetLocal | OpCode::SetLocalLong => {
+                let slot = read_operand(chunk, &mut ip, instruction == OpCode::SetLocalLong);
+                Lowering::Call(set_local, slot as u64)
+            }
+            OpCode::GetUpvalue | OpCode::GetUpvalueLong => {
+                let slot = read_operand(chunk, &mut ip, instruction == OpCode::GetUpvalueLong);
+                Lowering::Call(get_upvalue, slot as u64)
+            }
+            OpCode::SetUpvalue | OpCode::SetUpvalueLong => {
+                let slot = read_operand(chunk, &mut ip, instruction == OpCode::SetUpvalueLong);
+                Lowering::Call(set_upvalue, slot as u64)
+            }
+            OpCode::BoxLocal | OpCode::BoxLocalLong => {
+                let slot = read_operand(chunk, &mut ip, instruction == OpCode::BoxLocalLong);
+                Lowering::Call(box_local, slot as u64)
+            }
+            OpCode::Unbox => Lowering::Call(unbox, 0),
+            OpCode::SetBox => Lowering::Call(set_box, 0),
+            OpCode::JumpIfFalse => {
+                let offset = read_short(chunk, &mut ip) as usize;
+                Lowering::JumpIfFalse(ip + offset)
+            }
+            OpCode::Jump => {
+                let offset = read_short(chunk, &mut ip) as usize;
+                Lowering::Jump(ip + offset)
+            }
+            OpCode::Call | OpCode::CallLong | OpCode::TailCall | OpCode::TailCallLong => {
+                let long = matches!(instruction, OpCode::CallLong | OpCode::TailCallLong);
+                read_operand(chunk, &mut ip, long);
+                Lowering::Stop
+            }
+            OpCode::Closure => {
+                let function = chunk.read_constant(read_byte(chunk, &mut ip) as usize);
+                let upvalues = function
+                    .as_object()
+                    .ok()?
+                    .read()
+                    .as_function()
+                    .ok()?
+                    .upvalues()
+                    .len();
+                ip += upvalues * 3;
+                Lowering::Stop
+            }
+            OpCode::Pop => Lowering::Call(pop, 0),
+            OpCode::Void | OpCode::Null | OpCode::True | OpCode::False => {
+                Lowering::Call(push_literal, u8::from(instruction) as u64)
+            }
+            OpCode::Car | OpCode::Cdr | OpCode::Cons | OpCode::IsNull | OpCode::IsPair => {
+                Lowering::Call(primitive, u8::from(instruction) as u64)
+            }
+            OpCode::Return => Lowering::Stop,
+        };
+        instructions.push((start, ip, lowering));
+    }
+    Some(instructions)
+}
+
+/// Compiles `chunk` to native code, or returns `None` if it can't be
+fn compile(chunk: &Chunk<'_>) -> Option<Compiled> {
+    let instructions = lower(chunk)?;
+
+    let builder = JITBuilder::new(default_libcall_names()).ok()?;
+    let mut module = JITModule::new(builder);
+    let pointer = module.target_config().pointer_type();
+
+    let mut context = module.make_context();
+    context.func.signature.params.push(AbiParam::new(pointer));
+    context
+        .func
+        .signature
+        .params
+        .push(AbiParam::new(types::I64));
+    context
+        .func
+        .signature
+        .returns
+        .push(AbiParam::new(types::I64));
+    let mut helper_signature = module.make_signature();
+    helper_signature.params.push(AbiParam::new(pointer));
+    helper_signature.params.push(AbiParam::new(types::I64));
+    helper_signature.returns.push(AbiParam::new(types::I64));
+
+    let mut function_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut function_context);
+    let helper_signature = builder.import_signature(helper_signature);
+
+    let entry = builder.create_block();
+    let stop = builder.create_block();
+    builder.append_block_param(stop, types::I64);
+    let blocks: HashMap<usize, Block> = instructions
+        .iter()
+        .map(|(start, _, _)| (*start, builder.create_block()))
+        .collect();
+
+    // Native code starts at whichever instruction the interpreter is at
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let frame = builder.block_params(entry)[0];
+    let ip = builder.block_params(entry)[1];
+    let mut switch = Switch::new();
+    for (start, _, _) in &instructions {
+        switch.set_entry(*start as u128, blocks[start]);
+    }
+    let outside = builder.create_block();
+    switch.emit(&mut builder, ip, outside);
+    builder.switch_to_block(outside);
+    builder.ins().jump(stop, &[ip]);
+
+    // The last instruction always stops, but just in case, running off the end does too
+    let end = builder.create_block();
+    for (start, next, lowering) in &instructions {
+        builder.switch_to_block(blocks[start]);
+        let here = builder.ins().iconst(types::I64, *start as i64);
+        let next = blocks.get(next).copied().unwrap_or(end);
+        let call = |builder: &mut FunctionBuilder<'_>, helper: Helper, operand: u64| {
+            let callee = builder.ins().iconst(pointer, helper as usize as i64);
+            let operand = builder.ins().iconst(types::I64, operand as i64);
+            let call = builder
+                .ins()
+                .call_indirect(helper_signature, callee, &[frame, operand]);
+            builder.inst_results(call)[0]
+        };
+        match lowering {
+            Lowering::Call(helper, operand) => {
+                let stopped = call(&mut builder, *helper, *operand);
+                builder.ins().brif(stopped, stop, &[here], next, &[]);
+            }
+            Lowering::JumpIfFalse(target) => {
+                let target = *blocks.get(target)?;
+                let falsey = call(&mut builder, is_falsey, 0);
+                let failed = builder.ins().icmp_imm(IntCC::Equal, falsey, FAILED as i64);
+                let test = builder.create_block();
+                builder.ins().brif(failed, stop, &[here], test, &[]);
+                builder.switch_to_block(test);
+                builder.ins().brif(falsey, target, &[], next, &[]);
+            }
+            Lowering::Jump(target) => {
+                let target = *blocks.get(target)?;
+                builder.ins().jump(target, &[]);
+            }
+            Lowering::Stop => {
+                builder.ins().jump(stop, &[here]);
+            }
+        }
+    }
+
+    builder.switch_to_block(end);
+    let length = builder.ins().iconst(types::I64, chunk.code().len() as i64);
+    builder.ins().jump(stop, &[length]);
+
+    builder.switch_to_block(stop);
+    let stopped = builder.block_params(stop)[0];
+    builder.ins().return_(&[stopped]);
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    let id = module
+        .declare_function("chunk", Linkage::Local, &context.func.signature)
+        .ok()?;
+    module.define_function(id, &mut context).ok()?;
+    module.clear_context(&mut context);
+    module.finalize_definitions().ok()?;
+    let code = module.get_finalized_function(id);
+
+    // Safety: the function was just compiled with the signature of `Entry`
+    let entry = unsafe { core::mem::transmute::<*const u8, Entry>(code) };
+    Some(Compiled {
+        module: Some(module),
+        entry,
+    })
+}
+
+extern "C" fn push_constant(frame: &mut Frame<'_, '_>, offset: u64) -> u64 {
+    let constant = frame.chunk.constants().get(offset as usize).copied();
+    let result = constant
+        .map(|constant| frame.push(constant))
+        .ok_or_else(|| InterpretError::RuntimeError(format!("There is no constant {}", offset)));
+    frame.stop_on_error(result)
+}
+
+extern "C" fn get_global(frame: &mut Frame<'_, '_>, offset: u64) -> u64 {
+    let offset = offset as usize;
+    let slot = frame.chunk.global_slot(offset, &frame.globals.read());
+    match slot {
+        Some(slot) => {
+            let value = frame.globals.read().slot_value(slot);
+            frame.push(value);
+            0
+        }
+        None => {
+            frame.error = Some(undefined_variable(frame.chunk, offset));
+            1
+        }
+    }
+}
+
+extern "C" fn set_global(frame: &mut Frame<'_, '_>, offset: u64) -> u64 {
+    let offset = offset as usize;
+    let slot = frame.chunk.global_slot(offset, &frame.globals.read());
+    let result = slot
+        .ok_or_else(|| undefined_variable(frame.chunk, offset))
+        .and_then(|slot| {
+            let value = frame.top()?;
+            frame.globals.write(frame.mc).set_slot_value(slot, value);
+            Ok(())
+        });
+    frame.stop_on_error(result)
+}
+
+extern "C" fn get_local(frame: &mut Frame<'_, '_>, slot: u64) -> u64 {
+    let result = frame.local(slot).map(|slot| {
+        let value = frame.stack.read()[slot];
+        frame.push(value);
+    });
+    frame.stop_on_error(result)
+}
+
+extern "C" fn set_local(frame: &mut Frame<'_, '_>, slot: u64) -> u64 {
+    let result = frame.local(slot).and_then(|slot| {
+        let value = frame.top()?;
+        frame.stack.write(frame.mc)[slot] = value;
+        Ok(())
+    });
+    frame.stop_on_error(result)
+}
+
+extern "C" fn get_upvalue(frame: &mut Frame<'_, '_>, slot: u64) -> u64 {
+    let result = upvalue(frame.environment, slot as usize).map(|upvalue| {
+        frame.push(upvalue.location());
+    });
+    frame.stop_on_error(result)
+}
+
+extern "C" fn set_upvalue(frame: &mut Frame<'_, '_>, slot: u64) -> u64 {
+    let result = upvalue(frame.environment, slot as usize).and_then(|upvalue| {
+        upvalue.set_location(frame.top()?, frame.mc);
+        Ok(())
+    });
+    frame.stop_on_error(result)
+}
+
+extern "C" fn box_local(frame: &mut Frame<'_, '_>, slot: u64) -> u64 {
+    let result = frame.local(slot).map(|slot| {
+        let value = frame.stack.read()[slot];
+        frame.stack.write(frame.mc)[slot] = Value::boxed(frame.mc, Object::Cell(value));
+    });
+    frame.stop_on_error(result)
+}
+
+extern "C" fn unbox(frame: &mut Frame<'_, '_>, _: u64) -> u64 {
+    let result = frame.top().and_then(cell_contents).map(|value| {
+        *frame.stack.write(frame.mc).last_mut().unwrap() = value;
+    });
+    frame.stop_on_error(result)
+}
+
+extern "C" fn set_box(frame: &mut Frame<'_, '_>, _: u64) -> u64 {
+    let result = frame.top().and_then(|cell| {
+        frame.stack.write(frame.mc).pop();
+        set_cell_contents(cell, frame.top()?, frame.mc)
+    });
+    frame.stop_on_error(result)
+}
+
+extern "C" fn pop(frame: &mut Frame<'_, '_>, _: u64) -> u64 {
+    frame.stack.write(frame.mc).pop();
+    0
+}
+
+extern "C" fn push_literal(frame: &mut Frame<'_, '_>, instruction: u64) -> u64 {
+    let value = match OpCode::try_from(instruction as u8) {
+        Ok(OpCode::Null) => Value::Null,
+        Ok(OpCode::True) => Value::Bool(true),
+        Ok(OpCode::False) => Value::Bool(false),
+        _ => Value::Void,
+    };
+    frame.push(value);
+    0
+}
+
+/// Returns 1 if the value on top of the stack is false, leaving it there, or `FAILED` if there
+/// isn't one
+extern "C" fn is_falsey(frame: &mut Frame<'_, '_>, _: u64) -> u64 {
+    match frame.top() {
+        Ok(value) => value.is_falsey() as u64,
+        Err(error) => {
+            frame.error = Some(error);
+            FAILED
+        }
+    }
+}
+
+/// Runs an inlined primitive, stopping without touching the stack if it has to be called
+extern "C" fn primitive(frame: &mut Frame<'_, '_>, instruction: u64) -> u64 {
+    let instruction = match OpCode::try_from(instruction as u8) {
+        Ok(instruction) => instruction,
+        Err(_) => return 1,
+    };
+    let arg_count = if instruction == OpCode::Cons { 2 } else { 1 };
+    let result = {
+        let stack = frame.stack.read();
+        let callee = match stack.len().checked_sub(arg_count + 1) {
+            Some(callee) => callee,
+            None => return 1,
+        };
+        inline_primitive(instruction, stack[callee], &stack[callee + 1..], frame.mc)
+    };
+    match result {
+        Some(result) => {
+            let mut stack = frame.stack.write(frame.mc);
+            let callee = stack.len() - arg_count - 1;
+            stack.truncate(callee);
+            stack.push(result);
+            0
+        }
+        None => 1,
+    }
+}
@@ -0,0 +1,220 @@
+use gc_arena::{GcCell, MutationContext};
+use gc_arena_derive::Collect;
+
+use super::{InterpretError, Result, VirtualMachine};
+use crate::builtins::list_to_vec;
+use crate::chunk::Globals;
+use crate::memory::{Symbol, Token};
+use crate::value::{DisplayStyle, Print, Value};
+
+/// Libraries every program can import. They aren't split up the way R7RS splits them yet, so each
+/// of them exports every builtin.
+const STANDARD_LIBRARIES: &[&str] = &[
+    "(scheme base)",
+    "(scheme case-lambda)",
+    "(scheme char)",
+    "(scheme cxr)",
+    "(scheme eval)",
+    "(scheme file)",
+    "(scheme inexact)",
+    "(scheme lazy)",
+    "(scheme load)",
+    "(scheme process-context)",
+    "(scheme read)",
+    "(scheme repl)",
+    "(scheme time)",
+    "(scheme write)",
+    "(cheshire)",
+];
+
+/// A library made by `define-library`, which keeps its global variables apart from the program's
+#[derive(Debug, Clone, Collect)]
+#[collect(no_drop)]
+pub(crate) struct Library<'gc> {
+    globals: Globals<'gc>,
+
+    /// What the library exports, as (name inside the library, name it's imported as) pairs
+    exports: Vec<(Symbol<'gc>, Symbol<'gc>)>,
+}
+
+impl<'gc> Library<'gc> {
+    pub fn new(globals: Globals<'gc>, exports: Vec<(Symbol<'gc>, Symbol<'gc>)>) -> Self {
+        Self { globals, exports }
+    }
+}
+
+/// Gets the name a library is registered under from the list naming it, e.g. `(scheme base)`
+pub(crate) fn library_name(name: Value<'_>) -> Result<String> {
+    let parts = list_to_vec(name)?;
+    let is_valid = !parts.is_empty()
+        && parts
+            .iter()
+            .all(|part| part.is_symbol() || part.as_number().is_ok());
+    if !is_valid {
+        return Err(InterpretError::RuntimeError(format!(
+            "'{}' is not a library name",
+            name
+        )));
+    }
+    Ok(name.styled(DisplayStyle::Write).to_string())
+}
+
+/// Gets the name of the library an import set like `(prefix (my lib) my:)` imports from
+pub(crate) fn imported_library(set: Value<'_>) -> Result<Value<'_>> {
+    let parts = list_to_vec(set)?;
+    let keyword = parts.first().and_then(|part| part.as_symbol().ok());
+    match keyword
+        .map(|keyword| keyword.as_str().into_owned())
+        .as_deref()
+    {
+        Some("only" | "except" | "prefix" | "rename") if parts.len() >= 2 => {
+            imported_library(parts[1])
+        }
+        _ => Ok(set),
+    }
+}
+
+/// Gets the files a library could be defined in, relative to the load path. `(my lib)` can be in
+/// `my/lib.sld` or `my/lib.scm`.
+pub(crate) fn library_files(name: Value<'_>) -> Result<Vec<String>> {
+    library_name(name)?;
+    let path = list_to_vec(name)?
+        .iter()
+        .map(|part| part.styled(DisplayStyle::Display).to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+    Ok(vec![format!("{}.sld", path), format!("{}.scm", path)])
+}
+
+impl<'gc> VirtualMachine<'gc> {
+    /// Registers the standard libraries, which export whatever is bound when this is called. They
+    /// keep their own copy of the bindings, so redefining a builtin doesn't change what they export.
+    pub(super) fn define_standard_libraries(&self, mc: MutationContext<'gc, '_>) {
+        let globals = GcCell::allocate(mc, self.globals.read().clone());
+        let exports: Vec<_> = self
+            .global_names()
+            .into_iter()
+            .map(|name| (name, name))
+            .collect();
+        let mut libraries = self.libraries.write(mc);
+        for name in STANDARD_LIBRARIES {
+            libraries.insert(name.to_string(), Library::new(globals, exports.clone()));
+        }
+    }
+
+    /// Registers a library under `name`, replacing any library that already had that name
+    pub(crate) fn define_library(
+        &self,
+        name: String,
+        library: Library<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) {
+        self.libraries.write(mc).insert(name, library);
+    }
+
+    /// Finds the name of the library whose global variables are in `globals`
+    pub(crate) fn library_with_globals(&self, globals: Globals<'gc>) -> Option<String> {
+        self.libraries
+            .read()
+            .iter()
+            .find(|(_, library)| GcCell::ptr_eq(library.globals, globals))
+            .map(|(name, _)| name.clone())
+    }
+
+    /// Gets the global variables of the library registered under `name`
+    pub(crate) fn library_globals(&self, name: &str) -> Option<Globals<'gc>> {
+        self.libraries
+            .read()
+            .get(name)
+            .map(|library| library.globals)
+    }
+
+    /// Works out the bindings an import set like `(only (scheme base) car cdr)` brings in, as
+    /// (name, value) pairs. Values are copied when they're imported, so a library that later
+    /// `set!`s one of its exports doesn't change what was imported.
+    pub(crate) fn resolve_import(
+        &self,
+        set: Value<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Result<Vec<(Symbol<'gc>, Value<'gc>)>> {
+        let parts = list_to_vec(set)?;
+        let keyword = parts.first().and_then(|part| part.as_symbol().ok());
+        let keyword = keyword.map(|keyword| keyword.as_str().into_owned());
+        let identifiers = |parts: &[Value<'gc>]| -> Result<Vec<Symbol<'gc>>> {
+            parts.iter().map(|part| Ok(part.as_symbol()?)).collect()
+        };
+
+        match (keyword.as_deref(), parts.len()) {
+            (Some("only"), len) if len >= 2 => {
+                let only = identifiers(&parts[2..])?;
+                let bindings = self.resolve_import(parts[1], mc)?;
+                Ok(bindings
+                    .into_iter()
+                    .filter(|(name, _)| only.contains(name))
+                    .collect())
+            }
+            (Some("except"), len) if len >= 2 => {
+                let except = identifiers(&parts[2..])?;
+                let bindings = self.resolve_import(parts[1], mc)?;
+                Ok(bindings
+                    .into_iter()
+                    .filter(|(name, _)| !except.contains(name))
+                    .collect())
+            }
+            (Some("prefix"), 3) => {
+                let prefix = parts[2].as_symbol()?;
+                let bindings = self.resolve_import(parts[1], mc)?;
+                Ok(bindings
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let name = format!("{}{}", prefix, name);
+                        (self.intern_symbol(Token::new(mc, name.into()), mc), value)
+                    })
+                    .collect())
+            }
+            (Some("rename"), len) if len >= 2 => {
+                let renames = parts[2..]
+                    .iter()
+                    .map(|rename| match &identifiers(&list_to_vec(*rename)?)?[..] {
+                        [from, to] => Ok((*from, *to)),
+                        _ => Err(InterpretError::RuntimeError(format!(
+                            "'{}' is not a valid rename",
+                            rename
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let bindings = self.resolve_import(parts[1], mc)?;
+                Ok(bindings
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let renamed = renames.iter().find(|(from, _)| *from == name);
+                        (renamed.map_or(name, |(_, to)| *to), value)
+                    })
+                    .collect())
+            }
+            _ => self.import_library(set),
+        }
+    }
+
+    /// Gets the bindings a library exports
+    fn import_library(&self, name: Value<'gc>) -> Result<Vec<(Symbol<'gc>, Value<'gc>)>> {
+        let name = library_name(name)?;
+        let libraries = self.libraries.read();
+        let library = libraries
+            .get(&name)
+            .ok_or_else(|| InterpretError::RuntimeError(format!("Unknown library {}", name)))?;
+
+        let globals = library.globals.read();
+        library
+            .exports
+            .iter()
+            .map(|(internal, external)| match globals.get(internal) {
+                Some(value) => Ok((*external, *value)),
+                None => Err(InterpretError::RuntimeError(format!(
+                    "Library {} exports {} but doesn't define it",
+                    name, internal
+                ))),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,171 @@
+//! Profiling, which counts the calls made to each procedure and the time spent in them. Each call
+//! is timed from when it's made until its frame returns (or is escaped from), so a procedure's
+//! time includes the procedures it calls.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use gc_arena::Gc;
+
+use super::{describe_frame, VirtualMachine};
+use crate::object::{self, ObjFunction, ObjNative};
+
+/// Calls made to one procedure and the time spent in them
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    calls: u64,
+    time: Duration,
+
+    /// How many of the calls haven't returned yet. Only the outermost one is timed, so that
+    /// recursive calls aren't counted more than once.
+    running: usize,
+}
+
+/// What's been recorded since profiling started
+#[derive(Debug, Default)]
+pub(super) struct Profile {
+    /// Each procedure called, by the address of its code
+    entries: HashMap<usize, Entry>,
+
+    /// Calls that haven't returned yet, innermost last: the procedure, when it was called and how
+    /// many frames deep it runs
+    calls: Vec<(usize, Instant, usize)>,
+}
+
+impl Profile {
+    /// Ends the calls running deeper than `depth` frames
+    fn leave(&mut self, depth: usize) {
+        while let Some(&(key, start, _)) = self.calls.last().filter(|call| call.2 > depth) {
+            self.calls.pop();
+            if let Some(entry) = self.entries.get_mut(&key) {
+                entry.running -= 1;
+                if entry.running == 0 {
+                    entry.time += start.elapsed();
+                }
+            }
+        }
+    }
+}
+
+impl<'gc> VirtualMachine<'gc> {
+    /// Starts profiling procedure calls, throwing away anything recorded before
+    pub fn start_profiling(&self) {
+        *self.profile.borrow_mut() = Some(Profile::default());
+        self.profiling.set(true);
+    }
+
+    /// Stops profiling procedure calls
+    pub fn stop_profiling(&self) {
+        *self.profile.borrow_mut() = None;
+        self.profiling.set(false);
+    }
+
+    /// Lists each procedure called since profiling started, with how many times it was called and
+    /// how long was spent in it, most time first. Returns `None` if profiling isn't on.
+    pub fn profile_report(&self) -> Option<String> {
+        let profile = self.profile.borrow();
+        let profile = profile.as_ref()?;
+
+        // Calls still running haven't added their time yet
+        let now = Instant::now();
+        let mut times: HashMap<usize, Duration> = profile
+            .entries
+            .iter()
+            .map(|(key, entry)| (*key, entry.time))
+            .collect();
+        let mut seen = Vec::new();
+        for (key, start, _) in &profile.calls {
+            if !seen.contains(key) {
+                seen.push(*key);
+                *times.entry(*key).or_default() += now - *start;
+            }
+        }
+
+        let mut rows: Vec<_> = profile
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry, times[key]))
+            .collect();
+        rows.sort_by(|(a, a_time), (b, b_time)| {
+            b_time
+                .cmp(a_time)
+                .then(b.calls.cmp(&a.calls))
+                .then(a.name.cmp(&b.name))
+        });
+
+        let mut report = format!("{:<48} {:>10} {:>12}\n", "procedure", "calls", "time (ms)");
+        for (entry, time) in rows {
+            let millis = time.as_secs_f64() * 1000.0;
+            let _ = writeln!(
+                report,
+                "{:<48} {:>10} {:>12.3}",
+                entry.name, entry.calls, millis
+            );
+        }
+        Some(report)
+    }
+
+    /// Records a call to `function` if profiling is on. The VM must already be running it.
+    pub(super) fn profile_function(&self, function: &ObjFunction<'gc>, tail: bool) {
+        if self.profiling.get() {
+            let key = Gc::as_ptr(function.chunk()) as usize;
+            self.profile_call(key, tail, || {
+                let procedure = object::Procedure::Function {
+                    function: function.clone(),
+                    ip: 1,
+                };
+                describe_frame(&procedure)
+            });
+        }
+    }
+
+    /// Records a call to `native` if profiling is on. The VM must already be running it.
+    pub(super) fn profile_native(&self, native: &ObjNative<'gc>, tail: bool) {
+        if self.profiling.get() {
+            let key = native.function() as usize;
+            self.profile_call(key, tail, || native.name().map(|name| name.to_string()));
+        }
+    }
+
+    /// Ends the calls whose frames were returned from or escaped, if profiling is on
+    pub(super) fn profile_return(&self) {
+        if self.profiling.get() {
+            if let Some(profile) = &mut *self.profile.borrow_mut() {
+                profile.leave(self.depth.get());
+            }
+        }
+    }
+
+    /// Records a call to the procedure at `key`. A tail call replaces the running procedure, so
+    /// that call ends first. Procedures without a name (the continuations of natives, mostly)
+    /// aren't recorded.
+    fn profile_call(&self, key: usize, tail: bool, name: impl FnOnce() -> Option<String>) {
+        let mut profile = self.profile.borrow_mut();
+        let profile = match &mut *profile {
+            Some(profile) => profile,
+            None => return,
+        };
+
+        let depth = self.depth.get();
+        if tail {
+            profile.leave(depth.saturating_sub(1));
+        }
+        let entry = match profile.entries.get_mut(&key) {
+            Some(entry) => entry,
+            None => match name() {
+                Some(name) => profile.entries.entry(key).or_insert(Entry {
+                    name,
+                    calls: 0,
+                    time: Duration::default(),
+                    running: 0,
+                }),
+                None => return,
+            },
+        };
+        entry.calls += 1;
+        entry.running += 1;
+        profile.calls.push((key, Instant::now(), depth));
+    }
+}
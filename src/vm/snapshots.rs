@@ -0,0 +1,160 @@
+use gc_arena::{GcCell, MutationContext};
+
+use super::{InterpretError, Result, VirtualMachine};
+use crate::object::Procedure;
+
+/// How many snapshots are kept before the oldest ones are thrown away
+pub const MAX_SNAPSHOTS: usize = 256;
+
+/// Settings and position of time-travel debugging
+#[derive(Debug, Default, Copy, Clone)]
+pub(super) struct TimeTravel {
+    /// Take a snapshot every this many procedure calls, or never if `None`
+    interval: Option<usize>,
+
+    /// Procedure calls left until the next snapshot is due
+    countdown: usize,
+
+    /// The snapshot that's been stepped back to, if any
+    cursor: Option<usize>,
+}
+
+impl<'gc> VirtualMachine<'gc> {
+    /// Turns on time-travel debugging, which snapshots the machine every `interval` procedure
+    /// calls so that it can be stepped back to one of them later (`,back` in the REPL). `None`
+    /// turns it off again. Snapshots cover the frames being run and their local variables, but
+    /// not changes made to globals or to objects on the heap.
+    pub fn set_time_travel(&self, interval: Option<usize>) {
+        self.time_travel.set(TimeTravel {
+            interval: interval.map(|interval| interval.max(1)),
+            ..TimeTravel::default()
+        });
+    }
+
+    /// Whether time-travel debugging is turned on
+    pub fn is_time_travelling(&self) -> bool {
+        self.time_travel.get().interval.is_some()
+    }
+
+    /// Called whenever a procedure is entered, to take a snapshot if one is due
+    pub(super) fn record_snapshot(&self, mc: MutationContext<'gc, '_>) {
+        let mut state = self.time_travel.get();
+        let interval = match state.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        if state.countdown == 0 {
+            let top = self.stack.read().read().len();
+            self.reify_frames(mc);
+            let snapshot = self.save_current_continuation(top).snapshot(mc);
+            let mut snapshots = self.snapshots.write(mc);
+            snapshots.push(GcCell::allocate(mc, snapshot));
+            if snapshots.len() > MAX_SNAPSHOTS {
+                snapshots.remove(0);
+                state.cursor = state.cursor.and_then(|cursor| cursor.checked_sub(1));
+            }
+            state.countdown = interval;
+        }
+        state.countdown -= 1;
+        self.time_travel.set(state);
+    }
+
+    /// Goes back to the present, so that the next `step_back` starts from the latest snapshot
+    pub(super) fn reset_time_travel(&self) {
+        let mut state = self.time_travel.get();
+        state.cursor = None;
+        self.time_travel.set(state);
+    }
+
+    /// Steps back to the snapshot before the current one, returning a description of it, or
+    /// `None` if there isn't an earlier one
+    pub fn step_back(&self) -> Option<String> {
+        let mut state = self.time_travel.get();
+        let index = match state.cursor {
+            Some(cursor) => cursor.checked_sub(1)?,
+            None => self.snapshots.read().len().checked_sub(1)?,
+        };
+        state.cursor = Some(index);
+        self.time_travel.set(state);
+        Some(self.describe_snapshot(index))
+    }
+
+    /// Undoes `step_back`, returning a description of the snapshot after the current one, or
+    /// `None` if there isn't a later one
+    pub fn step_forward(&self) -> Option<String> {
+        let mut state = self.time_travel.get();
+        let index = state.cursor? + 1;
+        if index >= self.snapshots.read().len() {
+            return None;
+        }
+        state.cursor = Some(index);
+        self.time_travel.set(state);
+        Some(self.describe_snapshot(index))
+    }
+
+    /// Carries on running from the snapshot that was stepped back to. The snapshots after it are
+    /// forgotten, since running again will record new ones in their place.
+    pub fn resume_snapshot(&self, mc: MutationContext<'gc, '_>) -> Result<()> {
+        let mut state = self.time_travel.get();
+        let index = state.cursor.ok_or_else(|| {
+            InterpretError::RuntimeError("There's no snapshot to resume - step back first".into())
+        })?;
+
+        // The snapshot gets taken again as soon as its procedure is re-entered
+        let snapshot = {
+            let mut snapshots = self.snapshots.write(mc);
+            let snapshot = snapshots[index];
+            snapshots.truncate(index);
+            snapshot
+        };
+        state.cursor = None;
+        state.countdown = 0;
+        self.time_travel.set(state);
+
+        // Run a copy, so that the snapshot is left as it was
+        let frame = GcCell::allocate(mc, snapshot.read().snapshot(mc));
+        self.close_all_upvalues(mc);
+        self.apply_continuation(frame, mc);
+        Ok(())
+    }
+
+    /// Describes a snapshot as the procedure call it was taken at, along with where that
+    /// procedure was defined
+    fn describe_snapshot(&self, index: usize) -> String {
+        let snapshot = self.snapshots.read()[index];
+        let snapshot = snapshot.read();
+        let stack = snapshot.stack();
+        let stack = stack.read();
+        let top = snapshot.stack_top().min(stack.len());
+        let args = &stack[(snapshot.stack_base() + 1).min(top)..top];
+
+        let function = match snapshot.procedure() {
+            Procedure::Closure { closure, .. } => closure.function().clone(),
+            Procedure::Function { function, .. } => function.clone(),
+            Procedure::Native(native) => return format!("{}", native),
+        };
+        let mut call = match function.name() {
+            Some(name) => format!("({}", name),
+            None => format!("({}", function),
+        };
+        for arg in args {
+            call.push_str(&format!(" {}", arg));
+        }
+        call.push(')');
+
+        let chunk = function.chunk();
+        let count = self.snapshots.read().len();
+        match chunk.file() {
+            Some(file) => format!(
+                "[{}/{}] {} at line {} of {}",
+                index + 1,
+                count,
+                call,
+                chunk.get_line(0),
+                file
+            ),
+            None => format!("[{}/{}] {}", index + 1, count, call),
+        }
+    }
+}
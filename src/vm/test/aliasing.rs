@@ -0,0 +1,74 @@
+use super::run;
+
+#[test]
+fn reading_from_the_current_input_port_by_name() {
+    let (error, values) = run(
+        "aliasing-read",
+        "(define p (open-input-string \"(a b) c\"))\n\
+         (define both (with-input-from-string \"x\" (lambda () (read (current-input-port)))))\n\
+         (define first (read p))\n\
+         (define next (read-char p))\n\
+         (define last (read p))\n",
+        &["both", "first", "next", "last"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        vec![
+            Some("x".to_string()),
+            Some("(a b)".to_string()),
+            Some("#\\space".to_string()),
+            Some("c".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn writing_a_port_to_itself() {
+    let (error, values) = run(
+        "aliasing-write",
+        "(define o (open-output-string))\n\
+         (format o \"~a\" o)\n\
+         (write-char #\\! o)\n\
+         (define written (get-output-string o))\n",
+        &["written"],
+    );
+
+    assert_eq!(error, None);
+    let written = values[0].as_deref().unwrap();
+    assert!(written.starts_with("\"#<output port"), "{}", written);
+    assert!(written.ends_with(">!\""), "{}", written);
+}
+
+#[test]
+fn storing_an_object_inside_itself() {
+    let (error, values) = run(
+        "aliasing-store",
+        "(define v (make-vector 2 0))\n\
+         (vector-set! v 0 v)\n\
+         (define l (cons 1 2))\n\
+         (set-car! l l)\n\
+         (set-cdr! l v)\n\
+         (define same (eq? (vector-ref v 0) v))\n\
+         (define self (eq? (car l) l))\n",
+        &["same", "self"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("#t".to_string()), Some("#t".to_string())]);
+}
+
+#[test]
+fn reporting_an_error_about_an_object_in_use() {
+    let (error, _) = run(
+        "aliasing-error",
+        "(define v (make-vector 1 0))\n\
+         (vector-set! v 0 (cons v 1))\n\
+         (set-car! v 1)\n",
+        &[],
+    );
+
+    let error = error.expect("set-car! of a vector should fail");
+    assert!(error.contains("#<object in use>"), "{}", error);
+}
@@ -0,0 +1,41 @@
+use super::run;
+
+#[test]
+fn boxes_hold_a_changeable_value() {
+    let (error, values) = run(
+        "boxes",
+        "(define b (box 1))\n\
+         (define before (unbox b))\n\
+         (define (increment!) (set-box! b (+ (unbox b) 1)))\n\
+         (increment!)\n\
+         (increment!)\n\
+         (define after (unbox b))\n\
+         (define is-box (box? b))\n\
+         (define not-box (box? (cons 1 2)))\n\
+         (define nested (box (box \"s\")))\n",
+        &["b", "before", "after", "is-box", "not-box", "nested"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["#&3", "1", "3", "#t", "#f", "#&#&\"s\""]);
+}
+
+#[test]
+fn boxes_that_contain_themselves_print() {
+    let (error, values) = run(
+        "box-cycle",
+        "(define b (box 1))\n\
+         (set-box! b b)\n",
+        &["b"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("#0=#&#0#".to_string())]);
+}
+
+#[test]
+fn only_boxes_can_be_unboxed() {
+    let (error, _) = run("unbox-type", "(unbox (cons 1 2))\n", &[]);
+    assert!(error.unwrap().contains("is not a box"));
+}
@@ -0,0 +1,78 @@
+use super::run;
+
+#[test]
+fn bytevectors_can_be_built_and_changed() {
+    let (error, values) = run(
+        "bytevectors",
+        "(define b (make-bytevector 3 7))\n\
+         (bytevector-u8-set! b 1 255)\n\
+         (define second (bytevector-u8-ref b 1))\n\
+         (define length (bytevector-length b))\n\
+         (define copy (bytevector-copy (bytevector 1 2 3 4) 1 3))\n\
+         (define appended (bytevector-append (bytevector 1) (bytevector) (bytevector 2 3)))\n\
+         (define same (equal? (bytevector 1 2) (bytevector-copy (bytevector 0 1 2) 1)))\n\
+         (define is-bytevector (bytevector? b))\n",
+        &[
+            "b",
+            "second",
+            "length",
+            "copy",
+            "appended",
+            "same",
+            "is-bytevector",
+        ],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "#u8(7 255 7)",
+            "255",
+            "3",
+            "#u8(2 3)",
+            "#u8(1 2 3)",
+            "#t",
+            "#t"
+        ]
+    );
+}
+
+#[test]
+fn strings_convert_to_and_from_utf8() {
+    let (error, values) = run(
+        "utf8",
+        "(define bytes (string->utf8 \"aλ\"))\n\
+         (define part (string->utf8 \"abc\" 1 2))\n\
+         (define string (utf8->string (bytevector 104 105 206 187)))\n\
+         (define round-trip (utf8->string (string->utf8 \"héllo\") 1))\n",
+        &["bytes", "part", "string", "round-trip"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec!["#u8(97 206 187)", "#u8(98)", "\"hiλ\"", "\"éllo\""]
+    );
+}
+
+#[test]
+fn bytevectors_only_hold_bytes() {
+    let cases = [
+        ("(make-bytevector 2 256)\n", "256 is not a byte"),
+        ("(bytevector 1 -1)\n", "-1 is not a byte"),
+        ("(bytevector-u8-ref (bytevector 1) 1)\n", "out of range"),
+        ("(utf8->string (bytevector 255))\n", "is not valid UTF-8"),
+        ("(bytevector-length \"abc\")\n", "is not a bytevector"),
+        ("(make-bytevector 1e20 0)\n", "valid bytevector length"),
+        ("(make-bytevector -1)\n", "valid bytevector length"),
+        ("(make-bytevector 1.5)\n", "valid bytevector length"),
+    ];
+    for (i, (source, message)) in cases.iter().enumerate() {
+        let (error, _) = run(&format!("bytevector-error-{}", i), source, &[]);
+        let error = error.unwrap();
+        assert!(error.contains(message), "{}", error);
+    }
+}
@@ -0,0 +1,89 @@
+use super::run;
+
+#[test]
+fn characters_convert_to_and_from_code_points() {
+    let (error, values) = run(
+        "char-integer",
+        "(define a (char->integer #\\a))\n\
+         (define lambda (integer->char 955))\n\
+         (define round-trip (char->integer (integer->char 128512)))\n",
+        &["a", "lambda", "round-trip"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["97", "#\\λ", "128512"]);
+}
+
+#[test]
+fn only_scalar_values_are_characters() {
+    for (i, n) in ["55296", "1114112", "-1", "65.5"].iter().enumerate() {
+        let (error, _) = run(
+            &format!("integer-char-{}", i),
+            &format!("(integer->char {})\n", n),
+            &[],
+        );
+        let error = error.unwrap();
+        assert!(
+            error.contains(&format!("{} is not a Unicode scalar value", n)),
+            "{}",
+            error
+        );
+    }
+}
+
+#[test]
+fn digit_values_cover_every_script() {
+    let (error, values) = run(
+        "digit-value",
+        "(define ascii (digit-value #\\7))\n\
+         (define arabic-indic (digit-value (integer->char 1635)))\n\
+         (define fullwidth (digit-value (integer->char 65305)))\n\
+         (define letter (digit-value #\\x))\n\
+         (define roman (digit-value (integer->char 8547)))\n",
+        &["ascii", "arabic-indic", "fullwidth", "letter", "roman"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["7", "3", "9", "#f", "#f"]);
+}
+
+#[test]
+fn case_mapping_follows_unicode() {
+    let (error, values) = run(
+        "char-case",
+        "(define e (char-upcase #\\é))\n\
+         (define sigma (char-downcase #\\Σ))\n\
+         (define final-sigma (char-foldcase #\\ς))\n\
+         (define eszett (char-upcase #\\ß))\n\
+         (define folded (char-foldcase #\\A))\n",
+        &["e", "sigma", "final-sigma", "eszett", "folded"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["#\\É", "#\\σ", "#\\σ", "#\\ß", "#\\a"]);
+}
+
+#[test]
+fn strings_index_past_the_first_checkpoint() {
+    let text = format!("{}abc{}", "λ".repeat(40), "é".repeat(30));
+    let (error, values) = run(
+        "string-ref",
+        &format!(
+            "(define s {:?})\n\
+             (define len (string-length s))\n\
+             (define first (string-ref s 0))\n\
+             (define later (string-ref s 33))\n\
+             (define ascii (string-ref s 41))\n\
+             (define last (string-ref s 72))\n",
+            text
+        ),
+        &["len", "first", "later", "ascii", "last"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["73", "#\\λ", "#\\λ", "#\\b", "#\\é"]);
+}
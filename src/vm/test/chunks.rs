@@ -0,0 +1,104 @@
+use super::run;
+use crate::chunk::OpCode;
+
+#[test]
+fn emitted_chunks_run_as_procedures() {
+    let source = format!(
+        "(define chunk (make-chunk))\n\
+         (define index (chunk-add-constant! chunk 'hello))\n\
+         (chunk-emit! chunk {} 1)\n\
+         (chunk-emit! chunk index)\n\
+         (define end (chunk-emit! chunk {}))\n\
+         (define hello (make-procedure-from-chunk chunk 0 #f 'hello))\n\
+         (define result (hello))\n",
+        u8::from(OpCode::Constant),
+        u8::from(OpCode::Return),
+    );
+    let (error, values) = run(
+        "chunks-constant",
+        &source,
+        &["index", "end", "result", "hello"],
+    );
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        vec![
+            Some("0".to_string()),
+            Some("2".to_string()),
+            Some("hello".to_string()),
+            Some("#<procedure hello>".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn emitted_procedures_take_arguments() {
+    let source = format!(
+        "(define chunk (make-chunk))\n\
+         (chunk-emit! chunk {} 1)\n\
+         (chunk-emit! chunk 1)\n\
+         (chunk-emit! chunk {})\n\
+         (define identity (make-procedure-from-chunk chunk 1))\n\
+         (define result (identity 42))\n",
+        u8::from(OpCode::GetLocal),
+        u8::from(OpCode::Return),
+    );
+    let (error, values) = run("chunks-argument", &source, &["result"]);
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("42".to_string())]);
+}
+
+#[test]
+fn only_bytes_can_be_emitted() {
+    let (error, _) = run("chunks-bytes", "(chunk-emit! (make-chunk) 256)\n", &[]);
+    assert!(error
+        .unwrap()
+        .contains("256 is not an integer between 0 and 255"));
+}
+
+#[test]
+fn malformed_chunks_are_rejected() {
+    let constant = u8::from(OpCode::Constant);
+    let get_local = u8::from(OpCode::GetLocal);
+    let get_upvalue = u8::from(OpCode::GetUpvalue);
+    let jump = u8::from(OpCode::Jump);
+    let pop = u8::from(OpCode::Pop);
+    let ret = u8::from(OpCode::Return);
+    let cases = [
+        (vec![250], "has unknown opcode 250"),
+        (vec![constant, 3, ret], "has no constant 3"),
+        (vec![constant], "is cut off by the end of the code"),
+        (vec![get_local, 2, ret], "uses local 2 but there are only 2"),
+        (
+            vec![get_upvalue, 0, ret],
+            "uses upvalue 0 but there are only 0",
+        ),
+        (
+            vec![jump, 0, 1, constant, 0, ret],
+            "is jumped into the middle of",
+        ),
+        (vec![jump, 0, 9], "is past the end of the code"),
+        (
+            vec![pop, pop, ret],
+            "needs 1 values on the stack but there are only 0",
+        ),
+    ];
+
+    for (code, problem) in cases.iter() {
+        let emits: Vec<_> = code
+            .iter()
+            .map(|byte| format!("(chunk-emit! chunk {} 1)\n", byte))
+            .collect();
+        let source = format!(
+            "(define chunk (make-chunk))\n\
+             (chunk-add-constant! chunk 'hello)\n\
+             {}\
+             (make-procedure-from-chunk chunk 1)\n",
+            emits.concat()
+        );
+        let (error, _) = run("chunks-malformed", &source, &[]);
+        let error = error.unwrap();
+        assert!(error.contains("Invalid bytecode"), "{}", error);
+        assert!(error.contains(problem), "{}", error);
+    }
+}
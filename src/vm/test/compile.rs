@@ -0,0 +1,184 @@
+use super::run;
+
+#[test]
+fn options_return_metadata_with_the_procedure() {
+    let (error, values) = run(
+        "compile-options",
+        "(define compiled\n\
+           (compile '(+ 1 2) '((optimization-level . 1) (emit-debug-info . #f))))\n\
+         (define result ((car compiled)))\n\
+         (define level (cdr (assq 'optimization-level (cdr compiled))))\n\
+         (define debug (cdr (assq 'emit-debug-info (cdr compiled))))\n\
+         (define plain ((compile '(* 2 3))))\n",
+        &["result", "level", "debug", "plain"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["3", "1", "#f", "6"]);
+}
+
+#[test]
+fn thunks_are_named_after_their_source() {
+    let (error, values) = run(
+        "compile-names",
+        "(define short (compile '(+ 1 2)))\n\
+         (define long (compile '(list 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16)))\n",
+        &["short", "long"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "#<procedure (+ 1 2)>",
+            "#<procedure (list 1 2 3 4 5 6 7 8 9 10 11 12 13 14 1...>"
+        ]
+    );
+}
+
+#[test]
+fn unknown_options_are_errors() {
+    let (error, _) = run("compile-unknown", "(compile 1 '((fast . #t)))\n", &[]);
+    let error = error.unwrap();
+    assert!(error.contains("Unknown compile option fast"), "{}", error);
+
+    let (error, _) = run(
+        "compile-environment",
+        "(compile 1 '((target-environment . sandbox)))\n",
+        &[],
+    );
+    let error = error.unwrap();
+    assert!(error.contains("environment sandbox"), "{}", error);
+}
+
+#[test]
+fn included_files_are_compiled_in_place() {
+    let directory = std::env::temp_dir().join(format!("cheshire-{}-include", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(
+        directory.join("outer.scm"),
+        "(define (double x) (* x 2))\n(include \"inner.scm\")\n",
+    )
+    .unwrap();
+    std::fs::write(directory.join("inner.scm"), "(define four (double 2))\n").unwrap();
+    std::fs::write(
+        directory.join("shouting.scm"),
+        "(DEFINE (Triple X) (* X 3))\n",
+    )
+    .unwrap();
+
+    let include = |file: &str| directory.join(file).to_string_lossy().into_owned();
+    let (error, values) = run(
+        "include",
+        &format!(
+            "(include {:?})\n\
+             (include-ci {:?})\n\
+             (define nine (triple 3))\n\
+             (define (body) (include {:?}) four)\n\
+             (define also-four (body))\n",
+            include("outer.scm"),
+            include("shouting.scm"),
+            include("inner.scm"),
+        ),
+        &["four", "nine", "also-four"],
+    );
+
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        [
+            Some("4".to_string()),
+            Some("9".to_string()),
+            Some("4".to_string())
+        ]
+    );
+}
+
+#[test]
+fn macroexpand_expands_derived_forms() {
+    let (error, values) = run(
+        "macroexpand",
+        "(define call (macroexpand '(f x)))\n\
+         (define expansion (macroexpand-1 '(assert (> 1 2))))\n\
+         (define head (car (car expansion)))\n\
+         (define checked (car (cdr (car (cdr (cdr (car expansion)))))))\n\
+         (define simple (car (macroexpand '(assert #t))))\n",
+        &["call", "head", "checked", "simple"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values[0], "(f x)");
+    assert_eq!(values[1], "lambda");
+    assert!(values[2].starts_with("(>"), "{}", values[2]);
+    assert_eq!(values[3], "if");
+}
+
+#[test]
+fn repeated_constants_share_a_slot() {
+    let (error, values) = run(
+        "compile-constants",
+        "(define x 1)\n\
+         (define compiled\n\
+           (compile '(if x (cons 1 \"a\") (cons 1.0 (cons -0.0 (cons 0.0 \"a\"))))\n\
+                    '((emit-debug-info . #t))))\n\
+         (define constants (cdr (assq 'constants (cdr compiled))))\n\
+         (define result ((car compiled)))\n",
+        &["constants", "result"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["6", "(1 . \"a\")"]);
+}
+
+#[test]
+fn large_functions_use_long_operands() {
+    let params: Vec<_> = (0..200).map(|i| format!("p{}", i)).collect();
+    let args: Vec<_> = (0..200).map(|i| i.to_string()).collect();
+    let defines: String = (0..100)
+        .map(|i| format!("(define v{} {})\n", i, i))
+        .collect();
+    let source = format!(
+        "(define (big {})\n\
+           {}\
+           (set! v99 (+ p199 v99))\n\
+           (lambda () (set! v98 1) (+ v98 v99)))\n\
+         (define sum ((big {})))\n\
+         (define (numbers n acc) (if (= n 0) acc (numbers (- n 1) (cons n acc))))\n\
+         (define total ((compile (cons '+ (numbers 300 '())))))\n",
+        params.join(" "),
+        defines,
+        args.join(" ")
+    );
+    let (error, values) = run("compile-long-operands", &source, &["sum", "total"]);
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["299", "45150"]);
+}
+
+#[test]
+fn constant_tests_are_optimized_away() {
+    let (error, values) = run(
+        "compile-peephole",
+        "(define (size expr level)\n\
+           (let ((compiled (compile expr (cons (cons 'optimization-level level) '()))))\n\
+             (cons ((car compiled)) (cdr (assq 'code-size (cdr compiled))))))\n\
+         (define plain (size '(if #t 'yes 'no) 0))\n\
+         (define taken (size '(if #t 'yes 'no) 1))\n\
+         (define skipped (size '(if #f 'yes 'no) 1))\n\
+         (define nested (size '((lambda (x) (if x (if #f 1 x) 2)) 3) 1))\n",
+        &["plain", "taken", "skipped", "nested"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec!["(yes . 14)", "(yes . 3)", "(no . 3)", "(3 . 6)"]
+    );
+}
@@ -0,0 +1,105 @@
+use super::{load_with_config, read_globals, run_to_end};
+use crate::object::ObjWritePort;
+use crate::vm::{BuiltinGroup, InterpretError, VmConfig};
+
+#[test]
+fn output_goes_to_the_configured_port() {
+    let config = VmConfig::new()
+        .with_stack_capacity(16)
+        .with_output_port(ObjWritePort::string());
+    let mut arena = load_with_config(
+        "config-ports",
+        "(display \"hello\")\n(write 'there)\n",
+        config,
+    );
+
+    assert_eq!(run_to_end(&mut arena), None);
+    arena.mutate(|_, vm| {
+        let port = *vm.current_output_port().read();
+        let contents = port
+            .read()
+            .as_write_port()
+            .unwrap()
+            .contents()
+            .unwrap()
+            .to_vec();
+        assert_eq!(String::from_utf8(contents).unwrap(), "hellothere");
+    });
+}
+
+#[test]
+fn builtins_left_out_are_not_defined() {
+    let config = VmConfig::new().with_builtins(&[BuiltinGroup::Eval]);
+    let mut arena = load_with_config(
+        "config-builtins",
+        "(define pair (cons 1 2))\n\
+         (define compiled (procedure? compile))\n\
+         (define opened (open-input-file \"/etc/passwd\"))\n",
+        config,
+    );
+
+    let error = run_to_end(&mut arena).unwrap();
+    assert!(error.contains("open-input-file"), "{}", error);
+    assert_eq!(
+        read_globals(&mut arena, &["pair", "compiled", "opened"]),
+        vec![Some("(1 . 2)".to_string()), Some("#t".to_string()), None]
+    );
+}
+
+#[test]
+fn files_cannot_be_read_without_the_file_builtins() {
+    let directory = std::env::temp_dir().join(format!("cheshire-{}-no-files", std::process::id()));
+    std::fs::create_dir_all(directory.join("secrets")).unwrap();
+    std::fs::write(
+        directory.join("secrets/keys.sld"),
+        "(define-library (secrets keys) (export key) (begin (define key 42)))\n",
+    )
+    .unwrap();
+    std::fs::write(directory.join("key.scm"), "(define key 42)\n").unwrap();
+
+    let config = || VmConfig::new().with_builtins(&[BuiltinGroup::Eval]);
+    let included = format!("(include {:?})\n", directory.join("key.scm"));
+    let mut arena = load_with_config("config-include", &included, config());
+    let error = run_to_end(&mut arena).unwrap();
+    assert!(
+        error.contains("Can't include files without the file builtins"),
+        "{}",
+        error
+    );
+
+    let mut arena = load_with_config(
+        "config-import",
+        "(import (scheme base))\n(define base #t)\n(import (secrets keys))\n",
+        config(),
+    );
+    arena.mutate(|_, vm| vm.add_to_load_path(directory.clone()));
+    let error = run_to_end(&mut arena).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert!(
+        error.contains("Can't load library (secrets keys) without the file builtins"),
+        "{}",
+        error
+    );
+    assert_eq!(
+        read_globals(&mut arena, &["base", "key"]),
+        vec![Some("#t".to_string()), None]
+    );
+}
+
+#[test]
+fn limits_come_from_the_config() {
+    let source = "(define (forever n) (+ 1 (forever n)))\n(forever 0)\n";
+    let mut arena = load_with_config("config-depth", source, VmConfig::new().with_max_depth(50));
+    let error = run_to_end(&mut arena).unwrap();
+    assert!(
+        error.contains("Maximum recursion depth exceeded (50 frames)"),
+        "{}",
+        error
+    );
+
+    let mut arena = load_with_config("config-fuel", source, VmConfig::new().with_fuel(1_000));
+    assert_eq!(
+        run_to_end(&mut arena),
+        Some(InterpretError::FuelExhausted.to_string())
+    );
+}
@@ -0,0 +1,61 @@
+use core::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use super::{load, run_to_end};
+use crate::diagnostics::Diagnostics;
+
+/// Collects what's written to it where the test can still see it
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn diagnose(name: &str, source: &str) -> Vec<String> {
+    let buffer = Buffer::default();
+    let mut arena = load(name, source);
+    let diagnostics = Diagnostics::new(buffer.clone());
+    arena.mutate(|_, vm| vm.set_hooks(Some(Box::new(diagnostics))));
+    run_to_end(&mut arena);
+    arena.mutate(|mc, vm| vm.warn("careful \"now\"", mc));
+
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    output
+        .lines()
+        .filter(|line| !line.starts_with("(gc "))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn events_are_written_as_s_expressions() {
+    let events = diagnose("diagnostics-events", "(define x 1)\n\n(car x)\n");
+
+    let path = events[0]
+        .strip_prefix("(compile (file ")
+        .and_then(|rest| rest.strip_suffix(") (lines 1))"))
+        .unwrap()
+        .to_string();
+    assert!(path.ends_with("diagnostics-events.scm\""), "{}", path);
+    assert_eq!(
+        events[1..],
+        [
+            format!("(compile (file {}) (lines 3))", path),
+            format!(
+                "(error (message \"runtime error: 1 is not a pair\\n  in form 2 of {}) \
+                 (file {}) (form 2))",
+                &path[1..],
+                path
+            ),
+            "(warning (message \"careful \\\"now\\\"\"))".to_string(),
+        ]
+    );
+}
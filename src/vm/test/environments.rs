@@ -0,0 +1,77 @@
+use super::run;
+
+#[test]
+fn eval_uses_the_interaction_environment_by_default() {
+    let (error, values) = run(
+        "eval-default",
+        "(define x (eval '(+ 1 2)))\n\
+         (eval '(define y (* x 2)) (interaction-environment))\n\
+         (define same (eq? (interaction-environment) (interaction-environment)))\n",
+        &["x", "y", "same"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        [
+            Some("3".to_string()),
+            Some("6".to_string()),
+            Some("#t".to_string())
+        ]
+    );
+}
+
+#[test]
+fn environments_keep_their_definitions_apart() {
+    let (error, values) = run(
+        "eval-sandbox",
+        "(define sandbox (environment '(only (scheme base) +)))\n\
+         (eval '(define z (+ 1 2)) sandbox)\n\
+         (define get-z (eval '(lambda () z) sandbox))\n\
+         (define z-there (get-z))\n\
+         (define z-here (call-with-current-continuation (lambda (k)\n\
+           (with-exception-handler (lambda (e) (k 'undefined)) (lambda () z)))))\n\
+         (eval '(import (only (scheme base) car)) sandbox)\n\
+         (define first (eval '(car '(1 2)) sandbox))\n\
+         (define is-environment (environment? sandbox))\n",
+        &["z-there", "z-here", "first", "is-environment"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        [
+            Some("3".to_string()),
+            Some("undefined".to_string()),
+            Some("1".to_string()),
+            Some("#t".to_string())
+        ]
+    );
+}
+
+#[test]
+fn sandboxes_only_see_what_they_were_given() {
+    let (error, values) = run(
+        "eval-null",
+        "(define result (eval '(if #t 'special-forms-work 'no) (null-environment 5)))\n\
+         (eval '(cdr '(1 2)) (environment '(only (scheme base) car)))\n",
+        &["result"],
+    );
+
+    let error = error.unwrap();
+    assert!(error.contains("Undefined variable cdr"), "{}", error);
+    assert_eq!(values, [Some("special-forms-work".to_string())]);
+}
+
+#[test]
+fn report_environments_have_the_original_builtins() {
+    let (error, values) = run(
+        "eval-report",
+        "(define (car x) 'redefined)\n\
+         (define result (eval '(car '(1 2)) (scheme-report-environment 5)))\n",
+        &["result"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, [Some("1".to_string())]);
+}
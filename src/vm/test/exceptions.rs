@@ -0,0 +1,224 @@
+use super::{load, run, run_to_end};
+
+#[test]
+fn errors_are_passed_to_handlers() {
+    let (error, values) = run(
+        "error-handled",
+        "(define e (call-with-current-continuation (lambda (k)\n\
+           (with-exception-handler\n\
+             (lambda (x) (k x))\n\
+             (lambda () (error \"bad thing:\" 1 \"two\" 'three))))))\n",
+        &["e"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        [Some("#<error \"bad thing:\" 1 \"two\" three>".to_string())]
+    );
+}
+
+#[test]
+fn uncaught_errors_report_their_message_and_irritants() {
+    let (error, _) = run(
+        "error-uncaught",
+        "(error \"something went wrong with\" \"this\" (vector 1 2))\n",
+        &[],
+    );
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("uncaught exception: something went wrong with \"this\" #(1 2)"),
+        "{}",
+        error
+    );
+}
+
+/// Catches whatever `expr` raises, escaping from the handler so it doesn't return
+fn catching(expr: &str) -> String {
+    format!(
+        "(call-with-current-continuation (lambda (k)\n\
+           (with-exception-handler (lambda (x) (k x)) (lambda () {}))))",
+        expr
+    )
+}
+
+#[test]
+fn error_objects_can_be_taken_apart() {
+    let source = format!(
+        "(define e {})\n\
+         (define a (error-object? e))\n\
+         (define b (error-object-message e))\n\
+         (define c (error-object-irritants e))\n\
+         (define d (error-object? \"not an error\"))\n\
+         (define f (file-error? e))\n\
+         (define g (read-error? e))\n",
+        catching("(error \"message\" 1 'two)")
+    );
+    let (error, values) = run("error-object", &source, &["a", "b", "c", "d", "f", "g"]);
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, ["#t", "\"message\"", "(1 two)", "#f", "#f", "#f"]);
+}
+
+#[test]
+fn file_and_read_errors_are_told_apart() {
+    let missing = std::env::temp_dir().join(format!("cheshire-{}-missing", std::process::id()));
+    let missing = missing.to_string_lossy().replace('\\', "/");
+    let source = format!(
+        "(define file {})\n\
+         (define read {})\n\
+         (define a (file-error? file))\n\
+         (define b (read-error? file))\n\
+         (define c (file-error? read))\n\
+         (define d (read-error? read))\n\
+         (define e (error-object? read))\n",
+        catching(&format!("(open-input-file \"{}\")", missing)),
+        catching("(read (open-input-string \")\"))")
+    );
+    let (error, values) = run("file-read-errors", &source, &["a", "b", "c", "d", "e"]);
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, ["#t", "#f", "#f", "#t", "#t"]);
+}
+
+#[test]
+fn handlers_cant_return_from_non_continuable_raises() {
+    let (error, _) = run(
+        "raise-returned",
+        "(with-exception-handler (lambda (x) 0) (lambda () (raise 'oops)))\n",
+        &[],
+    );
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("non-continuable raise of 'oops'"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn runtime_errors_are_raised_as_error_objects() {
+    let source = format!(
+        "(define type {})\n\
+         (define unbound {})\n\
+         (define arity {})\n\
+         (define a (error-object? type))\n\
+         (define b (error-object-message unbound))\n\
+         (define c (error-object-message arity))\n\
+         (define d (file-error? type))\n",
+        catching("(car 5)"),
+        catching("undefined-variable"),
+        catching("((lambda (x) x) 1 2)")
+    );
+    let (error, values) = run("runtime-conditions", &source, &["a", "b", "c", "d"]);
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        [
+            "#t",
+            "\"Undefined variable undefined-variable\"",
+            "\"Expected 1 arguments but got 2\"",
+            "#f"
+        ]
+    );
+}
+
+#[test]
+fn runtime_errors_only_reach_the_host_when_unhandled() {
+    let (error, _) = run(
+        "runtime-reraised",
+        "(with-exception-handler\n\
+           (lambda (outer) (car 'outer))\n\
+           (lambda ()\n\
+             (with-exception-handler (lambda (inner) 0) (lambda () (vector-ref (vector) 1)))))\n",
+        &[],
+    );
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("runtime error: outer is not a pair"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn backtraces_name_the_procedures_that_were_running() {
+    let mut arena = load(
+        "backtrace",
+        "(define (inner x)\n\
+           (+ x 'a))\n\
+         (define (outer y)\n\
+           (let ((z (inner y)))\n\
+             z))\n\
+         (cons (outer 1) 1)\n",
+    );
+    let error = run_to_end(&mut arena).unwrap();
+    let backtrace = arena.mutate(|_, vm| vm.backtrace());
+
+    assert!(error.contains("'a' is not a number"), "{}", error);
+    assert_eq!(backtrace[0], "+");
+    assert!(
+        backtrace[1].starts_with("outer (") && backtrace[1].ends_with("backtrace.scm:4:10)"),
+        "{:?}",
+        backtrace
+    );
+    // The top level form is named after where it was read from
+    assert!(
+        backtrace[2].contains("backtrace.scm:6 (") && backtrace[2].ends_with("backtrace.scm:6:7)"),
+        "{:?}",
+        backtrace
+    );
+}
+
+#[test]
+fn compile_errors_say_where_the_form_was_read_from() {
+    let (error, _) = run(
+        "compile-error",
+        "(define x 1)\n\
+         (define (f)\n\
+         \x20 (let ((a)) a))\n",
+        &[],
+    );
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("[compile]: ") && error.contains("compile-error.scm:3:3)"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn failed_assertions_show_the_expression_and_its_arguments() {
+    let source = format!(
+        "(define x 3)\n\
+         (define (check arg0) (assert (< arg0 2 (+ arg0 1))))\n\
+         (define e {})\n\
+         (define a (error-object-message e))\n\
+         (define b (error-object-irritants e))\n\
+         (define c (error-object-message {}))\n\
+         (define d (begin (assert (= x 3)) 'passed))\n",
+        catching("(check x)"),
+        catching("(assert #f)")
+    );
+    let (error, values) = run("assert", &source, &["a", "b", "c", "d"]);
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        [
+            "\"Assertion failed: (< arg0 2 (+ arg0 1))\"",
+            "(3 2 4)",
+            "\"Assertion failed: #f\"",
+            "passed"
+        ]
+    );
+}
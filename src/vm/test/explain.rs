@@ -0,0 +1,26 @@
+use super::run_with;
+
+const PROGRAM: &str = "(define out (with-output-to-string (lambda () (+ 1 (* 2 3)))))\n";
+
+fn explained(name: &str, depth: usize) -> String {
+    let (error, values) = run_with(name, PROGRAM, |_, vm| vm.set_explain(Some(depth)), &["out"]);
+
+    assert_eq!(error, None);
+    values[0].clone().unwrap()
+}
+
+#[test]
+fn forms_are_printed_with_their_results() {
+    assert_eq!(
+        explained("explain-all", usize::MAX),
+        "\"  (+ 1 (* 2 3))\\n    (* 2 3)\\n    (* 2 3) => 6\\n  (+ 1 (* 2 3)) => 7\\n\""
+    );
+}
+
+#[test]
+fn deeper_forms_are_hidden() {
+    assert_eq!(
+        explained("explain-shallow", 2),
+        "\"  (+ 1 (* 2 3))\\n  (+ 1 (* 2 3)) => 7\\n\""
+    );
+}
@@ -0,0 +1,59 @@
+use super::{load, read_globals, run_to_end};
+use crate::vm::InterpretError;
+
+const PROGRAM: &str = "(define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1))))\n\
+                       (define total (count 1000 0))\n";
+
+#[test]
+fn running_out_of_fuel_stops_the_program() {
+    let mut arena = load("fuel-runaway", "(define (spin) (spin))\n(spin)\n");
+    arena.mutate(|_, vm| vm.set_fuel(Some(10_000)));
+
+    let error = loop {
+        let result = arena.mutate(|mc, vm| vm.interpret(mc));
+        if let Err(err) = result {
+            break err;
+        }
+    };
+    assert!(matches!(error, InterpretError::FuelExhausted), "{}", error);
+    arena.mutate(|_, vm| assert_eq!(vm.fuel(), Some(0)));
+}
+
+#[test]
+fn refueling_resumes_where_the_program_stopped() {
+    let mut arena = load("fuel-resume", PROGRAM);
+    let mut refills = 0;
+    arena.mutate(|_, vm| vm.set_fuel(Some(500)));
+    while let Some(error) = run_to_end(&mut arena) {
+        assert_eq!(error, InterpretError::FuelExhausted.to_string());
+        refills += 1;
+        arena.mutate(|_, vm| vm.set_fuel(Some(500)));
+    }
+
+    assert!(refills > 1);
+    assert_eq!(
+        read_globals(&mut arena, &["total"]),
+        vec![Some("1000".to_string())]
+    );
+}
+
+#[test]
+fn exception_handlers_cannot_catch_running_out_of_fuel() {
+    let mut arena = load(
+        "fuel-handlers",
+        "(define caught #f)\n\
+         (define (spin) (spin))\n\
+         (call-with-current-continuation\n\
+           (lambda (k) (with-exception-handler (lambda (x) (set! caught #t) (k x)) spin)))\n",
+    );
+    arena.mutate(|_, vm| vm.set_fuel(Some(1_000)));
+
+    assert_eq!(
+        run_to_end(&mut arena),
+        Some(InterpretError::FuelExhausted.to_string())
+    );
+    assert_eq!(
+        read_globals(&mut arena, &["caught"]),
+        vec![Some("#f".to_string())]
+    );
+}
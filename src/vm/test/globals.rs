@@ -0,0 +1,53 @@
+use super::run;
+
+#[test]
+fn code_sees_globals_defined_and_changed_after_it_first_ran() {
+    let (error, values) = run(
+        "globals-cached",
+        "(define x 1)\n\
+         (define (get-x) x)\n\
+         (define (bump!) (set! x (+ x 1)))\n\
+         (define first (get-x))\n\
+         (bump!)\n\
+         (define second (get-x))\n\
+         (define x 10)\n\
+         (define third (get-x))\n\
+         (define (get-y) y)\n\
+         (define y 'late)\n\
+         (define fourth (get-y))\n",
+        &["first", "second", "third", "fourth"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["1", "2", "10", "late"]);
+}
+
+#[test]
+fn setting_an_undefined_global_is_an_error() {
+    let (error, _) = run(
+        "globals-undefined",
+        "(define (f) (set! nowhere 1))\n(f)\n",
+        &[],
+    );
+    let error = error.unwrap();
+    assert!(error.contains("Undefined variable nowhere"), "{}", error);
+}
+
+#[test]
+fn globals_can_follow_hundreds_of_constants() {
+    let first: Vec<_> = (0..200).map(|i| format!("{}.5", i)).collect();
+    let second: Vec<_> = (200..300).map(|i| format!("{}.5", i)).collect();
+    let (first, second) = (first.join(" "), second.join(" "));
+    let source = format!(
+        "(define counter 1)\n\
+         (if (+ {} {}) (define late (+ (+ {}) counter)))\n\
+         (if (+ {} {}) (set! counter late))\n",
+        first, second, second, first, second
+    );
+    let (error, values) = run("globals-long", &source, &["late", "counter"]);
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["25001", "25001"]);
+}
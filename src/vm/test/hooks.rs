@@ -0,0 +1,99 @@
+use core::cell::RefCell;
+use std::env;
+use std::fs;
+use std::rc::Rc;
+
+use gc_arena::ArenaParameters;
+
+use crate::arena::{self, GcArena};
+use crate::value::Value;
+use crate::vm::{InterpretError, VirtualMachine, VmHooks};
+
+#[derive(Debug, Default)]
+struct Events {
+    defines: Vec<String>,
+    calls: Vec<(String, Vec<String>)>,
+    errors: Vec<String>,
+    collections: usize,
+}
+
+#[derive(Debug)]
+struct Recorder(Rc<RefCell<Events>>);
+
+impl VmHooks for Recorder {
+    fn on_define(&self, name: &str, _: Value<'_>) {
+        self.0.borrow_mut().defines.push(name.to_string());
+    }
+
+    fn on_call(&self, callee: Value<'_>, args: &[Value<'_>]) {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        self.0.borrow_mut().calls.push((callee.to_string(), args));
+    }
+
+    fn on_error(&self, error: &InterpretError) {
+        self.0.borrow_mut().errors.push(error.to_string());
+    }
+
+    fn on_gc(&self, _: usize) {
+        self.0.borrow_mut().collections += 1;
+    }
+}
+
+/// Runs a program with a recorder installed until it finishes or fails
+fn record(name: &str, source: &str) -> Events {
+    let path = env::temp_dir().join(format!("cheshire-{}-{}.scm", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    let path = path.to_string_lossy().into_owned();
+
+    let events = Rc::new(RefCell::new(Events::default()));
+    let mut arena = GcArena::new(ArenaParameters::default(), |mc| {
+        VirtualMachine::load_file(path, mc)
+    });
+    arena.mutate(|_, vm| vm.set_hooks(Some(Box::new(Recorder(events.clone())))));
+
+    loop {
+        let done = arena.mutate(|mc, vm| vm.interpret(mc).is_err() || vm.is_halted());
+        if done {
+            break;
+        }
+        arena::collect_debt(&mut arena);
+    }
+
+    drop(arena);
+    Rc::try_unwrap(events).unwrap().into_inner()
+}
+
+#[test]
+fn hooks_see_defines_and_calls() {
+    let events = record(
+        "hooks-calls",
+        "(define (add-one x) (+ x 1))\n(define y (add-one 41))\n",
+    );
+
+    assert_eq!(events.defines, vec!["add-one", "y"]);
+    assert!(events
+        .calls
+        .iter()
+        .any(|(callee, args)| callee.contains("add-one") && args == &["41"]));
+    assert!(events.calls.iter().any(|(_, args)| args == &["41", "1"]));
+    assert!(events.errors.is_empty());
+}
+
+#[test]
+fn hooks_see_errors() {
+    let events = record("hooks-errors", "(define x 1)\n(car x)\n");
+
+    assert_eq!(events.errors.len(), 1);
+    assert!(events.errors[0].contains("hooks-errors"));
+}
+
+#[test]
+fn hooks_see_collections() {
+    let events = record(
+        "hooks-gc",
+        "(define (churn n) (if (= n 0) 0 (begin (make-vector 100 0) (churn (- n 1)))))\n\
+         (churn 5000)\n",
+    );
+
+    assert!(events.collections > 0);
+}
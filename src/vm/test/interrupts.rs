@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::{load, read_globals, run_to_end};
+use crate::vm::InterpretError;
+
+#[test]
+fn setting_the_flag_interrupts_the_program() {
+    static FLAG: AtomicBool = AtomicBool::new(false);
+
+    let mut arena = load(
+        "interrupts-spin",
+        "(define caught #f)\n\
+         (define (spin) (spin))\n\
+         (call-with-current-continuation\n\
+           (lambda (k) (with-exception-handler (lambda (x) (set! caught #t) (k x)) spin)))\n",
+    );
+    arena.mutate(|_, vm| vm.set_interrupt_flag(Some(&FLAG)));
+    for _ in 0..100 {
+        arena.mutate(|mc, vm| vm.interpret(mc)).unwrap();
+    }
+    FLAG.store(true, Ordering::Relaxed);
+
+    assert_eq!(
+        run_to_end(&mut arena),
+        Some(InterpretError::Interrupted.to_string())
+    );
+    assert!(!FLAG.load(Ordering::Relaxed));
+    assert_eq!(
+        read_globals(&mut arena, &["caught"]),
+        vec![Some("#f".to_string())]
+    );
+}
+
+#[test]
+fn interrupted_programs_carry_on_when_interpreted_again() {
+    static FLAG: AtomicBool = AtomicBool::new(true);
+
+    let mut arena = load(
+        "interrupts-resume",
+        "(define (count n acc) (if (= n 0) acc (count (- n 1) (+ acc 1))))\n\
+         (define total (count 1000 0))\n",
+    );
+    arena.mutate(|_, vm| vm.set_interrupt_flag(Some(&FLAG)));
+
+    assert_eq!(
+        run_to_end(&mut arena),
+        Some(InterpretError::Interrupted.to_string())
+    );
+    assert_eq!(run_to_end(&mut arena), None);
+    assert_eq!(
+        read_globals(&mut arena, &["total"]),
+        vec![Some("1000".to_string())]
+    );
+}
@@ -0,0 +1,109 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use gc_arena::ArenaParameters;
+
+use crate::arena::GcArena;
+use crate::vm::VirtualMachine;
+
+fn write_program(name: &str, source: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("cheshire-{}-{}.scm", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn start(path: &Path) -> GcArena {
+    let path = path.to_string_lossy().into_owned();
+    GcArena::new(ArenaParameters::default(), |mc| {
+        VirtualMachine::load_file(path, mc)
+    })
+}
+
+/// Runs a single step, returning whether the program has finished
+fn step(arena: &mut GcArena) -> bool {
+    let halted = arena.mutate(|mc, vm| {
+        if let Err(err) = vm.interpret(mc) {
+            panic!("{}", err);
+        }
+        vm.is_halted()
+    });
+    arena.collect_debt();
+    halted
+}
+
+/// Steps both arenas alternately until they've both finished
+fn run_interleaved(first: &mut GcArena, second: &mut GcArena) {
+    let (mut first_done, mut second_done) = (false, false);
+    while !(first_done && second_done) {
+        if !first_done {
+            first_done = step(first);
+        }
+        if !second_done {
+            second_done = step(second);
+        }
+    }
+}
+
+fn global(arena: &mut GcArena, name: &str) -> Option<String> {
+    arena.mutate(|mc, vm| vm.global(name, mc).map(|value| value.to_string()))
+}
+
+#[test]
+fn random_state_is_per_vm() {
+    let reseeding = write_program(
+        "reseeding",
+        "(random-seed! 7)\n(define x (random 1000000))\n(define y (random 1000000))\n",
+    );
+    let default = write_program(
+        "default-seed",
+        "(define x (random 1000000))\n(define y (random 1000000))\n",
+    );
+
+    let mut first = start(&reseeding);
+    let mut second = start(&default);
+    run_interleaved(&mut first, &mut second);
+
+    let mut alone = start(&default);
+    while !step(&mut alone) {}
+
+    assert_eq!(global(&mut second, "x"), global(&mut alone, "x"));
+    assert_eq!(global(&mut second, "y"), global(&mut alone, "y"));
+    assert_ne!(global(&mut first, "x"), global(&mut second, "x"));
+}
+
+#[test]
+fn load_once_table_is_per_vm() {
+    let library = write_program("library", "(define loads (+ loads 1))\n");
+    let main = write_program(
+        "load-once",
+        &format!(
+            "(define loads 0)\n(load-once {:?})\n(load-once {:?})\n",
+            library, library
+        ),
+    );
+
+    let mut first = start(&main);
+    let mut second = start(&main);
+    run_interleaved(&mut first, &mut second);
+
+    assert_eq!(global(&mut first, "loads").as_deref(), Some("1"));
+    assert_eq!(global(&mut second, "loads").as_deref(), Some("1"));
+}
+
+#[test]
+fn globals_and_features_are_per_vm() {
+    let providing = write_program(
+        "providing",
+        "(provide 'isolated-feature)\n(define only-here #t)\n",
+    );
+    let checking = write_program("checking", "(define seen (provided? 'isolated-feature))\n");
+
+    let mut first = start(&providing);
+    let mut second = start(&checking);
+    run_interleaved(&mut first, &mut second);
+
+    assert_eq!(global(&mut first, "only-here").as_deref(), Some("#t"));
+    assert_eq!(global(&mut second, "only-here"), None);
+    assert_eq!(global(&mut second, "seen").as_deref(), Some("#f"));
+}
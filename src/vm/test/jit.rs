@@ -0,0 +1,93 @@
+use gc_arena::MutationContext;
+
+use super::{load, read_globals, run, run_to_end, run_with};
+use crate::chunk::{Chunk, OpCode};
+use crate::compiler::Upvalues;
+use crate::memory::Token;
+use crate::object::{ObjFunction, Object};
+use crate::value::Value;
+use crate::vm::VirtualMachine;
+
+#[test]
+fn hot_procedures_are_compiled_and_give_the_same_results() {
+    let mut arena = load(
+        "jit-compiled",
+        "(define total 0)\n\
+         (define (make-adder k) (lambda (x) (set! total (+ total k)) (+ x k)))\n\
+         (define add (make-adder 2))\n\
+         (define (walk items n acc)\n\
+           (if (= n 0)\n\
+               (cons (car acc) (cdr items))\n\
+               (if (null? items) 'empty (walk items (- n 1) (cons (add n) acc)))))\n\
+         (define walked (walk '(1 2 3) 3000 '()))\n\
+         (define (loop n) (if (pair? n) 'pair (if (= n 0) total (loop (- n 1)))))\n\
+         (define looped (loop 5000))\n",
+    );
+
+    assert_eq!(run_to_end(&mut arena), None);
+    assert_eq!(
+        read_globals(&mut arena, &["walked", "looped"]),
+        vec![Some("(3 2 3)".to_string()), Some("6000".to_string())]
+    );
+    arena.mutate(|mc, vm| {
+        for name in ["walk", "loop", "add"] {
+            let procedure = vm.global(name, mc).unwrap();
+            let procedure = procedure.as_object().unwrap();
+            let procedure = procedure.read();
+            let function = match procedure.as_closure() {
+                Ok(closure) => closure.function().clone(),
+                Err(_) => procedure.as_function().unwrap().clone(),
+            };
+            assert!(function.chunk().tier().is_compiled(), "{}", name);
+        }
+    });
+}
+
+#[test]
+fn compiled_code_raises_the_same_errors() {
+    let (error, values) = run(
+        "jit-errors",
+        "(define (count n) (if (= n 0) missing (count (- n 1))))\n\
+         (define caught\n\
+           (call-with-current-continuation\n\
+             (lambda (k) (with-exception-handler (lambda (x) (k x)) (lambda () (count 3000))))))\n\
+         (define (walk n) (if (= n 0) (car n) (walk (- n 1))))\n\
+         (walk 3000)\n",
+        &["caught"],
+    );
+
+    assert!(error.unwrap().contains("0 is not a pair"));
+    assert!(values[0]
+        .as_ref()
+        .unwrap()
+        .contains("Undefined variable missing"));
+}
+
+/// Defines `broken`, a procedure whose bytecode reads an upvalue it doesn't have, the way a
+/// corrupted compiled file might
+fn define_broken<'gc>(mc: MutationContext<'gc, '_>, vm: &VirtualMachine<'gc>) {
+    let mut chunk = Chunk::new();
+    chunk.write_operand(OpCode::GetUpvalue, 0, 1);
+    chunk.write(OpCode::Return.into(), 1);
+    let name = vm.intern_symbol(Token::new(mc, "broken".into()), mc);
+    let function = ObjFunction::new(mc, 0, false, chunk, Upvalues::default(), Some(name));
+    vm.define_global(name, Value::boxed(mc, Object::Function(function)), mc);
+}
+
+#[test]
+fn malformed_bytecode_raises_an_error_once_compiled() {
+    let (error, values) = run_with(
+        "jit-malformed",
+        "(define (try)\n\
+           (call-with-current-continuation\n\
+             (lambda (k)\n\
+               (with-exception-handler (lambda (x) (k (error-object-message x))) broken))))\n\
+         (define (loop n last) (if (= n 0) last (loop (- n 1) (try))))\n\
+         (define message (loop 1500 #f))\n",
+        define_broken,
+        &["message"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("\"There is no upvalue 0\"".to_string())]);
+}
@@ -0,0 +1,114 @@
+use super::{run, run_with};
+
+#[test]
+fn libraries_export_what_they_define() {
+    let (error, values) = run(
+        "library-exports",
+        "(define-library (shapes square)\n\
+           (export square (rename area square-area))\n\
+           (import (scheme base))\n\
+           (begin\n\
+             (define (square x) (* x x))\n\
+             (define (area side) (square side))))\n\
+         (import (shapes square))\n\
+         (define a (square 3))\n\
+         (define b (square-area 4))\n",
+        &["a", "b"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, [Some("9".to_string()), Some("16".to_string())]);
+}
+
+#[test]
+fn libraries_keep_their_globals_to_themselves() {
+    let (error, values) = run(
+        "library-globals",
+        "(define counter 'program)\n\
+         (define-library (counter)\n\
+           (export next!)\n\
+           (import (only (scheme base) +))\n\
+           (begin\n\
+             (define counter 0)\n\
+             (define (next!) (set! counter (+ counter 1)) counter)))\n\
+         (import (prefix (counter) counter:))\n\
+         (counter:next!)\n\
+         (define n (counter:next!))\n\
+         (define hidden (call-with-current-continuation (lambda (k)\n\
+           (with-exception-handler (lambda (e) (k 'undefined)) (lambda () next!)))))\n",
+        &["counter", "n", "hidden"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        [
+            Some("program".to_string()),
+            Some("2".to_string()),
+            Some("undefined".to_string())
+        ]
+    );
+}
+
+#[test]
+fn library_bodies_only_see_what_they_import() {
+    let (error, _) = run(
+        "library-imports",
+        "(define-library (lonely)\n\
+           (export f)\n\
+           (import (only (scheme base) car))\n\
+           (begin (define (f) (cdr '(1 2)))))\n\
+         (import (lonely))\n\
+         (f)\n",
+        &[],
+    );
+
+    let error = error.unwrap();
+    assert!(error.contains("Undefined variable cdr"), "{}", error);
+}
+
+#[test]
+fn importing_an_unknown_library_fails() {
+    let (error, _) = run("library-unknown", "(import (no such library))\n", &[]);
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("Unknown library (no such library)"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn imported_libraries_are_loaded_from_the_load_path() {
+    let directory = std::env::temp_dir().join(format!("cheshire-{}-load-path", std::process::id()));
+    std::fs::create_dir_all(directory.join("greetings")).unwrap();
+    std::fs::write(
+        directory.join("greetings/english.sld"),
+        "(define-library (greetings english)\n\
+           (export hello)\n\
+           (import (scheme base))\n\
+           (begin (define (hello name) (vector (quote hello) name))))\n",
+    )
+    .unwrap();
+    std::fs::write(directory.join("helper.scm"), "(define helped #t)\n").unwrap();
+
+    let (error, values) = run_with(
+        "library-load-path",
+        "(import (greetings english))\n\
+         (define greeting (hello \"world\"))\n\
+         (load \"helper.scm\")\n",
+        |_, vm| vm.add_to_load_path(directory.clone()),
+        &["greeting", "helped"],
+    );
+
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        [
+            Some("#(hello \"world\")".to_string()),
+            Some("#t".to_string())
+        ]
+    );
+}
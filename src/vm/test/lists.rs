@@ -0,0 +1,111 @@
+use super::run;
+
+#[test]
+fn append_splices_lists_together() {
+    let (error, values) = run(
+        "append-mut",
+        "(define a (cons 1 (cons 2 (quote ()))))\n\
+         (define b (cons 3 (quote ())))\n\
+         (define joined (append! a '() b 4))\n\
+         (define shared (eq? joined a))\n\
+         (define empty (append!))\n\
+         (define last (append! '() '() 5))\n",
+        &["joined", "a", "shared", "empty", "last"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["(1 2 3 . 4)", "(1 2 3 . 4)", "#t", "()", "5"]);
+}
+
+#[test]
+fn reverse_turns_the_pairs_around() {
+    let (error, values) = run(
+        "reverse-mut",
+        "(define a (cons 1 (cons 2 (cons 3 (quote ())))))\n\
+         (define r (reverse! a))\n\
+         (define empty (reverse! '()))\n",
+        &["r", "a", "empty"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["(3 2 1)", "(1)", "()"]);
+}
+
+#[test]
+fn only_lists_can_be_spliced() {
+    let (error, _) = run("append-mut-atom", "(append! 5 '(3))\n", &[]);
+
+    let error = error.unwrap();
+    assert!(error.contains("5 is not a mutable pair"), "{}", error);
+}
+
+#[test]
+fn list_primitives_run_inline() {
+    let (error, values) = run(
+        "list-primitives",
+        "(define (second l) (car (cdr l)))\n\
+         (define (walk l) (if (null? l) 'done (walk (cdr l))))\n\
+         (define got (second (cons 1 (cons 2 '()))))\n\
+         (define walked (walk '(1 2 3)))\n\
+         (define pairs (cons (pair? '(1)) (cons (pair? '()) (null? '()))))\n\
+         (define (shadowed car) (car 1))\n\
+         (define local (shadowed (lambda (x) (+ x 1))))\n",
+        &["got", "walked", "pairs", "local"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["2", "done", "(#t #f . #t)", "2"]);
+}
+
+#[test]
+fn redefined_list_primitives_are_called() {
+    let (error, values) = run(
+        "list-primitives-redefined",
+        "(define (first l) (car l))\n\
+         (define before (first '(1 2)))\n\
+         (define (car n) (if (= n 0) 'bottom (car (- n 1))))\n\
+         (define after (first 100000))\n",
+        &["before", "after"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["1", "bottom"]);
+}
+
+#[test]
+fn list_primitives_still_check_their_arguments() {
+    let (error, _) = run(
+        "list-primitives-atom",
+        "(define (f x) (cdr x))\n(f 5)\n",
+        &[],
+    );
+
+    let error = error.unwrap();
+    assert!(error.contains("5 is not a pair"), "{}", error);
+}
+
+#[test]
+fn circular_lists_cant_be_folded_or_sorted() {
+    let cases = [
+        ("(fold-right cons '() b)\n", "is a circular list"),
+        ("(fold-right cons '() '(1 2) a)\n", "is a circular list"),
+        ("(sort a <)\n", "is not a list or vector"),
+    ];
+    for (i, (source, message)) in cases.iter().enumerate() {
+        let source = format!(
+            "(define a (cons 1 (cons 2 '())))\n\
+             (set-cdr! (cdr a) a)\n\
+             (define b (cons 1 '()))\n\
+             (set-cdr! b b)\n\
+             {}",
+            source
+        );
+        let (error, _) = run(&format!("lists-circular-{}", i), &source, &[]);
+        let error = error.unwrap();
+        assert!(error.contains(message), "{}", error);
+    }
+}
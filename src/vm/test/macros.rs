@@ -0,0 +1,92 @@
+use super::run;
+
+/// Defines `list`, which there's no builtin for, and a few macros
+const MACROS: &str = "(define (list . elements) elements)\n\
+     (define-syntax swap!\n\
+       (er-macro-transformer\n\
+         (lambda (form rename compare)\n\
+           (let ((a (car (cdr form))) (b (car (cdr (cdr form)))))\n\
+             (list (rename 'let) (list (list (rename 'tmp) a))\n\
+                   (list (rename 'set!) a b)\n\
+                   (list (rename 'set!) b (rename 'tmp)))))))\n\
+     (define-syntax inc!\n\
+       (er-macro-transformer\n\
+         (lambda (form rename compare)\n\
+           (let ((x (car (cdr form))))\n\
+             (list (rename 'set!) x (list (rename '+) x 1))))))\n\
+     (define-syntax else?\n\
+       (er-macro-transformer\n\
+         (lambda (form rename compare)\n\
+           (compare (car (cdr form)) (rename 'else)))))\n";
+
+#[test]
+fn macro_uses_are_expanded_before_compiling() {
+    let (error, values) = run(
+        "er-macro-transformer",
+        &format!(
+            "{}\
+             (define tmp 1)\n\
+             (define other 2)\n\
+             (swap! tmp other)\n\
+             (define swapped (list tmp other))\n\
+             (define n 0)\n\
+             (define (bump) (inc! n) (if (else? else) (inc! n) #f) n)\n\
+             (define bumped (bump))\n\
+             (define shadowed ((lambda (inc!) (inc! 10)) (lambda (x) (* x 2))))\n\
+             (define evaluated (eval '(else? something)))\n",
+            MACROS
+        ),
+        &["swapped", "bumped", "shadowed", "evaluated"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["(2 1)", "2", "20", "#f"]);
+}
+
+#[test]
+fn renamed_identifiers_are_hygienic() {
+    let (error, values) = run(
+        "macro-hygiene",
+        &format!(
+            "{}\
+             (define n 1)\n\
+             (define (sneaky) (let ((+ -)) (inc! n)) n)\n\
+             (define result (sneaky))\n",
+            MACROS
+        ),
+        &["result"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, [Some("2".to_string())]);
+}
+
+#[test]
+fn macroexpand_runs_transformers() {
+    let (error, values) = run(
+        "macroexpand-macros",
+        &format!(
+            "{}\
+             (define once (macroexpand-1 '(inc! n)))\n\
+             (define fully (macroexpand '(else? else)))\n",
+            MACROS
+        ),
+        &["once", "fully"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["(set! n (+ n 1))", "#t"]);
+}
+
+#[test]
+fn define_syntax_is_only_allowed_at_the_top_level() {
+    let (error, _) = run(
+        "define-syntax-nested",
+        "(define (f) (define-syntax g (er-macro-transformer car)))\n",
+        &[],
+    );
+    let error = error.unwrap();
+    assert!(error.contains("only allowed at the top level"), "{}", error);
+}
@@ -0,0 +1,106 @@
+use std::env;
+use std::fs;
+
+use gc_arena::{ArenaParameters, MutationContext};
+
+use crate::arena::{self, GcArena};
+use crate::vm::{VirtualMachine, VmConfig};
+
+mod aliasing;
+mod boxes;
+mod bytevectors;
+mod characters;
+#[cfg(feature = "self-hosting")]
+mod chunks;
+mod compile;
+mod config;
+mod diagnostics;
+mod environments;
+mod exceptions;
+mod explain;
+mod fuel;
+mod globals;
+mod hooks;
+mod interrupts;
+mod isolation;
+#[cfg(feature = "jit")]
+mod jit;
+mod libraries;
+mod lists;
+mod macros;
+mod ports;
+mod predicates;
+mod printer;
+mod profile;
+mod prompts;
+mod records;
+#[cfg(feature = "regex")]
+mod regexps;
+mod serialize;
+mod stack;
+mod symbols;
+mod threads;
+mod time_travel;
+mod vectors;
+mod warnings;
+
+/// Runs a program until it finishes, returning the error it failed with (if any) and the printed
+/// values of the given globals
+fn run(name: &str, source: &str, globals: &[&str]) -> (Option<String>, Vec<Option<String>>) {
+    run_with(name, source, |_, _| {}, globals)
+}
+
+/// Like `run`, but lets `setup` prepare the VM (e.g. by defining globals) before it starts
+fn run_with<F>(
+    name: &str,
+    source: &str,
+    setup: F,
+    globals: &[&str],
+) -> (Option<String>, Vec<Option<String>>)
+where
+    F: for<'gc> FnOnce(MutationContext<'gc, '_>, &VirtualMachine<'gc>),
+{
+    let mut arena = load(name, source);
+    arena.mutate(|mc, vm| setup(mc, vm));
+    let error = run_to_end(&mut arena);
+    (error, read_globals(&mut arena, globals))
+}
+
+/// Writes a program to a temporary file and makes a VM that's ready to run it
+fn load(name: &str, source: &str) -> GcArena {
+    load_with_config(name, source, VmConfig::default())
+}
+
+/// Like `load`, but makes the VM with the settings in `config`
+fn load_with_config(name: &str, source: &str, config: VmConfig) -> GcArena {
+    let path = env::temp_dir().join(format!("cheshire-{}-{}.scm", std::process::id(), name));
+    fs::write(&path, source).unwrap();
+    let path = path.to_string_lossy().into_owned();
+
+    GcArena::new(ArenaParameters::default(), |mc| {
+        VirtualMachine::load_file_with_config(path, config, mc)
+    })
+}
+
+/// Runs the VM until it halts, returning the error it stopped with instead (if any)
+fn run_to_end(arena: &mut GcArena) -> Option<String> {
+    loop {
+        let result = arena.mutate(|mc, vm| match vm.interpret(mc) {
+            Ok(_) if vm.is_halted() => Some(None),
+            Ok(_) => None,
+            Err(err) => Some(Some(err.to_string())),
+        });
+        if let Some(error) = result {
+            return error;
+        }
+        arena::collect_debt(arena);
+    }
+}
+
+/// Prints the values of the given globals
+fn read_globals(arena: &mut GcArena, globals: &[&str]) -> Vec<Option<String>> {
+    globals
+        .iter()
+        .map(|name| arena.mutate(|mc, vm| vm.global(name, mc).map(|value| value.to_string())))
+        .collect()
+}
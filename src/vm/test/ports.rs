@@ -0,0 +1,509 @@
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::rc::Rc;
+
+use gc_arena::GcCell;
+
+use super::{load, run, run_to_end, run_with};
+use crate::object::{ObjReadPort, ObjWritePort, Object, PortBackend};
+
+#[test]
+fn lines_are_read_without_their_endings() {
+    let (error, values) = run(
+        "read-line",
+        "(define p (open-input-string \"first\\r\\nsecond\\n\\nlast\"))\n\
+         (define a (read-line p))\n\
+         (define b (read-line p))\n\
+         (define c (read-line p))\n\
+         (define d (read-line p))\n\
+         (define e (eof-object? (read-line p)))\n",
+        &["a", "b", "c", "d", "e"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec!["\"first\"", "\"second\"", "\"\"", "\"last\"", "#t"]
+    );
+}
+
+#[test]
+fn strings_are_read_by_character_count() {
+    let (error, values) = run(
+        "read-string",
+        "(define p (open-input-string \"aλbcd\"))\n\
+         (define a (read-string 2 p))\n\
+         (define none (read-string 0 p))\n\
+         (define b (read-string 10 p))\n\
+         (define c (eof-object? (read-string 1 p)))\n",
+        &["a", "none", "b", "c"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["\"aλ\"", "\"\"", "\"bcd\"", "#t"]);
+}
+
+#[test]
+fn reads_span_buffer_boundaries() {
+    // Put a two byte character across the end of the port's first buffer
+    let mut input = "a".repeat(8191);
+    input.push_str("λ\nrest");
+    let mut port = ObjReadPort::string(input.as_bytes());
+
+    let line = port.read_line().unwrap().unwrap();
+    assert_eq!(line.chars().count(), 8192);
+    assert!(line.ends_with('λ'));
+    assert_eq!(port.read_string(10).unwrap().as_deref(), Some("rest"));
+
+    let mut port = ObjReadPort::string(input.as_bytes());
+    let string = port.read_string(8192).unwrap().unwrap();
+    assert!(string.ends_with('λ'));
+    assert_eq!(port.read_char().unwrap(), Some('\n'));
+}
+
+#[test]
+fn strings_read_at_runtime_are_not_interned() {
+    let mut arena = load(
+        "read-interning",
+        "(define p (open-input-string \"(\\\"read\\\" . \\\"data\\\")\"))\n\
+         (define datum (read p))\n\
+         (define literal \"literal\")\n",
+    );
+    assert_eq!(run_to_end(&mut arena), None);
+
+    arena.mutate(|_, vm| {
+        let strings = vm.tables().strings();
+        let strings = strings.read();
+        assert!(strings.contains(&"literal".into()));
+        assert!(!strings.contains(&"read".into()));
+        assert!(!strings.contains(&"data".into()));
+    });
+}
+
+#[test]
+fn values_are_written_and_displayed() {
+    let (error, values) = run(
+        "write-display",
+        "(define p (open-output-string))\n\
+         (write \"a\\\"b\" p)\n\
+         (write-char #\\space p)\n\
+         (display \"a\\\"b\" p)\n\
+         (newline p)\n\
+         (write (cons #\\x (cons 'y '())) p)\n\
+         (display (cons #\\x (cons \"z\" '())) p)\n\
+         (write-string \"hello\" p 1 3)\n\
+         (define written (get-output-string p))\n\
+         (define current\n\
+           (with-output-to-string\n\
+             (lambda () (display 1) (newline) (write-string \"λx\"))))\n",
+        &["written", "current"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "\"\\\"a\\\\\\\"b\\\" a\\\"b\\n(#\\\\x y)(x z)el\"",
+            "\"1\\nλx\""
+        ]
+    );
+}
+
+#[test]
+fn the_error_port_is_separate_from_the_output_port() {
+    let (error, values) = run(
+        "current-error-port",
+        "(define same (eq? (current-error-port) (current-error-port)))\n\
+         (define output (output-port? (current-error-port)))\n\
+         (define distinct (eq? (current-error-port) (current-output-port)))\n\
+         (define redirected\n\
+           (with-output-to-string\n\
+             (lambda () (display (eq? (current-error-port) (current-output-port))))))\n",
+        &["same", "output", "distinct", "redirected"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["#t", "#t", "#f", "\"#f\""]);
+}
+
+#[test]
+fn warnings_go_to_the_current_error_port() {
+    let mut arena = load("error-port-warning", "(define x 1)\n");
+    run_to_end(&mut arena);
+    let output = arena.mutate(|mc, vm| {
+        let port = GcCell::allocate(mc, Object::WritePort(ObjWritePort::string()));
+        *vm.current_error_port().write(mc) = port;
+        vm.warn("look out", mc);
+        let contents = port
+            .read()
+            .as_write_port()
+            .unwrap()
+            .contents()
+            .unwrap()
+            .to_vec();
+        String::from_utf8(contents).unwrap()
+    });
+
+    assert_eq!(output, "warning: look out\n");
+}
+
+#[test]
+fn binary_ports_read_and_write_bytes() {
+    let (error, values) = run(
+        "binary-ports",
+        "(define in (open-input-bytevector (bytevector 1 2 255)))\n\
+         (define a (read-u8 in))\n\
+         (define b (peek-u8 in))\n\
+         (define rest (read-bytevector 5 in))\n\
+         (define end (eof-object? (read-u8 in)))\n\
+         (define out (open-output-bytevector))\n\
+         (write-u8 7 out)\n\
+         (write-bytevector (bytevector 1 2 3) out 1)\n\
+         (define written (get-output-bytevector out))\n\
+         (define kinds (cons (binary-port? in) (textual-port? in)))\n\
+         (define string-kinds (cons (binary-port? out) (textual-port? (open-input-string \"\"))))\n",
+        &["a", "b", "rest", "end", "written", "kinds", "string-kinds"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "1",
+            "2",
+            "#u8(2 255)",
+            "#t",
+            "#u8(7 2 3)",
+            "(#t . #f)",
+            "(#t . #t)"
+        ]
+    );
+}
+
+#[test]
+fn binary_files_are_not_decoded() {
+    let path = std::env::temp_dir().join(format!("cheshire-{}-binary.bin", std::process::id()));
+    let path = path.to_string_lossy().replace('\\', "/");
+    let (error, values) = run(
+        "binary-files",
+        &format!(
+            "(define out (open-binary-output-file \"{0}\"))\n\
+             (write-bytevector (bytevector 255 0 254) out)\n\
+             (flush-output-port out)\n\
+             (define in (open-binary-input-file \"{0}\"))\n\
+             (define bytes (read-bytevector 10 in))\n",
+            path
+        ),
+        &["bytes"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("#u8(255 0 254)".to_string())]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn textual_and_binary_operations_stay_apart() {
+    let cases = [
+        (
+            "(read-char (open-input-bytevector (bytevector 65)))\n",
+            "is not a textual port",
+        ),
+        (
+            "(write-u8 1 (open-output-string))\n",
+            "is not a binary port",
+        ),
+        (
+            "(read-u8 (open-input-string \"a\"))\n",
+            "is not a binary port",
+        ),
+        (
+            "(get-output-string (open-output-bytevector))\n",
+            "is not a string output port",
+        ),
+    ];
+    for (i, (source, message)) in cases.iter().enumerate() {
+        let (error, _) = run(&format!("binary-text-{}", i), source, &[]);
+        let error = error.unwrap();
+        assert!(error.contains(message), "{}", error);
+    }
+}
+
+#[test]
+fn closed_ports_report_themselves_closed() {
+    let (error, values) = run(
+        "close-port",
+        "(define in (open-input-string \"abc\"))\n\
+         (define out (open-output-string))\n\
+         (write-string \"kept\" out)\n\
+         (define kinds (vector (port? in) (port? out) (port? \"in\") (port? 'out)))\n\
+         (define before (cons (input-port-open? in) (output-port-open? out)))\n\
+         (close-input-port in)\n\
+         (close-port out)\n\
+         (close-port out)\n\
+         (define after (cons (input-port-open? in) (output-port-open? out)))\n\
+         (define contents (get-output-string out))\n",
+        &["kinds", "before", "after", "contents"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec!["#(#t #t #f #f)", "(#t . #t)", "(#f . #f)", "\"kept\""]
+    );
+}
+
+#[test]
+fn closed_ports_cant_be_used() {
+    let cases = [
+        "(define p (open-input-string \"a\"))\n(close-port p)\n(read-char p)\n",
+        "(define p (open-output-string))\n(close-output-port p)\n(write-string \"a\" p)\n",
+        "(define p (open-input-bytevector (bytevector 1)))\n(close-port p)\n(read-u8 p)\n",
+    ];
+    for (i, source) in cases.iter().enumerate() {
+        let (error, _) = run(&format!("closed-port-{}", i), source, &[]);
+        let error = error.unwrap();
+        assert!(error.contains("is closed"), "{}", error);
+    }
+}
+
+#[test]
+fn file_ports_are_closed_after_their_procedure() {
+    let path = std::env::temp_dir().join(format!("cheshire-{}-call-with.txt", std::process::id()));
+    let path = path.to_string_lossy().replace('\\', "/");
+    let (error, values) = run(
+        "call-with-file",
+        &format!(
+            "(define out #f)\n\
+             (define written\n\
+               (call-with-output-file \"{0}\"\n\
+                 (lambda (port) (set! out port) (write-string \"abc\" port) 'done)))\n\
+             (define in #f)\n\
+             (define read\n\
+               (call-with-input-file \"{0}\" (lambda (port) (set! in port) (read-line port))))\n\
+             (define open (cons (output-port-open? out) (input-port-open? in)))\n\
+             (define console (current-output-port))\n\
+             (with-output-to-file \"{0}\" (lambda () (display \"xyz\")))\n\
+             (define restored (eq? console (current-output-port)))\n\
+             (define current (with-input-from-file \"{0}\" read-line))\n",
+            path
+        ),
+        &["written", "read", "open", "restored", "current"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec!["done", "\"abc\"", "(#f . #f)", "#t", "\"xyz\""]
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn escaping_closes_file_ports() {
+    let path = std::env::temp_dir().join(format!("cheshire-{}-escape.txt", std::process::id()));
+    let path = path.to_string_lossy().replace('\\', "/");
+    let (error, values) = run(
+        "escape-file-port",
+        &format!(
+            "(define port #f)\n\
+             (define console (current-output-port))\n\
+             (define result\n\
+               (call-with-current-continuation\n\
+                 (lambda (k)\n\
+                   (with-output-to-file \"{0}\"\n\
+                     (lambda () (set! port (current-output-port)) (k 'escaped))))))\n\
+             (define open (output-port-open? port))\n\
+             (define restored (eq? console (current-output-port)))\n",
+            path
+        ),
+        &["result", "open", "restored"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["escaped", "#f", "#t"]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_ports_can_be_repositioned() {
+    let path = std::env::temp_dir().join(format!("cheshire-{}-position.bin", std::process::id()));
+    let path = path.to_string_lossy().replace('\\', "/");
+    let (error, values) = run(
+        "port-position",
+        &format!(
+            "(define out (open-binary-output-file \"{0}\"))\n\
+             (write-bytevector (bytevector 0 1 2 3 4 5) out)\n\
+             (define written (port-position out))\n\
+             (set-port-position! out 2)\n\
+             (write-u8 99 out)\n\
+             (close-port out)\n\
+             (define in (open-binary-input-file \"{0}\"))\n\
+             (set-port-position! in 1)\n\
+             (define bytes (read-bytevector 3 in))\n\
+             (define after (port-position in))\n\
+             (set-port-position! in 0)\n\
+             (define first (read-u8 in))\n\
+             (define text (open-input-string \"aλbc\"))\n\
+             (read-string 3 text)\n\
+             (define text-after (port-position text))\n\
+             (set-port-position! text 1)\n\
+             (define char (read-char text))\n",
+            path
+        ),
+        &["written", "bytes", "after", "first", "text-after", "char"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["6", "#u8(1 99 3)", "4", "0", "4", "#\\λ"]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn only_some_ports_can_be_repositioned() {
+    let (error, _) = run(
+        "unseekable-port",
+        "(set-port-position! (open-output-string) 0)\n",
+        &[],
+    );
+    let error = error.unwrap();
+    assert!(error.contains("can't be repositioned"), "{}", error);
+}
+
+#[test]
+fn output_is_held_back_according_to_the_buffering_mode() {
+    let path = std::env::temp_dir().join(format!("cheshire-{}-buffering.txt", std::process::id()));
+    let path = path.to_string_lossy().replace('\\', "/");
+    let (error, values) = run(
+        "buffering",
+        &format!(
+            "(define (contents)\n\
+               (let ((s (call-with-input-file \"{0}\" (lambda (p) (read-string 100 p)))))\n\
+                 (if (eof-object? s) \"\" s)))\n\
+             (define full (open-output-file \"{0}\" 'utf-8 'full))\n\
+             (write-string \"a\\nb\" full)\n\
+             (define held (contents))\n\
+             (flush-output-port full)\n\
+             (define flushed (contents))\n\
+             (define line (open-output-file \"{0}\" 'utf-8 'line))\n\
+             (write-string \"a\\nb\" line)\n\
+             (define lines (contents))\n\
+             (define none (open-output-file \"{0}\" 'utf-8 'none))\n\
+             (write-char #\\x none)\n\
+             (define unbuffered (contents))\n",
+            path
+        ),
+        &["held", "flushed", "lines", "unbuffered"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["\"\"", "\"a\\nb\"", "\"a\\n\"", "\"x\""]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn ports_without_an_os_handle_are_always_ready() {
+    let (error, values) = run(
+        "char-ready",
+        "(define p (open-input-string \"a\"))\n\
+         (define before (char-ready? p))\n\
+         (read-char p)\n\
+         (define at-end (char-ready? p))\n",
+        &["before", "at-end"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["#t", "#t"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn readiness_is_polled_from_the_os() {
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for both ends of the pipe, which the files below take ownership of
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let (reader, mut writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    let handle = reader.as_raw_fd();
+    let mut port = ObjReadPort::new(reader).with_handle(handle);
+
+    assert!(!port.is_char_ready().unwrap());
+    writer.write_all(b"xy").unwrap();
+    assert!(port.is_char_ready().unwrap());
+    assert_eq!(port.read_char().unwrap(), Some('x'));
+    assert!(port.is_char_ready().unwrap());
+}
+
+/// Backend that reads from a fixed input and keeps what's written and whether it was closed
+struct Console {
+    input: io::Cursor<&'static [u8]>,
+    output: Rc<RefCell<Vec<u8>>>,
+    closed: Rc<Cell<bool>>,
+}
+
+impl PortBackend for Console {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        io::Read::read(&mut self.input, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn is_ready(&self) -> bool {
+        (self.input.position() as usize) < self.input.get_ref().len()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.closed.set(true);
+        Ok(())
+    }
+}
+
+#[test]
+fn embedders_can_provide_port_backends() {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let closed = Rc::new(Cell::new(false));
+    let console = |output: &Rc<RefCell<Vec<u8>>>, closed: &Rc<Cell<bool>>| Console {
+        input: io::Cursor::new(b"hello\nworld"),
+        output: output.clone(),
+        closed: closed.clone(),
+    };
+    let (reader, writer) = (console(&output, &closed), console(&output, &closed));
+
+    let (error, values) = run_with(
+        "port-backends",
+        "(define a (read-line console-in))\n\
+         (define b (read-line console-in))\n\
+         (define ready (char-ready? console-in))\n\
+         (write-string \"hi\\n\" console-out)\n\
+         (write-string \"there\" console-out)\n\
+         (close-port console-out)\n",
+        |mc, vm| {
+            vm.define_input_port("console-in", reader, mc);
+            vm.define_output_port("console-out", writer, mc);
+        },
+        &["a", "b", "ready"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, ["\"hello\"", "\"world\"", "#f"]);
+    assert_eq!(&*output.borrow(), b"hi\nthere");
+    assert!(closed.get());
+}
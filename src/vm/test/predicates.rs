@@ -0,0 +1,61 @@
+use super::run;
+
+#[test]
+fn list_accepts_only_proper_lists() {
+    let (error, values) = run(
+        "predicates-list",
+        "(define circular (cons 1 (cons 2 (cons 3 '()))))\n\
+         (set-cdr! (cddr circular) circular)\n\
+         (define lasso (cons 0 circular))\n\
+         (define results\n\
+           (cons (list? '()) (cons (list? '(1 2 3)) (cons (list? (cons 1 (cons 2 '())))\n\
+             (cons (list? '(1 . 2)) (cons (list? circular) (cons (list? lasso) '())))))))\n",
+        &["results"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("(#t #t #t #f #f #f)".to_string())]);
+}
+
+#[test]
+fn booleans_and_null() {
+    let (error, values) = run(
+        "predicates-basic",
+        "(define results\n\
+           (cons (null? '()) (cons (null? '(1)) (cons (boolean? #f) (cons (boolean? '())\n\
+             (cons (not #f) (cons (not '()) (cons (not 0) '()))))))))\n",
+        &["results"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("(#t #f #t #f #t #f #f)".to_string())]);
+}
+
+#[test]
+fn equal_compares_cycles_by_structure() {
+    let (error, values) = run(
+        "predicates-equal-cycles",
+        "(define (cycle . items) (set-cdr! (last-pair items) items) items)\n\
+         (define (last-pair l) (if (null? (cdr l)) l (last-pair (cdr l))))\n\
+         (define ones (cycle 1))\n\
+         (define pairs-same\n\
+           (cons (equal? ones (cycle 1 1 1)) (equal? (cycle 1 2) (cycle 1 2 1 2))))\n\
+         (define pairs-different\n\
+           (cons (equal? ones (cycle 1 1 2)) (equal? (cycle 1 2) (cycle 1 2 1 3))))\n\
+         (define (vector-cycle x) (let ((v (make-vector 2 x))) (vector-set! v 1 v) v))\n\
+         (define (vector-cycle-2 x y)\n\
+           (let ((v (make-vector 2 x))) (vector-set! v 1 (vector y v)) v))\n\
+         (define vectors-same (equal? (vector-cycle 1) (vector-cycle-2 1 1)))\n\
+         (define vectors-different (equal? (vector-cycle 1) (vector-cycle-2 1 2)))\n",
+        &[
+            "pairs-same",
+            "pairs-different",
+            "vectors-same",
+            "vectors-different",
+        ],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["(#t . #t)", "(#f . #f)", "#t", "#f"]);
+}
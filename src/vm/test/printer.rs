@@ -0,0 +1,53 @@
+use super::run;
+
+fn printed(name: &str, source: &str, globals: &[&str]) -> Vec<String> {
+    let (error, values) = run(name, source, globals);
+    assert_eq!(error, None);
+    values.into_iter().map(Option::unwrap).collect()
+}
+
+#[test]
+fn circular_lists_are_labelled() {
+    let values = printed(
+        "printer-circular",
+        "(define ring (cons 1 (cons 2 '())))\n\
+         (set-cdr! (cdr ring) ring)\n\
+         (define tail (cons 0 ring))\n\
+         (define self (cons 1 2))\n\
+         (set-car! self self)\n",
+        &["ring", "tail", "self"],
+    );
+
+    assert_eq!(
+        values,
+        vec!["#0=(1 2 . #0#)", "(0 . #0=(1 2 . #0#))", "#0=(#0# . 2)"]
+    );
+}
+
+#[test]
+fn vectors_containing_themselves_are_labelled() {
+    let values = printed(
+        "printer-vector",
+        "(define v (make-vector 2 0))\n\
+         (vector-set! v 1 v)\n\
+         (define w (make-vector 1 0))\n\
+         (define pair (cons w '()))\n\
+         (vector-set! w 0 pair)\n",
+        &["v", "w"],
+    );
+
+    assert_eq!(values, vec!["#0=#(0 #0#)", "#0=#((#0#))"]);
+}
+
+#[test]
+fn shared_structure_without_cycles_is_printed_in_full() {
+    let values = printed(
+        "printer-shared",
+        "(define p (cons 1 2))\n\
+         (define shared (cons p p))\n\
+         (define v (make-vector 2 p))\n",
+        &["shared", "v"],
+    );
+
+    assert_eq!(values, vec!["((1 . 2) 1 . 2)", "#((1 . 2) (1 . 2))"]);
+}
@@ -0,0 +1,47 @@
+use super::{load_with_config, run, run_to_end};
+use crate::object::ObjWritePort;
+use crate::vm::VmConfig;
+
+#[test]
+fn profile_counts_calls_to_each_procedure() {
+    let config = VmConfig::new().with_output_port(ObjWritePort::string());
+    let mut arena = load_with_config(
+        "profile-calls",
+        "(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))\n\
+         (define (loop n) (if (= n 0) 'done (loop (- n 1))))\n\
+         (fib 5)\n\
+         (profile-start!)\n\
+         (fib 10)\n\
+         (loop 100)\n\
+         (profile-report)\n",
+        config,
+    );
+
+    assert_eq!(run_to_end(&mut arena), None);
+    arena.mutate(|_, vm| {
+        let port = *vm.current_output_port().read();
+        let contents = port
+            .read()
+            .as_write_port()
+            .unwrap()
+            .contents()
+            .unwrap()
+            .to_vec();
+        let report = String::from_utf8(contents).unwrap();
+        let calls = |name: &str| {
+            let row = report.lines().find(|row| row.starts_with(name)).unwrap();
+            row.split_whitespace().rev().nth(1).unwrap().to_string()
+        };
+
+        assert!(report.starts_with("procedure"), "{}", report);
+        assert_eq!(calls("fib ("), "177", "{}", report);
+        assert_eq!(calls("loop ("), "101", "{}", report);
+        assert_eq!(calls("profile-report"), "1", "{}", report);
+    });
+}
+
+#[test]
+fn profile_report_needs_profiling_started() {
+    let (error, _) = run("profile-not-started", "(profile-report)\n", &[]);
+    assert!(error.unwrap().contains("Profiling hasn't been started"));
+}
@@ -0,0 +1,88 @@
+use super::run;
+
+fn values(name: &str, source: &str, globals: &[&str]) -> Vec<String> {
+    let (error, values) = run(name, source, globals);
+    assert_eq!(error, None);
+    values.into_iter().map(Option::unwrap).collect()
+}
+
+#[test]
+fn returning_normally_skips_the_handler() {
+    let values = values(
+        "prompts-return",
+        "(define result (call-with-prompt 'p (lambda () 5) (lambda (k) 'aborted)))\n",
+        &["result"],
+    );
+
+    assert_eq!(values, vec!["5"]);
+}
+
+#[test]
+fn aborting_calls_the_handler_with_the_values() {
+    let values = values(
+        "prompts-abort",
+        "(define (both k x y) (cons x y))\n\
+         (define result (call-with-prompt 'p (lambda () (+ 1 (abort-to-prompt 'p 10 20))) both))\n",
+        &["result"],
+    );
+
+    assert_eq!(values, vec!["(10 . 20)"]);
+}
+
+#[test]
+fn resuming_returns_from_the_abort() {
+    let values = values(
+        "prompts-resume",
+        "(define (twice k x) (k (k x)))\n\
+         (define result (call-with-prompt 'p (lambda () (* 2 (abort-to-prompt 'p 5))) twice))\n\
+         (define tail (call-with-prompt 'p (lambda () (abort-to-prompt 'p 3)) (lambda (k x) (k x))))\n",
+        &["result", "tail"],
+    );
+
+    assert_eq!(values, vec!["20", "3"]);
+}
+
+#[test]
+fn aborting_skips_inner_prompts_with_other_tags() {
+    let values = values(
+        "prompts-tags",
+        "(define result\n\
+           (call-with-prompt 'outer\n\
+             (lambda ()\n\
+               (* 2 (call-with-prompt 'inner\n\
+                      (lambda () (+ 1 (abort-to-prompt 'outer 5)))\n\
+                      (lambda (k) 0))))\n\
+             (lambda (k x) (+ 100 (k x)))))\n",
+        &["result"],
+    );
+
+    assert_eq!(values, vec!["112"]);
+}
+
+#[test]
+fn prompts_can_implement_generators() {
+    let values = values(
+        "prompts-generators",
+        "(define (yielded k x) (cons x k))\n\
+         (define (generate lst)\n\
+           (call-with-prompt 'yield\n\
+             (lambda () (for-each (lambda (x) (abort-to-prompt 'yield x)) lst) 'done)\n\
+             yielded))\n\
+         (define (next g) (call-with-prompt 'yield (lambda () ((cdr g) #f)) yielded))\n\
+         (define g (generate '(1 2)))\n\
+         (define first (car g))\n\
+         (define second (car (next g)))\n\
+         (define last (next (next g)))\n",
+        &["first", "second", "last"],
+    );
+
+    assert_eq!(values, vec!["1", "2", "done"]);
+}
+
+#[test]
+fn aborting_without_a_prompt_is_an_error() {
+    let (error, _) = run("prompts-missing", "(abort-to-prompt 'nowhere 1)\n", &[]);
+
+    let error = error.unwrap();
+    assert!(error.contains("No prompt with tag 'nowhere'"), "{}", error);
+}
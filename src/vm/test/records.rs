@@ -0,0 +1,84 @@
+use super::run;
+
+const POINT: &str = "(define-record-type <point>\n\
+                       (make-point x y)\n\
+                       point?\n\
+                       (x point-x set-point-x!)\n\
+                       (y point-y))\n";
+
+#[test]
+fn records_have_constructors_accessors_and_modifiers() {
+    let source = format!(
+        "{}\
+         (define p (make-point 1 2))\n\
+         (set-point-x! p 10)\n\
+         (define x (point-x p))\n\
+         (define y (point-y p))\n\
+         (define is-point (point? p))\n\
+         (define is-not-point (point? (cons 1 2)))\n",
+        POINT
+    );
+    let (error, values) = run(
+        "records",
+        &source,
+        &[
+            "x",
+            "y",
+            "is-point",
+            "is-not-point",
+            "p",
+            "<point>",
+            "point-x",
+        ],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "10",
+            "2",
+            "#t",
+            "#f",
+            "#<point 10 2>",
+            "#<record-type <point>>",
+            "#<procedure point-x>"
+        ]
+    );
+}
+
+#[test]
+fn constructors_can_leave_fields_out() {
+    let (error, values) = run(
+        "records-partial",
+        "(define-record-type node (make-node value) node? (value node-value) (next node-next set-node-next!))\n\
+         (define-record-type pair-type make-pair-type pair-type? left right)\n\
+         (define n (make-node 1))\n\
+         (set-node-next! n n)\n\
+         (define p (make-pair-type 'a 'b))\n",
+        &["n", "p"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["#0=#<node 1 #0#>", "#<pair-type a b>"]);
+}
+
+#[test]
+fn accessors_check_the_record_type() {
+    let source = format!(
+        "{}\
+         (define-record-type <other> (make-other x) other? (x other-x))\n\
+         (point-x (make-other 1))\n",
+        POINT
+    );
+    let (error, _) = run("records-type", &source, &[]);
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("'#<other 1>' is not a point record"),
+        "{}",
+        error
+    );
+}
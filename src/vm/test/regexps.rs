@@ -0,0 +1,66 @@
+use super::run;
+
+#[test]
+fn regexps_find_matches_and_groups() {
+    let (error, values) = run(
+        "regexp-match",
+        "(define date (regexp \"(\\\\d+)-(\\\\d+)(-x)?\"))\n\
+         (define groups (regexp-match date \"on 2024-06!\"))\n\
+         (define positions (regexp-match-positions date \"λ 2024-06\"))\n\
+         (define none (regexp-match \"z+\" \"abc\"))\n\
+         (define is-regexp (regexp? date))\n\
+         (define string-is-not (regexp? \"a\"))\n",
+        &[
+            "date",
+            "groups",
+            "positions",
+            "none",
+            "is-regexp",
+            "string-is-not",
+        ],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "#<regexp \"(\\\\d+)-(\\\\d+)(-x)?\">",
+            "(\"2024-06\" \"2024\" \"06\" #f)",
+            "((2 . 9) (2 . 6) (7 . 9) #f)",
+            "#f",
+            "#t",
+            "#f",
+        ]
+    );
+}
+
+#[test]
+fn regexps_replace_and_split() {
+    let (error, values) = run(
+        "regexp-replace",
+        "(define first (regexp-replace \"o\" \"foo boo\" \"0\"))\n\
+         (define all (regexp-replace* \"o\" \"foo boo\" \"0\"))\n\
+         (define swapped (regexp-replace \"(\\\\w+) (\\\\w+)\" \"hello world\" \"$2 $1\"))\n\
+         (define fields (regexp-split (regexp \",\\\\s*\") \"a, b,c\"))\n",
+        &["first", "all", "swapped", "fields"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "\"f0o boo\"",
+            "\"f00 b00\"",
+            "\"world hello\"",
+            "(\"a\" \"b\" \"c\")",
+        ]
+    );
+}
+
+#[test]
+fn invalid_regexps_are_errors() {
+    let (error, _) = run("regexp-invalid", "(regexp \"(\")\n", &[]);
+    assert!(error.unwrap().contains("Invalid regexp"));
+}
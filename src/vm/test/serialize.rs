@@ -0,0 +1,236 @@
+use std::env;
+
+use gc_arena::MutationContext;
+
+use super::{run, run_with};
+use crate::memory::Token;
+use crate::object::{ObjNative, Object};
+use crate::value::Value;
+use crate::vm::{Result, Stack, VirtualMachine};
+
+fn checkpoint_path(name: &str) -> String {
+    let path = env::temp_dir().join(format!("cheshire-{}-{}.chk", std::process::id(), name));
+    path.to_string_lossy().into_owned()
+}
+
+/// Defines `checkpoint`, which saves its continuation to `path` and returns `saved`
+fn checkpoint(path: &str) -> String {
+    format!(
+        "(define (checkpoint)\n\
+           (call-with-current-continuation\n\
+             (lambda (k) (save-continuation k \"{}\") 'saved)))\n",
+        path
+    )
+}
+
+fn resume(name: &str, path: &str, globals: &[&str]) -> Vec<Option<String>> {
+    let (error, values) = run(
+        name,
+        &format!("((load-continuation \"{}\") 'resumed)\n", path),
+        globals,
+    );
+    assert_eq!(error, None);
+    values
+}
+
+#[test]
+fn resuming_in_a_fresh_vm_finishes_the_program() {
+    let path = checkpoint_path("program");
+    let source = format!(
+        "{}\
+         (define (work) (let ((x 10)) (cons (checkpoint) (+ x 1))))\n\
+         (define result (work))\n\
+         (define after (car result))\n",
+        checkpoint(&path)
+    );
+    let (error, values) = run("serialize-program", &source, &["result", "after"]);
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        vec![Some("(saved . 11)".to_string()), Some("saved".to_string())]
+    );
+
+    // The rest of the original file is read from where it left off, defining the globals there
+    let values = resume("serialize-program-resume", &path, &["result", "after"]);
+    assert_eq!(
+        values,
+        vec![
+            Some("(resumed . 11)".to_string()),
+            Some("resumed".to_string())
+        ]
+    );
+}
+
+#[test]
+fn resuming_keeps_shared_objects_and_string_ports() {
+    let path = checkpoint_path("shared");
+    let source = format!(
+        "{}\
+         (define result\n\
+           (let ((port (open-output-string)) (v (make-vector 1 0)))\n\
+             (let ((shared (cons v v)))\n\
+               (write-char #\\a port)\n\
+               (vector-set! (car shared) 0 (checkpoint))\n\
+               (write-char #\\b port)\n\
+               (cons (get-output-string port) (vector-ref (cdr shared) 0)))))\n",
+        checkpoint(&path)
+    );
+    let (error, values) = run("serialize-shared", &source, &["result"]);
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("(\"ab\" . saved)".to_string())]);
+
+    let values = resume("serialize-shared-resume", &path, &["result"]);
+    assert_eq!(values, vec![Some("(\"ab\" . resumed)".to_string())]);
+}
+
+fn opaque<'gc>(
+    _: &VirtualMachine<'gc>,
+    stack: Stack<'gc>,
+    _: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    Ok(Some(stack.read()[1]))
+}
+
+fn define_opaque<'gc>(mc: MutationContext<'gc, '_>, vm: &VirtualMachine<'gc>) {
+    let name = vm.intern_symbol(Token::new(mc, "opaque".into()), mc);
+    let native = ObjNative::new(1, false, opaque, Some(name));
+    vm.define_global(name, Value::boxed(mc, Object::Native(native)), mc);
+}
+
+/// Defines `opaque` as a native registered under its own name
+fn define_registered_opaque<'gc>(mc: MutationContext<'gc, '_>, vm: &VirtualMachine<'gc>) {
+    vm.register_native("opaque", opaque);
+    let name = vm.intern_symbol(Token::new(mc, "opaque".into()), mc);
+    let native = ObjNative::registered(1, false, ("opaque", opaque), Some(name));
+    vm.define_global(name, Value::boxed(mc, Object::Native(native)), mc);
+}
+
+#[test]
+fn registered_natives_are_saved_by_name() {
+    let path = checkpoint_path("registered");
+    let source = format!(
+        "{}(define result (opaque (checkpoint)))\n",
+        checkpoint(&path)
+    );
+    let (error, values) = run_with(
+        "serialize-registered",
+        &source,
+        define_registered_opaque,
+        &["result"],
+    );
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("saved".to_string())]);
+
+    let (error, values) = run_with(
+        "serialize-registered-resume",
+        &format!("((load-continuation \"{}\") 'resumed)\n", path),
+        define_registered_opaque,
+        &["result"],
+    );
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("resumed".to_string())]);
+}
+
+#[test]
+fn unregistered_natives_cannot_be_saved() {
+    let path = checkpoint_path("unregistered");
+    let source = format!("{}(opaque (checkpoint))\n", checkpoint(&path));
+    let (error, _) = run_with("serialize-unregistered", &source, define_opaque, &[]);
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("#<native procedure opaque>, it isn't a registered native"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn compiled_files_load_without_their_source() {
+    let source = env::temp_dir().join(format!("cheshire-{}-compiled.scm", std::process::id()));
+    let output = checkpoint_path("compiled");
+    std::fs::write(
+        &source,
+        "(define-syntax inc!\n\
+           (er-macro-transformer\n\
+             (lambda (form rename compare)\n\
+               (let ((x (car (cdr form))))\n\
+                 (cons (rename 'set!) (cons x (cons (cons (rename '+) (cons x '(1))) '())))))))\n\
+         (define count 0)\n\
+         (inc! count)\n\
+         (define (square x) (* x x))\n\
+         (define result (cons count (cons (square 4) (cons \"text\" 'sym))))\n",
+    )
+    .unwrap();
+
+    // Compiling only runs the syntax definition
+    let (error, values) = run(
+        "compile-file",
+        &format!(
+            "(compile-file {:?} {:?})\n",
+            source.to_string_lossy(),
+            output
+        ),
+        &["count", "result"],
+    );
+    std::fs::remove_file(&source).unwrap();
+    assert_eq!(error, None);
+    assert_eq!(values, vec![None, None]);
+
+    let (error, values) = run(
+        "load-compiled",
+        &format!("(load {:?})\n", output),
+        &["count", "result"],
+    );
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        vec![
+            Some("1".to_string()),
+            Some("(1 16 \"text\" . sym)".to_string())
+        ]
+    );
+}
+
+#[test]
+fn invalid_utf8_in_compiled_strings_is_replaced() {
+    let literal = "x".repeat(130);
+    let source = env::temp_dir().join(format!("cheshire-{}-invalid-utf8.scm", std::process::id()));
+    let output = checkpoint_path("invalid-utf8");
+    std::fs::write(&source, format!("(define s {:?})\n", literal)).unwrap();
+    let (error, _) = run(
+        "compile-invalid-utf8",
+        &format!(
+            "(compile-file {:?} {:?})\n",
+            source.to_string_lossy(),
+            output
+        ),
+        &[],
+    );
+    std::fs::remove_file(&source).unwrap();
+    assert_eq!(error, None);
+
+    // Corrupt 100 bytes of the literal so they no longer decode
+    let mut compiled = std::fs::read(&output).unwrap();
+    let start = compiled
+        .windows(literal.len())
+        .position(|window| window == literal.as_bytes())
+        .unwrap();
+    compiled[start..start + 100].fill(0xFF);
+    std::fs::write(&output, compiled).unwrap();
+
+    let (error, values) = run(
+        "load-invalid-utf8",
+        &format!(
+            "(load {:?})\n\
+             (define len (string-length s))\n\
+             (define replaced (string-ref s 99))\n\
+             (define kept (string-ref s 100))\n",
+            output
+        ),
+        &["len", "replaced", "kept"],
+    );
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["130", "#\\\u{FFFD}", "#\\x"]);
+}
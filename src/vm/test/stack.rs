@@ -0,0 +1,124 @@
+use super::{run, run_with};
+
+#[test]
+fn closures_keep_their_variables_after_the_frame_returns() {
+    let (error, values) = run(
+        "stack-closures",
+        "(define (make-counter)\n\
+           (let ((count 0))\n\
+             (cons (lambda () (set! count (+ count 1)) count)\n\
+                   (lambda () count))))\n\
+         (define counter (make-counter))\n\
+         (define (clobber a b c d) (+ a b c d))\n\
+         ((car counter))\n\
+         (clobber 10 20 30 40)\n\
+         ((car counter))\n\
+         (define shared ((cdr counter)))\n\
+         (define other ((cdr (make-counter))))\n",
+        &["shared", "other"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["2", "0"]);
+}
+
+#[test]
+fn closures_see_assignments_made_while_their_frame_runs() {
+    let (error, values) = run(
+        "stack-open-upvalues",
+        "(define (f)\n\
+           (define x 1)\n\
+           (define (get) x)\n\
+           (set! x 5)\n\
+           (get))\n\
+         (define seen (f))\n",
+        &["seen"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("5".to_string())]);
+}
+
+#[test]
+fn deep_recursion_runs_on_one_stack() {
+    let (error, values) = run(
+        "stack-deep",
+        "(define (sum n) (if (= n 0) 0 (+ n (sum (- n 1)))))\n\
+         (define total (sum 10000))\n\
+         (define (loop n acc) (if (= n 0) acc (loop (- n 1) (+ acc 1))))\n\
+         (define looped (loop 100000 0))\n",
+        &["total", "looped"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["50005000", "100000"]);
+}
+
+#[test]
+fn continuations_captured_deep_in_calls_can_be_reentered() {
+    let (error, values) = run(
+        "stack-reentry",
+        "(define k #f)\n\
+         (define results '())\n\
+         (define (deep n)\n\
+           (if (= n 0) (call-with-current-continuation (lambda (c) (set! k c) 0)) (+ 1 (deep (- n 1)))))\n\
+         (define count 0)\n\
+         (define (go)\n\
+           (set! results (cons (deep 50) results))\n\
+           (set! count (+ count 1))\n\
+           (if (< count 3) (k count) results))\n\
+         (define out (go))\n\
+         (define after (deep 3))\n",
+        &["out", "after"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["(52 51 50)", "3"]);
+}
+
+#[test]
+fn reentered_continuations_see_assignments_to_locals() {
+    let (error, values) = run(
+        "stack-reentry-assignments",
+        "(define (f)\n\
+           (define k #f)\n\
+           (define n 0)\n\
+           (call-with-current-continuation (lambda (c) (set! k c)))\n\
+           (set! n (+ n 1))\n\
+           (if (< n 3) (k 0))\n\
+           n)\n\
+         (define result (f))\n",
+        &["result"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("3".to_string())]);
+}
+
+#[test]
+fn recursing_past_the_depth_limit_raises_a_catchable_error() {
+    let (error, values) = run_with(
+        "stack-limit",
+        "(define (forever n) (+ 1 (forever n)))\n\
+         (define (sum n) (if (= n 0) 0 (+ n (sum (- n 1)))))\n\
+         (define caught\n\
+           (call-with-current-continuation\n\
+             (lambda (k) (with-exception-handler (lambda (x) (k x)) (lambda () (forever 0))))))\n\
+         (define total (sum 50))\n\
+         (define again\n\
+           (call-with-current-continuation\n\
+             (lambda (k) (with-exception-handler (lambda (x) (k x)) (lambda () (forever 0))))))\n\
+         (forever 0)\n",
+        |_, vm| vm.set_max_depth(100),
+        &["caught", "total", "again"],
+    );
+
+    assert!(error.unwrap().contains("Maximum recursion depth exceeded"));
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert!(values[0].contains("Maximum recursion depth exceeded"));
+    assert_eq!(values[1], "1275");
+    assert_eq!(values[0], values[2]);
+}
@@ -0,0 +1,70 @@
+use super::run;
+
+#[test]
+fn string_to_symbol_interns_its_result() {
+    let (error, values) = run(
+        "string-to-symbol",
+        "(define literal (eq? 'foo (string->symbol \"foo\")))\n\
+         (define built (eq? (string->symbol (symbol->string 'bar)) 'bar))\n\
+         (define alist (assq (string->symbol \"b\") (cons (cons 'a 1) (cons (cons 'b 2) '()))))\n",
+        &["literal", "built", "alist"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["#t", "#t", "(b . 2)"]);
+}
+
+#[test]
+fn gensyms_are_always_fresh() {
+    let (error, values) = run(
+        "gensym",
+        "(define a (gensym))\n\
+         (define b (gensym 'tmp))\n\
+         (define c (generate-uninterned-symbol \"x\"))\n\
+         (define distinct (eq? (gensym) (gensym)))\n\
+         (define uninterned (eq? b (string->symbol (symbol->string b))))\n\
+         (define is-symbol (symbol? a))\n",
+        &["a", "b", "c", "distinct", "uninterned", "is-symbol"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["g0", "tmp1", "x2", "#f", "#f", "#t"]);
+}
+
+#[test]
+fn symbols_compare_by_identity_and_name() {
+    let (error, values) = run(
+        "symbol-compare",
+        "(define same (symbol=? 'a 'a (string->symbol \"a\")))\n\
+         (define different (symbol=? 'a 'a 'b))\n\
+         (define fresh (symbol=? (gensym \"a\") (gensym \"a\")))\n\
+         (define ordered (symbol<? 'apple 'banana 'cherry))\n\
+         (define unordered (symbol<? 'b 'a))\n\
+         (define equal-names (symbol<? 'a 'a))\n\
+         (define sorted (sort (cons 'pear (cons 'fig (cons 'kiwi '()))) symbol<?))\n",
+        &[
+            "same",
+            "different",
+            "fresh",
+            "ordered",
+            "unordered",
+            "equal-names",
+            "sorted",
+        ],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec!["#t", "#f", "#f", "#t", "#f", "#f", "(fig kiwi pear)"]
+    );
+}
+
+#[test]
+fn symbol_comparisons_only_take_symbols() {
+    let (error, _) = run("symbol-compare-type", "(symbol=? 'a \"b\" 'c)\n", &[]);
+    assert!(error.unwrap().contains("\"b\""));
+}
@@ -0,0 +1,98 @@
+use super::run;
+
+#[test]
+fn threads_take_turns_when_they_yield() {
+    let (error, values) = run(
+        "threads-yield",
+        "(define log '())\n\
+         (define (note x) (set! log (cons x log)))\n\
+         (define (worker name)\n\
+           (lambda () (note name) (yield) (note name) name))\n\
+         (define a (spawn (worker 'a)))\n\
+         (define b (spawn (worker 'b)))\n\
+         (note 'main)\n\
+         (define joined (cons (thread-join! a) (thread-join! b)))\n\
+         (define again (thread-join! a))\n\
+         (define kinds (cons (thread? a) (thread? 'a)))\n",
+        &["log", "joined", "again", "kinds"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["(b a b a main)", "(a . b)", "a", "(#t . #f)"]);
+}
+
+#[test]
+fn channels_pass_values_between_threads_in_order() {
+    let (error, values) = run(
+        "threads-channels",
+        "(define requests (make-channel))\n\
+         (define replies (make-channel))\n\
+         (define (serve)\n\
+           (let ((n (channel-receive requests)))\n\
+             (if (= n 0) 'done (begin (channel-send! replies (* n n)) (serve)))))\n\
+         (define server (spawn serve))\n\
+         (channel-send! requests 3)\n\
+         (channel-send! requests 4)\n\
+         (define first (channel-receive replies))\n\
+         (define second (channel-receive replies))\n\
+         (channel-send! requests 0)\n\
+         (define finished (thread-join! server))\n\
+         (define kinds (cons (channel? requests) (channel? server)))\n",
+        &["first", "second", "finished", "kinds"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["9", "16", "done", "(#t . #f)"]);
+}
+
+#[test]
+fn waiting_with_no_other_thread_ready_is_a_deadlock() {
+    let (error, values) = run(
+        "threads-deadlock",
+        "(define caught\n\
+           (call-with-current-continuation\n\
+             (lambda (k)\n\
+               (with-exception-handler (lambda (x) (k x))\n\
+                 (lambda () (channel-receive (make-channel)))))))\n",
+        &["caught"],
+    );
+
+    assert_eq!(error, None);
+    assert!(values[0].as_ref().unwrap().contains("Deadlock"));
+}
+
+#[test]
+fn errors_in_threads_are_raised_again_by_thread_join() {
+    let (error, values) = run(
+        "threads-errors",
+        "(define (message x) (if (error-object? x) (error-object-message x) x))\n\
+         (define (catch thunk)\n\
+           (call-with-current-continuation\n\
+             (lambda (k) (with-exception-handler (lambda (x) (k (message x))) thunk))))\n\
+         (define failing (spawn (lambda () (yield) (error \"worker failed\" 1))))\n\
+         (define waited (catch (lambda () (thread-join! failing))))\n\
+         (define again (catch (lambda () (thread-join! failing))))\n\
+         (define broken (spawn (lambda () (car '()))))\n\
+         (yield)\n\
+         (define finished (catch (lambda () (thread-join! broken))))\n\
+         (define raised (catch (lambda () (thread-join! (spawn (lambda () (raise 'oops)))))))\n\
+         (define still-running 'yes)\n\
+         (thread-join! broken)\n",
+        &["waited", "again", "finished", "raised", "still-running"],
+    );
+
+    assert!(error.unwrap().contains("in form 11 of"));
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "\"worker failed\"",
+            "\"worker failed\"",
+            "\"() is not a pair\"",
+            "oops",
+            "yes"
+        ]
+    );
+}
@@ -0,0 +1,58 @@
+use super::{load, read_globals, run_to_end};
+use crate::memory::Token;
+
+const PROGRAM: &str = "(define (g x) (car x))\n\
+                       (define (f x) (cons 1 (g x)))\n\
+                       (define result (f 5))\n";
+
+#[test]
+fn stepping_back_walks_through_the_calls() {
+    let mut arena = load("time-travel-steps", PROGRAM);
+    arena.mutate(|_, vm| vm.set_time_travel(Some(1)));
+    assert!(run_to_end(&mut arena).is_some());
+
+    arena.mutate(|_, vm| {
+        let g = vm.step_back().unwrap();
+        assert!(g.contains("(g 5) at line 1"), "{}", g);
+        let f = vm.step_back().unwrap();
+        assert!(f.contains("(f 5) at line 2"), "{}", f);
+
+        let g = vm.step_forward().unwrap();
+        assert!(g.contains("(g 5)"), "{}", g);
+        assert_eq!(vm.step_forward(), None);
+    });
+}
+
+#[test]
+fn resuming_after_a_fix_finishes_the_program() {
+    let mut arena = load("time-travel-resume", PROGRAM);
+    arena.mutate(|_, vm| vm.set_time_travel(Some(1)));
+    assert!(run_to_end(&mut arena).is_some());
+
+    // Replace the broken `g` with `not`, then go back to the call to `f` and try again
+    arena.mutate(|mc, vm| {
+        let not = vm.global("not", mc).unwrap();
+        let g = vm.intern_symbol(Token::new(mc, "g".into()), mc);
+        vm.define_global(g, not, mc);
+
+        vm.step_back().unwrap();
+        vm.step_back().unwrap();
+        vm.resume_snapshot(mc).unwrap();
+    });
+    assert_eq!(run_to_end(&mut arena), None);
+    assert_eq!(
+        read_globals(&mut arena, &["result"]),
+        vec![Some("(1 . #f)".to_string())]
+    );
+}
+
+#[test]
+fn nothing_is_recorded_unless_time_travel_is_on() {
+    let mut arena = load("time-travel-off", PROGRAM);
+    assert!(run_to_end(&mut arena).is_some());
+
+    arena.mutate(|mc, vm| {
+        assert_eq!(vm.step_back(), None);
+        assert!(vm.resume_snapshot(mc).is_err());
+    });
+}
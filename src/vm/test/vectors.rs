@@ -0,0 +1,307 @@
+use gc_arena::{Gc, MutationContext};
+
+use super::{run, run_with};
+use crate::memory::Token;
+use crate::object::{ObjPair, ObjString, ObjVector};
+use crate::value::{Datum, Value};
+use crate::vm::VirtualMachine;
+
+/// Binds `v` to the constant vector `#(#(1) "s" (1) 2)`
+fn define_constant_vector<'gc>(mc: MutationContext<'gc, '_>, vm: &VirtualMachine<'gc>) {
+    let inner = ObjVector::new(vec![Datum::Number(1.0)].into_boxed_slice());
+    let pair = ObjPair::new(Datum::Number(1.0), Datum::Null);
+    let elements = vec![
+        Datum::Vector(Gc::allocate(mc, inner)),
+        Datum::String(Gc::allocate(mc, ObjString::from("s"))),
+        Datum::Pair(Gc::allocate(mc, pair)),
+        Datum::Number(2.0),
+    ];
+    let vector = Gc::allocate(mc, ObjVector::new(elements.into_boxed_slice()));
+
+    let name = vm.intern_symbol(Token::new(mc, "v".into()), mc);
+    vm.define_global(name, Value::Vector(vector), mc);
+}
+
+const SAME_ELEMENTS: &str = "(define nested (eqv? (vector-ref v 0) (vector-ref v 0)))\n\
+                             (define nested-eq (eq? (vector-ref v 0) (vector-ref v 0)))\n\
+                             (define string (eqv? (vector-ref v 1) (vector-ref v 1)))\n\
+                             (define pair (eqv? (vector-ref v 2) (vector-ref v 2)))\n\
+                             (define number (eqv? (vector-ref v 3) (vector-ref v 3)))\n\
+                             (define different (eqv? (vector-ref v 0) (vector-ref v 2)))\n";
+
+const RESULTS: &[&str] = &[
+    "nested",
+    "nested-eq",
+    "string",
+    "pair",
+    "number",
+    "different",
+];
+
+fn expected() -> Vec<Option<String>> {
+    ["#t", "#t", "#t", "#t", "#t", "#f"]
+        .iter()
+        .map(|value| Some(value.to_string()))
+        .collect()
+}
+
+#[test]
+fn constant_vector_elements_keep_their_identity() {
+    let (error, values) = run_with(
+        "constant-vector-ref",
+        SAME_ELEMENTS,
+        define_constant_vector,
+        RESULTS,
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, expected());
+}
+
+#[test]
+fn constant_and_mutable_vectors_agree() {
+    let source = format!(
+        "(define v (make-vector 4 2))\n\
+         (vector-set! v 0 (make-vector 1 1))\n\
+         (vector-set! v 1 \"s\")\n\
+         (vector-set! v 2 (cons 1 '()))\n\
+         {}",
+        SAME_ELEMENTS
+    );
+    let (error, values) = run("mutable-vector-ref", &source, RESULTS);
+
+    assert_eq!(error, None);
+    assert_eq!(values, expected());
+}
+
+#[test]
+fn constant_vector_elements_read_like_literals() {
+    let (error, values) = run_with(
+        "constant-vector-elements",
+        "(define inner (vector-ref (vector-ref v 0) 0))\n\
+         (define same (equal? v '#(#(1) \"s\" (1) 2)))\n",
+        define_constant_vector,
+        &["inner", "same"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(values, vec![Some("1".to_string()), Some("#t".to_string())]);
+}
+
+#[test]
+fn lists_and_vectors_convert_both_ways() {
+    let (error, values) = run(
+        "list-vector-conversions",
+        "(define v (list->vector '(1 2 3)))\n\
+         (vector-set! v 0 'a)\n\
+         (define all (vector->list v))\n\
+         (define tail (vector->list v 1))\n\
+         (define middle (vector->list '#(1 2 3 4) 1 3))\n\
+         (define empty (vector->list v 3))\n",
+        &["v", "all", "tail", "middle", "empty"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        vec![
+            Some("#(a 2 3)".to_string()),
+            Some("(a 2 3)".to_string()),
+            Some("(2 3)".to_string()),
+            Some("(2 3)".to_string()),
+            Some("()".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn vector_to_list_checks_its_range() {
+    let (error, _) = run("vector-to-list-range", "(vector->list '#(1 2) 1 3)\n", &[]);
+
+    let error = error.unwrap();
+    assert!(error.contains("Range 1 to 3 is out of range"), "{}", error);
+}
+
+#[test]
+fn vectors_can_be_built_and_filled() {
+    let (error, values) = run(
+        "vector-fill",
+        "(define v (vector 1 2 3 4))\n\
+         (define empty (vector))\n\
+         (define all (vector 1 2))\n\
+         (vector-fill! all 'x)\n\
+         (vector-fill! v 0 1 3)\n\
+         (define tail (vector 1 2 3))\n\
+         (vector-fill! tail 9 2)\n",
+        &["v", "empty", "all", "tail"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["#(1 0 0 4)", "#()", "#(x x)", "#(1 2 9)"]);
+}
+
+#[test]
+fn vector_fill_checks_its_range() {
+    let (error, _) = run(
+        "vector-fill-range",
+        "(vector-fill! (vector 1) 0 0 2)\n",
+        &[],
+    );
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("Range 0 to 2 is out of range for vector #(1)"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn vector_map_stops_at_the_shortest_vector() {
+    let (error, values) = run(
+        "vector-map",
+        "(define doubled (vector-map (lambda (x) (* x 2)) '#(1 2 3)))\n\
+         (define sums (vector-map + (vector 1 2 3) '#(10 20)))\n\
+         (define none (vector-map car (vector)))\n",
+        &["doubled", "sums", "none"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(values, vec!["#(2 4 6)", "#(11 22)", "#()"]);
+}
+
+#[test]
+fn vector_for_each_visits_elements_in_order() {
+    let (error, values) = run(
+        "vector-for-each",
+        "(define seen '())\n\
+         (define result\n\
+           (vector-for-each (lambda (x y) (set! seen (cons (cons x y) seen)))\n\
+                            '#(a b c) (vector 1 2 3)))\n",
+        &["seen", "result"],
+    );
+
+    assert_eq!(error, None);
+    assert_eq!(
+        values,
+        vec![
+            Some("((c . 3) (b . 2) (a . 1))".to_string()),
+            Some("#<void>".to_string())
+        ]
+    );
+}
+
+#[test]
+fn vectors_can_be_copied_and_appended() {
+    let (error, values) = run(
+        "vector-copy",
+        "(define v (vector 1 2 3 4 5))\n\
+         (define copy (vector-copy v))\n\
+         (vector-set! copy 0 'x)\n\
+         (define part (vector-copy '#(a b c d) 1 3))\n\
+         (define both (vector-append '#(a) (vector 1 2) (vector)))\n\
+         (define shifted (vector 1 2 3 4 5))\n\
+         (vector-copy! shifted 1 shifted 0 3)\n\
+         (define into (vector 0 0 0))\n\
+         (vector-copy! into 1 '#(a b))\n",
+        &["v", "copy", "part", "both", "shifted", "into"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "#(1 2 3 4 5)",
+            "#(x 2 3 4 5)",
+            "#(b c)",
+            "#(a 1 2)",
+            "#(1 1 2 3 5)",
+            "#(0 a b)"
+        ]
+    );
+}
+
+#[test]
+fn vector_copy_to_checks_there_is_room() {
+    let (error, _) = run(
+        "vector-copy-room",
+        "(vector-copy! (vector 1 2) 1 '#(a b))\n",
+        &[],
+    );
+
+    let error = error.unwrap();
+    assert!(
+        error.contains("Can't copy 2 elements to index 1 of a vector of length 2"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn vectors_can_be_sliced_and_sorted_in_place() {
+    let (error, values) = run(
+        "vector-sort",
+        "(define v (vector 3 1 2 5 4))\n\
+         (define middle (subvector v 1 4))\n\
+         (define result (vector-sort! v <))\n\
+         (define pairs (vector (cons 1 'a) (cons 0 'b) (cons 1 'c) (cons 0 'd)))\n\
+         (vector-sort! pairs (lambda (x y) (< (car x) (car y))))\n",
+        &["v", "middle", "result", "pairs"],
+    );
+
+    assert_eq!(error, None);
+    let values: Vec<_> = values.into_iter().map(Option::unwrap).collect();
+    assert_eq!(
+        values,
+        vec![
+            "#(1 2 3 4 5)",
+            "#(1 2 5)",
+            "#<void>",
+            "#((0 . b) (0 . d) (1 . a) (1 . c))"
+        ]
+    );
+}
+
+#[test]
+fn only_vectors_can_be_sorted_in_place() {
+    let (error, _) = run("vector-sort-list", "(vector-sort! (cons 2 1) <)\n", &[]);
+
+    let error = error.unwrap();
+    assert!(error.contains("is not a Vector"), "{}", error);
+}
+
+#[test]
+fn indexing_past_the_end_is_an_error() {
+    let cases = [
+        (
+            "(vector-ref (vector 1 2 3) 3)",
+            "Index 3 is out of range for a vector of length 3",
+        ),
+        (
+            "(vector-ref '#(1 2) -1)",
+            "Index -1 is out of range for a vector of length 2",
+        ),
+        (
+            "(vector-set! (vector) 0 'x)",
+            "Index 0 is out of range for a vector of length 0",
+        ),
+        (
+            "(string-ref \"abc\" 5)",
+            "Index 5 is out of range for a string of length 3",
+        ),
+        (
+            "(string-ref (make-string 2) 1.5)",
+            "Index 1.5 is out of range for a string of length 2",
+        ),
+        ("(vector-copy '#(1 2) -1)", "Range -1 to 2 is out of range"),
+    ];
+
+    for (i, (source, message)) in cases.iter().enumerate() {
+        let (error, _) = run(&format!("index-range-{}", i), &format!("{}\n", source), &[]);
+        let error = error.unwrap();
+        assert!(error.contains(message), "{}: {}", source, error);
+    }
+}
@@ -0,0 +1,81 @@
+use core::cell::RefCell;
+use std::rc::Rc;
+
+use super::{load, read_globals, run_to_end};
+use crate::vm::VmHooks;
+
+#[derive(Debug)]
+struct Recorder(Rc<RefCell<Vec<String>>>);
+
+impl VmHooks for Recorder {
+    fn on_warning(&self, message: &str) {
+        self.0.borrow_mut().push(message.to_string());
+    }
+}
+
+/// Runs a program, returning the warnings it got along with the values of the given globals
+fn warnings(
+    name: &str,
+    source: &str,
+    on: bool,
+    globals: &[&str],
+) -> (Vec<String>, Vec<Option<String>>) {
+    let warnings = Rc::new(RefCell::new(Vec::new()));
+    let mut arena = load(name, source);
+    arena.mutate(|_, vm| {
+        vm.set_hooks(Some(Box::new(Recorder(warnings.clone()))));
+        vm.set_warnings(on);
+    });
+    assert_eq!(run_to_end(&mut arena), None);
+    let values = read_globals(&mut arena, globals);
+    let warnings = warnings.borrow().clone();
+    (warnings, values)
+}
+
+const PROGRAM: &str = "(define-syntax swap!\n\
+       (er-macro-transformer\n\
+         (lambda (form rename _compare)\n\
+           (cons (rename 'let) (cons (cons (cons (rename 'tmp) (cons (car (cdr form)) '()))\n\
+                                          (cons (cons (rename 'spare) '(0)) '()))\n\
+             (cons (cons (rename 'set!) (cdr form))\n\
+                   (cons (cons (rename 'set!) (cons (car (cdr (cdr form)))\n\
+                                                     (cons (rename 'tmp) '()))) '())))))))\n\
+     (define (swapped a b) (swap! a b) (cons a b))\n\
+     (define (pick x y _z)\n\
+       (set! y 3)\n\
+       (let ((unused 1) (z 2))\n\
+         (if (> x 0) (car (cons x z)) x)\n\
+         (set! z 3)\n\
+         ((lambda () z))))\n\
+     (define picked (pick 1 2 3))\n\
+     (define pair (swapped 1 2))\n";
+
+#[test]
+fn unused_variables_and_unreachable_code_are_warned_about() {
+    let (warnings, values) = warnings("warnings", PROGRAM, true, &["picked", "pair"]);
+    let warnings: Vec<_> = warnings
+        .iter()
+        .map(|warning| {
+            let (message, position) = warning.split_once(" (at ").unwrap();
+            let position = position.rsplit_once(".scm:").unwrap().1;
+            format!("{} at {}", message, position.trim_end_matches(')'))
+        })
+        .collect();
+    assert_eq!(
+        warnings,
+        [
+            "unreachable code after a tail call at 14:1",
+            "unused variable unused at 12:1",
+            "unused variable y at 10:1",
+        ]
+    );
+
+    // The `if` ended the procedure, so the body never got as far as returning 3
+    assert_eq!(values, [Some("1".to_string()), Some("(2 . 1)".to_string())]);
+}
+
+#[test]
+fn warnings_are_off_unless_asked_for() {
+    let (warnings, _) = warnings("warnings-off", PROGRAM, false, &[]);
+    assert_eq!(warnings, Vec::<String>::new());
+}
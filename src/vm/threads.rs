@@ -0,0 +1,375 @@
+//! Green threads, which take turns running on the one VM. A thread runs until it yields or has to
+//! wait (for another thread to finish, or for a value to be sent on a channel), at which point the
+//! frame it was in is set aside and the next thread that's ready picks up where it left off.
+//! Threads and channels are records of types that only this module makes. Anything a thread
+//! raises and doesn't handle finishes it, and is raised again in the threads that join it.
+
+use gc_arena::{GcCell, MutationContext};
+
+use super::{borrow_mut, InterpretError, Result, Stack, VirtualMachine};
+use crate::builtins::{self, uncons};
+use crate::memory::Token;
+use crate::object::{
+    ObjContinuation, ObjNative, ObjPair, ObjRecord, ObjRecordType, Object, Procedure,
+};
+use crate::value::{TypeError, Value};
+
+/// Fields of a thread: whether it's finished, what it returned (or raised), whether it raised it,
+/// and the threads waiting for it
+const THREAD_FIELDS: &[&str] = &["done", "result", "raised", "joiners"];
+
+/// Fields of a channel: the values sent but not received yet, and the threads waiting to receive
+/// one. Each is a queue kept in two fields, see `enqueue`.
+const CHANNEL_FIELDS: &[&str] = &["items", "items-back", "receivers", "receivers-back"];
+
+const DONE: usize = 0;
+const RESULT: usize = 1;
+const RAISED: usize = 2;
+const JOINERS: usize = 3;
+const ITEMS: usize = 0;
+const RECEIVERS: usize = 2;
+
+impl<'gc> VirtualMachine<'gc> {
+    /// Makes a thread that calls `thunk` once it gets a turn
+    pub(crate) fn spawn_thread(
+        &self,
+        thunk: Value<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Value<'gc> {
+        let (thread_type, _) = self.thread_record_types(mc);
+        let fields = Box::new([
+            Value::Bool(false),
+            Value::Void,
+            Value::Bool(false),
+            Value::Null,
+        ]);
+        let thread = Value::boxed(mc, Object::Record(ObjRecord::new(thread_type, fields)));
+
+        // The thread starts out in a frame of its own, which calls the thunk once it's resumed
+        let start = ObjNative::registered(0, false, builtins::THREAD_START, None);
+        let stack = vec![
+            Value::boxed(mc, Object::Native(start.clone())),
+            thread,
+            thunk,
+        ];
+        let top = stack.len();
+        let frame = ObjContinuation::new(
+            None,
+            Procedure::Native(start),
+            GcCell::allocate(mc, stack),
+            0..top,
+            self.current_ports(),
+            Value::Null,
+            // Only for finding files the thread loads next to the one that spawned it. Errors it
+            // raises are reported where it's joined.
+            *self.loading.read(),
+        );
+        self.ready_thread(GcCell::allocate(mc, frame), Value::Void, mc);
+        thread
+    }
+
+    /// Lets the other threads that are ready have a turn before the running one carries on
+    pub(crate) fn yield_thread(&self, mc: MutationContext<'gc, '_>) -> Result<()> {
+        let frame = self.suspend_thread(mc)?;
+        self.ready_thread(frame, Value::Void, mc);
+        self.switch_thread(mc)
+    }
+
+    /// Gets what `thread` returned, or if it hasn't finished yet, waits for it to and returns
+    /// `None`. If it raised something instead, that's raised again with the native's `stack`.
+    pub(crate) fn join_thread(
+        &self,
+        thread: Value<'gc>,
+        stack: Stack<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Result<Option<Value<'gc>>> {
+        let (thread_type, _) = self.thread_record_types(mc);
+        let thread = record_of_type(thread, thread_type, "thread")?;
+        let (done, result, raised) = {
+            let thread = thread.read();
+            let fields = thread.as_record()?.fields();
+            (fields[DONE], fields[RESULT], fields[RAISED])
+        };
+        if !raised.is_falsey() {
+            return builtins::raise_with(self, stack, result, false, mc);
+        }
+        if !done.is_falsey() {
+            return Ok(Some(result));
+        }
+
+        let joiner = self.joining_frame(mc)?;
+        let joiner = Value::boxed(mc, Object::Continuation(joiner));
+        let mut object = borrow_mut(&thread, mc)?;
+        let fields = object.as_record_mut()?.fields_mut();
+        fields[JOINERS] = cons(joiner, fields[JOINERS], mc);
+        drop(object);
+        self.switch_thread(mc)?;
+        Ok(None)
+    }
+
+    /// Finishes `thread` with `result`, waking up the threads waiting for it, and moves on to the
+    /// next thread that's ready. If the thread `raised` the result, so do the threads it wakes up.
+    pub(crate) fn finish_thread(
+        &self,
+        thread: Value<'gc>,
+        result: Value<'gc>,
+        raised: bool,
+        mc: MutationContext<'gc, '_>,
+    ) -> Result<()> {
+        let thread = thread.as_object()?;
+        let mut object = borrow_mut(&thread, mc)?;
+        let fields = object.as_record_mut()?.fields_mut();
+        fields[DONE] = Value::Bool(true);
+        fields[RESULT] = result;
+        fields[RAISED] = Value::Bool(raised);
+        let mut joiners = Vec::new();
+        let mut rest = core::mem::replace(&mut fields[JOINERS], Value::Null);
+        drop(object);
+        while let Some((joiner, cdr)) = uncons(rest) {
+            joiners.push(joiner);
+            rest = cdr;
+        }
+
+        // Joiners were added to the front of the list, so wake them up from the back. Each gets
+        // the thread to join again, see `joining_frame`.
+        for joiner in joiners.into_iter().rev() {
+            let frame = resumable_frame(joiner, mc)?;
+            self.ready_thread(frame, Value::Box(thread), mc);
+        }
+        self.switch_thread(mc)
+    }
+
+    /// Finishes the running thread with `obj`, which it raised and didn't handle
+    pub(crate) fn fail_thread(&self, obj: Value<'gc>, mc: MutationContext<'gc, '_>) -> Result<()> {
+        let thread = self.running_thread(mc)?;
+        self.finish_thread(thread, obj, true, mc)
+    }
+
+    /// Makes an empty channel
+    pub(crate) fn make_channel(&self, mc: MutationContext<'gc, '_>) -> Value<'gc> {
+        let (_, channel_type) = self.thread_record_types(mc);
+        let fields = Box::new([Value::Null; 4]);
+        Value::boxed(mc, Object::Record(ObjRecord::new(channel_type, fields)))
+    }
+
+    /// Sends `value` on `channel`, straight to the thread that's been waiting the longest to
+    /// receive one if there is one. Channels hold onto any number of values, so this never waits.
+    pub(crate) fn channel_send(
+        &self,
+        channel: Value<'gc>,
+        value: Value<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Result<()> {
+        let (_, channel_type) = self.thread_record_types(mc);
+        let channel = record_of_type(channel, channel_type, "channel")?;
+        match dequeue(channel, RECEIVERS, mc)? {
+            Some(receiver) => {
+                let frame = resumable_frame(receiver, mc)?;
+                self.ready_thread(frame, value, mc);
+            }
+            None => enqueue(channel, ITEMS, value, mc)?,
+        }
+        Ok(())
+    }
+
+    /// Receives the oldest value sent on `channel`, or if there isn't one yet, waits for one and
+    /// returns `None`
+    pub(crate) fn channel_receive(
+        &self,
+        channel: Value<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) -> Result<Option<Value<'gc>>> {
+        let (_, channel_type) = self.thread_record_types(mc);
+        let channel = record_of_type(channel, channel_type, "channel")?;
+        if let Some(value) = dequeue(channel, ITEMS, mc)? {
+            return Ok(Some(value));
+        }
+
+        let frame = self.suspend_thread(mc)?;
+        let receiver = Value::boxed(mc, Object::Continuation(frame.read().clone()));
+        enqueue(channel, RECEIVERS, receiver, mc)?;
+        self.switch_thread(mc)?;
+        Ok(None)
+    }
+
+    /// Whether `value` is a thread
+    pub(crate) fn is_thread(&self, value: Value<'gc>, mc: MutationContext<'gc, '_>) -> bool {
+        let (thread_type, _) = self.thread_record_types(mc);
+        record_of_type(value, thread_type, "thread").is_ok()
+    }
+
+    /// Whether `value` is a channel
+    pub(crate) fn is_channel(&self, value: Value<'gc>, mc: MutationContext<'gc, '_>) -> bool {
+        let (_, channel_type) = self.thread_record_types(mc);
+        record_of_type(value, channel_type, "channel").is_ok()
+    }
+
+    /// Forgets about every thread but the running one, for when the REPL gives up on whatever
+    /// was being evaluated
+    pub(super) fn clear_threads(&self, mc: MutationContext<'gc, '_>) {
+        self.ready_threads.write(mc).clear();
+    }
+
+    /// Gets the frame the running native returns to, which is where the thread it's running in
+    /// carries on from once it's resumed
+    fn suspend_thread(
+        &self,
+        mc: MutationContext<'gc, '_>,
+    ) -> Result<GcCell<'gc, ObjContinuation<'gc>>> {
+        let frame = *self.parent_continuation(mc).read();
+        frame.ok_or_else(|| {
+            InterpretError::RuntimeError("There is nothing to carry on with after waiting".into())
+        })
+    }
+
+    /// Gets the running thread from the bottom of its continuation, which is the frame
+    /// `thread_start` left to finish it with the thread on its stack
+    fn running_thread(&self, mc: MutationContext<'gc, '_>) -> Result<Value<'gc>> {
+        let mut bottom = *self.parent_continuation(mc).read();
+        while let Some(frame) = bottom.and_then(|frame| frame.read().frames()) {
+            bottom = Some(frame);
+        }
+        if let Some(frame) = bottom {
+            let frame = frame.read();
+            if let Procedure::Native(native) = frame.procedure() {
+                if native.registered_name() == Some(builtins::THREAD_EXIT.0) {
+                    return Ok(frame.stack().read()[frame.stack_base() + 1]);
+                }
+            }
+        }
+        Err(InterpretError::RuntimeError(
+            "Only a spawned thread can fail".into(),
+        ))
+    }
+
+    /// Makes the frame a thread joining another waits in. It's resumed with the joined thread on
+    /// its stack, and joins it again to return what it returned or raise what it raised. It runs
+    /// with the handlers `thread-join!` was called with, which the frame it returns to might not
+    /// have if the call was a tail call.
+    fn joining_frame(&self, mc: MutationContext<'gc, '_>) -> Result<ObjContinuation<'gc>> {
+        let frame = self.suspend_thread(mc)?;
+        let joined = ObjNative::registered(1, false, builtins::THREAD_JOINED, None);
+        let stack = vec![Value::boxed(mc, Object::Native(joined.clone()))];
+        Ok(ObjContinuation::new(
+            Some(frame),
+            Procedure::Native(joined),
+            GcCell::allocate(mc, stack),
+            0..1,
+            self.current_ports(),
+            *self.handlers.read(),
+            *self.loading.read(),
+        ))
+    }
+
+    fn ready_thread(
+        &self,
+        frame: GcCell<'gc, ObjContinuation<'gc>>,
+        value: Value<'gc>,
+        mc: MutationContext<'gc, '_>,
+    ) {
+        self.ready_threads.write(mc).push_back((frame, value));
+    }
+
+    /// Switches to the next thread that's ready. If none are, every thread is waiting on another
+    /// one and would wait forever.
+    fn switch_thread(&self, mc: MutationContext<'gc, '_>) -> Result<()> {
+        let next = self.ready_threads.write(mc).pop_front();
+        let (frame, value) = next.ok_or_else(|| {
+            InterpretError::RuntimeError("Deadlock: every thread is waiting".into())
+        })?;
+        self.apply_continuation(frame, mc);
+        self.push_stack(value, mc);
+        Ok(())
+    }
+
+    /// Gets the record types of threads and channels, making them the first time
+    fn thread_record_types(
+        &self,
+        mc: MutationContext<'gc, '_>,
+    ) -> (GcCell<'gc, Object<'gc>>, GcCell<'gc, Object<'gc>>) {
+        if let Some(types) = *self.thread_types.read() {
+            return types;
+        }
+
+        let record_type = |name: &str, fields: &[&str]| {
+            let name = self.intern_symbol(Token::new(mc, name.into()), mc);
+            let fields = fields
+                .iter()
+                .map(|field| self.intern_symbol(Token::new(mc, (*field).into()), mc))
+                .collect();
+            GcCell::allocate(mc, Object::RecordType(ObjRecordType::new(name, fields)))
+        };
+        let types = (
+            record_type("<thread>", THREAD_FIELDS),
+            record_type("<channel>", CHANNEL_FIELDS),
+        );
+        *self.thread_types.write(mc) = Some(types);
+        types
+    }
+}
+
+/// Gets the object behind `value`, as long as it's a record of `record_type`
+fn record_of_type<'gc>(
+    value: Value<'gc>,
+    record_type: GcCell<'gc, Object<'gc>>,
+    name: &str,
+) -> Result<GcCell<'gc, Object<'gc>>> {
+    if let Value::Box(object) = value {
+        if matches!(&*object.read(), Object::Record(record) if record.is_a(record_type)) {
+            return Ok(object);
+        }
+    }
+    Err(TypeError(format!("'{}' is not a {}", value, name)).into())
+}
+
+/// Gets a frame waiting in a thread or channel back out of the continuation object it was kept
+/// in. Each thread only ever carries on from a frame once, so it isn't copied first the way
+/// applying a continuation copies it.
+fn resumable_frame<'gc>(
+    waiting: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<GcCell<'gc, ObjContinuation<'gc>>> {
+    let frame = waiting.as_object()?.read().as_continuation()?.clone();
+    Ok(GcCell::allocate(mc, frame))
+}
+
+fn cons<'gc>(car: Value<'gc>, cdr: Value<'gc>, mc: MutationContext<'gc, '_>) -> Value<'gc> {
+    Value::boxed(mc, Object::Pair(ObjPair::new(car, cdr)))
+}
+
+/// Adds `value` to the back of the queue kept in two fields of `record`: field `index` holds the
+/// front of the queue as a list, and the one after it holds the back as a list in reverse
+fn enqueue<'gc>(
+    record: GcCell<'gc, Object<'gc>>,
+    index: usize,
+    value: Value<'gc>,
+    mc: MutationContext<'gc, '_>,
+) -> Result<()> {
+    let mut object = borrow_mut(&record, mc)?;
+    let fields = object.as_record_mut()?.fields_mut();
+    fields[index + 1] = cons(value, fields[index + 1], mc);
+    Ok(())
+}
+
+/// Takes the value at the front of the queue kept in two fields of `record` (see `enqueue`), if
+/// it isn't empty
+fn dequeue<'gc>(
+    record: GcCell<'gc, Object<'gc>>,
+    index: usize,
+    mc: MutationContext<'gc, '_>,
+) -> Result<Option<Value<'gc>>> {
+    let mut object = borrow_mut(&record, mc)?;
+    let fields = object.as_record_mut()?.fields_mut();
+    if fields[index].is_null() {
+        let mut back = core::mem::replace(&mut fields[index + 1], Value::Null);
+        while let Some((value, rest)) = uncons(back) {
+            fields[index] = cons(value, fields[index], mc);
+            back = rest;
+        }
+    }
+
+    Ok(uncons(fields[index]).map(|(value, rest)| {
+        fields[index] = rest;
+        value
+    }))
+}